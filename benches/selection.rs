@@ -0,0 +1,90 @@
+//! Sorting, filtering and parsing of a large mirror status.
+//!
+//! Budget, in release mode: parsing 2000 mirrors under 10 ms, sorting or
+//! filtering them under 1 ms.
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use reflecto::{Filters, MirrorList, SortKey};
+use std::hint::black_box;
+
+/// number of mirrors of the generated status
+const MIRRORS: usize = 2000;
+
+/// status document shaped like the one of archlinux.org
+fn status() -> String {
+    let countries = [
+        ("France", "FR"),
+        ("Germany", "DE"),
+        ("Japan", "JP"),
+        ("", ""),
+    ];
+    let urls = (0..MIRRORS)
+        .map(|i| {
+            let (country, code) = countries[i % countries.len()];
+            let protocol = if i % 3 == 0 { "http" } else { "https" };
+            let score = if i % 7 == 0 {
+                "null".to_string()
+            } else {
+                format!("{:.2}", (i * 37 % 1000) as f64 / 10.0)
+            };
+            format!(
+                r#"{{"url": "{protocol}://mirror{i}.example.org/archlinux/",
+                "protocol": "{protocol}", "last_sync": "2024-05-01T{:02}:{:02}:00Z",
+                "completion_pct": 0.9, "delay": {}, "duration_avg": 0.5,
+                "duration_stddev": 0.1, "score": {score}, "active": true,
+                "country": "{country}", "country_code": "{code}", "isos": {},
+                "ipv4": true, "ipv6": {}, "details": ""}}"#,
+                i % 24,
+                i % 60,
+                i * 13 % 7200,
+                i % 2 == 0,
+                i % 5 == 0,
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",\n");
+    format!(r#"{{"cutoff": 86400, "last_check": "2024-05-01T23:00:00Z", "urls": [{urls}]}}"#)
+}
+
+fn parse(c: &mut Criterion) {
+    let status = status();
+    c.bench_function("parse status", |b| {
+        b.iter(|| MirrorList::from_status_str(black_box(&status)).unwrap())
+    });
+}
+
+fn sort(c: &mut Criterion) {
+    let mlist: MirrorList = serde_json::from_str(&status()).unwrap();
+    for key in [
+        SortKey::Score,
+        SortKey::Age,
+        SortKey::Country,
+        SortKey::Duration,
+    ] {
+        c.bench_function(&format!("sort by {key}"), |b| {
+            b.iter_batched(
+                || mlist.clone(),
+                |mut m| m.sort(key.clone()),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+}
+
+fn filter(c: &mut Criterion) {
+    let mlist: MirrorList = serde_json::from_str(&status()).unwrap();
+    let filters = Filters {
+        countries: vec!["FR".into(), "DE".into()],
+        isos: true,
+        ..Default::default()
+    };
+    c.bench_function("filter", |b| {
+        b.iter_batched(
+            || mlist.clone(),
+            |m| m.filter_by(black_box(&filters)),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(benches, parse, sort, filter);
+criterion_main!(benches);
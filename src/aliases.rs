@@ -0,0 +1,139 @@
+//! Mirrors known to be CDN frontends or aliases of the same backend.
+//!
+//! Such mirrors are listed separately in the status, but they serve the same
+//! files from the same servers, so that the first mirrors of a list can all be
+//! the same backend. A few are built in; others are read from
+//! `$XDG_CONFIG_HOME/reflecto/aliases.toml`, or from the file given on the
+//! command line. Each key names a backend, and its value lists the host names
+//! of its mirrors, subdomains included.
+//!
+//! ```toml
+//! "Example CDN" = ["mirror.example.net", "example-cdn.org"]
+//! ```
+use crate::config::config_dir;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Backends known without configuration, and the hosts of their mirrors.
+/// Only names of a single service are listed, not servers merely sharing a
+/// domain: the subdomains of mirror.pkgbuild.com, e.g. europe and america, are
+/// distinct servers
+const BUILTIN: &[(&str, &[&str])] = &[
+    // a single mirror served from the Cloudflare CDN
+    ("Cloudflare", &["cloudflaremirrors.com"]),
+    // mirrors.kernel.org is served by the frontends of mirrors.edge.kernel.org
+    (
+        "kernel.org",
+        &["mirrors.kernel.org", "mirrors.edge.kernel.org"],
+    ),
+];
+
+/// Host names of mirrors, by backend
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Aliases(pub BTreeMap<String, Vec<String>>);
+
+impl Aliases {
+    /// The built-in backends
+    pub fn builtin() -> Self {
+        Self(
+            BUILTIN
+                .iter()
+                .map(|(backend, hosts)| {
+                    (
+                        backend.to_string(),
+                        hosts.iter().map(|h| h.to_string()).collect(),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read aliases {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("invalid aliases {}", path.display()))
+    }
+
+    /// The built-in backends, with the ones of a file if it exists. A host
+    /// listed in the file takes the backend of the file
+    pub fn with_file_if_exists(path: Option<&Path>) -> Result<Self> {
+        let mut aliases = Self::builtin();
+        if let Some(path) = path.filter(|p| p.exists()) {
+            aliases.extend(Self::from_file(path)?);
+        }
+        Ok(aliases)
+    }
+
+    pub fn default_path() -> Option<PathBuf> {
+        config_dir().map(|d| d.join("aliases.toml"))
+    }
+
+    /// Add backends, their hosts replacing the ones of other backends
+    pub fn extend(&mut self, other: Self) {
+        for (backend, hosts) in other.0 {
+            for known in self.0.values_mut() {
+                known.retain(|h| !hosts.iter().any(|o| o.eq_ignore_ascii_case(h)));
+            }
+            self.0.entry(backend).or_default().extend(hosts);
+        }
+        self.0.retain(|_, hosts| !hosts.is_empty());
+    }
+
+    /// Backend of a host, the one of its longest known host name when several
+    /// match
+    pub fn backend(&self, host: &str) -> Option<&str> {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.0
+            .iter()
+            .flat_map(|(backend, hosts)| hosts.iter().map(move |h| (backend, h)))
+            .filter(|(_, h)| {
+                let h = h.to_ascii_lowercase();
+                host == h || host.strip_suffix(&h).is_some_and(|s| s.ends_with('.'))
+            })
+            .max_by_key(|(_, h)| h.len())
+            .map(|(backend, _)| backend.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backends() {
+        let mut aliases = Aliases::builtin();
+        assert_eq!(aliases.backend("Mirrors.Kernel.org"), Some("kernel.org"));
+        assert_eq!(
+            aliases.backend("mirrors.edge.kernel.org."),
+            Some("kernel.org")
+        );
+        assert_eq!(aliases.backend("notmirrors.kernel.org"), None);
+        assert_eq!(aliases.backend("europe.mirror.pkgbuild.com"), None);
+        assert_eq!(aliases.backend("mirror.example.org"), None);
+        let file: Aliases = toml::from_str(
+            r#"
+            "Arch Linux" = ["mirror.pkgbuild.com"]
+            "Example" = ["example.org", "mirrors.kernel.org"]
+            "Edge" = ["edge.kernel.org"]
+            "#,
+        )
+        .unwrap();
+        aliases.extend(file);
+        assert_eq!(
+            aliases.backend("geo.mirror.pkgbuild.com"),
+            Some("Arch Linux")
+        );
+        assert_eq!(aliases.backend("mirror.example.org"), Some("Example"));
+        assert_eq!(aliases.backend("mirrors.kernel.org"), Some("Example"));
+        // the longest known host name wins
+        assert_eq!(
+            aliases.backend("mirrors.edge.kernel.org"),
+            Some("kernel.org")
+        );
+        assert_eq!(aliases.backend("cdn.edge.kernel.org"), Some("Edge"));
+        assert_eq!(aliases.0["kernel.org"], ["mirrors.edge.kernel.org"]);
+    }
+}
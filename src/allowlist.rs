@@ -0,0 +1,191 @@
+//! Firewall rules allowing connections to the selected mirrors only.
+//!
+//! `reflecto export-allowlist` resolves the hosts of the selected mirrors and
+//! prints their addresses and ports, for servers whose egress is restricted.
+//! Hosts that could not be resolved are kept by name in the plain format and
+//! left out, in a comment, of the rules.
+use crate::MirrorList;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::net::IpAddr;
+
+/// Format of the exported allowlist
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum AllowlistFormat {
+    /// nftables table of two sets of address and port pairs, to be loaded
+    /// with `nft -f`
+    Nft,
+    /// iptables and ip6tables commands appending rules to the OUTPUT chain
+    Iptables,
+    /// one address per line, or host name when not resolved
+    #[default]
+    Plain,
+}
+
+/// Host and port of a mirror, and the addresses of the host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Destination {
+    pub host: String,
+    pub port: u16,
+    pub addresses: Vec<IpAddr>,
+}
+
+/// Destinations of the first `number` mirrors, in order, each once
+pub fn destinations(mlist: &MirrorList, number: usize) -> Vec<Destination> {
+    let mut destinations: Vec<Destination> = Vec::new();
+    for m in mlist.mirrors.iter().take(number) {
+        let Ok(url) = reqwest::Url::parse(&m.url) else {
+            continue;
+        };
+        let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+            continue;
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if destinations
+            .iter()
+            .any(|d| d.host == host && d.port == port)
+        {
+            continue;
+        }
+        destinations.push(Destination {
+            host: host.into(),
+            port,
+            addresses: m.addresses.clone().unwrap_or_default(),
+        });
+    }
+    destinations
+}
+
+/// Allowlist of the destinations
+pub fn render(destinations: &[Destination], format: AllowlistFormat) -> String {
+    match format {
+        AllowlistFormat::Plain => plain(destinations),
+        AllowlistFormat::Nft => nft(destinations),
+        AllowlistFormat::Iptables => iptables(destinations),
+    }
+}
+
+fn plain(destinations: &[Destination]) -> String {
+    let mut lines = Vec::new();
+    for d in destinations {
+        if d.addresses.is_empty() {
+            lines.push(d.host.clone());
+        }
+        lines.extend(d.addresses.iter().map(IpAddr::to_string));
+    }
+    let mut seen = HashSet::new();
+    lines.retain(|l| seen.insert(l.clone()));
+    lines.into_iter().map(|l| l + "\n").collect()
+}
+
+/// comments naming the hosts that could not be resolved
+fn unresolved(destinations: &[Destination]) -> String {
+    destinations
+        .iter()
+        .filter(|d| d.addresses.is_empty())
+        .map(|d| format!("# unresolved: {}\n", d.host))
+        .collect()
+}
+
+fn nft(destinations: &[Destination]) -> String {
+    let mut out = String::from(
+        "# use with e.g. `ip daddr . tcp dport @mirrors_v4 accept`\n\
+         # and `ip6 daddr . tcp dport @mirrors_v6 accept`\n",
+    );
+    out += &unresolved(destinations);
+    out += "table inet reflecto {\n";
+    for (name, v4) in [("mirrors_v4", true), ("mirrors_v6", false)] {
+        let elements = destinations
+            .iter()
+            .flat_map(|d| {
+                d.addresses
+                    .iter()
+                    .filter(move |a| a.is_ipv4() == v4)
+                    .map(move |a| format!("{a} . {}", d.port))
+            })
+            .collect::<Vec<_>>();
+        let mut seen = HashSet::new();
+        let elements = elements
+            .into_iter()
+            .filter(|e| seen.insert(e.clone()))
+            .collect::<Vec<_>>();
+        let family = if v4 { "ipv4_addr" } else { "ipv6_addr" };
+        let _ = writeln!(out, "\tset {name} {{\n\t\ttype {family} . inet_service");
+        if !elements.is_empty() {
+            let _ = writeln!(out, "\t\telements = {{ {} }}", elements.join(", "));
+        }
+        out += "\t}\n";
+    }
+    out += "}\n";
+    out
+}
+
+fn iptables(destinations: &[Destination]) -> String {
+    let mut out = unresolved(destinations);
+    let mut seen = HashSet::new();
+    for d in destinations {
+        for a in d.addresses.iter().filter(|a| seen.insert((**a, d.port))) {
+            let (command, prefix) = match a {
+                IpAddr::V4(_) => ("iptables", 32),
+                IpAddr::V6(_) => ("ip6tables", 128),
+            };
+            let _ = writeln!(
+                out,
+                "{command} -A OUTPUT -d {a}/{prefix} -p tcp --dport {} \
+                 -m comment --comment {} -j ACCEPT",
+                d.port, d.host
+            );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Distro;
+
+    fn destinations_of_list() -> Vec<Destination> {
+        let content = "https://a.example.org/\n\
+                       https://a.example.org/arch/\n\
+                       http://b.example.org:8080/\n\
+                       http://c.example.org/";
+        let mut ml = MirrorList::from_mirrorlist(content, Distro::Arch);
+        ml.mirrors[0].addresses = Some(vec![
+            "192.0.2.1".parse().unwrap(),
+            "2001:db8::1".parse().unwrap(),
+        ]);
+        ml.mirrors[2].addresses = Some(vec!["192.0.2.1".parse().unwrap()]);
+        ml.mirrors.push(ml.mirrors[0].clone());
+        ml.mirrors[4].url = "https://a2.example.org/".into();
+        destinations(&ml, 5)
+    }
+
+    #[test]
+    fn destinations_once() {
+        let d = destinations_of_list();
+        assert_eq!(d.len(), 4);
+        assert_eq!((d[1].host.as_str(), d[1].port), ("b.example.org", 8080));
+        assert!(d[2].addresses.is_empty());
+        assert_eq!(d[3].host, "a2.example.org");
+    }
+
+    #[test]
+    fn formats() {
+        let d = destinations_of_list();
+        assert_eq!(
+            render(&d, AllowlistFormat::Plain),
+            "192.0.2.1\n2001:db8::1\nc.example.org\n"
+        );
+        let nft = render(&d, AllowlistFormat::Nft);
+        assert!(nft.contains("# unresolved: c.example.org\n"));
+        assert!(nft.contains("elements = { 192.0.2.1 . 443, 192.0.2.1 . 8080 }"));
+        assert!(nft.contains("elements = { 2001:db8::1 . 443 }"));
+        let iptables = render(&d, AllowlistFormat::Iptables);
+        assert_eq!(iptables.lines().count(), 4);
+        assert!(iptables.contains(
+            "ip6tables -A OUTPUT -d 2001:db8::1/128 -p tcp --dport 443 \
+             -m comment --comment a.example.org -j ACCEPT"
+        ));
+    }
+}
@@ -0,0 +1,116 @@
+//! Selection written as a directory to embed into an archiso profile.
+//!
+//! The bundle is laid out as the profile, so that copying it over the profile
+//! puts the mirrorlist and the status in the image:
+//!
+//! - `airootfs/etc/pacman.d/mirrorlist`: the written mirrors
+//! - `airootfs/etc/reflecto/status.json`: status of the kept mirrors, with
+//!   their measurements, to rank them again offline
+//! - `reflecto-bundle.json`: the manifest describing the bundle
+use crate::report;
+use crate::{Error, MirrorList, RenderOptions};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Path of the mirrorlist, relative to the bundle directory
+pub const MIRRORLIST_PATH: &str = "airootfs/etc/pacman.d/mirrorlist";
+/// Path of the status, relative to the bundle directory
+pub const STATUS_PATH: &str = "airootfs/etc/reflecto/status.json";
+/// Path of the manifest, relative to the bundle directory
+pub const MANIFEST_PATH: &str = "reflecto-bundle.json";
+
+/// Description of a bundle
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub created: DateTime<Utc>,
+    /// version of reflecto that wrote the bundle
+    pub generator: String,
+    pub distro: crate::Distro,
+    /// when the mirror status was last checked by the distribution
+    pub last_check: Option<DateTime<Utc>>,
+    /// number of mirrors in the mirrorlist
+    pub mirrors: usize,
+    /// hash of the URLs of the mirrorlist. See [`crate::state::mirrors_hash_of`]
+    pub mirrors_hash: String,
+    pub files: Vec<ManifestFile>,
+}
+
+/// File of a bundle
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// path relative to the bundle directory
+    pub path: String,
+    /// size, in bytes
+    pub size: usize,
+}
+
+/// Write a bundle of the mirrors in `dir`, the mirrorlist rendered with the
+/// options, creating it if needed. Files of a previous bundle are replaced
+pub fn write(dir: &Path, mlist: &MirrorList, options: &RenderOptions) -> Result<Manifest> {
+    let number = options.limit.of(mlist.len());
+    let files = [
+        (MIRRORLIST_PATH, mlist.to_file_content(options)),
+        (STATUS_PATH, report::status_json(mlist, mlist.len())),
+    ];
+    let mut manifest = Manifest {
+        created: mlist.now(),
+        generator: format!("reflecto {}", env!("CARGO_PKG_VERSION")),
+        distro: mlist.distro,
+        last_check: mlist.last_check,
+        mirrors: number,
+        mirrors_hash: crate::state::mirrors_hash_of(&mlist.urls().take(number).collect::<Vec<_>>()),
+        files: Vec::new(),
+    };
+    for (path, content) in files {
+        write_file(&dir.join(path), &content)?;
+        manifest.files.push(ManifestFile {
+            path: path.into(),
+            size: content.len(),
+        });
+    }
+    let content = serde_json::to_string_pretty(&manifest).expect("manifest is always serializable");
+    write_file(&dir.join(MANIFEST_PATH), &content)?;
+    Ok(manifest)
+}
+
+/// write a file and its directory, reporting failures as [`Error::WriteFailed`]
+fn write_file(path: &Path, content: &str) -> Result<()> {
+    let failed = |e| {
+        anyhow::Error::new(e).context(Error::WriteFailed {
+            path: path.to_path_buf(),
+        })
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(failed)?;
+    }
+    std::fs::write(path, content).map_err(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Distro, Number};
+
+    #[test]
+    fn layout() {
+        let dir = std::env::temp_dir().join(format!("reflecto-bundle-{}", std::process::id()));
+        let content = "https://a.example.org/\nhttps://b.example.org/";
+        let ml = MirrorList::from_mirrorlist(content, Distro::Arch);
+        let manifest = write(&dir, &ml, &RenderOptions::new(Number::Limit(1))).unwrap();
+        assert_eq!(manifest.mirrors, 1);
+        assert_eq!(manifest.files.len(), 2);
+        let mirrorlist = std::fs::read_to_string(dir.join(MIRRORLIST_PATH)).unwrap();
+        assert!(mirrorlist.contains("Server = https://a.example.org/$repo/os/$arch"));
+        assert!(!mirrorlist.contains("b.example.org"));
+        let status = MirrorList::from_status_file(&dir.join(STATUS_PATH), Distro::Arch).unwrap();
+        assert_eq!(status.len(), 2);
+        let saved = std::fs::read_to_string(dir.join(MANIFEST_PATH)).unwrap();
+        assert_eq!(serde_json::from_str::<Manifest>(&saved).unwrap(), manifest);
+        // a file is in the way of the directory of the mirrorlist
+        let e = write(&dir.join(MANIFEST_PATH), &ml, &RenderOptions::default()).unwrap_err();
+        assert!(matches!(e.downcast_ref(), Some(Error::WriteFailed { .. })));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
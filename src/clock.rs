@@ -0,0 +1,43 @@
+//! Source of the current time.
+//!
+//! A run reads the clock once and uses that reference for every age
+//! computation, so that mirrors are compared against the same instant.
+use chrono::{DateTime, Utc};
+
+pub trait Clock: std::fmt::Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system clock
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Clock of a run: stopped at `now` if given, else with `deterministic` at
+/// the last check of the status, so that runs on the same input agree, else
+/// the system clock
+pub fn reference(
+    now: Option<DateTime<Utc>>,
+    deterministic: bool,
+    last_check: Option<DateTime<Utc>>,
+) -> Box<dyn Clock> {
+    match now.or(last_check.filter(|_| deterministic)) {
+        Some(t) => Box::new(FixedClock(t)),
+        None => Box::new(SystemClock),
+    }
+}
+
+/// A clock stopped at a given instant, e.g. for tests
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
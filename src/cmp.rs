@@ -0,0 +1,110 @@
+//! Ordering of mirrors and measurements.
+//!
+//! Numbers are ordered ascending, unknown values and NaN last, so that a
+//! mirror lacking a measurement never outranks a measured one. Sorts are
+//! stable: items comparing equal keep their previous order, e.g. the order of
+//! the mirror status. Sorting on several keys compares tuples of values, the
+//! first key first.
+use chrono::{DateTime, Utc};
+use std::cmp::Ordering;
+
+/// Ascending order of two numbers, unknown values and NaN last
+pub fn numbers(a: Option<f64>, b: Option<f64>) -> Ordering {
+    SortValue::number(a).cmp(&SortValue::number(b))
+}
+
+/// Sort the items by key, keeping the order of items of equal keys. Each key
+/// is computed once per item
+pub fn sort_by_key<T, K: Ord>(items: &mut [T], key: impl FnMut(&T) -> K) {
+    items.sort_by_cached_key(key)
+}
+
+/// Value of a mirror for a sort key, ordered as the key orders mirrors
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum SortValue {
+    Date(DateTime<Utc>),
+    Text(String),
+    /// ascending, unknown (or not a number) last
+    Number {
+        unknown: bool,
+        value: TotalF64,
+    },
+}
+
+impl SortValue {
+    pub(crate) fn number(value: Option<f64>) -> Self {
+        match value.filter(|v| !v.is_nan()) {
+            Some(v) => SortValue::Number {
+                unknown: false,
+                value: TotalF64(v),
+            },
+            None => SortValue::Number {
+                unknown: true,
+                value: TotalF64(0.0),
+            },
+        }
+    }
+}
+
+/// f64 with a total order
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TotalF64(f64);
+
+impl PartialEq for TotalF64 {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for TotalF64 {}
+
+impl PartialOrd for TotalF64 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TotalF64 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_last() {
+        assert_eq!(numbers(Some(1.0), Some(2.0)), Ordering::Less);
+        assert_eq!(numbers(Some(f64::INFINITY), None), Ordering::Less);
+        assert_eq!(numbers(None, Some(-1.0)), Ordering::Greater);
+        assert_eq!(numbers(Some(f64::NAN), Some(1e300)), Ordering::Greater);
+        assert_eq!(numbers(Some(f64::NAN), None), Ordering::Equal);
+        assert_eq!(numbers(None, None), Ordering::Equal);
+    }
+
+    #[test]
+    fn stable() {
+        let mut items = vec![
+            ("a", Some(2.0)),
+            ("b", None),
+            ("c", Some(1.0)),
+            ("d", Some(f64::NAN)),
+            ("e", Some(2.0)),
+            ("f", None),
+        ];
+        sort_by_key(&mut items, |i| SortValue::number(i.1));
+        let names = items.iter().map(|i| i.0).collect::<String>();
+        assert_eq!(names, "caebdf");
+    }
+
+    #[test]
+    fn several_keys() {
+        let mut items = vec![("b", 1.0), ("a", 2.0), ("a", 1.0)];
+        sort_by_key(&mut items, |i| {
+            (SortValue::Text(i.0.into()), SortValue::number(Some(i.1)))
+        });
+        assert_eq!(items, [("a", 1.0), ("a", 2.0), ("b", 1.0)]);
+    }
+}
@@ -0,0 +1,116 @@
+//! Collation of the country names of the table of countries.
+//!
+//! With the `collation` feature, names are compared with the rules of a
+//! locale, by default the one of `LC_ALL`, `LC_COLLATE` or `LANG`. Without it,
+//! case and accents are ignored and the letters left are compared by code
+//! point, whatever the locale.
+use std::cmp::Ordering;
+#[cfg(feature = "collation")]
+use {icu_collator::Collator, icu_locale_core::Locale, tracing::warn};
+#[cfg(not(feature = "collation"))]
+use {unicode_normalization::char::is_combining_mark, unicode_normalization::UnicodeNormalization};
+
+/// Comparison of strings following the rules of a locale
+pub struct Collation {
+    #[cfg(feature = "collation")]
+    collator: icu_collator::CollatorBorrowed<'static>,
+}
+
+impl Collation {
+    /// Collation of a POSIX locale, e.g. `sv_SE.UTF-8`, or of a BCP 47 tag,
+    /// e.g. `sv-SE`. `C`, `POSIX` and unknown locales use the root collation
+    #[cfg(feature = "collation")]
+    pub fn new(locale: &str) -> Self {
+        let tag = locale
+            .split(['.', '@'])
+            .next()
+            .unwrap_or_default()
+            .replace('_', "-");
+        let parsed = match tag.as_str() {
+            "" | "C" | "POSIX" => Locale::UNKNOWN,
+            tag => tag.parse().unwrap_or_else(|e| {
+                warn!("unknown locale {locale}: {e}, using the root collation");
+                Locale::UNKNOWN
+            }),
+        };
+        // the compiled data falls back to the root collation
+        let collator = Collator::try_new((&parsed).into(), Default::default())
+            .or_else(|_| Collator::try_new((&Locale::UNKNOWN).into(), Default::default()))
+            .expect("the root collation is compiled in");
+        Self { collator }
+    }
+
+    /// Collation ignoring case and accents, reflecto being built without the
+    /// collation feature
+    #[cfg(not(feature = "collation"))]
+    pub fn new(_locale: &str) -> Self {
+        Self {}
+    }
+
+    /// Collation of the locale of the environment: `LC_ALL`, `LC_COLLATE` or
+    /// `LANG`, the first one set
+    pub fn from_env() -> Self {
+        let locale = ["LC_ALL", "LC_COLLATE", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|v| !v.is_empty())
+            .unwrap_or_default();
+        Self::new(&locale)
+    }
+
+    /// Order of two strings
+    #[cfg(feature = "collation")]
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        self.collator.compare(a, b)
+    }
+
+    /// Order of two strings, regardless of case and accents
+    #[cfg(not(feature = "collation"))]
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        key(a).cmp(&key(b))
+    }
+}
+
+/// key sorting strings by code point, regardless of case and accents
+#[cfg(not(feature = "collation"))]
+fn key(s: &str) -> String {
+    s.nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(collation: &Collation) -> Vec<&'static str> {
+        let mut names = vec!["Zambia", "Österrike", "oman", "Oman"];
+        names.sort_by(|a, b| collation.compare(a, b).then_with(|| a.cmp(b)));
+        names
+    }
+
+    #[test]
+    #[cfg(feature = "collation")]
+    fn by_locale() {
+        assert_eq!(
+            sorted(&Collation::new("sv_SE.UTF-8")),
+            ["oman", "Oman", "Zambia", "Österrike"]
+        );
+        for locale in ["de_DE.UTF-8", "de-DE", "C", "", "not a locale"] {
+            assert_eq!(
+                sorted(&Collation::new(locale)),
+                ["oman", "Oman", "Österrike", "Zambia"]
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "collation"))]
+    fn without_locale() {
+        assert_eq!(
+            sorted(&Collation::new("sv_SE.UTF-8")),
+            ["Oman", "oman", "Österrike", "Zambia"]
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Configuration file.
+//!
+//! The configuration is read from `$XDG_CONFIG_HOME/reflecto/config.toml`
+//! (`~/.config/reflecto/config.toml` if not set), or from the file given on
+//! the command line. In a systemd service with `ConfigurationDirectory=`, the
+//! configuration directory is the one given by systemd.
+//!
+//! ```toml
+//! [outputs.core]
+//! path = "/etc/pacman.d/mirrorlist-core"
+//! number = 5
+//! template = "Server = {url}$repo/os/$arch"
+//!
+//! [outputs.multilib]
+//! path = "/etc/pacman.d/mirrorlist-multilib"
+//! ipv6 = true
+//! ```
+use crate::Number;
+use anyhow::{Context, Result};
+use serde::{de, Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Files rendered in a single run, by name
+    #[serde(default)]
+    pub outputs: BTreeMap<String, Output>,
+}
+
+/// A mirrorlist file rendered from a subset of the selected mirrors
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Output {
+    /// where to save the file
+    pub path: PathBuf,
+    /// number of mirrors to keep, a positive number or "all". All if not set
+    #[serde(default, deserialize_with = "output_number")]
+    pub number: Option<Number>,
+    /// template of server lines. See [`crate::MirrorList::to_file_content_with_template`]
+    pub template: Option<String>,
+    /// only keep mirrors synchronized in the last n hours
+    pub age: Option<f64>,
+    #[serde(default)]
+    pub isos: bool,
+    #[serde(default)]
+    pub ipv4: bool,
+    #[serde(default)]
+    pub ipv6: bool,
+}
+
+/// Number of mirrors of an output, parsed as on the command line, except for
+/// 0 which would write an empty file
+fn output_number<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Number>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Count(usize),
+        Word(String),
+    }
+    let word = match Option::<Repr>::deserialize(d)? {
+        None => return Ok(None),
+        Some(Repr::Count(n)) => n.to_string(),
+        Some(Repr::Word(s)) => s,
+    };
+    if word.trim() == "0" {
+        return Err(de::Error::custom("number must be positive, or \"all\""));
+    }
+    word.parse().map(Some).map_err(de::Error::custom)
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read configuration {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("invalid configuration {}", path.display()))
+    }
+
+    /// Read the configuration from its default location, if it exists
+    pub fn from_default_path() -> Result<Self> {
+        Self::from_file_if_exists(Self::default_path().as_deref())
+    }
+
+    /// Read the configuration from a file if it exists, empty otherwise
+    pub fn from_file_if_exists(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) if path.exists() => Self::from_file(path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// Default location of the configuration file
+    pub fn default_path() -> Option<PathBuf> {
+        config_dir().map(|d| d.join("config.toml"))
+    }
+}
+
+/// Default location of the blacklist. See [`crate::urlfile`]
+pub fn blacklist_path() -> Option<PathBuf> {
+    config_dir().map(|d| d.join("blacklist"))
+}
+
+/// Default location of the list of pinned mirrors. See [`crate::urlfile`]
+pub fn pins_path() -> Option<PathBuf> {
+    config_dir().map(|d| d.join("pins"))
+}
+
+/// Directory of the configuration files: the one given by systemd to a service,
+/// or the reflecto directory in the user configuration directory
+pub fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("CONFIGURATION_DIRECTORY")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| config_home().map(|d| d.join("reflecto")))
+}
+
+/// User configuration directory
+pub fn config_home() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outputs() {
+        let config: Config = toml::from_str(
+            r#"
+            [outputs.core]
+            path = "/tmp/core"
+            number = 5
+            template = "Server = {url}core/os/$arch"
+
+            [outputs.extra]
+            path = "/tmp/extra"
+            ipv6 = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.outputs.len(), 2);
+        let core = &config.outputs["core"];
+        assert_eq!(core.number, Some(Number::Limit(5)));
+        assert!(!core.ipv6);
+        let extra = &config.outputs["extra"];
+        assert!(extra.template.is_none());
+        assert!(extra.ipv6);
+        assert_eq!(extra.number, None);
+    }
+
+    #[test]
+    fn output_numbers() {
+        let number = |value: &str| {
+            toml::from_str::<Config>(&format!("[outputs.a]\npath = \"/tmp\"\nnumber = {value}"))
+                .map(|c| c.outputs["a"].number)
+        };
+        assert_eq!(number("\"all\"").unwrap(), Some(Number::All));
+        assert_eq!(number("\"3\"").unwrap(), Some(Number::Limit(3)));
+        assert!(number("0").is_err());
+        assert!(number("\"none\"").is_err());
+    }
+
+    #[test]
+    fn unknown_field() {
+        let r: Result<Config, _> = toml::from_str("[outputs.core]\npath = \"/tmp\"\nfoo = 1");
+        assert!(r.is_err());
+        let config: Config = toml::from_str("").unwrap();
+        assert!(config.outputs.is_empty());
+    }
+}
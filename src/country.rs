@@ -0,0 +1,327 @@
+//! Offline database of countries.
+//!
+//! Countries can be designated by their name, by their ISO 3166-1 alpha-2
+//! code or by a common alias, case insensitively.
+use anyhow::{bail, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Country {
+    /// ISO 3166-1 alpha-2 code
+    pub code: &'static str,
+    /// name, as used by archlinux.org
+    pub name: &'static str,
+}
+
+/// (code, name) of every country
+static COUNTRIES: &[(&str, &str)] = &[
+    ("AD", "Andorra"),
+    ("AE", "United Arab Emirates"),
+    ("AF", "Afghanistan"),
+    ("AG", "Antigua and Barbuda"),
+    ("AL", "Albania"),
+    ("AM", "Armenia"),
+    ("AO", "Angola"),
+    ("AR", "Argentina"),
+    ("AT", "Austria"),
+    ("AU", "Australia"),
+    ("AZ", "Azerbaijan"),
+    ("BA", "Bosnia and Herzegovina"),
+    ("BB", "Barbados"),
+    ("BD", "Bangladesh"),
+    ("BE", "Belgium"),
+    ("BF", "Burkina Faso"),
+    ("BG", "Bulgaria"),
+    ("BH", "Bahrain"),
+    ("BI", "Burundi"),
+    ("BJ", "Benin"),
+    ("BN", "Brunei"),
+    ("BO", "Bolivia"),
+    ("BR", "Brazil"),
+    ("BS", "Bahamas"),
+    ("BT", "Bhutan"),
+    ("BW", "Botswana"),
+    ("BY", "Belarus"),
+    ("BZ", "Belize"),
+    ("CA", "Canada"),
+    ("CD", "Congo (the Democratic Republic of the)"),
+    ("CF", "Central African Republic"),
+    ("CG", "Congo"),
+    ("CH", "Switzerland"),
+    ("CI", "Côte d'Ivoire"),
+    ("CL", "Chile"),
+    ("CM", "Cameroon"),
+    ("CN", "China"),
+    ("CO", "Colombia"),
+    ("CR", "Costa Rica"),
+    ("CU", "Cuba"),
+    ("CV", "Cabo Verde"),
+    ("CY", "Cyprus"),
+    ("CZ", "Czechia"),
+    ("DE", "Germany"),
+    ("DJ", "Djibouti"),
+    ("DK", "Denmark"),
+    ("DM", "Dominica"),
+    ("DO", "Dominican Republic"),
+    ("DZ", "Algeria"),
+    ("EC", "Ecuador"),
+    ("EE", "Estonia"),
+    ("EG", "Egypt"),
+    ("ER", "Eritrea"),
+    ("ES", "Spain"),
+    ("ET", "Ethiopia"),
+    ("FI", "Finland"),
+    ("FJ", "Fiji"),
+    ("FR", "France"),
+    ("GA", "Gabon"),
+    ("GB", "United Kingdom"),
+    ("GD", "Grenada"),
+    ("GE", "Georgia"),
+    ("GH", "Ghana"),
+    ("GL", "Greenland"),
+    ("GM", "Gambia"),
+    ("GN", "Guinea"),
+    ("GQ", "Equatorial Guinea"),
+    ("GR", "Greece"),
+    ("GT", "Guatemala"),
+    ("GW", "Guinea-Bissau"),
+    ("GY", "Guyana"),
+    ("HK", "Hong Kong"),
+    ("HN", "Honduras"),
+    ("HR", "Croatia"),
+    ("HT", "Haiti"),
+    ("HU", "Hungary"),
+    ("ID", "Indonesia"),
+    ("IE", "Ireland"),
+    ("IL", "Israel"),
+    ("IN", "India"),
+    ("IQ", "Iraq"),
+    ("IR", "Iran"),
+    ("IS", "Iceland"),
+    ("IT", "Italy"),
+    ("JM", "Jamaica"),
+    ("JO", "Jordan"),
+    ("JP", "Japan"),
+    ("KE", "Kenya"),
+    ("KG", "Kyrgyzstan"),
+    ("KH", "Cambodia"),
+    ("KP", "North Korea"),
+    ("KR", "South Korea"),
+    ("KW", "Kuwait"),
+    ("KZ", "Kazakhstan"),
+    ("LA", "Laos"),
+    ("LB", "Lebanon"),
+    ("LI", "Liechtenstein"),
+    ("LK", "Sri Lanka"),
+    ("LR", "Liberia"),
+    ("LS", "Lesotho"),
+    ("LT", "Lithuania"),
+    ("LU", "Luxembourg"),
+    ("LV", "Latvia"),
+    ("LY", "Libya"),
+    ("MA", "Morocco"),
+    ("MC", "Monaco"),
+    ("MD", "Moldova"),
+    ("ME", "Montenegro"),
+    ("MG", "Madagascar"),
+    ("MK", "North Macedonia"),
+    ("ML", "Mali"),
+    ("MM", "Myanmar"),
+    ("MN", "Mongolia"),
+    ("MO", "Macao"),
+    ("MR", "Mauritania"),
+    ("MT", "Malta"),
+    ("MU", "Mauritius"),
+    ("MV", "Maldives"),
+    ("MW", "Malawi"),
+    ("MX", "Mexico"),
+    ("MY", "Malaysia"),
+    ("MZ", "Mozambique"),
+    ("NA", "Namibia"),
+    ("NC", "New Caledonia"),
+    ("NE", "Niger"),
+    ("NG", "Nigeria"),
+    ("NI", "Nicaragua"),
+    ("NL", "Netherlands"),
+    ("NO", "Norway"),
+    ("NP", "Nepal"),
+    ("NZ", "New Zealand"),
+    ("OM", "Oman"),
+    ("PA", "Panama"),
+    ("PE", "Peru"),
+    ("PG", "Papua New Guinea"),
+    ("PH", "Philippines"),
+    ("PK", "Pakistan"),
+    ("PL", "Poland"),
+    ("PR", "Puerto Rico"),
+    ("PS", "Palestine"),
+    ("PT", "Portugal"),
+    ("PY", "Paraguay"),
+    ("QA", "Qatar"),
+    ("RE", "Réunion"),
+    ("RO", "Romania"),
+    ("RS", "Serbia"),
+    ("RU", "Russia"),
+    ("RW", "Rwanda"),
+    ("SA", "Saudi Arabia"),
+    ("SC", "Seychelles"),
+    ("SD", "Sudan"),
+    ("SE", "Sweden"),
+    ("SG", "Singapore"),
+    ("SI", "Slovenia"),
+    ("SK", "Slovakia"),
+    ("SL", "Sierra Leone"),
+    ("SN", "Senegal"),
+    ("SO", "Somalia"),
+    ("SR", "Suriname"),
+    ("SV", "El Salvador"),
+    ("SY", "Syria"),
+    ("TG", "Togo"),
+    ("TH", "Thailand"),
+    ("TJ", "Tajikistan"),
+    ("TM", "Turkmenistan"),
+    ("TN", "Tunisia"),
+    ("TR", "Türkiye"),
+    ("TT", "Trinidad and Tobago"),
+    ("TW", "Taiwan"),
+    ("TZ", "Tanzania"),
+    ("UA", "Ukraine"),
+    ("UG", "Uganda"),
+    ("US", "United States"),
+    ("UY", "Uruguay"),
+    ("UZ", "Uzbekistan"),
+    ("VE", "Venezuela"),
+    ("VN", "Vietnam"),
+    ("YE", "Yemen"),
+    ("ZA", "South Africa"),
+    ("ZM", "Zambia"),
+    ("ZW", "Zimbabwe"),
+];
+
+/// (alias, code) of common alternative names
+static ALIASES: &[(&str, &str)] = &[
+    ("America", "US"),
+    ("USA", "US"),
+    ("United States of America", "US"),
+    ("UK", "GB"),
+    ("Great Britain", "GB"),
+    ("Britain", "GB"),
+    ("England", "GB"),
+    ("Holland", "NL"),
+    ("The Netherlands", "NL"),
+    ("Czech Republic", "CZ"),
+    ("Russian Federation", "RU"),
+    ("Korea", "KR"),
+    ("Republic of Korea", "KR"),
+    ("Korea, Republic of", "KR"),
+    ("Turkey", "TR"),
+    ("Viet Nam", "VN"),
+    ("Macedonia", "MK"),
+    ("Ivory Coast", "CI"),
+    ("Cape Verde", "CV"),
+    ("Macau", "MO"),
+    ("Burma", "MM"),
+    ("Reunion", "RE"),
+    ("Iran, Islamic Republic of", "IR"),
+    ("Moldova, Republic of", "MD"),
+    ("Taiwan, Province of China", "TW"),
+    ("UAE", "AE"),
+];
+
+/// Every known country
+pub fn all() -> impl Iterator<Item = Country> {
+    COUNTRIES.iter().map(|&(code, name)| Country { code, name })
+}
+
+/// Names and codes of every country and alias, e.g. for shell completion
+pub fn names() -> impl Iterator<Item = &'static str> {
+    COUNTRIES
+        .iter()
+        .flat_map(|(code, name)| [*name, *code])
+        .chain(ALIASES.iter().map(|(alias, _)| *alias))
+}
+
+/// Find a country by name, code or alias
+pub fn lookup(input: &str) -> Option<Country> {
+    let input = input.trim().replace('_', " ");
+    if input.is_empty() {
+        return None;
+    }
+    let code = ALIASES
+        .iter()
+        .find(|(alias, _)| alias.eq_ignore_ascii_case(&input))
+        .map(|(_, code)| *code);
+    all().find(|c| {
+        code == Some(c.code)
+            || c.code.eq_ignore_ascii_case(&input)
+            || c.name.to_lowercase() == input.to_lowercase()
+    })
+}
+
+/// Closest country name, for misspelled input
+pub fn suggest(input: &str) -> Option<&'static str> {
+    let input = input.trim().to_lowercase();
+    COUNTRIES
+        .iter()
+        .map(|(_, name)| *name)
+        .chain(ALIASES.iter().map(|(alias, _)| *alias))
+        .map(|name| (strsim::jaro_winkler(&input, &name.to_lowercase()), name))
+        .filter(|(score, _)| *score > 0.8)
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, name)| name)
+}
+
+/// Find a country, with a suggestion in the error if not found
+pub fn normalize(input: &str) -> Result<Country> {
+    if let Some(country) = lookup(input) {
+        return Ok(country);
+    }
+    match suggest(input) {
+        Some(name) => bail!("unknown country '{input}', did you mean '{name}'?"),
+        None => bail!("unknown country '{input}'"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_variants() {
+        for input in ["Germany", "germany", "DE", "de", " De "] {
+            assert_eq!(lookup(input).unwrap().code, "DE", "{input}");
+        }
+        assert_eq!(lookup("USA").unwrap().name, "United States");
+        assert_eq!(lookup("uk").unwrap().name, "United Kingdom");
+        assert_eq!(lookup("United_States").unwrap().code, "US");
+        assert_eq!(lookup("Czech Republic").unwrap().name, "Czechia");
+        assert!(lookup("").is_none());
+        assert!(lookup("Atlantis").is_none());
+    }
+
+    #[test]
+    fn suggestion() {
+        assert_eq!(suggest("Czechai"), Some("Czechia"));
+        assert_eq!(suggest("Grmany"), Some("Germany"));
+        let e = normalize("Czechai").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "unknown country 'Czechai', did you mean 'Czechia'?"
+        );
+        assert!(normalize("zzzzzzzzzz")
+            .unwrap_err()
+            .to_string()
+            .ends_with("'zzzzzzzzzz'"));
+    }
+
+    #[test]
+    fn unique() {
+        let mut codes = all().map(|c| c.code).collect::<Vec<_>>();
+        let len = codes.len();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), len);
+        assert!(ALIASES
+            .iter()
+            .all(|(_, code)| all().any(|c| c.code == *code)));
+    }
+}
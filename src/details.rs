@@ -0,0 +1,226 @@
+//! Mirror details, as published on the mirror pages of archlinux.org.
+//!
+//! Each mirror of the status has a `details` URL. Its JSON version contains
+//! the results of the latest checks of the mirror. Retrieving them requires
+//! the `details` feature.
+use crate::parse_date;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+#[cfg(feature = "details")]
+use {
+    crate::pipeline::Pipeline,
+    crate::session::Client,
+    anyhow::Result,
+    std::collections::HashMap,
+    tracing::{debug, info, warn},
+};
+
+/// maximum number of details pages retrieved simultaneously
+#[cfg(feature = "details")]
+const CONCURRENT_FETCHES: usize = 8;
+
+/// Result of a mirror check by archlinux.org
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+pub struct Check {
+    #[serde(default, with = "parse_date")]
+    pub check_time: Option<DateTime<Utc>>,
+    #[serde(default, with = "parse_date")]
+    pub last_sync: Option<DateTime<Utc>>,
+    /// time, in seconds, needed to retrieve the check file
+    pub duration: Option<f64>,
+    #[serde(default)]
+    pub is_success: bool,
+}
+
+/// Details of one URL of a mirror
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Details {
+    /// mirror tier, 0 being the main mirror
+    #[serde(skip)]
+    pub tier: Option<u8>,
+    pub completion_pct: Option<f64>,
+    pub duration_avg: Option<f64>,
+    pub duration_stddev: Option<f64>,
+    /// check history, most recent first
+    #[serde(default)]
+    pub logs: Vec<Check>,
+}
+
+impl Details {
+    /// Average duration of the successful checks
+    pub fn average_duration(&self) -> Option<f64> {
+        let durations = self
+            .logs
+            .iter()
+            .filter(|c| c.is_success)
+            .filter_map(|c| c.duration)
+            .collect::<Vec<_>>();
+        if durations.is_empty() {
+            self.duration_avg
+        } else {
+            Some(durations.iter().sum::<f64>() / durations.len() as f64)
+        }
+    }
+
+    /// Fraction of the checks in the history that succeeded
+    pub fn success_rate(&self) -> Option<f64> {
+        if self.logs.is_empty() {
+            return self.completion_pct;
+        }
+        let success = self.logs.iter().filter(|c| c.is_success).count();
+        Some(success as f64 / self.logs.len() as f64)
+    }
+}
+
+#[cfg(feature = "details")]
+/// Details page of a mirror, covering every URL of the mirror
+#[derive(Debug, Default, Clone, Deserialize)]
+struct DetailsPage {
+    /// -1 for mirrors without a tier
+    tier: Option<i8>,
+    #[serde(default)]
+    urls: Vec<DetailsUrl>,
+}
+
+#[cfg(feature = "details")]
+#[derive(Debug, Default, Clone, Deserialize)]
+struct DetailsUrl {
+    url: String,
+    #[serde(flatten)]
+    details: Details,
+}
+
+#[cfg(feature = "details")]
+impl DetailsPage {
+    /// details of every URL, by URL
+    fn into_details(self) -> HashMap<String, Details> {
+        self.urls
+            .into_iter()
+            .map(|u| {
+                let details = Details {
+                    tier: self.tier.and_then(|t| u8::try_from(t).ok()),
+                    ..u.details
+                };
+                (u.url, details)
+            })
+            .collect()
+    }
+}
+
+/// JSON version of a details page
+pub fn json_url(details: &str) -> String {
+    format!("{}/json/", details.trim_end_matches('/'))
+}
+
+#[cfg(feature = "details")]
+async fn fetch_page(client: Client, url: String) -> Result<HashMap<String, Details>> {
+    debug!("retrieve details {}", url);
+    let body = client.get(&json_url(&url)).send().await?.text().await?;
+    let page: DetailsPage = serde_json::from_str(&body)?;
+    Ok(page.into_details())
+}
+
+#[cfg(feature = "details")]
+/// Retrieve the details of every page, concurrently.
+///
+/// Returns the details by mirror URL. Pages that could not be retrieved are
+/// ignored.
+pub async fn fetch_all(
+    pages: Vec<String>,
+    timeout: Option<chrono::Duration>,
+) -> Result<HashMap<String, Details>> {
+    Ok(fetch_with(&Pipeline::new(timeout, None)?, pages).await.0)
+}
+
+#[cfg(feature = "details")]
+/// Retrieve the details of every page as a stage of the pipeline.
+///
+/// Returns the details by mirror URL and the number of pages not retrieved
+/// before the deadline of the pipeline.
+pub async fn fetch_with(
+    pipeline: &Pipeline,
+    pages: Vec<String>,
+) -> (HashMap<String, Details>, usize) {
+    let output = pipeline
+        .stage("details", pages, CONCURRENT_FETCHES, fetch_page)
+        .await;
+    let details = output
+        .results
+        .into_iter()
+        .flatten()
+        .collect::<HashMap<_, _>>();
+    info!(
+        "details retrieved for {} urls, {} pages failed",
+        details.len(),
+        output.failures
+    );
+    if output.cancelled > 0 {
+        warn!(
+            "deadline reached, {} details pages not retrieved",
+            output.cancelled
+        );
+    }
+    (details, output.cancelled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "details")]
+    static PAGE: &str = r#"{
+        "name": "ntua.gr",
+        "tier": 1,
+        "urls": [
+            {
+                "url": "http://ftp.ntua.gr/pub/linux/archlinux/",
+                "protocol": "http",
+                "completion_pct": 0.75,
+                "duration_avg": 0.5,
+                "logs": [
+                    {"check_time": "2024-05-01T15:00:00Z", "last_sync": "2024-05-01T14:25:08Z", "duration": 0.2, "is_success": true},
+                    {"check_time": "2024-05-01T14:00:00Z", "last_sync": null, "duration": null, "is_success": false},
+                    {"check_time": "2024-05-01T13:00:00Z", "last_sync": "2024-05-01T12:25:08Z", "duration": 0.4, "is_success": true}
+                ]
+            },
+            {
+                "url": "rsync://ftp.ntua.gr/archlinux/",
+                "protocol": "rsync",
+                "completion_pct": 1.0,
+                "duration_avg": 0.7
+            }
+        ]
+    }"#;
+
+    #[cfg(feature = "details")]
+    #[test]
+    fn parse_page() {
+        let page: DetailsPage = serde_json::from_str(PAGE).unwrap();
+        let details = page.into_details();
+        assert_eq!(details.len(), 2);
+        let http = &details["http://ftp.ntua.gr/pub/linux/archlinux/"];
+        assert_eq!(http.tier, Some(1));
+        assert_eq!(http.logs.len(), 3);
+        assert!((http.average_duration().unwrap() - 0.3).abs() < 1e-9);
+        assert!((http.success_rate().unwrap() - 2.0 / 3.0).abs() < 1e-9);
+        let rsync = &details["rsync://ftp.ntua.gr/archlinux/"];
+        assert_eq!(rsync.average_duration(), Some(0.7));
+        assert_eq!(rsync.success_rate(), Some(1.0));
+    }
+
+    #[cfg(feature = "details")]
+    #[test]
+    fn untiered_page() {
+        let page: DetailsPage =
+            serde_json::from_str(r#"{"tier": -1, "urls": [{"url": "https://m/"}]}"#).unwrap();
+        assert_eq!(page.into_details()["https://m/"].tier, None);
+    }
+
+    #[test]
+    fn url() {
+        assert_eq!(
+            json_url("https://archlinux.org/mirrors/ntua.gr/333/"),
+            "https://archlinux.org/mirrors/ntua.gr/333/json/"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Comparison of two snapshots of the mirror status.
+use crate::{Mirror, MirrorList};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Difference between two snapshots for a mirror
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Added {
+        url: String,
+    },
+    Removed {
+        url: String,
+    },
+    Score {
+        url: String,
+        old: Option<f64>,
+        new: Option<f64>,
+    },
+    LastSync {
+        url: String,
+        old: Option<DateTime<Utc>>,
+        new: Option<DateTime<Utc>>,
+    },
+    Protocol {
+        url: String,
+        old: String,
+        new: String,
+    },
+}
+
+fn opt<T: fmt::Display>(v: &Option<T>) -> String {
+    v.as_ref().map_or_else(|| "none".into(), |v| v.to_string())
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added { url } => write!(f, "+ {url}"),
+            Change::Removed { url } => write!(f, "- {url}"),
+            Change::Score { url, old, new } => {
+                write!(f, "~ {url} score {} -> {}", opt(old), opt(new))
+            }
+            Change::LastSync { url, old, new } => {
+                write!(f, "~ {url} last sync {} -> {}", opt(old), opt(new))
+            }
+            Change::Protocol { url, old, new } => write!(f, "~ {url} protocol {old} -> {new}"),
+        }
+    }
+}
+
+/// mirrors by URL, ignoring a trailing '/'
+fn by_url(mlist: &MirrorList) -> BTreeMap<&str, &Mirror> {
+    mlist
+        .mirrors
+        .iter()
+        .map(|m| (m.url.trim_end_matches('/'), m))
+        .collect()
+}
+
+/// Changes from `old` to `new`, by URL
+pub fn diff(old: &MirrorList, new: &MirrorList) -> Vec<Change> {
+    let old = by_url(old);
+    let new = by_url(new);
+    let mut changes = Vec::new();
+    for (url, m) in &old {
+        let Some(n) = new.get(url) else {
+            changes.push(Change::Removed { url: m.url.clone() });
+            continue;
+        };
+        if m.score != n.score {
+            changes.push(Change::Score {
+                url: n.url.clone(),
+                old: m.score,
+                new: n.score,
+            });
+        }
+        if m.last_sync != n.last_sync {
+            changes.push(Change::LastSync {
+                url: n.url.clone(),
+                old: m.last_sync,
+                new: n.last_sync,
+            });
+        }
+        // the status may change the protocol of a URL whose scheme is kept
+        let (old_protocol, new_protocol) = (m.advertised_protocol(), n.advertised_protocol());
+        if old_protocol != new_protocol {
+            changes.push(Change::Protocol {
+                url: n.url.clone(),
+                old: old_protocol.to_string(),
+                new: new_protocol.to_string(),
+            });
+        }
+    }
+    for (url, n) in &new {
+        if !old.contains_key(url) {
+            changes.push(Change::Added { url: n.url.clone() });
+        }
+    }
+    changes
+}
+
+/// Changes of ranking between two selections, given by URL in rank order
+pub fn ranking(previous: &[String], current: &[String]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (rank, url) in current.iter().enumerate() {
+        match previous.iter().position(|u| u == url) {
+            None => lines.push(format!("+ {url} at rank {}", rank + 1)),
+            Some(old) if old != rank => {
+                lines.push(format!("~ {url} rank {} -> {}", old + 1, rank + 1))
+            }
+            Some(_) => {}
+        }
+    }
+    for url in previous.iter().filter(|u| !current.contains(u)) {
+        lines.push(format!("- {url}"));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static OLD: &str = r#"{"urls": [
+        {"url": "https://a.example.org/", "protocol": "https", "score": 1.5,
+         "last_sync": "2024-05-01T14:25:08Z", "details": ""},
+        {"url": "http://b.example.org/", "protocol": "http", "score": 2.0,
+         "last_sync": null, "details": ""},
+        {"url": "https://c.example.org/", "protocol": "https", "score": 3.0,
+         "last_sync": null, "details": ""}
+    ]}"#;
+    static NEW: &str = r#"{"urls": [
+        {"url": "https://a.example.org", "protocol": "https", "score": 1.5,
+         "last_sync": "2024-05-01T15:25:08Z", "details": ""},
+        {"url": "http://b.example.org/", "protocol": "rsync", "score": null,
+         "last_sync": null, "details": ""},
+        {"url": "https://d.example.org/", "protocol": "https", "score": 3.0,
+         "last_sync": null, "details": ""}
+    ]}"#;
+
+    #[test]
+    fn changes() {
+        let old: MirrorList = serde_json::from_str(OLD).unwrap();
+        let new: MirrorList = serde_json::from_str(NEW).unwrap();
+        let lines = diff(&old, &new)
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                "~ http://b.example.org/ score 2 -> none",
+                "~ http://b.example.org/ protocol http -> rsync",
+                "~ https://a.example.org/ last sync 2024-05-01 14:25:08 UTC -> 2024-05-01 15:25:08 UTC",
+                "- https://c.example.org/",
+                "+ https://d.example.org/",
+            ]
+        );
+        assert!(diff(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn ranking_changes() {
+        let urls = |s: &[&str]| s.iter().map(|u| u.to_string()).collect::<Vec<_>>();
+        let previous = urls(&["a", "b", "c"]);
+        let current = urls(&["b", "a", "d"]);
+        assert_eq!(
+            ranking(&previous, &current),
+            vec!["~ b rank 2 -> 1", "~ a rank 1 -> 2", "+ d at rank 3", "- c"]
+        );
+        assert!(ranking(&current, &current).is_empty());
+    }
+}
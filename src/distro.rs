@@ -0,0 +1,329 @@
+//! Arch Linux and Arch-derived distributions.
+//!
+//! Each distribution publishes its mirrors in its own format. The adapters in
+//! this module convert them into a [`MirrorList`].
+use crate::error::snippet;
+use crate::{Mirror, MirrorList, Protocol};
+use anyhow::{bail, Result};
+use chrono::{NaiveDate, TimeDelta, Utc};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Distro {
+    /// Arch Linux
+    #[default]
+    Arch,
+    /// Manjaro
+    Manjaro,
+    /// EndeavourOS
+    Endeavouros,
+    /// Artix Linux
+    Artix,
+    /// Arch Linux ARM
+    ArchArm,
+}
+
+/// Files of the latest installation image. See [`Distro::iso_paths`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IsoPaths {
+    pub image: &'static str,
+    pub checksums: &'static str,
+    pub signature: &'static str,
+}
+
+impl fmt::Display for Distro {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Distro::Arch => write!(f, "arch"),
+            Distro::Manjaro => write!(f, "manjaro"),
+            Distro::Endeavouros => write!(f, "endeavouros"),
+            Distro::Artix => write!(f, "artix"),
+            Distro::ArchArm => write!(f, "arch-arm"),
+        }
+    }
+}
+
+impl Distro {
+    /// Human readable name
+    pub fn name(&self) -> &'static str {
+        match self {
+            Distro::Arch => "Arch Linux",
+            Distro::Manjaro => "Manjaro",
+            Distro::Endeavouros => "EndeavourOS",
+            Distro::Artix => "Artix Linux",
+            Distro::ArchArm => "Arch Linux ARM",
+        }
+    }
+
+    /// URL from which the list of mirrors is retrieved
+    pub fn status_url(&self) -> &'static str {
+        match self {
+            Distro::Arch => crate::MIRROR_STATUS_URL,
+            Distro::Manjaro => "https://repo.manjaro.org/status.json",
+            Distro::Endeavouros => "https://raw.githubusercontent.com/endeavouros-team/PKGBUILDS/master/endeavouros-mirrorlist/endeavouros-mirrorlist",
+            Distro::Artix => "https://gitea.artixlinux.org/packages/artix-mirrorlist/raw/branch/master/mirrorlist",
+            Distro::ArchArm => "https://raw.githubusercontent.com/archlinuxarm/PKGBUILDs/master/core/pacman-mirrorlist/mirrorlist",
+        }
+    }
+
+    /// Path appended to the mirror URL in `Server = ` lines
+    pub fn server_path(&self) -> &'static str {
+        match self {
+            Distro::Arch | Distro::Artix => "$repo/os/$arch",
+            Distro::Manjaro => "$branch/$repo/$arch",
+            Distro::Endeavouros => "$repo/$arch",
+            Distro::ArchArm => "$arch/$repo",
+        }
+    }
+
+    /// Path, relative to the mirror URL, of the file downloaded to measure the download rate
+    pub fn test_path(&self) -> &'static str {
+        match self {
+            Distro::Arch => "extra/os/x86_64/extra.db",
+            Distro::Manjaro => "stable/extra/x86_64/extra.db",
+            Distro::Endeavouros => "endeavouros/x86_64/endeavouros.db",
+            Distro::Artix => "system/os/x86_64/system.db",
+            Distro::ArchArm => "aarch64/core/core.db",
+        }
+    }
+
+    /// Paths, relative to the mirror URL, of small files fetched one after the
+    /// other to measure the time of a metadata refresh, as done by pacman
+    pub fn metadata_paths(&self) -> &'static [&'static str] {
+        match self {
+            Distro::Arch => &["lastupdate", "lastsync", "core/os/x86_64/core.db"],
+            Distro::Manjaro => &["state", "stable/core/x86_64/core.db"],
+            Distro::Endeavouros => &["state", "endeavouros/x86_64/endeavouros.db"],
+            Distro::Artix => &["lastupdate", "system/os/x86_64/system.db"],
+            Distro::ArchArm => &["aarch64/core/core.db"],
+        }
+    }
+
+    /// Paths, relative to the mirror URL, of the latest installation image
+    /// and of its checksums and signature, if known
+    pub fn iso_paths(&self) -> Option<IsoPaths> {
+        match self {
+            Distro::Arch => Some(IsoPaths {
+                image: "iso/latest/archlinux-x86_64.iso",
+                checksums: "iso/latest/sha256sums.txt",
+                signature: "iso/latest/archlinux-x86_64.iso.sig",
+            }),
+            Distro::Manjaro | Distro::Endeavouros | Distro::Artix | Distro::ArchArm => None,
+        }
+    }
+
+    /// URL of the snapshot of the repositories on `date` in the archive of the
+    /// distribution, if it has one
+    pub fn archive_url(&self, date: NaiveDate) -> Option<String> {
+        match self {
+            Distro::Arch => Some(format!(
+                "https://archive.archlinux.org/repos/{}/",
+                date.format("%Y/%m/%d")
+            )),
+            Distro::Manjaro | Distro::Endeavouros | Distro::Artix | Distro::ArchArm => None,
+        }
+    }
+
+    /// Build a mirror list from the content retrieved from the status URL
+    pub fn parse(&self, body: &str) -> Result<MirrorList> {
+        let mut mlist = match self {
+            Distro::Arch => match MirrorList::from_status_str(body) {
+                Ok(x) => x,
+                Err(e) => bail!("malformed JSON ({e}): \"{}\"", snippet(body)),
+            },
+            Distro::Manjaro => parse_manjaro(body)?,
+            Distro::Endeavouros | Distro::Artix | Distro::ArchArm => {
+                parse_mirrorlist(body, self.server_path())
+            }
+        };
+        mlist.distro = *self;
+        Ok(mlist)
+    }
+}
+
+/// Mirror as described in <https://repo.manjaro.org/status.json>
+#[derive(Debug, Deserialize)]
+struct ManjaroMirror {
+    url: String,
+    #[serde(default)]
+    protocols: Vec<String>,
+    country: Option<String>,
+    /// time since last synchronisation as "HH:MM", or -1
+    last_sync: serde_json::Value,
+}
+
+impl From<ManjaroMirror> for Mirror {
+    fn from(m: ManjaroMirror) -> Self {
+        let last_sync = m
+            .last_sync
+            .as_str()
+            .and_then(|s| s.split_once(':'))
+            .and_then(|(h, m)| Some((h.parse::<i64>().ok()?, m.parse::<i64>().ok()?)))
+            .map(|(h, m)| Utc::now() - TimeDelta::hours(h) - TimeDelta::minutes(m));
+        let protocol = if m.protocols.iter().any(|p| p == "https") {
+            Protocol::Https
+        } else {
+            Protocol::from_url(&m.url)
+        };
+        Mirror {
+            protocol,
+            country: m.country.map(|c| c.replace('_', " ")),
+            last_sync,
+            ..Mirror::from_url(&m.url)
+        }
+    }
+}
+
+fn parse_manjaro(body: &str) -> Result<MirrorList> {
+    let mirrors: Vec<ManjaroMirror> = match serde_json::from_str(body) {
+        Ok(x) => x,
+        Err(e) => bail!("malformed Manjaro status ({e}): \"{}\"", snippet(body)),
+    };
+    Ok(MirrorList {
+        mirrors: mirrors.into_iter().map(Mirror::from).collect(),
+        ..Default::default()
+    })
+}
+
+/// Build a mirror list from the content of a mirrorlist file.
+///
+/// Commented out servers are also kept. Countries are taken from the comment
+/// lines preceding servers (`## Germany`). Lines containing only a URL are
+/// considered as servers too, so that a plain list of URLs can be used.
+pub(crate) fn parse_mirrorlist(content: &str, server_path: &str) -> MirrorList {
+    let mut mirrors = Vec::new();
+    let mut country = None;
+    for line in content.lines() {
+        let line = line.trim();
+        let uncommented = line.trim_start_matches('#').trim();
+        if let Some(url) = uncommented
+            .strip_prefix("Server")
+            .and_then(|l| l.trim_start().strip_prefix('='))
+        {
+            let url = url.trim();
+            let url = url.strip_suffix(server_path).unwrap_or(url);
+            if url.is_empty() {
+                continue;
+            }
+            mirrors.push(Mirror {
+                country: country.clone(),
+                ..Mirror::from_url(url)
+            });
+        } else if line.contains("://") && !line.starts_with('#') && !line.contains(' ') {
+            let url = line.strip_suffix(server_path).unwrap_or(line);
+            let url = if url.ends_with('/') {
+                url.to_string()
+            } else {
+                format!("{url}/")
+            };
+            mirrors.push(Mirror {
+                country: country.clone(),
+                ..Mirror::from_url(&url)
+            });
+        } else if is_country_header(line, uncommented) {
+            country = Some(uncommented.to_string());
+        }
+    }
+    crate::normalize_urls(&mut mirrors);
+    MirrorList {
+        mirrors,
+        ..Default::default()
+    }
+}
+
+/// `## United States` is a country header, `## Generated on 2024-01-01` is not
+fn is_country_header(line: &str, uncommented: &str) -> bool {
+    line.starts_with("##")
+        && !uncommented.is_empty()
+        && uncommented.len() < 40
+        && uncommented
+            .chars()
+            .all(|c| c.is_alphabetic() || c == ' ' || c == '-' || c == '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Number, RenderOptions};
+
+    static MANJARO: &str = r#"[
+        {
+            "branches": [1, 1, 1],
+            "country": "United_States",
+            "last_sync": "01:30",
+            "protocols": ["http", "https"],
+            "url": "https://mirror.example.com/manjaro/"
+        },
+        {
+            "branches": [0, 0, 0],
+            "country": "Germany",
+            "last_sync": -1,
+            "protocols": ["http"],
+            "url": "http://manjaro.example.de/"
+        }
+    ]"#;
+
+    static MIRRORLIST: &str = "##\n## Arch Linux ARM repository mirrorlist\n\
+        ## Generated on 2024-01-01\n##\n\n\
+        ### Germany\n\
+        Server = http://de.mirror.archlinuxarm.org/$arch/$repo\n\n\
+        ## United States\n\
+        # Server = http://fl.us.mirror.archlinuxarm.org/$arch/$repo\n";
+
+    #[test]
+    fn url_list() {
+        let ml = parse_mirrorlist(
+            "https://a.org/arch\n\n# comment\nhttp://b.org/arch/$repo/os/$arch\n",
+            Distro::Arch.server_path(),
+        );
+        let urls = ml
+            .mirrors
+            .iter()
+            .map(|m| m.url.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(urls, vec!["https://a.org/arch/", "http://b.org/arch/"]);
+        assert!(ml.mirrors.iter().all(|m| m.last_sync.is_none()));
+    }
+
+    #[test]
+    fn manjaro() {
+        let ml = Distro::Manjaro.parse(MANJARO).unwrap();
+        assert_eq!(ml.distro, Distro::Manjaro);
+        assert_eq!(ml.mirrors.len(), 2);
+        let m = &ml.mirrors[0];
+        assert_eq!(m.country.as_deref(), Some("United States"));
+        assert!(matches!(m.protocol, Protocol::Https));
+        let age = m.age(Utc::now()).unwrap();
+        assert!(age >= TimeDelta::minutes(90) && age < TimeDelta::minutes(91));
+        assert!(ml.mirrors[1].last_sync.is_none());
+        assert!(matches!(ml.mirrors[1].protocol, Protocol::Http));
+    }
+
+    #[test]
+    fn snapshot() {
+        let date = NaiveDate::from_ymd_opt(2025, 3, 1).unwrap();
+        let ml = MirrorList::from_snapshot(Distro::Arch, date).unwrap();
+        assert_eq!(
+            ml.to_file_content(&RenderOptions::new(Number::All))
+                .lines()
+                .last(),
+            Some("Server = https://archive.archlinux.org/repos/2025/03/01/$repo/os/$arch")
+        );
+        assert!(MirrorList::from_snapshot(Distro::Manjaro, date).is_err());
+    }
+
+    #[test]
+    fn mirrorlist() {
+        let ml = Distro::ArchArm.parse(MIRRORLIST).unwrap();
+        assert_eq!(ml.mirrors.len(), 2);
+        assert_eq!(ml.mirrors[0].url, "http://de.mirror.archlinuxarm.org/");
+        assert_eq!(ml.mirrors[0].country.as_deref(), Some("Germany"));
+        assert_eq!(ml.mirrors[1].country.as_deref(), Some("United States"));
+        assert!(ml
+            .to_file_content(&RenderOptions::new(Number::Limit(1)))
+            .ends_with("Server = http://de.mirror.archlinuxarm.org/$arch/$repo"));
+    }
+}
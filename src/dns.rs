@@ -0,0 +1,176 @@
+//! Resolution of the host names of mirrors, for firewalls allowing
+//! connections by address.
+//!
+//! Addresses are compared with the ones recorded by the previous run: a host
+//! whose addresses change is likely behind round-robin DNS or a CDN, and hard
+//! to allowlist.
+//!
+//! The reachability of a host over IPv4 and IPv6 is measured by connecting to
+//! its addresses of each family, as the flags of the mirror status only tell
+//! what the mirror advertises.
+//!
+//! A [`CountingResolver`] counts the connections opened by a client, e.g. to
+//! check whether a mirror keeps the connection open between two requests.
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Addresses, by host name
+pub type Addresses = BTreeMap<String, Vec<IpAddr>>;
+
+/// A and AAAA records of a host, sorted
+pub async fn resolve(host: &str) -> Result<Vec<IpAddr>> {
+    let mut addresses = tokio::net::lookup_host((host, 0))
+        .await
+        .with_context(|| format!("unable to resolve {host}"))?
+        .map(|a| a.ip())
+        .collect::<Vec<_>>();
+    addresses.sort();
+    addresses.dedup();
+    Ok(addresses)
+}
+
+/// Whether the sorted addresses of a host differ from the previous ones, if
+/// they were recorded
+pub fn changed(previous: Option<&Vec<IpAddr>>, current: &[IpAddr]) -> bool {
+    previous.is_some_and(|p| p.as_slice() != current)
+}
+
+/// Whether a connection succeeded over each IP version
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reachability {
+    pub ipv4: bool,
+    pub ipv6: bool,
+}
+
+/// Connect to the addresses of a host, both IP versions at once, each
+/// version trying its addresses in turn until one accepts the connection. A
+/// host that cannot be resolved is unreachable
+pub async fn reachability(
+    host: &str,
+    port: u16,
+    timeout: Option<std::time::Duration>,
+) -> Reachability {
+    let addresses = resolve(host).await.unwrap_or_default();
+    let connects = |v4: bool| {
+        let addresses = addresses
+            .iter()
+            .filter(|a| a.is_ipv4() == v4)
+            .map(|a| SocketAddr::new(*a, port))
+            .collect::<Vec<_>>();
+        async move {
+            for address in addresses {
+                let connect = tokio::net::TcpStream::connect(address);
+                let connected = match timeout {
+                    Some(t) => matches!(tokio::time::timeout(t, connect).await, Ok(Ok(_))),
+                    None => connect.await.is_ok(),
+                };
+                if connected {
+                    return true;
+                }
+            }
+            false
+        }
+    };
+    let (ipv4, ipv6) = tokio::join!(connects(true), connects(false));
+    Reachability { ipv4, ipv6 }
+}
+
+/// Resolver of a client counting its lookups, one for each new connection.
+/// Hosts given by address are not looked up
+#[derive(Debug, Default)]
+pub struct CountingResolver {
+    lookups: AtomicUsize,
+}
+
+impl CountingResolver {
+    /// Number of host names looked up so far
+    pub fn lookups(&self) -> usize {
+        self.lookups.load(Ordering::Relaxed)
+    }
+}
+
+impl reqwest::dns::Resolve for CountingResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addresses = tokio::net::lookup_host((host, 0)).await?;
+            Ok(Box::new(addresses) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn localhost() {
+        let addresses = resolve("127.0.0.1").await.unwrap();
+        assert_eq!(addresses, [IpAddr::from([127, 0, 0, 1])]);
+        assert!(resolve("invalid..host").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn reachable() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let reached = reachability("127.0.0.1", port, None).await;
+        assert_eq!(
+            reached,
+            Reachability {
+                ipv4: true,
+                ipv6: false
+            }
+        );
+        assert_eq!(
+            reachability("invalid..host", port, None).await,
+            Reachability::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn counted_connections() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        // server answering every request of a connection
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            while stream.read(&mut buf).await.unwrap_or(0) > 0 {
+                let response = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        let resolver = std::sync::Arc::new(CountingResolver::default());
+        let client = reqwest::Client::builder()
+            .dns_resolver(resolver.clone())
+            .build()
+            .unwrap();
+        let url = format!("http://localhost:{port}/");
+        for _ in 0..2 {
+            client
+                .get(&url)
+                .send()
+                .await
+                .unwrap()
+                .bytes()
+                .await
+                .unwrap();
+        }
+        assert_eq!(resolver.lookups(), 1);
+    }
+
+    #[test]
+    fn changes() {
+        let one = vec![IpAddr::from([192, 0, 2, 1])];
+        let two = vec![IpAddr::from([192, 0, 2, 1]), IpAddr::from([192, 0, 2, 2])];
+        assert!(!changed(None, &one));
+        assert!(!changed(Some(&one), &one));
+        assert!(changed(Some(&one), &two));
+    }
+}
@@ -0,0 +1,214 @@
+//! Errors specific to reflecto.
+//!
+//! They are returned wrapped in [`anyhow::Error`] and can be recovered with
+//! `downcast_ref`.
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// No mirror is left to be written
+    EmptySelection,
+    /// The mirror status could not be retrieved: error status or HTML page
+    UnexpectedResponse {
+        url: String,
+        status: u16,
+        content_type: Option<String>,
+        /// beginning of the response body
+        snippet: String,
+    },
+    /// Another run holds the lock
+    Locked { path: PathBuf },
+    /// The mirror status could not be retrieved, whatever the reason
+    FetchFailed,
+    /// A mirrorlist could not be written
+    WriteFailed { path: PathBuf },
+    /// Some selected mirrors could not be measured
+    MeasurementFailed { count: usize },
+    /// The mirrorlist was not refreshed recently enough, or never
+    Stale { last_refresh: Option<DateTime<Utc>> },
+    /// The connectivity check got another answer than the expected one,
+    /// likely from a captive portal
+    CaptivePortal {
+        url: String,
+        /// what was received instead
+        answer: String,
+    },
+}
+
+impl Error {
+    /// Build an [`Error::UnexpectedResponse`], keeping only the beginning of the body
+    pub fn unexpected_response(
+        url: &str,
+        status: u16,
+        content_type: Option<String>,
+        body: &str,
+    ) -> Self {
+        Error::UnexpectedResponse {
+            url: url.into(),
+            status,
+            content_type,
+            snippet: snippet(body),
+        }
+    }
+}
+
+/// maximum number of characters of a body quoted in an error
+const SNIPPET_LEN: usize = 160;
+
+/// beginning of a text, on a single line
+pub fn snippet(body: &str) -> String {
+    let text = body.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() > SNIPPET_LEN {
+        format!("{}…", text.chars().take(SNIPPET_LEN).collect::<String>())
+    } else {
+        text
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::EmptySelection => write!(
+                f,
+                "no mirror matches the given criteria, the mirrorlist would be empty"
+            ),
+            Error::FetchFailed => write!(
+                f,
+                "unable to retrieve mirror status, use --from-mirrorlist to rank the \
+                 mirrors of a mirrorlist file offline"
+            ),
+            Error::WriteFailed { path } => write!(f, "unable to write {}", path.display()),
+            Error::MeasurementFailed { count } => {
+                write!(f, "measurement failed for {count} selected mirrors")
+            }
+            Error::Stale { last_refresh: None } => write!(f, "the mirrorlist was never refreshed"),
+            Error::Stale {
+                last_refresh: Some(d),
+            } => write!(
+                f,
+                "the mirrorlist was last refreshed on {}",
+                d.format("%Y-%m-%d %H:%M:%S UTC")
+            ),
+            Error::CaptivePortal { url, answer } => write!(
+                f,
+                "requests are intercepted: {url} answered {answer}. A captive portal \
+                 may require to log in before the mirrors can be measured"
+            ),
+            Error::Locked { path } => write!(
+                f,
+                "another run holds the lock {}, use --wait-lock to wait for it",
+                path.display()
+            ),
+            Error::UnexpectedResponse {
+                url,
+                status,
+                content_type,
+                snippet,
+            } => {
+                write!(
+                    f,
+                    "unexpected response from {url}: HTTP status {status}, content type {}: \"{snippet}\". ",
+                    content_type.as_deref().unwrap_or("unknown"),
+                )?;
+                if *status == 429 || *status == 503 {
+                    write!(f, "The server is rate limiting requests, retry later")?;
+                } else {
+                    write!(
+                        f,
+                        "A proxy or a captive portal may intercept requests, \
+                         check the network connection and retry"
+                    )?;
+                }
+                write!(
+                    f,
+                    " or rank the mirrors of a mirrorlist file offline with --from-mirrorlist."
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Exit status of the command line tool, for scripting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Success = 0,
+    /// any error not listed below
+    Failure = 1,
+    /// no mirror is left after filtering
+    NoMirrors = 2,
+    /// the mirror status could not be retrieved, or a captive portal
+    /// intercepts requests
+    FetchFailure = 3,
+    /// a file could not be written
+    WriteFailure = 4,
+    /// a measurement failed, with `--strict`
+    PartialMeasurement = 5,
+    /// the mirrorlist is older than the threshold of `status`
+    Stale = 6,
+}
+
+impl ExitStatus {
+    /// Exit status corresponding to an error
+    pub fn of(e: &anyhow::Error) -> Self {
+        match e.downcast_ref::<Error>() {
+            Some(Error::EmptySelection) => ExitStatus::NoMirrors,
+            Some(
+                Error::FetchFailed | Error::UnexpectedResponse { .. } | Error::CaptivePortal { .. },
+            ) => ExitStatus::FetchFailure,
+            Some(Error::WriteFailed { .. }) => ExitStatus::WriteFailure,
+            Some(Error::MeasurementFailed { .. }) => ExitStatus::PartialMeasurement,
+            Some(Error::Stale { .. }) => ExitStatus::Stale,
+            Some(Error::Locked { .. }) => ExitStatus::Failure,
+            None if e.downcast_ref::<reqwest::Error>().is_some() => ExitStatus::FetchFailure,
+            None => ExitStatus::Failure,
+        }
+    }
+}
+
+impl From<ExitStatus> for ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        ExitCode::from(status as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncated_snippet() {
+        let body = format!("<html>\n  <body>{}</body></html>", "x".repeat(500));
+        let s = snippet(&body);
+        assert!(s.starts_with("<html> <body>xxx"));
+        assert_eq!(s.chars().count(), SNIPPET_LEN + 1);
+        assert_eq!(snippet("short"), "short");
+    }
+
+    #[test]
+    fn exit_status() {
+        let e = anyhow::Error::new(Error::EmptySelection);
+        assert_eq!(ExitStatus::of(&e), ExitStatus::NoMirrors);
+        let e = anyhow::anyhow!("malformed JSON").context(Error::FetchFailed);
+        assert_eq!(ExitStatus::of(&e), ExitStatus::FetchFailure);
+        let io = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        let e = anyhow::Error::new(io).context(Error::WriteFailed {
+            path: "/etc/pacman.d/mirrorlist".into(),
+        });
+        assert_eq!(ExitStatus::of(&e), ExitStatus::WriteFailure);
+        assert_eq!(
+            format!("{e:#}"),
+            "unable to write /etc/pacman.d/mirrorlist: permission denied"
+        );
+        assert_eq!(
+            ExitStatus::of(&anyhow::anyhow!("other")),
+            ExitStatus::Failure
+        );
+        let e = anyhow::Error::new(Error::Stale { last_refresh: None });
+        assert_eq!(ExitStatus::of(&e), ExitStatus::Stale);
+    }
+}
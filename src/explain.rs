@@ -0,0 +1,150 @@
+//! Log of the selection decisions, telling why each mirror was kept or not.
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// What happened to a mirror
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "decision", rename_all = "snake_case")]
+pub enum Outcome {
+    /// written at this rank, starting at 1
+    Selected { rank: usize },
+    /// removed by a criterion, e.g. "age" or "blacklist"
+    Rejected { reason: String },
+    /// passed every criterion but was not among the kept mirrors
+    Truncated,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Decision {
+    pub url: String,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+    /// inconsistencies found in the status, e.g. a protocol not matching the
+    /// scheme of the URL
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+}
+
+/// Format of the decision log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+/// Decision log
+#[derive(Debug, Default, Clone)]
+pub struct Explanation {
+    /// every mirror of the input, in order
+    urls: Vec<String>,
+    /// reason of the rejection of mirrors, by URL
+    rejected: HashMap<String, String>,
+    /// notes about mirrors, by URL
+    notes: HashMap<String, Vec<String>>,
+}
+
+impl Explanation {
+    pub fn new<'a>(urls: impl IntoIterator<Item = &'a str>) -> Self {
+        Self {
+            urls: urls.into_iter().map(String::from).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Record the rejection of a mirror, keeping the first reason
+    pub fn reject(&mut self, url: &str, reason: &str) {
+        self.rejected
+            .entry(url.into())
+            .or_insert_with(|| reason.into());
+    }
+
+    /// Record a note about a mirror, whatever the decision
+    pub fn note(&mut self, url: &str, note: String) {
+        self.notes.entry(url.into()).or_default().push(note);
+    }
+
+    /// Decision about every mirror, the mirrors in `selected` being written
+    pub fn decisions<'a>(&self, selected: impl IntoIterator<Item = &'a str>) -> Vec<Decision> {
+        let ranks = selected
+            .into_iter()
+            .enumerate()
+            .map(|(i, url)| (url, i + 1))
+            .collect::<HashMap<_, _>>();
+        self.urls
+            .iter()
+            .map(|url| {
+                let outcome = match (ranks.get(url.as_str()), self.rejected.get(url)) {
+                    (Some(rank), _) => Outcome::Selected { rank: *rank },
+                    (None, Some(reason)) => Outcome::Rejected {
+                        reason: reason.clone(),
+                    },
+                    (None, None) => Outcome::Truncated,
+                };
+                Decision {
+                    url: url.clone(),
+                    outcome,
+                    notes: self.notes.get(url).cloned().unwrap_or_default(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Render decisions, one per line for text
+pub fn render(decisions: &[Decision], format: Format) -> String {
+    match format {
+        Format::Json => serde_json::to_string_pretty(decisions).unwrap_or_default(),
+        Format::Text => decisions
+            .iter()
+            .map(|d| {
+                let line = match &d.outcome {
+                    Outcome::Selected { rank } => format!("{rank:>4} {}", d.url),
+                    Outcome::Rejected { reason } => format!("   - {} rejected: {reason}", d.url),
+                    Outcome::Truncated => format!("   - {} not kept", d.url),
+                };
+                if d.notes.is_empty() {
+                    line
+                } else {
+                    format!("{line} ({})", d.notes.join("; "))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decisions() {
+        let mut explanation = Explanation::new(["a", "b", "c", "d"]);
+        explanation.reject("b", "age");
+        explanation.reject("b", "country");
+        explanation.reject("d", "blacklist");
+        explanation.note("a", "protocol https in the status".into());
+        let decisions = explanation.decisions(["c", "d"]);
+        assert_eq!(
+            decisions.iter().map(|d| &d.outcome).collect::<Vec<_>>(),
+            vec![
+                &Outcome::Truncated,
+                &Outcome::Rejected {
+                    reason: "age".into()
+                },
+                &Outcome::Selected { rank: 1 },
+                &Outcome::Selected { rank: 2 },
+            ]
+        );
+        assert_eq!(
+            render(&decisions[..2], Format::Text),
+            "   - a not kept (protocol https in the status)\n   - b rejected: age"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&decisions[1..3], Format::Json)).unwrap();
+        assert_eq!(json[0]["decision"], "rejected");
+        assert_eq!(json[0]["reason"], "age");
+        assert!(json[0].get("notes").is_none());
+        assert_eq!(json[1]["rank"], 1);
+    }
+}
@@ -0,0 +1,334 @@
+//! Retrieval of mirror status documents.
+use crate::{Error, Session};
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// How failed retrievals are retried
+#[derive(Debug, Clone, PartialEq)]
+pub struct Retry {
+    /// number of retries after the first attempt
+    pub retries: u32,
+    /// delay before the first retry, doubled after each attempt
+    pub backoff: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl Retry {
+    /// delay before a retry, `attempt` starting at 0
+    fn delay(&self, attempt: u32) -> Duration {
+        self.backoff.saturating_mul(2u32.saturating_pow(attempt))
+    }
+}
+
+/// whether a failed retrieval may succeed later
+fn is_transient(e: &anyhow::Error) -> bool {
+    match e.downcast_ref::<Error>() {
+        Some(Error::UnexpectedResponse { status, .. }) => *status == 429 || *status >= 500,
+        _ => true,
+    }
+}
+
+/// get the content of the mirror status
+///
+/// The status is requested compressed (gzip or brotli) when the server supports it.
+/// Error statuses and HTML pages are reported as [`Error::UnexpectedResponse`].
+pub async fn fetch(session: &Session, url: &str) -> Result<String> {
+    read(url, session.client()?.get(url).send().await?).await
+}
+
+/// Connectivity check of NetworkManager on Arch Linux
+pub const PREFLIGHT_URL: &str = "http://ping.archlinux.org/nm-check.txt";
+/// Content of [`PREFLIGHT_URL`]
+pub const PREFLIGHT_EXPECTED: &str = "NetworkManager is online";
+
+/// Small resource of known content, fetched before measuring the mirrors to
+/// detect a captive portal, which would make every mirror look fast but
+/// broken
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Preflight {
+    /// plain HTTP URL, so that a portal can intercept it
+    pub url: String,
+    /// content of the resource, surrounding whitespace ignored
+    pub expected: String,
+}
+
+impl Default for Preflight {
+    fn default() -> Self {
+        Self {
+            url: PREFLIGHT_URL.into(),
+            expected: PREFLIGHT_EXPECTED.into(),
+        }
+    }
+}
+
+impl Preflight {
+    /// Fetch the resource, without following redirections. Fails with
+    /// [`Error::CaptivePortal`] when the answer is not the resource. A failed
+    /// connection is only warned about, the resource may be blocked while the
+    /// mirrors are not
+    pub async fn check(&self, timeout: Option<Duration>, session: &Session) -> Result<()> {
+        let mut builder = session
+            .client_builder()
+            .redirect(reqwest::redirect::Policy::none());
+        if let Some(t) = timeout {
+            builder = builder.timeout(t);
+        }
+        let response = match builder.build()?.get(&self.url).send().await {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("connectivity check failed: {e}");
+                return Ok(());
+            }
+        };
+        let status = response.status();
+        let answer = if status.is_redirection() {
+            let location = response
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|l| l.to_str().ok())
+                .unwrap_or("elsewhere");
+            format!("a redirection to {location}")
+        } else if !status.is_success() {
+            format!("HTTP status {}", status.as_u16())
+        } else {
+            let body = response.text().await?;
+            if body.trim() == self.expected.trim() {
+                debug!("connectivity check passed");
+                return Ok(());
+            }
+            format!("\"{}\"", crate::error::snippet(&body))
+        };
+        Err(Error::CaptivePortal {
+            url: self.url.clone(),
+            answer,
+        }
+        .into())
+    }
+}
+
+/// Validators of the last retrieved version of a document
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Conditional {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// get the content of the mirror status if it changed since the last call,
+/// `None` if not
+pub async fn fetch_if_changed(
+    session: &Session,
+    url: &str,
+    conditional: &mut Conditional,
+) -> Result<Option<String>> {
+    let mut request = session.client()?.get(url);
+    if let Some(etag) = &conditional.etag {
+        request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+    }
+    if let Some(date) = &conditional.last_modified {
+        request = request.header(reqwest::header::IF_MODIFIED_SINCE, date);
+    }
+    let response = request.send().await?;
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        debug!("{url} not modified");
+        return Ok(None);
+    }
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok())
+            .map(String::from)
+    };
+    let validators = Conditional {
+        etag: header(reqwest::header::ETAG),
+        last_modified: header(reqwest::header::LAST_MODIFIED),
+    };
+    let body = read(url, response).await?;
+    *conditional = validators;
+    Ok(Some(body))
+}
+
+/// get the content of the first reachable mirror status among the given URLs
+/// if it changed since the last call, `None` if not, each URL keeping its own
+/// validators
+pub async fn fetch_first_if_changed(
+    session: &Session,
+    urls: &[String],
+    conditionals: &mut HashMap<String, Conditional>,
+) -> Result<Option<String>> {
+    let mut error = anyhow::anyhow!("no status URL");
+    for url in urls {
+        let conditional = conditionals.entry(url.clone()).or_default();
+        match fetch_if_changed(session, url, conditional).await {
+            Ok(body) => return Ok(body),
+            Err(e) => {
+                warn!("unable to retrieve mirrors from {url}: {e}");
+                error = e;
+            }
+        }
+    }
+    Err(error)
+}
+
+/// body of a response, checking it is a successful non-HTML one
+async fn read(url: &str, response: reqwest::Response) -> Result<String> {
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+    let body = response.text().await?;
+
+    let is_html = content_type.as_deref().is_some_and(|c| c.contains("html"));
+    if !status.is_success() || is_html {
+        return Err(Error::unexpected_response(url, status.as_u16(), content_type, &body).into());
+    }
+    Ok(body)
+}
+
+/// get the content of the mirror status, retrying on transient failures
+pub async fn fetch_retry(session: &Session, url: &str, retry: &Retry) -> Result<String> {
+    let mut attempt = 0;
+    loop {
+        match fetch(session, url).await {
+            Ok(body) => return Ok(body),
+            Err(e) if attempt < retry.retries && is_transient(&e) => {
+                let delay = retry.delay(attempt);
+                warn!("unable to retrieve {url}: {e}. Retrying in {delay:?}");
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => {
+                debug!("giving up {url} after {} attempts", attempt + 1);
+                return Err(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Response, Server};
+
+    #[test]
+    fn backoff() {
+        let retry = Retry {
+            retries: 3,
+            backoff: Duration::from_millis(100),
+        };
+        assert_eq!(retry.delay(0), Duration::from_millis(100));
+        assert_eq!(retry.delay(2), Duration::from_millis(400));
+    }
+
+    #[tokio::test]
+    async fn captive_portal() {
+        let server = Server::start(vec![
+            Response::new(200, "text/plain", "NetworkManager is online\n"),
+            Response::new(200, "text/html", "<html>Log in to the hotel Wi-Fi</html>"),
+        ])
+        .await;
+        let preflight = Preflight {
+            url: server.url.clone(),
+            ..Default::default()
+        };
+        let session = Session::default();
+        assert!(preflight.check(None, &session).await.is_ok());
+        let e = preflight.check(None, &session).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<Error>(),
+            Some(Error::CaptivePortal { answer, .. }) if answer.contains("hotel")
+        ));
+        let unreachable = Preflight {
+            url: "http://127.0.0.1:1/".into(),
+            ..Default::default()
+        };
+        assert!(unreachable.check(None, &session).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn retries() {
+        let server = Server::start(vec![
+            Response::new(503, "text/plain", "unavailable"),
+            Response::new(502, "text/plain", "bad gateway"),
+            Response::json("{}"),
+        ])
+        .await;
+        let retry = Retry {
+            retries: 1,
+            backoff: Duration::from_millis(1),
+        };
+        assert!(fetch_retry(&Session::default(), &server.url, &retry)
+            .await
+            .is_err());
+        assert_eq!(
+            fetch_retry(&Session::default(), &server.url, &retry)
+                .await
+                .unwrap(),
+            "{}"
+        );
+    }
+
+    #[tokio::test]
+    async fn conditional() {
+        let mut changed = Response::json("{}");
+        changed.headers.push(("ETag".into(), "\"v1\"".into()));
+        let server = Server::start(vec![changed, Response::new(304, "text/plain", "")]).await;
+        let mut validators = Conditional::default();
+        let body = fetch_if_changed(&Session::default(), &server.url, &mut validators)
+            .await
+            .unwrap();
+        assert_eq!(body.as_deref(), Some("{}"));
+        assert_eq!(validators.etag.as_deref(), Some("\"v1\""));
+        let body = fetch_if_changed(&Session::default(), &server.url, &mut validators)
+            .await
+            .unwrap();
+        assert_eq!(body, None);
+        assert_eq!(validators.etag.as_deref(), Some("\"v1\""));
+    }
+
+    #[tokio::test]
+    async fn conditional_fallback() {
+        let failing = Server::start(vec![Response::new(500, "text/plain", "")]).await;
+        let server = Server::start(vec![Response::json("{}")]).await;
+        let urls = vec![failing.url.clone(), server.url.clone()];
+        let mut validators = HashMap::new();
+        let body = fetch_first_if_changed(&Session::default(), &urls, &mut validators)
+            .await
+            .unwrap();
+        assert_eq!(body.as_deref(), Some("{}"));
+        assert!(
+            fetch_first_if_changed(&Session::default(), &urls[..1], &mut validators)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn no_retry_on_client_error() {
+        let server = Server::start(vec![
+            Response::new(404, "text/plain", "not found"),
+            Response::json("{}"),
+        ])
+        .await;
+        let retry = Retry {
+            retries: 5,
+            backoff: Duration::from_millis(1),
+        };
+        assert!(fetch_retry(&Session::default(), &server.url, &retry)
+            .await
+            .is_err());
+    }
+}
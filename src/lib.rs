@@ -1,20 +1,82 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use chrono::DateTime;
 use chrono::Utc;
 use clap::ValueEnum;
-use serde::Deserialize;
+use cmp::SortValue;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::borrow::Cow;
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::fs::File;
-use std::io::Write;
 use std::path::Path;
+use std::sync::atomic::{self, AtomicU64};
+use std::sync::Arc;
 use tokio::task::JoinSet;
-use tracing::{debug, info, instrument, span, Level};
+use tracing::{debug, info, instrument, span, warn, Level};
+use unicode_width::UnicodeWidthStr;
+
+pub mod aliases;
+pub mod allowlist;
+pub mod auth;
+pub mod bundle;
+pub mod clock;
+pub mod cmp;
+pub mod collation;
+pub mod config;
+pub mod country;
+pub mod details;
+pub mod diff;
+pub mod distro;
+pub mod dns;
+pub mod error;
+pub mod explain;
+pub mod fetch;
+pub mod lock;
+pub mod monitor;
+pub mod notify;
+pub mod overrides;
+pub mod pacman;
+pub mod pipeline;
+pub mod plan;
+pub mod power;
+pub mod profile;
+pub mod proxy;
+pub mod report;
+pub mod run;
+pub mod schema;
+pub mod session;
+pub mod speedtest;
+pub mod state;
+pub mod stats;
+#[cfg(test)]
+mod testing;
+pub mod trace;
+pub mod units;
+pub mod urlfile;
+
+pub use distro::Distro;
+pub use error::Error;
+pub use fetch::Retry;
+pub use pipeline::Pipeline;
+pub use run::{run, RunConfig, RunReport};
+pub use schema::SchemaMapping;
+pub use session::Session;
 
 pub static MIRROR_STATUS_URL: &str = "https://archlinux.org/mirrors/status/json";
 
-#[derive(Debug, Clone, ValueEnum)]
+/// maximum number of probes, e.g. latency measurements, done simultaneously
+const CONCURRENT_PROBES: usize = 32;
+/// maximum number of rate tests done simultaneously by the adaptive mode
+const MAX_ADAPTIVE_TESTS: usize = 16;
+/// maximum number of rate tests done simultaneously under a byte budget, so
+/// that the budget is not split among every mirror
+const MAX_BUDGETED_TESTS: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum SortKey {
     /// Last server syncrhonisation
     Age,
@@ -26,6 +88,10 @@ pub enum SortKey {
     Score,
     /// Mirror status delay
     Delay,
+    /// Average check duration. Uses mirror details when retrieved
+    Duration,
+    /// Time to fetch small metadata files. Implies --bench-profile metadata
+    Metadata,
 }
 
 impl fmt::Display for SortKey {
@@ -36,6 +102,159 @@ impl fmt::Display for SortKey {
             SortKey::Country => write!(f, "country"),
             SortKey::Score => write!(f, "score"),
             SortKey::Delay => write!(f, "delay"),
+            SortKey::Duration => write!(f, "duration"),
+            SortKey::Metadata => write!(f, "metadata"),
+        }
+    }
+}
+
+impl SortKey {
+    /// Order of two mirrors for this key, the first mirrors being the best ones.
+    ///
+    /// It is a total order: antisymmetric and transitive. Mirrors whose value is
+    /// unknown (or not a number) come last, except for [`SortKey::Age`], which
+    /// sorts by last synchronization, unknown first, and [`SortKey::Country`],
+    /// which sorts unknown countries first. Scores and delays are compared
+    /// rounded to the unit. See [`cmp`] for the order of numbers.
+    pub fn cmp(&self, m: &Mirror, n: &Mirror) -> Ordering {
+        self.value(m).cmp(&self.value(n))
+    }
+
+    /// value of a mirror for this key
+    fn value(&self, m: &Mirror) -> SortValue {
+        let rounded = |v: Option<f64>| v.map(|v| v.round());
+        match self {
+            SortKey::Age => SortValue::Date(m.last_sync.unwrap_or_default()),
+            SortKey::Rate => SortValue::number(m.download_rate.as_ref().map(|r| -r.0)),
+            SortKey::Country => SortValue::Text(m.country.clone().unwrap_or_default()),
+            SortKey::Score => SortValue::number(rounded(m.score)),
+            SortKey::Delay => SortValue::number(rounded(m.delay)),
+            SortKey::Duration => SortValue::number(m.duration()),
+            SortKey::Metadata => SortValue::number(m.metadata_time),
+        }
+    }
+
+    /// names accepted when parsing, including those of Reflector
+    fn names(&self) -> &'static [&'static str] {
+        match self {
+            SortKey::Age => &["age", "last_sync"],
+            SortKey::Rate => &["rate"],
+            SortKey::Country => &["country"],
+            SortKey::Score => &["score"],
+            SortKey::Delay => &["delay"],
+            SortKey::Duration => &["duration"],
+            SortKey::Metadata => &["metadata"],
+        }
+    }
+}
+
+impl std::str::FromStr for SortKey {
+    type Err = anyhow::Error;
+
+    /// Parse a sort key from its name, one of Reflector or an unambiguous prefix
+    fn from_str(s: &str) -> Result<Self> {
+        let input = s.trim().to_lowercase().replace('-', "_");
+        let keys = SortKey::value_variants();
+        if let Some(key) = keys.iter().find(|k| k.names().contains(&input.as_str())) {
+            return Ok(key.clone());
+        }
+        let matching = keys
+            .iter()
+            .filter(|k| !input.is_empty() && k.names().iter().any(|n| n.starts_with(&input)))
+            .collect::<Vec<_>>();
+        match matching[..] {
+            [key] => Ok(key.clone()),
+            [] => anyhow::bail!(
+                "unknown sort key '{s}', expected one of {}",
+                keys.iter()
+                    .map(|k| k.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            _ => anyhow::bail!(
+                "ambiguous sort key '{s}', may be {}",
+                matching
+                    .iter()
+                    .map(|k| k.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+/// Number of mirrors kept
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "NumberRepr", into = "NumberRepr")]
+pub enum Number {
+    /// every mirror
+    #[default]
+    All,
+    /// at most this number of mirrors, never 0
+    Limit(usize),
+}
+
+impl Number {
+    /// Number of mirrors kept among `len` mirrors
+    pub fn of(self, len: usize) -> usize {
+        match self {
+            Number::All => len,
+            Number::Limit(n) => n.min(len),
+        }
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Number::All => write!(f, "all"),
+            Number::Limit(n) => write!(f, "{n}"),
+        }
+    }
+}
+
+impl std::str::FromStr for Number {
+    type Err = anyhow::Error;
+
+    /// Parse a number of mirrors, 0 or `all` meaning every mirror
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "all" | "0" => Ok(Number::All),
+            n => match n.parse() {
+                Ok(n) => Ok(Number::Limit(n)),
+                Err(_) => {
+                    anyhow::bail!("invalid number of mirrors '{s}', expected a number or all")
+                }
+            },
+        }
+    }
+}
+
+/// Number of mirrors as written in files: an integer, or `all`
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum NumberRepr {
+    Count(usize),
+    Word(String),
+}
+
+impl TryFrom<NumberRepr> for Number {
+    type Error = anyhow::Error;
+
+    fn try_from(repr: NumberRepr) -> Result<Self> {
+        match repr {
+            NumberRepr::Count(0) => Ok(Number::All),
+            NumberRepr::Count(n) => Ok(Number::Limit(n)),
+            NumberRepr::Word(s) => s.parse(),
+        }
+    }
+}
+
+impl From<Number> for NumberRepr {
+    fn from(number: Number) -> Self {
+        match number {
+            Number::All => NumberRepr::Word("all".into()),
+            Number::Limit(n) => NumberRepr::Count(n),
         }
     }
 }
@@ -49,93 +268,748 @@ impl Bandwidth {
         if bytes_quantity == 0 {
             Self(f64::NAN)
         } else {
-            Self(bytes_quantity as f64 / (1000.0 * duration.num_milliseconds() as f64))
+            // bytes per microsecond, i.e. MB/s
+            let micros = duration
+                .num_microseconds()
+                .unwrap_or(1000 * duration.num_milliseconds());
+            Self(bytes_quantity as f64 / micros.max(1) as f64)
+        }
+    }
+}
+
+/// Bytes downloaded by the rate tests, shared between the tests, with an
+/// optional maximum, and the maximum rate of each test
+#[derive(Debug, Default, Clone)]
+pub struct ByteBudget {
+    used: Arc<AtomicU64>,
+    max: Option<u64>,
+    throttle: Option<u64>,
+}
+
+impl ByteBudget {
+    pub fn new(max: Option<u64>) -> Self {
+        Self {
+            used: Arc::default(),
+            max,
+            throttle: None,
+        }
+    }
+
+    /// Limit each transfer to `rate` bytes per second, so that the tests do
+    /// not saturate the network of the host
+    pub fn with_throttle(self, rate: Option<u64>) -> Self {
+        Self {
+            throttle: rate,
+            ..self
+        }
+    }
+
+    /// Maximum rate of each transfer, in bytes per second, if any
+    pub fn throttle(&self) -> Option<u64> {
+        self.throttle
+    }
+
+    /// Maximum number of bytes, if any
+    pub fn max(&self) -> Option<u64> {
+        self.max
+    }
+
+    /// Number of bytes downloaded so far
+    pub fn used(&self) -> u64 {
+        self.used.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Whether no more bytes may be downloaded
+    pub fn exhausted(&self) -> bool {
+        self.max.is_some_and(|max| self.used() >= max)
+    }
+
+    /// Account for downloaded bytes. Returns false once the budget is exhausted
+    fn consume(&self, bytes: u64) -> bool {
+        let used = self.used.fetch_add(bytes, atomic::Ordering::Relaxed) + bytes;
+        self.max.is_none_or(|max| used < max)
+    }
+}
+
+/// Token bucket pacing a transfer, allowing bursts of a tenth of a second
+#[derive(Debug)]
+struct Throttle {
+    /// bytes per second
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last: tokio::time::Instant,
+}
+
+impl Throttle {
+    fn new(rate: u64) -> Self {
+        let rate = rate.max(1) as f64;
+        Self {
+            rate,
+            capacity: rate / 10.0,
+            tokens: rate / 10.0,
+            last: tokio::time::Instant::now(),
+        }
+    }
+
+    /// Wait until `bytes` received bytes fit in the rate
+    async fn consume(&mut self, bytes: usize) {
+        let now = tokio::time::Instant::now();
+        let refill = now.duration_since(self.last).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + refill).min(self.capacity) - bytes as f64;
+        self.last = now;
+        if self.tokens < 0.0 {
+            tokio::time::sleep(std::time::Duration::from_secs_f64(-self.tokens / self.rate)).await;
+        }
+    }
+}
+
+/// What to do when a stage of the selection fails
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailurePolicy {
+    /// stop with an error
+    Fail,
+    /// log a warning and continue without the results of the stage
+    #[default]
+    Warn,
+    /// continue silently without the results of the stage
+    Skip,
+}
+
+impl FailurePolicy {
+    /// Handle the outcome of `stage`: the error is returned with `Fail`,
+    /// logged otherwise
+    pub fn handle<T>(self, stage: &str, res: Result<T>) -> Result<Option<T>> {
+        match (res, self) {
+            (Ok(v), _) => Ok(Some(v)),
+            (Err(e), FailurePolicy::Fail) => Err(e.context(format!("{stage} failed"))),
+            (Err(e), FailurePolicy::Warn) => {
+                warn!("{stage} failed: {e:#}");
+                Ok(None)
+            }
+            (Err(e), FailurePolicy::Skip) => {
+                debug!("{stage} failed: {e:#}");
+                Ok(None)
+            }
         }
     }
 }
 
+/// What is measured to rank mirrors by speed
+#[derive(Debug, Default, Clone, Copy, PartialEq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BenchProfile {
+    /// download rate of one big database
+    #[default]
+    Throughput,
+    /// time to fetch several small files, as a pacman metadata refresh
+    Metadata,
+}
+
+/// How the download rates of several mirrors are measured
+#[derive(Debug, Default, Clone, Copy, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TestMode {
+    /// every mirror at once, fast but the tests share the uplink
+    #[default]
+    Parallel,
+    /// one mirror at a time
+    Sequential,
+    /// latencies in parallel, then rates one at a time, lowest latency first
+    Hybrid,
+    /// rounds of mirrors at once, more while a mirror tested again keeps its
+    /// rate, fewer when its rate collapses
+    Adaptive,
+}
+
 /// List of archlinux mirror status as described in
 /// <https://archlinux.org/mirrors/status/>
+///
+/// Malformed mirror entries are skipped rather than failing the whole
+/// deserialization.
 #[derive(Debug, Default, Clone, Deserialize)]
+#[serde(from = "RawMirrorList")]
 pub struct MirrorList {
-    #[serde(rename = "urls")]
     mirrors: Vec<Mirror>,
 
+    source: Option<String>,
+
+    distro: Distro,
+
+    /// number of malformed entries skipped during deserialization
+    skipped: usize,
+
+    /// when the status was last updated upstream
+    last_check: Option<DateTime<Utc>>,
+
+    /// reference time for age computations. The system time when not set
+    now: Option<DateTime<Utc>>,
+
+    /// last filters applied, recorded in the file header
+    filters: Option<Filters>,
+
+    /// durations of the retrieval and of the parsing of the status
+    phases: run::Phases,
+
+    /// last sort applied, unless the order changed since
+    sorted_by: Option<SortKey>,
+
+    /// settings of the requests measuring the mirrors
+    session: Session,
+}
+
+/// Mirror status, before deserialization of individual mirrors
+#[derive(Deserialize)]
+struct RawMirrorList {
+    urls: Vec<Box<RawValue>>,
+    #[serde(default, with = "parse_date")]
+    last_check: Option<DateTime<Utc>>,
     #[serde(default)]
     source: Option<String>,
 }
 
+/// Mirror status borrowing the text of the mirror entries
+#[derive(Deserialize)]
+struct BorrowedMirrorList<'a> {
+    #[serde(borrow)]
+    urls: Vec<&'a RawValue>,
+    #[serde(default, with = "parse_date")]
+    last_check: Option<DateTime<Utc>>,
+    #[serde(default, borrow)]
+    source: Option<Cow<'a, str>>,
+}
+
+/// Deserialize the mirror entries, skipping malformed ones. Returns the mirrors
+/// and the number of skipped entries
+fn parse_entries<'a>(entries: impl ExactSizeIterator<Item = &'a RawValue>) -> (Vec<Mirror>, usize) {
+    /// URL of an entry, borrowed when not escaped
+    #[derive(Deserialize)]
+    struct Url<'a> {
+        #[serde(borrow)]
+        url: Option<Cow<'a, str>>,
+    }
+
+    let mut mirrors = Vec::with_capacity(entries.len());
+    let mut skipped = 0;
+    for entry in entries {
+        match serde_json::from_str::<Mirror>(entry.get()) {
+            Ok(m) => mirrors.push(m),
+            Err(e) => {
+                let url = serde_json::from_str::<Url>(entry.get())
+                    .ok()
+                    .and_then(|u| u.url)
+                    .unwrap_or(Cow::Borrowed("?"));
+                warn!("skipping malformed mirror {url}: {e}");
+                skipped += 1;
+            }
+        }
+    }
+    if skipped > 0 {
+        warn!("{skipped} malformed mirror entries skipped");
+    }
+    normalize_urls(&mut mirrors);
+    (mirrors, skipped)
+}
+
+/// Normalize the URLs of the mirrors, warning about the ones that cannot be
+/// fixed. See [`Mirror::normalize_url`]
+fn normalize_urls(mirrors: &mut [Mirror]) {
+    let (mut invalid, mut mismatched) = (0, 0);
+    for m in mirrors.iter_mut() {
+        m.normalize_url();
+        if let Some(issue) = m.url_issue {
+            debug!("{:?}: {issue}", m.url);
+            invalid += 1;
+        }
+        if let Some(note) = m.protocol_mismatch() {
+            debug!("{}: {note}", m.url);
+            mismatched += 1;
+        }
+    }
+    if invalid > 0 {
+        warn!("{invalid} invalid mirror URLs");
+    }
+    if mismatched > 0 {
+        info!("protocol of {mismatched} mirrors taken from their URL, not the status");
+    }
+}
+
+impl From<RawMirrorList> for MirrorList {
+    fn from(raw: RawMirrorList) -> Self {
+        let (mirrors, skipped) = parse_entries(raw.urls.iter().map(AsRef::as_ref));
+        Self {
+            mirrors,
+            source: raw.source,
+            last_check: raw.last_check,
+            skipped,
+            ..Default::default()
+        }
+    }
+}
+
 impl MirrorList {
+    /// Parse a mirror status document.
+    ///
+    /// Unlike deserializing a [`MirrorList`] from any source, the mirror
+    /// entries are borrowed from `body` until each of them is deserialized.
+    pub fn from_status_str(body: &str) -> serde_json::Result<Self> {
+        let raw: BorrowedMirrorList = serde_json::from_str(body)?;
+        let (mirrors, skipped) = parse_entries(raw.urls.into_iter());
+        Ok(Self {
+            mirrors,
+            source: raw.source.map(Cow::into_owned),
+            last_check: raw.last_check,
+            skipped,
+            ..Default::default()
+        })
+    }
+
     pub async fn from_default_url() -> Result<Self> {
         Self::from_url(MIRROR_STATUS_URL).await
     }
 
     pub async fn from_url(url: &str) -> Result<Self> {
-        let body = reqwest::get(url).await?.text().await?;
+        Self::from_distro_url(Distro::Arch, url).await
+    }
 
-        // XXX
-        let mut file = File::create(Path::new("/tmp/json.json"))?;
-        file.write_all(&body.clone().into_bytes())?;
-        // XXX
+    /// Retrieve the mirrors of a distribution, in the format this distribution publishes them
+    pub async fn from_distro_url(distro: Distro, url: &str) -> Result<Self> {
+        Self::from_distro_urls(
+            distro,
+            &[url.into()],
+            &Retry::default(),
+            &Session::default(),
+        )
+        .await
+    }
 
-        let mut mlist: Self = match serde_json::from_str(&body) {
-            Ok(x) => x,
-            Err(e) => {
-                eprintln!("malformed JSON: {}", &body);
-                return Err(e.into());
+    /// Retrieve the mirrors of a distribution from the first URL that works.
+    ///
+    /// Each URL is retried according to `retry` before moving to the next one.
+    /// The requests, and the later measurements of the list, use `session`.
+    pub async fn from_distro_urls(
+        distro: Distro,
+        urls: &[String],
+        retry: &Retry,
+        session: &Session,
+    ) -> Result<Self> {
+        Self::from_first_url(urls, retry, session, |body| distro.parse(body)).await
+    }
+
+    async fn from_first_url<F>(
+        urls: &[String],
+        retry: &Retry,
+        session: &Session,
+        parse: F,
+    ) -> Result<Self>
+    where
+        F: Fn(&str) -> Result<Self>,
+    {
+        let started = std::time::Instant::now();
+        let mut error = anyhow::anyhow!("no URL to retrieve mirrors from");
+        for url in urls {
+            match fetch::fetch_retry(session, url, retry)
+                .await
+                .and_then(|body| Self::parse_timed(&parse, &body, started))
+            {
+                Ok(mut mlist) => {
+                    mlist.source = Some(url.into());
+                    mlist.session = session.clone();
+                    return Ok(mlist);
+                }
+                Err(e) => {
+                    warn!("unable to retrieve mirrors from {url}: {e}");
+                    error = e;
+                }
+            }
+        }
+        Err(error)
+    }
+
+    /// Same as [`MirrorList::from_distro_urls`], but the URLs, holding the same
+    /// status, are requested at once. The first valid status is kept and the
+    /// other requests are cancelled
+    pub async fn race_distro_urls(
+        distro: Distro,
+        urls: &[String],
+        retry: &Retry,
+        session: &Session,
+    ) -> Result<Self> {
+        Self::from_fastest_url(urls, retry, session, move |body| distro.parse(body)).await
+    }
+
+    /// Same as [`MirrorList::from_schema_urls`], racing the URLs as
+    /// [`MirrorList::race_distro_urls`] does
+    pub async fn race_schema_urls(
+        mapping: &SchemaMapping,
+        urls: &[String],
+        retry: &Retry,
+        session: &Session,
+    ) -> Result<Self> {
+        let mapping = mapping.clone();
+        Self::from_fastest_url(urls, retry, session, move |body| mapping.parse(body)).await
+    }
+
+    async fn from_fastest_url<F>(
+        urls: &[String],
+        retry: &Retry,
+        session: &Session,
+        parse: F,
+    ) -> Result<Self>
+    where
+        F: Fn(&str) -> Result<Self> + Send + Sync + 'static,
+    {
+        let parse = Arc::new(parse);
+        let started = std::time::Instant::now();
+        let mut set = JoinSet::new();
+        for url in urls {
+            let (url, retry, parse) = (url.clone(), retry.clone(), parse.clone());
+            let session = session.clone();
+            set.spawn(async move {
+                let res = fetch::fetch_retry(&session, &url, &retry)
+                    .await
+                    .and_then(|body| Self::parse_timed(&*parse, &body, started));
+                (url, res)
+            });
+        }
+        let mut error = anyhow::anyhow!("no URL to retrieve mirrors from");
+        while let Some(res) = set.join_next().await {
+            match res {
+                Ok((url, Ok(mut mlist))) => {
+                    debug!("mirrors retrieved first from {url}");
+                    mlist.source = Some(url);
+                    mlist.session = session.clone();
+                    // dropping the set cancels the other requests
+                    return Ok(mlist);
+                }
+                Ok((url, Err(e))) => {
+                    warn!("unable to retrieve mirrors from {url}: {e}");
+                    error = e;
+                }
+                Err(e) => error = e.into(),
             }
+        }
+        Err(error)
+    }
+
+    /// Parse a status retrieved since `started`, recording the durations of
+    /// the retrieval and of the parsing
+    fn parse_timed<F>(parse: &F, body: &str, started: std::time::Instant) -> Result<Self>
+    where
+        F: Fn(&str) -> Result<Self>,
+    {
+        let fetch = started.elapsed();
+        let mut mlist = parse(body)?;
+        mlist.phases.fetch = fetch.as_secs_f64();
+        mlist.phases.parse = (started.elapsed() - fetch).as_secs_f64();
+        Ok(mlist)
+    }
+
+    /// Build a mirror list from the content of a mirrorlist file, or from a list of URLs.
+    ///
+    /// Only URLs and countries are known, other fields are left empty.
+    pub fn from_mirrorlist(content: &str, distro: Distro) -> Self {
+        let mut mlist = distro::parse_mirrorlist(content, distro.server_path());
+        mlist.distro = distro;
+        mlist
+    }
+
+    /// The archive of the distribution as only mirror, at its snapshot of
+    /// `date`, for reproducible builds
+    pub fn from_snapshot(distro: Distro, date: chrono::NaiveDate) -> Result<Self> {
+        let Some(url) = distro.archive_url(date) else {
+            anyhow::bail!("no archive known for {}", distro.name());
         };
-        mlist.source = Some(url.into());
+        let mut mlist = Self::from_mirrorlist(&url, distro);
+        mlist.source = Some(format!("snapshot of {date} in the archive"));
+        Ok(mlist)
+    }
+
+    /// Read a mirrorlist file, or a file listing URLs
+    pub fn from_mirrorlist_file(path: &Path, distro: Distro) -> Result<Self> {
+        let started = std::time::Instant::now();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read mirrorlist {}", path.display()))?;
+        let mut mlist =
+            Self::parse_timed(&|c| Ok(Self::from_mirrorlist(c, distro)), &content, started)?;
+        mlist.source = Some(path.display().to_string());
+        Ok(mlist)
+    }
+
+    /// Read a mirror status saved in a file
+    pub fn from_status_file(path: &Path, distro: Distro) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read {}", path.display()))?;
+        let mut mlist = distro.parse(&content)?;
+        mlist.source = Some(path.display().to_string());
         Ok(mlist)
     }
 
-    /// Sort mirrors by sortkey
+    /// Retrieve mirrors from a custom status document, described by a schema mapping
+    pub async fn from_schema_url(mapping: &SchemaMapping, url: &str) -> Result<Self> {
+        Self::from_schema_urls(
+            mapping,
+            &[url.into()],
+            &Retry::default(),
+            &Session::default(),
+        )
+        .await
+    }
+
+    /// Retrieve mirrors from the first custom status document that works
+    pub async fn from_schema_urls(
+        mapping: &SchemaMapping,
+        urls: &[String],
+        retry: &Retry,
+        session: &Session,
+    ) -> Result<Self> {
+        Self::from_first_url(urls, retry, session, |body| mapping.parse(body)).await
+    }
+
+    /// Add the mirrors of another list.
+    ///
+    /// Mirrors whose URL is already in the list are ignored. The last check
+    /// is the latest of both lists.
+    pub fn merge(&mut self, other: Self) {
+        if self.mirrors.is_empty() && self.source.is_none() {
+            self.distro = other.distro;
+        }
+        let known = self
+            .mirrors
+            .iter()
+            .map(|m| m.url.trim_end_matches('/').to_string())
+            .collect::<HashSet<_>>();
+        if !other.mirrors.is_empty() {
+            self.sorted_by = None;
+        }
+        let mut added = HashSet::new();
+        self.mirrors.extend(other.mirrors.into_iter().filter(|m| {
+            let url = m.url.trim_end_matches('/').to_string();
+            !known.contains(&url) && added.insert(url)
+        }));
+        self.skipped += other.skipped;
+        self.last_check = self.last_check.max(other.last_check);
+        // the lists may have been retrieved at once
+        self.phases.fetch = self.phases.fetch.max(other.phases.fetch);
+        self.phases.parse += other.phases.parse;
+        self.source = match (self.source.take(), other.source) {
+            (Some(s), Some(o)) => Some(format!("{s}, {o}")),
+            (s, o) => s.or(o),
+        };
+    }
+
+    /// Durations of the retrieval and of the parsing of the status, the other
+    /// phases being zero
+    pub fn phases(&self) -> run::Phases {
+        self.phases
+    }
+
+    /// number of mirrors in the list
+    pub fn len(&self) -> usize {
+        self.mirrors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mirrors.is_empty()
+    }
+
+    /// Fail with [`Error::EmptySelection`] if there is no mirror in the list
+    pub fn ensure_not_empty(&self) -> Result<()> {
+        if self.mirrors.is_empty() {
+            return Err(Error::EmptySelection.into());
+        }
+        Ok(())
+    }
+
+    /// number of malformed mirror entries skipped when parsing the status
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// URL of the first mirror of the list
+    pub fn top_url(&self) -> Option<&str> {
+        self.mirrors.first().map(|m| m.url.as_str())
+    }
+
+    /// Sort mirrors by sortkey. The sort is stable, see [`SortKey::cmp`] and
+    /// [`cmp`]
     pub fn sort(&mut self, by: SortKey) {
-        match by {
-            SortKey::Age => self
-                .mirrors
-                .sort_by_key(|m| m.last_sync.unwrap_or_default()),
-            SortKey::Rate => self.mirrors.sort_by(|m, n| {
-                // inverse m and n to sordt in desc ordoer
-                n.download_rate
-                    .clone()
-                    .unwrap_or_default()
-                    .partial_cmp(&m.download_rate.clone().unwrap_or_default())
-                    .unwrap_or(Ordering::Equal)
-            }),
-            SortKey::Country => self
-                .mirrors
-                .sort_by_key(|m| m.country.clone().unwrap_or_default()),
-            SortKey::Score => self
-                .mirrors
-                .sort_by_key(|m| m.score.unwrap_or(f64::INFINITY).round() as i32),
-            SortKey::Delay => self
-                .mirrors
-                .sort_by_key(|m| m.delay.unwrap_or(f64::INFINITY).round() as i32),
+        cmp::sort_by_key(&mut self.mirrors, |m| by.value(m));
+        self.sorted_by = Some(by);
+    }
+
+    /// Sort mirrors by URL, so that later stable sorts order ties the same
+    /// whatever the order of the status
+    pub fn sort_by_url(&mut self) {
+        self.mirrors.sort_by(|a, b| a.url.cmp(&b.url));
+        self.sorted_by = None;
+    }
+
+    /// Last sort applied, if the mirrors are still in its order. Filtering and
+    /// removing mirrors keep the order; shuffling, pinning, merging,
+    /// preferring protocols, measuring the mirrors and changing their fields,
+    /// e.g. with overrides, do not
+    pub fn last_sort(&self) -> Option<&SortKey> {
+        self.sorted_by.as_ref()
+    }
+
+    /// Keep the first `number` mirrors, the best ones for the last sort
+    /// applied. Returns the removed mirrors, in order, with the source,
+    /// distribution and clock of this list
+    pub fn truncate(&mut self, number: usize) -> Self {
+        let tail = self.mirrors.split_off(number.min(self.mirrors.len()));
+        self.with_mirrors(tail)
+    }
+
+    /// Remove the first `number` mirrors, the best ones for the last sort
+    /// applied, and return them, keeping the next ones. E.g. the 20 latest
+    /// mirrors, to be sorted by rate afterwards
+    pub fn take_top(&mut self, number: usize) -> Self {
+        let tail = self.mirrors.split_off(number.min(self.mirrors.len()));
+        let top = std::mem::replace(&mut self.mirrors, tail);
+        self.with_mirrors(top)
+    }
+
+    /// List of other mirrors, with the metadata of this one
+    fn with_mirrors(&self, mirrors: Vec<Mirror>) -> Self {
+        Self {
+            mirrors,
+            source: self.source.clone(),
+            distro: self.distro,
+            last_check: self.last_check,
+            now: self.now,
+            filters: self.filters.clone(),
+            sorted_by: self.sorted_by.clone(),
+            session: self.session.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// return the content to put in mirrorlist: the header, if any, then the
+    /// server lines
+    pub fn to_file_content(&self, options: &RenderOptions) -> String {
+        let header = self.render_header(options);
+        let servers = self.render_servers(options);
+        if header.is_empty() {
+            servers
+        } else {
+            format!("{header}\n\n{servers}")
         }
     }
 
-    /// return the content to put in mirrorlist
-    pub fn to_file_content(&self, number: usize) -> String {
-        let mut lines = vec![self.file_preambule(), "".into()];
-        lines.push(self.server_list(number));
+    /// return the content to put in mirrorlist, rendering server lines with a template.
+    ///
+    /// The template may contain the placeholders `{url}`, `{country}`,
+    /// `{country_code}`, `{protocol}`, `{tier}`, `{note}` and `{age_hours}`, the
+    /// hours since the last synchronization.
+    pub fn to_file_content_with_template(&self, number: usize, template: &str) -> String {
+        self.to_file_content(&RenderOptions {
+            template: Some(template.into()),
+            ..RenderOptions::new(Number::Limit(number))
+        })
+    }
+
+    /// Comment lines describing the mirrorlist, the fields of the options in
+    /// their order. Empty without fields
+    pub fn render_header(&self, options: &RenderOptions) -> String {
+        let mut lines = Vec::new();
+        for field in &options.header {
+            match field {
+                HeaderField::Title => {
+                    lines.push(format!(
+                        "# {} mirror list generated by reflecto.rs",
+                        self.distro.name()
+                    ));
+                    lines.push("#".into());
+                }
+                HeaderField::Date => lines.push(format!(
+                    "# generated: \t{}",
+                    self.now().format("%Y-%m-%d %H:%M:%S UTC")
+                )),
+                HeaderField::Source => {
+                    if let Some(s) = &self.source {
+                        lines.push(format!("# from: \t{s}"));
+                    }
+                }
+                HeaderField::Filters => {
+                    for f in self.filters.iter().flat_map(Filters::summary) {
+                        lines.push(format!("# filter: \t{f}"));
+                    }
+                }
+                HeaderField::Warnings => {
+                    for w in self.diversity_warnings(options.limit.of(self.mirrors.len())) {
+                        lines.push(format!("# warning: \t{w}"));
+                    }
+                }
+            }
+        }
         lines.join("\n")
     }
 
-    ///generate the file preambule
-    fn file_preambule(&self) -> String {
-        let mut lines: Vec<String> = vec![
-            "# Arch Linux mirror list generated by reflecto.rs".into(),
-            "#".into(),
-        ];
-        // TODO: add status lines (date, program name,...)
-        if let Some(s) = &self.source {
-            lines.push(format!("# from: \t{s}"));
+    /// Server lines of the kept mirrors, with their annotations, grouped by
+    /// country if asked, then the ones of the commented extra mirrors, without
+    /// header
+    pub fn render_servers(&self, options: &RenderOptions) -> String {
+        let template = match &options.template {
+            Some(t) => Cow::Borrowed(t.as_str()),
+            None => Cow::Owned(self.default_template()),
+        };
+        let template = Annotation::apply(&template, &options.annotations);
+        let limit = options.limit.of(self.mirrors.len());
+        let mut lines = if options.group_by_country {
+            vec![self.grouped_server_list(limit, &template)]
+        } else {
+            vec![self.server_list(limit, &template)]
+        };
+        let extras = self
+            .mirrors
+            .iter()
+            .skip(limit)
+            .take(options.commented_extras);
+        if options.group_by_country && extras.len() > 0 {
+            lines.push(String::new());
         }
+        lines.extend(extras.map(|m| format!("#{}", m.render(&template, self.now()))));
         lines.join("\n")
     }
 
-    fn server_list(&self, limit: usize) -> String {
+    /// template of server lines for the distribution
+    pub fn default_template(&self) -> String {
+        format!("Server = {{url}}{}", self.distro.server_path())
+    }
+
+    /// Warnings about keeping only the first `number` mirrors when it loses
+    /// every country or protocol but one
+    pub fn diversity_warnings(&self, number: usize) -> Vec<String> {
+        if number >= self.mirrors.len() || number < 2 {
+            return Vec::new();
+        }
+        let kept = &self.mirrors[..number];
+        let mut warnings = Vec::new();
+        let countries = |ms: &[Mirror]| ms.iter().map(|m| m.code()).collect::<HashSet<_>>();
+        let kept_countries = countries(kept);
+        if kept_countries.len() == 1 && countries(&self.mirrors).len() > 1 {
+            let country = kept_countries.into_iter().flatten().next();
+            warnings.push(format!(
+                "the {number} kept mirrors are all located in {}",
+                country.as_deref().unwrap_or("an unknown country")
+            ));
+        }
+        let protocols = |ms: &[Mirror]| {
+            ms.iter()
+                .map(|m| m.protocol.to_string())
+                .collect::<HashSet<_>>()
+        };
+        let kept_protocols = protocols(kept);
+        if kept_protocols.len() == 1 && protocols(&self.mirrors).len() > 1 {
+            let protocol = kept_protocols.into_iter().next().unwrap_or_default();
+            warnings.push(format!("the {number} kept mirrors all use {protocol}"));
+        }
+        warnings
+    }
+
+    fn server_list(&self, limit: usize, template: &str) -> String {
         let limit = if limit > self.mirrors.len() {
             self.mirrors.len()
         } else {
@@ -144,38 +1018,64 @@ impl MirrorList {
 
         self.mirrors[0..limit]
             .iter()
-            .map(|m| format!("Server = {}$repo/os/$arch", m.url))
+            .map(|m| m.render(template, self.now()))
             .collect::<Vec<_>>()
             .join("\n")
     }
 
-    /// get a list of all countries in which a mirror is present
-    /// returns a Hashmap<(Country, Code), Count>
-    fn get_countries(&self) -> HashMap<(String, String), usize> {
-        let mut countries = HashMap::new();
-        for mirror in self.mirrors.iter() {
-            if mirror.country.is_none() || mirror.country_code.is_none() {
-                continue;
+    /// Server lines of the first `limit` mirrors under a `## Country` comment
+    /// per country, like the stock mirrorlist. Countries come in the order of
+    /// their first mirror, so that the best mirror is still the first server;
+    /// mirrors without country are grouped under `## Worldwide`
+    fn grouped_server_list(&self, limit: usize, template: &str) -> String {
+        let mut groups: Vec<(&str, Vec<String>)> = Vec::new();
+        for m in self.mirrors.iter().take(limit) {
+            let country = m
+                .country
+                .as_deref()
+                .filter(|c| !c.is_empty())
+                .unwrap_or("Worldwide");
+            let line = m.render(template, self.now());
+            match groups.iter_mut().find(|(c, _)| *c == country) {
+                Some((_, lines)) => lines.push(line),
+                None => groups.push((country, vec![line])),
             }
-            let key = (
-                mirror.country.clone().unwrap(),
-                mirror.country_code.clone().unwrap(),
-            );
-            *countries.entry(key).or_insert(0) += 1;
         }
-        countries
+        groups
+            .into_iter()
+            .map(|(country, lines)| format!("## {country}\n{}", lines.join("\n")))
+            .collect::<Vec<_>>()
+            .join("\n\n")
     }
 
-    /// get a csv-like string listing countries
+    /// get a list of all countries in which a mirror is present
+    /// returns a Hashmap<(Country, Code), Count>
+    fn get_countries(&self) -> HashMap<(String, String), usize> {
+        count_countries(&self.mirrors)
+    }
+
+    /// get a csv-like string listing countries
     pub fn print_countries(&self) -> String {
+        self.print_countries_with(None)
+    }
+
+    /// get a csv-like string listing countries.
+    ///
+    /// Countries are sorted by the given collation, or by byte order if none.
+    pub fn print_countries_with(&self, collation: Option<&collation::Collation>) -> String {
         let mut lines = Vec::new();
         let mut countries = self.get_countries().into_iter().collect::<Vec<_>>();
-        countries.sort();
+        match collation {
+            Some(collation) => {
+                countries.sort_by(|a, b| collation.compare(&a.0 .0, &b.0 .0).then_with(|| a.cmp(b)))
+            }
+            None => countries.sort(),
+        }
         let longuest = countries
             .iter()
-            .map(|c| c.0 .0.chars().count())
+            .map(|c| c.0 .0.width())
             .max()
-            .expect("at least one country");
+            .unwrap_or_default();
         let longuest = longuest.max(7); // minimal value: length of "Country"
         lines.push(format!("Country{} Code Count", " ".repeat(longuest - 7)));
         lines.push(format!("{} ---- ----", "-".repeat(longuest)));
@@ -190,21 +1090,75 @@ impl MirrorList {
 
     #[instrument]
     pub async fn update_download_rate(&mut self, timeout: Option<chrono::Duration>, limit: usize) {
+        self.update_download_rate_until(timeout, limit, None, &ByteBudget::default())
+            .await
+    }
+
+    /// Same as [`MirrorList::update_download_rate`], but stops testing at the
+    /// deadline, keeping the rates measured so far, or once the budget is
+    /// exhausted. Mirrors are updated in place, keeping their order.
+    #[instrument]
+    pub async fn update_download_rate_until(
+        &mut self,
+        timeout: Option<chrono::Duration>,
+        limit: usize,
+        deadline: Option<tokio::time::Instant>,
+        budget: &ByteBudget,
+    ) {
         let mut left = self.mirrors.len().min(limit);
-        let mut mirrors = Vec::new();
+        if left == 0 {
+            return;
+        }
+        let wanted = left;
+        self.sorted_by = None;
+        // under a budget, the first mirrors are tested before the next ones
+        let concurrency = match budget.max() {
+            Some(_) => left.min(MAX_BUDGETED_TESTS),
+            None => self.mirrors.len(),
+        };
+        let semaphore = Arc::new(tokio::sync::Semaphore::new(concurrency.max(1)));
+        // the tests run on mirrors knowing only the URL, their results being
+        // copied back in place
         let mut set = JoinSet::new();
-        for m in self.mirrors.drain(..) {
-            mirrors.push(m.clone());
-            set.spawn(m.update_download_rate(timeout));
+        for (i, m) in self.mirrors.iter().enumerate() {
+            let test = Mirror::from_url(&m.url).update_download_rate(
+                timeout,
+                self.distro,
+                budget.clone(),
+                self.session.clone(),
+            );
+            let semaphore = semaphore.clone();
+            set.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                (i, test.await)
+            });
         }
-        while let Some(res) = set.join_next().await {
+        let (mut tested, mut failed) = (0, 0);
+        let expired = pipeline::expiry(deadline);
+        tokio::pin!(expired);
+        loop {
+            let res = tokio::select! {
+                res = set.join_next() => match res {
+                    Some(res) => res,
+                    None => break,
+                },
+                _ = &mut expired => {
+                    warn!("deadline reached, {} mirrors not tested", set.len());
+                    break;
+                }
+            };
             match res {
-                Ok(Ok(m)) => {
-                    let _ = &self.mirrors.push(m);
+                Ok((i, Ok(result))) => {
+                    self.mirrors[i].copy_rate_test(result);
+                    tested += 1;
                     left -= 1;
                 }
                 _ => {
-                    debug!("failed to update a mirror")
+                    debug!("failed to update a mirror");
+                    // the tests failing once the budget is exhausted are cut by it
+                    if !budget.exhausted() {
+                        failed += 1;
+                    }
                 }
             }
             if left == 0 {
@@ -213,19 +1167,854 @@ impl MirrorList {
             }
         }
         set.shutdown().await;
+        if budget.exhausted() {
+            warn!(
+                "download budget exhausted, {} mirrors not tested",
+                wanted.saturating_sub(tested + failed)
+            );
+        }
+    }
+
+    /// Same as [`MirrorList::update_download_rate_until`], measuring the rates
+    /// as described by `mode`
+    pub async fn update_download_rate_mode(
+        &mut self,
+        timeout: Option<chrono::Duration>,
+        limit: usize,
+        deadline: Option<tokio::time::Instant>,
+        budget: &ByteBudget,
+        mode: TestMode,
+    ) {
+        self.sorted_by = None;
+        if mode == TestMode::Parallel {
+            return self
+                .update_download_rate_until(timeout, limit, deadline, budget)
+                .await;
+        }
+        if mode == TestMode::Adaptive {
+            return self
+                .update_download_rate_adaptive(timeout, limit, deadline, budget)
+                .await;
+        }
+        if mode == TestMode::Hybrid {
+            self.update_latencies(timeout, deadline).await;
+            // mirrors without latency keep their order, last
+            cmp::sort_by_key(&mut self.mirrors, |m| SortValue::number(m.latency));
+            self.sorted_by = None;
+        }
+        let mut left = self.mirrors.len().min(limit);
+        for m in self.mirrors.iter_mut() {
+            if left == 0 {
+                debug!("enough mirror updated");
+                break;
+            }
+            if budget.exhausted() {
+                warn!("download budget exhausted");
+                break;
+            }
+            let test = m.update_dl_rate(timeout, self.distro, budget, &self.session);
+            let res = match deadline {
+                Some(d) => match tokio::time::timeout_at(d, test).await {
+                    Ok(res) => res,
+                    Err(_) => {
+                        warn!("deadline reached, remaining mirrors not tested");
+                        break;
+                    }
+                },
+                None => test.await,
+            };
+            match res {
+                Ok(()) => left -= 1,
+                Err(e) => debug!("failed to update {}: {e}", m.url),
+            }
+        }
+    }
+
+    /// Measure the rates in rounds, as many mirrors at once as the concurrency
+    /// adapted to the previous rounds. The mirrors of a round whose transfers
+    /// collapsed are tested again, once, at the lowered concurrency. The rate
+    /// test of the reference, see [`Concurrency`], only adapts the concurrency
+    async fn update_download_rate_adaptive(
+        &mut self,
+        timeout: Option<chrono::Duration>,
+        limit: usize,
+        deadline: Option<tokio::time::Instant>,
+        budget: &ByteBudget,
+    ) {
+        let mut left = self.mirrors.len().min(limit);
+        let mut queue = (0..self.mirrors.len())
+            .map(|i| (i, false))
+            .collect::<std::collections::VecDeque<_>>();
+        let mut concurrency = Concurrency::default();
+        let expired = pipeline::expiry(deadline);
+        tokio::pin!(expired);
+        let mut deadline_reached = false;
+        while left > 0 && !queue.is_empty() && !deadline_reached {
+            if budget.exhausted() {
+                warn!(
+                    "download budget exhausted, {} mirrors not tested",
+                    queue.len()
+                );
+                break;
+            }
+            let round = queue
+                .drain(..concurrency.new_tests().min(left).min(queue.len()))
+                .collect::<Vec<_>>();
+            // the reference is marked with no retry state
+            let reference = concurrency.reference.map(|(i, _)| (i, None));
+            let mut set = JoinSet::new();
+            for (i, retried) in round.iter().map(|&(i, r)| (i, Some(r))).chain(reference) {
+                let test = Mirror::from_url(&self.mirrors[i].url).update_download_rate(
+                    timeout,
+                    self.distro,
+                    budget.clone(),
+                    self.session.clone(),
+                );
+                set.spawn(async move { (i, retried, test.await) });
+            }
+            let mut results = Vec::new();
+            let mut reference_rate = None;
+            loop {
+                tokio::select! {
+                    res = set.join_next() => match res {
+                        Some(Ok((i, Some(retried), Ok(result)))) => results.push((i, retried, result)),
+                        Some(Ok((_, None, Ok(result)))) => {
+                            reference_rate = result.download_rate.map(|r| r.0);
+                        }
+                        Some(_) => debug!("failed to update a mirror"),
+                        None => break,
+                    },
+                    _ = &mut expired => {
+                        warn!("deadline reached, {} mirrors not tested", set.len() + queue.len());
+                        deadline_reached = true;
+                        break;
+                    }
+                }
+            }
+            set.shutdown().await;
+            // the tests finished before the deadline are kept
+            let collapsed = !deadline_reached && {
+                let level = concurrency.level;
+                let collapsed = concurrency.update(reference_rate);
+                if concurrency.level != level {
+                    debug!(
+                        "rate tests concurrency changed from {level} to {}",
+                        concurrency.level
+                    );
+                }
+                collapsed
+            };
+            if !collapsed {
+                concurrency.follow(
+                    results
+                        .iter()
+                        .filter_map(|(i, _, m)| Some((*i, m.download_rate.as_ref()?.0))),
+                );
+            }
+            for (i, retried, result) in results.into_iter().rev() {
+                if collapsed && !retried {
+                    queue.push_front((i, true));
+                } else if left > 0 {
+                    self.mirrors[i].copy_rate_test(result);
+                    left -= 1;
+                }
+            }
+        }
+    }
+
+    /// Measure concurrently the time, in seconds, to get the headers of the
+    /// test file. Mirrors not answering are left unknown
+    async fn update_latencies(
+        &mut self,
+        timeout: Option<chrono::Duration>,
+        deadline: Option<tokio::time::Instant>,
+    ) {
+        let Ok(pipeline) = Pipeline::with_session(timeout, deadline, &self.session) else {
+            return;
+        };
+        let test_path = self.distro.test_path();
+        let urls = self.mirrors.iter().map(|m| m.url.clone()).collect();
+        let output = pipeline
+            .stage(
+                "latency",
+                urls,
+                CONCURRENT_PROBES,
+                |client, url| async move {
+                    let start = tokio::time::Instant::now();
+                    let test_url = format!("{}/{test_path}", url.trim_end_matches('/'));
+                    client.head(&test_url).send().await?.error_for_status()?;
+                    Ok((url, start.elapsed().as_secs_f64()))
+                },
+            )
+            .await;
+        debug!("latency measured for {} mirrors", output.results.len());
+        let latencies = output.results.into_iter().collect::<HashMap<_, _>>();
+        for m in self.mirrors.iter_mut() {
+            m.latency = latencies.get(&m.url).copied();
+        }
+    }
 
-        // push not updated mirrors
-        let ok_urls = &self
+    /// Resolve concurrently the host names of the first `number` mirrors,
+    /// comparing their addresses with the `previous` ones. Hosts not resolved
+    /// are left unknown. Returns the hosts whose addresses changed
+    pub async fn resolve_addresses(
+        &mut self,
+        number: usize,
+        timeout: Option<chrono::Duration>,
+        deadline: Option<tokio::time::Instant>,
+        previous: &dns::Addresses,
+    ) -> Result<Vec<String>> {
+        let pipeline = Pipeline::with_session(timeout, deadline, &self.session)?;
+        let timeout = timeout.map(|d| d.to_std()).transpose()?;
+        let mut hosts = self
             .mirrors
             .iter()
-            .map(|m| m.url.clone())
+            .take(number)
+            .filter_map(Mirror::host)
             .collect::<Vec<_>>();
-        self.mirrors.append(
-            &mut mirrors
-                .into_iter()
-                .filter(|m| !ok_urls.contains(&m.url))
-                .collect::<Vec<_>>(),
+        hosts.sort();
+        hosts.dedup();
+        let output = pipeline
+            .stage("dns", hosts, CONCURRENT_PROBES, |_, host| async move {
+                let addresses = match timeout {
+                    Some(t) => tokio::time::timeout(t, dns::resolve(&host)).await??,
+                    None => dns::resolve(&host).await?,
+                };
+                Ok((host, addresses))
+            })
+            .await;
+        debug!("{} hosts resolved", output.results.len());
+        let resolved = output.results.into_iter().collect::<HashMap<_, _>>();
+        let mut changed = Vec::new();
+        for m in self.mirrors.iter_mut().take(number) {
+            let Some(host) = m.host() else { continue };
+            let Some(addresses) = resolved.get(&host) else {
+                continue;
+            };
+            let before = previous.get(&host);
+            let dns_changed = dns::changed(before, addresses);
+            m.addresses = Some(addresses.clone());
+            m.dns_changed = before.map(|_| dns_changed);
+            if dns_changed && !changed.contains(&host) {
+                changed.push(host);
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Check concurrently that the first `number` mirrors publish the
+    /// checksums and signature of the latest installation image. Mirrors not
+    /// answering are left unknown
+    pub async fn check_iso_files(
+        &mut self,
+        number: usize,
+        timeout: Option<chrono::Duration>,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<()> {
+        let Some(paths) = self.distro.iso_paths() else {
+            anyhow::bail!("no installation image known for {}", self.distro.name());
+        };
+        let pipeline = Pipeline::with_session(timeout, deadline, &self.session)?;
+        let urls = self.urls().take(number).map(String::from).collect();
+        let output = pipeline
+            .stage(
+                "iso files",
+                urls,
+                CONCURRENT_PROBES,
+                |client, url| async move {
+                    let mut present = true;
+                    for path in [paths.checksums, paths.signature] {
+                        let file_url = format!("{}/{path}", url.trim_end_matches('/'));
+                        let request = client.head(&file_url);
+                        let status = request.send().await?.status();
+                        if status.is_client_error() {
+                            present = false;
+                        } else if !status.is_success() {
+                            anyhow::bail!("{file_url}: {status}");
+                        }
+                    }
+                    Ok((url, present))
+                },
+            )
+            .await;
+        let results = output.results.into_iter().collect::<HashMap<_, _>>();
+        for m in self.mirrors.iter_mut().take(number) {
+            m.iso_files = results.get(&m.url).copied();
+        }
+        Ok(())
+    }
+
+    /// Resolved addresses of the first `number` mirrors, by host name
+    pub fn addresses(&self, number: usize) -> dns::Addresses {
+        self.mirrors
+            .iter()
+            .take(number)
+            .filter_map(|m| Some((m.host()?, m.addresses.clone()?)))
+            .collect()
+    }
+
+    /// Remove the mirrors matching an entry of the list. Returns the number of
+    /// removed mirrors
+    pub fn exclude(&mut self, list: &urlfile::UrlFile) -> usize {
+        let len = self.mirrors.len();
+        self.mirrors.retain(|m| !list.matches(&m.url));
+        len - self.mirrors.len()
+    }
+
+    /// Measure the time to fetch the small metadata files of every mirror, one
+    /// file after the other on one connection. Mirrors failing are left unknown
+    pub async fn update_metadata_time(
+        &mut self,
+        timeout: Option<chrono::Duration>,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<()> {
+        let pipeline = Pipeline::with_session(timeout, deadline, &self.session)?;
+        self.sorted_by = None;
+        let paths = self.distro.metadata_paths();
+        let urls = self.mirrors.iter().map(|m| m.url.clone()).collect();
+        let output = pipeline
+            .stage(
+                "metadata",
+                urls,
+                CONCURRENT_PROBES,
+                |client, url| async move {
+                    let start = tokio::time::Instant::now();
+                    for path in paths {
+                        let file_url = format!("{}/{path}", url.trim_end_matches('/'));
+                        client
+                            .get(&file_url)
+                            .header(reqwest::header::CACHE_CONTROL, "no-cache")
+                            .send()
+                            .await?
+                            .error_for_status()?
+                            .bytes()
+                            .await?;
+                    }
+                    Ok((url, start.elapsed().as_secs_f64()))
+                },
+            )
+            .await;
+        info!(
+            "metadata fetch time measured for {} mirrors",
+            output.results.len()
         );
+        let times = output.results.into_iter().collect::<HashMap<_, _>>();
+        for m in self.mirrors.iter_mut() {
+            m.metadata_time = times.get(&m.url).copied();
+        }
+        Ok(())
+    }
+
+    /// Check whether the mirrors keep the connection open between two
+    /// requests, concurrently, by counting the connections opened by a client
+    /// of its own. Mirrors given by address are judged on the headers of their
+    /// response. Mirrors not answering are left unknown
+    pub async fn probe_keep_alive(
+        &mut self,
+        timeout: Option<chrono::Duration>,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<()> {
+        let pipeline = Pipeline::with_session(timeout, deadline, &self.session)?;
+        let timeout = timeout.map(|d| d.to_std()).transpose()?;
+        let test_path = self.distro.test_path();
+        let urls = self.mirrors.iter().map(|m| m.url.clone()).collect();
+        let session = pipeline.session().clone();
+        let output = pipeline
+            .stage("keep-alive", urls, CONCURRENT_PROBES, |_, url| {
+                let session = session.clone();
+                async move {
+                    let resolver = Arc::new(dns::CountingResolver::default());
+                    let mut builder = session
+                        .client_builder()
+                        .pool_max_idle_per_host(1)
+                        .dns_resolver(resolver.clone());
+                    if let Some(t) = timeout {
+                        builder = builder.timeout(t);
+                    }
+                    let client = session.wrap(builder.build()?);
+                    let test_url = format!("{}/{test_path}", url.trim_end_matches('/'));
+                    let head = || client.head(&test_url);
+                    let first = head().send().await?.error_for_status()?;
+                    let advertised = keeps_alive(first.version(), first.headers());
+                    // the connection is given back once the response is read
+                    first.bytes().await?;
+                    // pacman issues its requests one after the other
+                    head().send().await?.error_for_status()?;
+                    let kept = match resolver.lookups() {
+                        0 => advertised,
+                        lookups => lookups == 1,
+                    };
+                    Ok((url, kept))
+                }
+            })
+            .await;
+        let results = output.results.into_iter().collect::<HashMap<_, _>>();
+        for m in self.mirrors.iter_mut() {
+            m.keep_alive = results.get(&m.url).copied();
+        }
+        Ok(())
+    }
+
+    /// Remove the mirrors known to close the connection after each request.
+    /// Returns their URLs
+    pub fn remove_without_keep_alive(&mut self) -> Vec<String> {
+        let (closing, kept) = self
+            .mirrors
+            .drain(..)
+            .partition(|m| m.keep_alive == Some(false));
+        self.mirrors = kept;
+        closing.into_iter().map(|m: Mirror| m.url).collect()
+    }
+
+    /// Remove the mirrors that are unreachable or lack the first metadata file
+    /// of the distribution, with a cheap HEAD request, before measuring them.
+    /// Mirrors answering otherwise, or not probed before the deadline, are
+    /// kept. Returns the URLs of the removed mirrors
+    pub async fn remove_dead(
+        &mut self,
+        timeout: Option<chrono::Duration>,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<Vec<String>> {
+        let pipeline = Pipeline::with_session(timeout, deadline, &self.session)?;
+        let path = self.distro.metadata_paths()[0];
+        let urls = self.mirrors.iter().map(|m| m.url.clone()).collect();
+        let output = pipeline
+            .stage(
+                "liveness",
+                urls,
+                CONCURRENT_PROBES,
+                |client, url| async move {
+                    let probe_url = format!("{}/{path}", url.trim_end_matches('/'));
+                    let probe = client.head(&probe_url);
+                    let alive = match probe.send().await {
+                        Ok(r) => !matches!(
+                            r.status(),
+                            reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::GONE
+                        ),
+                        Err(e) if e.is_connect() || e.is_timeout() => false,
+                        Err(e) => return Err(e.into()),
+                    };
+                    Result::Ok((url, alive))
+                },
+            )
+            .await;
+        let dead = output
+            .results
+            .into_iter()
+            .filter_map(|(url, alive)| (!alive).then_some(url))
+            .collect::<HashSet<_>>();
+        let (removed, kept) = self.mirrors.drain(..).partition(|m| dead.contains(&m.url));
+        self.mirrors = kept;
+        Ok(removed.into_iter().map(|m: Mirror| m.url).collect())
+    }
+
+    /// Connect concurrently to every mirror over IPv4 and IPv6, recording
+    /// which versions work. Mirrors not checked before the deadline are left
+    /// unknown
+    pub async fn check_reachability(
+        &mut self,
+        timeout: Option<chrono::Duration>,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<()> {
+        let pipeline = Pipeline::with_session(timeout, deadline, &self.session)?;
+        let timeout = timeout.map(|d| d.to_std()).transpose()?;
+        let urls = self.mirrors.iter().map(|m| m.url.clone()).collect();
+        let output = pipeline
+            .stage(
+                "reachability",
+                urls,
+                CONCURRENT_PROBES,
+                |_, url| async move {
+                    let parsed = reqwest::Url::parse(&url)?;
+                    let (Some(host), Some(port)) =
+                        (parsed.host_str(), parsed.port_or_known_default())
+                    else {
+                        anyhow::bail!("no host in {url}");
+                    };
+                    let host = host.trim_start_matches('[').trim_end_matches(']');
+                    let reached = dns::reachability(host, port, timeout).await;
+                    Ok((url, reached))
+                },
+            )
+            .await;
+        let results = output.results.into_iter().collect::<HashMap<_, _>>();
+        for m in self.mirrors.iter_mut() {
+            m.reachable = results.get(&m.url).copied();
+        }
+        Ok(())
+    }
+
+    /// Remove the mirrors to which a connection failed over a required IP
+    /// version. Mirrors not checked are kept. Returns the URLs of the removed
+    /// mirrors with the version that failed
+    pub fn remove_unreachable(&mut self, ipv4: bool, ipv6: bool) -> Vec<(String, &'static str)> {
+        let failed = |m: &Mirror| match m.reachable {
+            Some(r) if ipv4 && !r.ipv4 => Some("ipv4 unreachable"),
+            Some(r) if ipv6 && !r.ipv6 => Some("ipv6 unreachable"),
+            _ => None,
+        };
+        let mut removed = Vec::new();
+        self.mirrors.retain(|m| match failed(m) {
+            Some(reason) => {
+                removed.push((m.url.clone(), reason));
+                false
+            }
+            None => true,
+        });
+        removed
+    }
+
+    /// URLs of the mirrors whose protocol in the status did not match the
+    /// scheme of the URL, with a description of the mismatch
+    pub fn protocol_mismatches(&self) -> Vec<(&str, String)> {
+        self.mirrors
+            .iter()
+            .filter_map(|m| Some((m.url.as_str(), m.protocol_mismatch()?)))
+            .collect()
+    }
+
+    /// Number of mirrors whose URL is invalid
+    pub fn invalid_urls(&self) -> usize {
+        self.mirrors
+            .iter()
+            .filter(|m| m.url_issue.is_some())
+            .count()
+    }
+
+    /// Remove the mirrors whose URL is invalid. Returns their URLs and what is
+    /// wrong with them
+    pub fn remove_invalid_urls(&mut self) -> Vec<(String, &'static str)> {
+        let mut removed = Vec::new();
+        self.mirrors.retain(|m| match m.url_issue {
+            Some(issue) => {
+                removed.push((m.url.clone(), issue));
+                false
+            }
+            None => true,
+        });
+        removed
+    }
+
+    /// Remove the mirrors whose rate test was redirected to another host.
+    /// Returns their URLs
+    pub fn remove_redirecting(&mut self) -> Vec<String> {
+        let (redirecting, kept) = self.mirrors.drain(..).partition(Mirror::redirects);
+        self.mirrors = kept;
+        redirecting.into_iter().map(|m: Mirror| m.url).collect()
+    }
+
+    /// Reduce by `penalty`, a fraction, the download rate of the mirrors whose
+    /// rate test was redirected to another host. Returns the number of
+    /// penalized mirrors
+    pub fn penalize_redirects(&mut self, penalty: f64) -> usize {
+        let mut count = 0;
+        for m in self.mirrors.iter_mut().filter(|m| m.redirects()) {
+            if let Some(rate) = m.download_rate.as_mut() {
+                rate.0 *= 1.0 - penalty.clamp(0.0, 1.0);
+                count += 1;
+            }
+        }
+        if count > 0 {
+            self.sorted_by = None;
+        }
+        count
+    }
+
+    /// Order the mirrors by protocol preference, the first protocol of
+    /// `preferred` first, other protocols last, and remove the URLs of a host
+    /// when it has a URL of a better-ranked protocol. Sorting afterwards keeps
+    /// this order between equal mirrors. Returns the number of removed URLs
+    pub fn prefer_protocols(&mut self, preferred: &[Protocol]) -> usize {
+        let rank = |m: &Mirror| {
+            preferred
+                .iter()
+                .position(|p| *p == m.protocol)
+                .unwrap_or(preferred.len())
+        };
+        self.mirrors.sort_by_key(rank);
+        self.sorted_by = None;
+        let len = self.mirrors.len();
+        // the first URL of each host has its best rank
+        let mut best = HashMap::new();
+        self.mirrors.retain(|m| {
+            let rank = rank(m);
+            m.host()
+                .is_none_or(|host| *best.entry(host).or_insert(rank) == rank)
+        });
+        len - self.mirrors.len()
+    }
+
+    /// Replace the fields of the mirrors with their local overrides. Returns the
+    /// number of overridden mirrors
+    pub fn apply_overrides(&mut self, overrides: &overrides::Overrides) -> usize {
+        let mut count = 0;
+        for m in self.mirrors.iter_mut() {
+            if let Some(o) = overrides.get(&m.url) {
+                m.apply_override(o);
+                count += 1;
+            }
+        }
+        if count > 0 {
+            self.sorted_by = None;
+        }
+        count
+    }
+
+    /// Record the backend of the mirrors that are known aliases. Returns the
+    /// number of such mirrors
+    pub fn apply_aliases(&mut self, aliases: &aliases::Aliases) -> usize {
+        let mut count = 0;
+        for m in self.mirrors.iter_mut() {
+            m.backend = m.host().and_then(|h| aliases.backend(&h).map(String::from));
+            count += usize::from(m.backend.is_some());
+        }
+        count
+    }
+
+    /// Number of mirrors sharing their backend with another one, the ones
+    /// [`MirrorList::dedupe_backends`] moves whatever the order
+    pub fn redundant_aliases(&self) -> usize {
+        let backends = self
+            .mirrors
+            .iter()
+            .filter_map(|m| m.backend.as_ref())
+            .collect::<Vec<_>>();
+        backends.len() - backends.iter().collect::<HashSet<_>>().len()
+    }
+
+    /// Move the mirrors of a backend already listed before them after the
+    /// other mirrors, keeping their order, so that the first mirrors are
+    /// distinct backends. Returns the URLs of the moved mirrors and their
+    /// backend
+    pub fn dedupe_backends(&mut self) -> Vec<(String, String)> {
+        let mut seen = HashSet::new();
+        let (first, aliases): (Vec<_>, Vec<_>) = std::mem::take(&mut self.mirrors)
+            .into_iter()
+            .partition(|m| m.backend.as_ref().is_none_or(|b| seen.insert(b.clone())));
+        let moved = aliases
+            .iter()
+            .map(|m: &Mirror| (m.url.clone(), m.backend.clone().unwrap_or_default()))
+            .collect::<Vec<_>>();
+        self.mirrors = first;
+        self.mirrors.extend(aliases);
+        if !moved.is_empty() {
+            self.sorted_by = None;
+        }
+        moved
+    }
+
+    /// When the status was last updated upstream, if known
+    pub fn last_check(&self) -> Option<DateTime<Utc>> {
+        self.last_check
+    }
+
+    /// The mirrors, in order
+    pub fn mirrors(&self) -> &[Mirror] {
+        &self.mirrors
+    }
+
+    /// At most `len` mirrors, starting at the `offset`-th one, borrowed from
+    /// the list. As sorting is stable, pages read one after the other from a
+    /// list left unchanged cover each mirror once, mirrors of equal rank
+    /// keeping the order of the status
+    pub fn page(&self, offset: usize, len: usize) -> Page<'_> {
+        let start = offset.min(self.mirrors.len());
+        let end = start.saturating_add(len).min(self.mirrors.len());
+        Page {
+            mirrors: &self.mirrors[start..end],
+            offset: start,
+            total: self.mirrors.len(),
+        }
+    }
+
+    /// URLs of the mirrors, in order
+    pub fn urls(&self) -> impl Iterator<Item = &str> {
+        self.mirrors.iter().map(|m| m.url.as_str())
+    }
+
+    /// Everything known about each mirror, as "field: value" lines
+    pub fn describe(&self) -> String {
+        let now = self.now();
+        self.mirrors
+            .iter()
+            .map(|m| m.describe(now))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    /// Copy of the mirrors matching an entry of the list
+    pub fn select(&self, list: &urlfile::UrlFile) -> Self {
+        Self {
+            mirrors: self
+                .mirrors
+                .iter()
+                .filter(|m| list.matches(&m.url))
+                .cloned()
+                .collect(),
+            ..self.clone()
+        }
+    }
+
+    /// Put the pinned mirrors first, sorted by `by` if given, adding those
+    /// missing. The pinned mirrors of the list are moved, keeping their
+    /// measurements, and only the missing ones are taken from `pinned`.
+    /// Returns the URLs of the added mirrors, e.g. those that did not pass the
+    /// filters
+    pub fn pin_first(&mut self, pinned: Self, by: Option<&SortKey>) -> Vec<String> {
+        let urls = pinned
+            .mirrors
+            .iter()
+            .map(|m| m.url.clone())
+            .collect::<HashSet<_>>();
+        let (present, others): (Vec<_>, Vec<_>) = std::mem::take(&mut self.mirrors)
+            .into_iter()
+            .partition(|m| urls.contains(&m.url));
+        let mut present = present
+            .into_iter()
+            .map(|m| (m.url.clone(), m))
+            .collect::<HashMap<_, _>>();
+        let mut added = Vec::new();
+        let mut first = pinned
+            .mirrors
+            .into_iter()
+            .map(|m| {
+                present.remove(&m.url).unwrap_or_else(|| {
+                    added.push(m.url.clone());
+                    m
+                })
+            })
+            .collect::<Vec<_>>();
+        if let Some(by) = by {
+            cmp::sort_by_key(&mut first, |m| by.value(m));
+        }
+        first.extend(others);
+        self.mirrors = first;
+        self.sorted_by = None;
+        added
+    }
+
+    /// Compute the score of mirrors having none from the other fields of their status,
+    /// so that they are not ranked last. Returns the number of updated mirrors
+    pub fn fill_missing_scores(&mut self) -> usize {
+        let mut count = 0;
+        for m in self.mirrors.iter_mut().filter(|m| m.score.is_none()) {
+            m.score = m.local_score();
+            count += usize::from(m.score.is_some());
+        }
+        if count > 0 {
+            self.sorted_by = None;
+        }
+        count
+    }
+
+    /// Keep `number` mirrors randomly chosen among the first `pool` ones, to spread
+    /// the load among good mirrors.
+    ///
+    /// Mirrors are weighted by their download rate when sorted by rate, by the inverse
+    /// of their score when sorted by score, and by their rank otherwise. The chosen
+    /// mirrors keep their relative order. A `number` or a `pool` of 0 keeps the
+    /// list unchanged.
+    pub fn pick_random(&mut self, number: usize, pool: usize, by: &SortKey, rng: &mut impl Rng) {
+        if number == 0 || pool == 0 {
+            return;
+        }
+        let pool = pool.min(self.mirrors.len());
+        let mut weights = self.mirrors[..pool]
+            .iter()
+            .enumerate()
+            .map(|(rank, m)| {
+                let w = match by {
+                    SortKey::Rate => m.download_rate.as_ref().map_or(0.0, |r| r.0),
+                    SortKey::Score => m.score.map_or(0.0, |s| 1.0 / s.max(f64::EPSILON)),
+                    _ => 1.0 / (rank + 1) as f64,
+                };
+                if w.is_finite() && w > 0.0 {
+                    w
+                } else {
+                    // keep a chance for unmeasured mirrors
+                    f64::EPSILON
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut chosen = Vec::new();
+        for _ in 0..number.min(pool) {
+            let total = weights.iter().sum::<f64>();
+            let mut target = rng.random::<f64>() * total;
+            let idx = weights
+                .iter()
+                .position(|w| {
+                    target -= w;
+                    *w > 0.0 && target < 0.0
+                })
+                .unwrap_or_else(|| weights.iter().rposition(|w| *w > 0.0).unwrap_or(0));
+            weights[idx] = 0.0;
+            chosen.push(idx);
+        }
+        chosen.sort();
+        let mut idx = 0;
+        self.mirrors.retain(|_| {
+            idx += 1;
+            chosen.binary_search(&(idx - 1)).is_ok()
+        });
+    }
+
+    /// Put the mirrors in random order, e.g. so that machines refreshing at
+    /// the same time do not all test the same mirrors first
+    pub fn shuffle(&mut self, rng: &mut impl Rng) {
+        self.mirrors.shuffle(rng);
+        self.sorted_by = None;
+    }
+
+    /// URLs of the first `number` mirrors whose rate test (if `rate`) or
+    /// details retrieval (if `details`) failed or did not happen
+    pub fn measurement_failures(&self, number: usize, rate: bool, details: bool) -> Vec<&str> {
+        self.mirrors
+            .iter()
+            .take(number)
+            .filter(|m| {
+                let no_rate = m.download_rate.as_ref().is_none_or(|r| r.0.is_nan());
+                (rate && no_rate) || (details && m.detail.is_none())
+            })
+            .map(|m| m.url.as_str())
+            .collect()
+    }
+
+    /// Retrieve the details of every mirror. See [`details`]
+    pub async fn update_details(&mut self, timeout: Option<chrono::Duration>) -> Result<()> {
+        self.update_details_with(&Pipeline::with_session(timeout, None, &self.session)?)
+            .await
+    }
+
+    /// Retrieve the details of every mirror as a stage of the pipeline.
+    ///
+    /// The details retrieved before the deadline of the pipeline are kept, an
+    /// error being returned if some are missing.
+    #[cfg(feature = "details")]
+    pub async fn update_details_with(&mut self, pipeline: &Pipeline) -> Result<()> {
+        let mut pages = self
+            .mirrors
+            .iter()
+            .map(|m| m.details.clone())
+            .filter(|d| !d.is_empty())
+            .collect::<Vec<_>>();
+        pages.sort();
+        pages.dedup();
+        let (mut details, cancelled) = details::fetch_with(pipeline, pages).await;
+        for m in self.mirrors.iter_mut() {
+            if let Some(d) = details.remove(&m.url) {
+                m.detail = Some(d);
+            }
+        }
+        if cancelled > 0 {
+            anyhow::bail!("maximum duration reached, {cancelled} details pages not retrieved");
+        }
+        Ok(())
+    }
+
+    /// Always fails, details retrieval being disabled
+    #[cfg(not(feature = "details"))]
+    pub async fn update_details_with(&mut self, _pipeline: &Pipeline) -> Result<()> {
+        anyhow::bail!("reflecto was built without the details feature")
     }
 
     /// Filter out mirrors based on criteria:
@@ -234,324 +2023,2375 @@ impl MirrorList {
     /// ipv4: if true, return only ipv4 hosts
     /// ipv6: if true, return only ipv6 hosts
     pub fn filter(self, age: Option<f64>, isos: bool, ipv4: bool, ipv6: bool) -> Self {
-        let mut ml = self.mirrors;
-        if let Some(age) = age {
-            ml.retain(|m| match m.age() {
-                Some(d) => d.num_hours() as f64 + d.num_minutes() as f64 / 60.0 < age,
-                _ => false,
-            });
-        }
-        if isos {
-            ml.retain(|m| m.isos.unwrap_or(false))
-        }
-        if ipv4 {
-            ml.retain(|m| m.ipv4.unwrap_or(false))
-        }
-        if ipv6 {
-            ml.retain(|m| m.ipv6.unwrap_or(false))
-        }
+        self.filter_by(&Filters {
+            age,
+            isos,
+            ipv4,
+            ipv6,
+            ..Default::default()
+        })
+    }
 
+    /// Use the current time of `clock` as the reference for age computations
+    pub fn with_clock(self, clock: &dyn clock::Clock) -> Self {
         Self {
-            mirrors: ml,
+            now: Some(clock.now()),
             ..self
         }
     }
-}
 
-fn get_country_line(country: &str, code: &str, count: usize, country_len: usize) -> String {
-    debug_assert!(country_len >= country.chars().count());
-    let padding = " ".repeat(country_len - country.chars().count());
-    debug_assert!(code.len() == 2);
-    format!("{}{} {: >4} {: >4}", country, padding, code, count)
-}
+    /// Measure the mirrors with the settings of `session`, e.g. its
+    /// credentials
+    pub fn with_session(self, session: Session) -> Self {
+        Self { session, ..self }
+    }
 
-#[derive(Debug, Default, Clone, Deserialize)]
-struct Mirror {
-    /// url
-    url: String,
-    protocol: Protocol,
-    score: Option<f64>,
-    delay: Option<f64>,
-    country: Option<String>,
-    country_code: Option<String>,
+    /// Settings of the requests measuring the mirrors
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
 
-    #[serde(default)]
-    #[serde(with = "parse_date")]
-    last_sync: Option<DateTime<Utc>>,
+    /// Reference time for age computations
+    pub fn now(&self) -> DateTime<Utc> {
+        self.now.unwrap_or_else(Utc::now)
+    }
 
-    isos: Option<bool>,
-    ipv4: Option<bool>,
-    ipv6: Option<bool>,
-    /// detailed url
-    details: String,
+    /// Filter out mirrors not matching every criteria
+    pub fn filter_by(self, filters: &Filters) -> Self {
+        let now = self.now();
+        let mut ml = self.mirrors;
+        ml.retain(|m| filters.rejection(m, now).is_none());
+        Self {
+            mirrors: ml,
+            filters: Some(filters.clone()),
+            ..self
+        }
+    }
 
-    #[serde(skip)]
-    download_rate: Option<Bandwidth>,
+    /// URL of every mirror not matching the criteria, with the first criterion
+    /// it does not match
+    pub fn rejections(&self, filters: &Filters) -> Vec<(String, &'static str)> {
+        let now = self.now();
+        self.mirrors
+            .iter()
+            .filter_map(|m| filters.rejection(m, now).map(|r| (m.url.clone(), r)))
+            .collect()
+    }
 }
 
-/// home made implementation of serde deserializer for dates
-mod parse_date {
-    use chrono::DateTime;
-    use chrono::Utc;
-    use serde::{self, Deserialize, Deserializer};
+/// Criteria used to select mirrors
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Filters {
+    /// only keep mirrors synchronized in the last n hours
+    pub age: Option<f64>,
+    /// only keep ISOs hosts
+    pub isos: bool,
+    /// only keep IPv4 hosts
+    pub ipv4: bool,
+    /// only keep IPv6 hosts
+    pub ipv6: bool,
+    /// only keep mirrors located in one of these countries, given by code.
+    /// See [`country::normalize`]
+    pub countries: Vec<String>,
+    /// only keep mirrors whose percentage of successful checks is at least this value
+    pub completion: Option<f64>,
+    /// only keep mirrors of this tier or of a lower tier. Requires mirror details
+    pub tier: Option<u8>,
+}
 
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        if let Ok(s) = String::deserialize(deserializer) {
-            if let Ok(d) = DateTime::parse_from_rfc3339(&s) {
-                Ok(Some(d.into()))
-            } else {
-                todo!()
+impl Filters {
+    /// name of the first criterion the mirror does not match
+    fn rejection(&self, m: &Mirror, now: DateTime<Utc>) -> Option<&'static str> {
+        if let Some(age) = self.age {
+            if !m.age_hours(now).is_some_and(|h| h < age) {
+                return Some("age");
             }
-        } else {
+        }
+        if self.isos && !m.isos.unwrap_or(false) {
+            return Some("isos");
+        }
+        if self.ipv4 && !m.ipv4.unwrap_or(false) {
+            return Some("ipv4");
+        }
+        if self.ipv6 && !m.ipv6.unwrap_or(false) {
+            return Some("ipv6");
+        }
+        if !self.countries.is_empty()
+            && !m
+                .code()
+                .is_some_and(|c| self.countries.iter().any(|f| f.eq_ignore_ascii_case(&c)))
+        {
+            return Some("country");
+        }
+        if self
+            .tier
+            .is_some_and(|tier| m.tier().is_none_or(|t| t > tier))
+        {
+            return Some("tier");
+        }
+        if let Some(completion) = self.completion {
+            if !m.completion().is_some_and(|c| c * 100.0 >= completion) {
+                return Some("completion");
+            }
+        }
+        None
+    }
+
+    /// Human readable description of every active criterion
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if !self.countries.is_empty() {
+            lines.push(format!("country {}", self.countries.join(", ")));
+        }
+        if let Some(age) = self.age {
+            lines.push(format!("synchronized in the last {age} hours"));
+        }
+        for (active, flag) in [
+            (self.isos, "isos"),
+            (self.ipv4, "ipv4"),
+            (self.ipv6, "ipv6"),
+        ] {
+            if active {
+                lines.push(flag.into());
+            }
+        }
+        if let Some(completion) = self.completion {
+            lines.push(format!("completion >= {completion}%"));
+        }
+        if let Some(tier) = self.tier {
+            lines.push(format!("tier <= {tier}"));
+        }
+        lines
+    }
+}
+
+/// Information added as a comment after each server line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Annotation {
+    /// Mirror tier
+    Tier,
+    /// Local note, from the overrides
+    Note,
+    /// Whether the rate test was answered from a cache, inflating the rate
+    Cached,
+    /// Hours since the last synchronization
+    Age,
+    /// CDN or backend the mirror is known to be an alias of
+    Backend,
+}
+
+impl Annotation {
+    fn template(&self) -> &'static str {
+        match self {
+            Annotation::Tier => "tier {tier}",
+            Annotation::Note => "{note}",
+            Annotation::Cached => "{cached}",
+            Annotation::Age => "{age}",
+            Annotation::Backend => "backend {backend}",
+        }
+    }
+
+    /// Add annotations to a server line template
+    pub fn apply(template: &str, annotations: &[Annotation]) -> String {
+        if annotations.is_empty() {
+            return template.into();
+        }
+        let comments = annotations
+            .iter()
+            .map(|a| a.template())
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("{template} # {comments}")
+    }
+}
+
+/// Whether a response lets the client reuse the connection
+fn keeps_alive(version: reqwest::Version, headers: &reqwest::header::HeaderMap) -> bool {
+    let connection = headers
+        .get(reqwest::header::CONNECTION)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    if version <= reqwest::Version::HTTP_10 {
+        connection.contains("keep-alive")
+    } else {
+        !connection.contains("close")
+    }
+}
+
+/// Whether response headers show the response was served from a cache
+fn looks_cached(headers: &reqwest::header::HeaderMap) -> bool {
+    let value = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_ascii_uppercase()
+    };
+    let age = value("age").trim().parse::<u64>().unwrap_or(0);
+    age > 0
+        || [
+            "x-cache",
+            "x-cache-status",
+            "cf-cache-status",
+            "x-proxy-cache",
+        ]
+        .iter()
+        .any(|name| value(name).contains("HIT"))
+}
+
+/// Number of mirrors by (country, code), mirrors without country being ignored
+fn count_countries<'a>(
+    mirrors: impl IntoIterator<Item = &'a Mirror>,
+) -> HashMap<(String, String), usize> {
+    let mut countries = HashMap::new();
+    for mirror in mirrors {
+        let (Some(country), Some(code)) = (&mirror.country, mirror.code()) else {
+            continue;
+        };
+        *countries.entry((country.clone(), code)).or_insert(0) += 1;
+    }
+    countries
+}
+
+fn get_country_line(country: &str, code: &str, count: usize, country_len: usize) -> String {
+    debug_assert!(country_len >= country.width());
+    let padding = " ".repeat(country_len - country.width());
+    debug_assert!(code.len() == 2);
+    format!("{}{} {: >4} {: >4}", country, padding, code, count)
+}
+
+/// A mirror URL and what is known about it
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Mirror {
+    /// url
+    url: String,
+    protocol: Protocol,
+    score: Option<f64>,
+    delay: Option<f64>,
+    country: Option<String>,
+    country_code: Option<String>,
+
+    #[serde(default)]
+    #[serde(with = "parse_date")]
+    last_sync: Option<DateTime<Utc>>,
+
+    isos: Option<bool>,
+    ipv4: Option<bool>,
+    ipv6: Option<bool>,
+    completion_pct: Option<f64>,
+    duration_avg: Option<f64>,
+    duration_stddev: Option<f64>,
+
+    /// detailed url
+    details: String,
+
+    #[serde(skip)]
+    download_rate: Option<Bandwidth>,
+
+    /// content of the details page, if retrieved
+    #[serde(skip)]
+    detail: Option<details::Details>,
+
+    /// local note. See [`overrides`]
+    #[serde(skip)]
+    note: Option<String>,
+
+    /// whether the test file looked served from a cache during the rate test
+    #[serde(skip)]
+    cached: Option<bool>,
+
+    /// response to the rate test
+    #[serde(skip)]
+    response: Option<ResponseInfo>,
+
+    /// whether the connection was kept open between two requests
+    #[serde(skip)]
+    keep_alive: Option<bool>,
+
+    /// time, in seconds, to fetch the metadata files one after the other
+    #[serde(skip)]
+    metadata_time: Option<f64>,
+
+    /// time, in seconds, to get the headers of the test file
+    #[serde(skip)]
+    latency: Option<f64>,
+
+    /// resolved addresses of the host, sorted
+    #[serde(skip)]
+    addresses: Option<Vec<std::net::IpAddr>>,
+
+    /// whether the addresses differ from the ones of the previous run
+    #[serde(skip)]
+    dns_changed: Option<bool>,
+
+    /// whether the checksums and signature of the installation image are
+    /// published
+    #[serde(skip)]
+    iso_files: Option<bool>,
+
+    /// IP versions over which a connection succeeded
+    #[serde(skip)]
+    reachable: Option<dns::Reachability>,
+
+    /// what is wrong with the URL, if normalization could not fix it
+    #[serde(skip)]
+    url_issue: Option<&'static str>,
+
+    /// protocol of the status, when it did not match the scheme of the URL and
+    /// was replaced by it
+    #[serde(skip)]
+    advertised_protocol: Option<Protocol>,
+
+    /// CDN or backend the mirror is a known alias of
+    #[serde(skip)]
+    backend: Option<String>,
+}
+
+/// How a mirrorlist is rendered. See [`MirrorList::render_header`] and
+/// [`MirrorList::render_servers`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderOptions {
+    /// number of mirrors written
+    pub limit: Number,
+    /// template of server lines, the one of the distribution if not set. See
+    /// [`MirrorList::to_file_content_with_template`]
+    pub template: Option<String>,
+    /// information added as a comment after each server line
+    pub annotations: Vec<Annotation>,
+    /// number of mirrors following the written ones whose server lines are
+    /// written commented out, as fallbacks to enable by hand
+    pub commented_extras: usize,
+    /// lines of the header, in order
+    pub header: Vec<HeaderField>,
+    /// server lines grouped under a comment naming their country
+    pub group_by_country: bool,
+}
+
+impl RenderOptions {
+    /// Options writing `limit` mirrors with the default header
+    pub fn new(limit: Number) -> Self {
+        Self {
+            limit,
+            template: None,
+            annotations: Vec::new(),
+            commented_extras: 0,
+            header: HeaderField::DEFAULT.to_vec(),
+            group_by_country: false,
+        }
+    }
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self::new(Number::All)
+    }
+}
+
+/// Line of the header of a mirrorlist
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HeaderField {
+    /// Name of the distribution and of the generator
+    Title,
+    /// When the mirrorlist was generated
+    Date,
+    /// Where the mirror status was retrieved
+    Source,
+    /// Filters of the mirrors
+    Filters,
+    /// Warnings about the kept mirrors, e.g. all in one country
+    Warnings,
+}
+
+impl HeaderField {
+    /// Fields of the header by default
+    pub const DEFAULT: [HeaderField; 4] = [
+        HeaderField::Title,
+        HeaderField::Source,
+        HeaderField::Filters,
+        HeaderField::Warnings,
+    ];
+}
+
+impl fmt::Display for HeaderField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderField::Title => write!(f, "title"),
+            HeaderField::Date => write!(f, "date"),
+            HeaderField::Source => write!(f, "source"),
+            HeaderField::Filters => write!(f, "filters"),
+            HeaderField::Warnings => write!(f, "warnings"),
+        }
+    }
+}
+
+/// Consecutive mirrors of a list. See [`MirrorList::page`]
+#[derive(Debug, Clone, Copy)]
+pub struct Page<'a> {
+    pub mirrors: &'a [Mirror],
+    /// position of the first mirror of the page in the list
+    pub offset: usize,
+    /// number of mirrors of the list
+    pub total: usize,
+}
+
+impl Page<'_> {
+    /// Offset of the next page, if any. An empty page, e.g. of length 0, has
+    /// no next page
+    pub fn next_offset(&self) -> Option<usize> {
+        let end = self.offset + self.mirrors.len();
+        (!self.mirrors.is_empty() && end < self.total).then_some(end)
+    }
+}
+
+/// Outcome of the download of a test file
+struct RateTest {
+    /// `None` when the download was cut by the budget
+    rate: Option<Bandwidth>,
+    /// whether the file seems to be served from a cache
+    cached: bool,
+    response: ResponseInfo,
+}
+
+/// Number of rate tests done at once by the adaptive mode. From the second
+/// round on, a mirror of the previous round is tested again with the new ones,
+/// so that its rate is compared with its own earlier rate: the level doubles
+/// while it holds, and halves when it collapses
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Concurrency {
+    level: usize,
+    /// index of the mirror tested again, and its rate at the previous level
+    reference: Option<(usize, f64)>,
+}
+
+impl Default for Concurrency {
+    fn default() -> Self {
+        Self {
+            level: 2,
+            reference: None,
+        }
+    }
+}
+
+impl Concurrency {
+    /// the level keeps doubling while the reference keeps this fraction of
+    /// its rate
+    const SCALING: f64 = 0.8;
+    /// transfers collapse when the reference drops below this fraction of its
+    /// rate
+    const COLLAPSE: f64 = 0.5;
+
+    /// Number of new mirrors of the next round, next to the reference
+    fn new_tests(&self) -> usize {
+        let reference = usize::from(self.reference.is_some());
+        self.level.saturating_sub(reference).max(1)
+    }
+
+    /// Adapt the level to the rate of the reference in a round, `None` if its
+    /// test failed. Returns whether the transfers collapsed
+    fn update(&mut self, rate: Option<f64>) -> bool {
+        let Some((_, earlier)) = self.reference else {
+            self.level = (self.level * 2).min(MAX_ADAPTIVE_TESTS);
+            return false;
+        };
+        let Some(rate) = rate else {
+            return false;
+        };
+        if rate < earlier * Self::COLLAPSE && self.level > 1 {
+            self.level /= 2;
+            return true;
+        }
+        if rate >= earlier * Self::SCALING {
+            self.level = (self.level * 2).min(MAX_ADAPTIVE_TESTS);
+        }
+        false
+    }
+
+    /// Test again the fastest mirror of a round that did not collapse
+    fn follow(&mut self, rates: impl Iterator<Item = (usize, f64)>) {
+        if let Some(fastest) = rates.max_by(|a, b| a.1.total_cmp(&b.1)) {
+            self.reference = Some(fastest);
+        }
+    }
+}
+
+/// Download a file to measure the download rate, stopping once the budget is
+/// exhausted.
+///
+/// Under a throttle, the timeout is extended by the time the throttle takes
+/// to let the file through, so that slow transfers are not failures.
+async fn rate_test(
+    url: &str,
+    timeout: Option<chrono::Duration>,
+    budget: &ByteBudget,
+    session: &Session,
+) -> Result<RateTest> {
+    let mut span = session.trace().span("rate test", url);
+    // transparent decompression is disabled, so that the measured rate
+    // is based on the bytes actually transferred
+    let mut builder = session.client_builder().no_gzip().no_brotli();
+    let timeout = timeout.map(|d| d.to_std()).transpose()?;
+    let throttled = budget.throttle();
+    if let (Some(d), None) = (timeout, throttled) {
+        builder = builder.timeout(d);
+    }
+    let client = session.wrap(builder.build()?);
+    if budget.exhausted() {
+        anyhow::bail!("download budget exhausted");
+    }
+    let now = Utc::now();
+    // a unique query and no-cache ask caches in front of the mirror to
+    // forward the request
+    let url = format!(
+        "{url}{}nocache={}",
+        if url.contains('?') { '&' } else { '?' },
+        now.timestamp_nanos_opt().unwrap_or_default()
+    );
+    let started = tokio::time::Instant::now();
+    let request = client
+        .get(&url)
+        .header(reqwest::header::CACHE_CONTROL, "no-cache")
+        .header(reqwest::header::PRAGMA, "no-cache")
+        .send();
+    let mut response = match (timeout, throttled) {
+        (Some(d), Some(_)) => tokio::time::timeout(d, request)
+            .await
+            .map_err(|_| anyhow::anyhow!("rate test timed out"))??,
+        _ => request.await?,
+    };
+    if !response.status().is_success() {
+        anyhow::bail!("HTTP status {}", response.status().as_u16());
+    }
+    let deadline = match (timeout, throttled) {
+        (Some(d), Some(rate)) => {
+            let length = response.content_length().unwrap_or_default() as f64;
+            Some(started + d + std::time::Duration::from_secs_f64(length / rate.max(1) as f64))
+        }
+        _ => None,
+    };
+    let info = ResponseInfo::new(&url, &response);
+    let cached = looks_cached(response.headers());
+    let mut received = 0;
+    let mut complete = true;
+    let mut throttle = throttled.map(Throttle::new);
+    loop {
+        let chunk = match deadline {
+            Some(d) => tokio::time::timeout_at(d, response.chunk())
+                .await
+                .map_err(|_| anyhow::anyhow!("rate test timed out"))?,
+            None => response.chunk().await,
+        };
+        match chunk {
+            Ok(Some(chunk)) => {
+                received += chunk.len();
+                if !budget.consume(chunk.len() as u64) {
+                    debug!("download budget exhausted after {received} bytes");
+                    complete = false;
+                    break;
+                }
+                if let Some(throttle) = &mut throttle {
+                    throttle.consume(chunk.len()).await;
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                // TODO: get the first bytes received before the timeout
+                debug!("{:?}", &e);
+                return Err(e.into());
+            }
+        }
+    }
+    let end = Utc::now();
+    span.arg("bytes", received);
+    Ok(RateTest {
+        rate: complete.then(|| Bandwidth::from_duration(end - now, received)),
+        cached,
+        response: info,
+    })
+}
+
+/// Metadata of the response to a rate test
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResponseInfo {
+    pub status: u16,
+    /// URL after redirects
+    pub final_url: String,
+    /// `Server` header
+    pub server: Option<String>,
+    /// HTTP version, e.g. HTTP/1.1. The TLS version is not exposed by the HTTP client
+    pub version: String,
+    /// whether a redirect led to another host
+    pub cross_host_redirect: bool,
+}
+
+impl ResponseInfo {
+    fn new(requested: &str, response: &reqwest::Response) -> Self {
+        let host = |url: &str| reqwest::Url::parse(url).ok()?.host_str().map(String::from);
+        Self {
+            status: response.status().as_u16(),
+            final_url: response.url().to_string(),
+            server: response
+                .headers()
+                .get(reqwest::header::SERVER)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from),
+            version: format!("{:?}", response.version()),
+            cross_host_redirect: host(requested) != response.url().host_str().map(String::from),
+        }
+    }
+}
+
+/// home made implementation of serde deserializer for dates
+mod parse_date {
+    use chrono::DateTime;
+    use chrono::Utc;
+    use serde::{self, de::Error, Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if let Ok(s) = String::deserialize(deserializer) {
+            match DateTime::parse_from_rfc3339(&s) {
+                Ok(d) => Ok(Some(d.into())),
+                Err(e) => Err(D::Error::custom(format!("invalid date {s}: {e}"))),
+            }
+        } else {
             Ok(None)
         }
     }
-}
+}
+
+impl Mirror {
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Mirror for which nothing but the URL is known
+    fn from_url(url: &str) -> Self {
+        Self {
+            url: url.into(),
+            protocol: Protocol::from_url(url),
+            ..Default::default()
+        }
+    }
+
+    fn describe(&self, now: DateTime<Utc>) -> String {
+        fn opt<T: fmt::Display>(v: Option<T>) -> String {
+            v.map_or_else(|| "unknown".into(), |v| v.to_string())
+        }
+        let mut lines = vec![
+            format!("url: {}", self.url),
+            format!("protocol: {}", self.protocol),
+            format!(
+                "country: {} ({})",
+                opt(self.country.as_deref().filter(|c| !c.is_empty())),
+                opt(self.code())
+            ),
+            format!("last sync: {}", opt(self.last_sync)),
+            format!(
+                "age: {}",
+                opt(self
+                    .age(now)
+                    .map(|d| format!("{:.1} hours", d.num_minutes() as f64 / 60.0)))
+            ),
+            format!("score: {}", opt(self.score)),
+            format!("delay: {}", opt(self.delay.map(|d| format!("{d} s")))),
+            format!("completion: {}", opt(self.completion())),
+            format!(
+                "duration: {}",
+                opt(self.duration().map(|d| format!("{d:.3} s")))
+            ),
+            format!("isos: {}", opt(self.isos)),
+            format!("ipv4: {}", opt(self.ipv4)),
+            format!("ipv6: {}", opt(self.ipv6)),
+            format!("tier: {}", opt(self.tier())),
+        ];
+        if !self.details.is_empty() {
+            lines.push(format!("details: {}", self.details));
+        }
+        if let Some(rate) = &self.download_rate {
+            lines.push(format!("download rate: {:.3} MB/s", rate.0));
+        }
+        if let Some(note) = &self.note {
+            lines.push(format!("note: {note}"));
+        }
+        if let Some(detail) = &self.detail {
+            lines.push("checks:".into());
+            for c in &detail.logs {
+                lines.push(format!(
+                    "  {} {} last sync {} duration {}",
+                    opt(c.check_time),
+                    if c.is_success { "ok" } else { "failed" },
+                    opt(c.last_sync),
+                    opt(c.duration.map(|d| format!("{d:.3} s")))
+                ));
+            }
+        }
+        lines.join("\n")
+    }
+
+    fn apply_override(&mut self, o: &overrides::Override) {
+        let o = o.clone();
+        self.country = o.country.or(self.country.take());
+        self.country_code = o.country_code.or(self.country_code.take());
+        self.score = o.score.or(self.score);
+        self.isos = o.isos.or(self.isos);
+        self.ipv4 = o.ipv4.or(self.ipv4);
+        self.ipv6 = o.ipv6.or(self.ipv6);
+        self.note = o.note.or(self.note.take());
+    }
+
+    /// Fill a server line template, ages computed at `now`
+    fn render(&self, template: &str, now: DateTime<Utc>) -> String {
+        let age_hours = self.age_hours(now).map(|h| format!("{h:.1}"));
+        template
+            .replace("{url}", &self.url)
+            .replace("{country}", self.country.as_deref().unwrap_or_default())
+            .replace(
+                "{country_code}",
+                self.country_code.as_deref().unwrap_or_default(),
+            )
+            .replace("{protocol}", &self.protocol.to_string())
+            .replace(
+                "{tier}",
+                &self.tier().map(|t| t.to_string()).unwrap_or_default(),
+            )
+            .replace("{note}", self.note.as_deref().unwrap_or_default())
+            .replace("{backend}", self.backend.as_deref().unwrap_or_default())
+            .replace(
+                "{cached}",
+                match self.cached {
+                    Some(true) => "cached",
+                    Some(false) => "not cached",
+                    None => "",
+                },
+            )
+            .replace("{age_hours}", age_hours.as_deref().unwrap_or_default())
+            .replace(
+                "{age}",
+                &match &age_hours {
+                    Some(h) => format!("age {h}h"),
+                    None => "age unknown".into(),
+                },
+            )
+    }
+
+    /// Country code, guessed from the country name if not provided
+    fn code(&self) -> Option<String> {
+        match &self.country_code {
+            Some(code) if !code.is_empty() => Some(code.clone()),
+            _ => self
+                .country
+                .as_deref()
+                .and_then(country::lookup)
+                .map(|c| c.code.to_string()),
+        }
+    }
+
+    /// Host name of the URL
+    fn host(&self) -> Option<String> {
+        reqwest::Url::parse(&self.url)
+            .ok()?
+            .host_str()
+            .map(String::from)
+    }
+
+    /// Trim the URL and end its path with a slash, so that server lines are
+    /// well-formed, recording what cannot be fixed: whitespace in the URL, a
+    /// malformed URL or an unsupported scheme. A protocol not matching the
+    /// scheme is replaced by the one of the scheme
+    fn normalize_url(&mut self) {
+        let trimmed = self.url.trim();
+        if trimmed.len() != self.url.len() {
+            self.url = trimmed.into();
+        }
+        let parsed = reqwest::Url::parse(&self.url);
+        self.url_issue = match &parsed {
+            _ if self.url.contains(char::is_whitespace) => Some("whitespace in url"),
+            Ok(u) if u.host_str().is_some_and(|h| !h.is_empty()) => {
+                if !matches!(u.scheme(), "http" | "https" | "ftp" | "rsync") {
+                    Some("unsupported scheme")
+                } else {
+                    let scheme = Protocol::from_url(&self.url);
+                    if scheme != self.protocol {
+                        self.advertised_protocol = Some(self.protocol);
+                        self.protocol = scheme;
+                    }
+                    None
+                }
+            }
+            _ => Some("malformed url"),
+        };
+        if let Ok(u) = parsed {
+            // the parser gives the path / to URLs without path
+            if !self.url.ends_with('/') && u.query().is_none() && u.fragment().is_none() {
+                self.url.push('/');
+            }
+        }
+    }
+
+    /// Protocol given by the status, even if replaced by the scheme of the URL
+    fn advertised_protocol(&self) -> Protocol {
+        self.advertised_protocol.unwrap_or(self.protocol)
+    }
+
+    /// Protocol of the status replaced by the scheme of the URL, if it was
+    fn protocol_mismatch(&self) -> Option<String> {
+        self.advertised_protocol.map(|p| {
+            format!(
+                "protocol {p} in the status, {} used from the URL",
+                self.protocol
+            )
+        })
+    }
+
+    /// Whether the rate test was redirected to another host
+    fn redirects(&self) -> bool {
+        self.response
+            .as_ref()
+            .is_some_and(|r| r.cross_host_redirect)
+    }
+
+    /// Mirror tier, from details
+    fn tier(&self) -> Option<u8> {
+        self.detail.as_ref().and_then(|d| d.tier)
+    }
+
+    /// Update download rate.
+    ///
+    /// The download stops early once the budget is exhausted, the mirror
+    /// being left unmeasured.
+    async fn update_dl_rate(
+        &mut self,
+        timeout: Option<chrono::Duration>,
+        distro: Distro,
+        budget: &ByteBudget,
+        session: &Session,
+    ) -> Result<()> {
+        let span = span!(Level::DEBUG, "update download rate", url = self.url.clone());
+        let _guard = span.enter();
+        let url = format!("{}/{}", self.url.trim_end_matches('/'), distro.test_path());
+        let test = rate_test(&url, timeout, budget, session).await?;
+        if test.response.cross_host_redirect {
+            warn!("{} redirects to {}", self.url, test.response.final_url);
+        }
+        if test.cached {
+            debug!("test file of {} served from a cache", self.url);
+        }
+        self.download_rate = test.rate;
+        self.cached = Some(test.cached);
+        self.response = Some(test.response);
+        info!("donwload rate updated for url {}", self.url.clone());
+        Ok(())
+    }
+
+    /// Copy the results of a rate test done on another instance of the mirror
+    fn copy_rate_test(&mut self, from: Mirror) {
+        self.download_rate = from.download_rate;
+        self.cached = from.cached;
+        self.response = from.response;
+    }
+
+    /// Update download rate. Function that can be used by MirrorList
+    async fn update_download_rate(
+        mut self,
+        timeout: Option<chrono::Duration>,
+        distro: Distro,
+        budget: ByteBudget,
+        session: Session,
+    ) -> Result<Self> {
+        self.update_dl_rate(timeout, distro, &budget, &session)
+            .await?;
+        Ok(self)
+    }
+
+    /// Score computed as archweb does, from the delay, the check durations and
+    /// the completion
+    fn local_score(&self) -> Option<f64> {
+        let completion = self.completion_pct.filter(|c| *c > 0.0)?;
+        let hours = self.delay? / 3600.0;
+        let duration = self.duration_avg? + self.duration_stddev.unwrap_or(0.0);
+        Some((hours + duration) / completion)
+    }
+
+    /// Average check duration, from details if available
+    fn duration(&self) -> Option<f64> {
+        self.detail
+            .as_ref()
+            .and_then(|d| d.average_duration())
+            .or(self.duration_avg)
+    }
+
+    /// Fraction of successful checks, from details if available
+    fn completion(&self) -> Option<f64> {
+        self.detail
+            .as_ref()
+            .and_then(|d| d.success_rate())
+            .or(self.completion_pct)
+    }
+
+    /// Compute mirror age based on last server synchronisation
+    fn age(&self, now: DateTime<Utc>) -> Option<chrono::Duration> {
+        self.last_sync.map(|last_sync| now - last_sync)
+    }
+
+    /// Hours since the last synchronization at `now`
+    fn age_hours(&self, now: DateTime<Utc>) -> Option<f64> {
+        self.age(now).map(|d| d.num_seconds() as f64 / 3600.0)
+    }
+}
+
+/// Protocol of a mirror URL
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Ftp,
+    #[default]
+    Https,
+    Http,
+    Rsync,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::Ftp => write!(f, "ftp"),
+            Protocol::Https => write!(f, "https"),
+            Protocol::Http => write!(f, "http"),
+            Protocol::Rsync => write!(f, "rsync"),
+        }
+    }
+}
+
+impl Protocol {
+    /// Guess protocol from the URL scheme
+    fn from_url(url: &str) -> Self {
+        match url.split_once("://").map(|(scheme, _)| scheme) {
+            Some("ftp") => Protocol::Ftp,
+            Some("http") => Protocol::Http,
+            Some("rsync") => Protocol::Rsync,
+            _ => Protocol::Https,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+    use itertools::Itertools;
+    use proptest::prelude::*;
+
+    static MIRROR0: &str = r#"
+             {
+                 "url": "https://mirrors.rutgers.edu/archlinux/",
+                 "protocol": "https",
+                 "last_sync": null,
+                 "completion_pct": 0.0,
+                 "delay": null,
+                 "duration_avg": null,
+                 "duration_stddev": null,
+                 "score": null,
+                 "active": true,
+                 "country": "United States",
+                 "country_code": "US",
+                 "isos": true,
+                 "ipv4": true,
+                 "ipv6": false,
+                 "details": "https://archlinux.org/mirrors/rutgers.edu/910/"
+             }"#;
+    static MIRROR1: &str = r#"
+             {
+                 "url": "http://ftp.ntua.gr/pub/linux/archlinux/",
+                 "protocol": "http",
+                 "last_sync": "2024-05-01T14:25:08Z",
+                 "completion_pct": 1.0,
+                 "delay": 6354,
+                 "duration_avg": 0.4358575581256008,
+                 "duration_stddev": 0.6512862688716142,
+                 "score": 2.852143826997215,
+                 "active": true,
+                 "country": "Greece",
+                 "country_code": "GR",
+                 "isos": true,
+                 "ipv4": true,
+                 "ipv6": true,
+                 "details": "https://archlinux.org/mirrors/ntua.gr/333/"
+             }"#;
+    static MIRROR2: &str = r#"
+            {
+                "url": "https://mirror.aarnet.edu.au/pub/archlinux/",
+                "protocol": "https",
+                "last_sync": "2024-04-01T08:22:54Z",
+                "completion_pct": 1.0,
+                "delay": 1863,
+                "duration_avg": 1.1129106909958357,
+                "duration_stddev": 0.23354254068513589,
+                "score": 1.8639532316809715,
+                "active": true,
+                "country": "Australia",
+                "country_code": "AU",
+                "isos": true,
+                "ipv4": true,
+                "ipv6": true,
+                "details": "https://archlinux.org/mirrors/aarnet.edu.au/5/"
+            }"#;
+    static MIRROR3: &str = r#"
+            {
+                "url": "http://mirror.rackspace.com/archlinux/",
+                "protocol": "http",
+                "last_sync": "2024-05-04T09:30:12Z",
+                "completion_pct": 0.8645833333333334,
+                "delay": 12205,
+                "duration_avg": 0.3613546647523579,
+                "duration_stddev": 0.42918278405415544,
+                "score": 4.83564170785653,
+                "active": true,
+                "country": "",
+                "country_code": "",
+                "isos": true,
+                "ipv4": true,
+                "ipv6": false,
+                "details": "https://archlinux.org/mirrors/rackspace.com/712/"
+            }"#;
+
+    #[test]
+    fn mirror0() {
+        let _: Mirror = serde_json::from_str(MIRROR0).unwrap();
+    }
+
+    #[test]
+    fn mirror1() {
+        let _: Mirror = serde_json::from_str(MIRROR1).unwrap();
+    }
+
+    #[test]
+    fn sort_delay() {
+        let j = format!("{{\"urls\":[{MIRROR0},{MIRROR1},{MIRROR2}]}}");
+        let mut ml: MirrorList = serde_json::from_str(&j).unwrap();
+        ml.sort(SortKey::Delay);
+        assert_eq!(
+            ml.mirrors[0].url,
+            "https://mirror.aarnet.edu.au/pub/archlinux/"
+        );
+        assert_eq!(ml.mirrors[2].url, "https://mirrors.rutgers.edu/archlinux/");
+    }
+
+    #[test]
+    fn sort_age() {
+        let j = format!("{{\"urls\":[{MIRROR0},{MIRROR1},{MIRROR2}]}}");
+        let mut ml: MirrorList = serde_json::from_str(&j).unwrap();
+        ml.sort(SortKey::Age);
+        // null
+        assert_eq!(ml.mirrors[0].url, "https://mirrors.rutgers.edu/archlinux/");
+
+        // 2024-04
+        assert_eq!(
+            ml.mirrors[1].url,
+            "https://mirror.aarnet.edu.au/pub/archlinux/"
+        );
+
+        // 2024-05
+        assert_eq!(ml.mirrors[2].url, "http://ftp.ntua.gr/pub/linux/archlinux/");
+    }
+
+    #[tokio::test]
+    async fn update_duration() {
+        let m: Mirror = serde_json::from_str(MIRROR3).unwrap();
+        let m = m
+            .update_download_rate(
+                None,
+                Distro::Arch,
+                ByteBudget::default(),
+                Session::default(),
+            )
+            .await
+            .unwrap();
+        assert!(m.download_rate.is_some());
+    }
+
+    #[tokio::test]
+    async fn update_duration_large_timeout() {
+        let m: Mirror = serde_json::from_str(MIRROR3).unwrap();
+        let m = m
+            .update_download_rate(
+                chrono::Duration::new(20, 0),
+                Distro::Arch,
+                ByteBudget::default(),
+                Session::default(),
+            )
+            .await
+            .unwrap();
+        assert!(m.download_rate.is_some());
+    }
+
+    #[tokio::test]
+    async fn update_duration_small_timeout() {
+        let m: Mirror = serde_json::from_str(MIRROR3).unwrap();
+        let r = m
+            .clone()
+            .update_download_rate(
+                chrono::Duration::new(0, 1),
+                Distro::Arch,
+                ByteBudget::default(),
+                Session::default(),
+            )
+            .await;
+        assert!(r.is_err());
+    }
+
+    #[tokio::test]
+    async fn update_duration_interrupt() {
+        let m: Mirror = serde_json::from_str(MIRROR3).unwrap();
+        let mut s = JoinSet::new();
+        s.spawn(m.update_download_rate(
+            None,
+            Distro::Arch,
+            ByteBudget::default(),
+            Session::default(),
+        ));
+        s.abort_all();
+    }
+
+    #[tokio::test]
+    async fn update_mirrorlist_dl_rate() {
+        let mut mlist = MirrorList::from_default_url().await.unwrap();
+        mlist.mirrors.truncate(15);
+        let mlentgth = mlist.mirrors.len();
+        mlist.update_download_rate(None, 3).await;
+        mlist.sort(SortKey::Rate);
+        let mirrors = &mlist.mirrors.clone();
+        assert!(!&mirrors.is_empty());
+        assert!(
+            mirrors[0].download_rate.clone().unwrap() >= mirrors[1].download_rate.clone().unwrap()
+        );
+        assert_eq!(mlentgth, mlist.mirrors.len());
+    }
+
+    #[test]
+    fn merge() {
+        let j = format!("{{\"urls\":[{MIRROR0},{MIRROR1}]}}");
+        let mut ml: MirrorList = serde_json::from_str(&j).unwrap();
+        ml.source = Some("first".into());
+        let j = format!("{{\"urls\":[{MIRROR1},{MIRROR2},{MIRROR2}]}}");
+        let mut other: MirrorList = serde_json::from_str(&j).unwrap();
+        other.source = Some("second".into());
+        ml.phases.fetch = 1.0;
+        other.phases.fetch = 2.0;
+        ml.merge(other);
+        assert_eq!(ml.len(), 3);
+        assert_eq!(ml.phases.fetch, 2.0);
+        assert_eq!(
+            ml.mirrors[2].url,
+            "https://mirror.aarnet.edu.au/pub/archlinux/"
+        );
+        assert_eq!(ml.source.as_deref(), Some("first, second"));
+    }
+
+    #[test]
+    fn header_and_servers() {
+        let j = format!("{{\"urls\":[{MIRROR1},{MIRROR2}]}}");
+        let mut ml: MirrorList = serde_json::from_str(&j).unwrap();
+        ml.source = Some("status".into());
+        let options = RenderOptions {
+            limit: Number::Limit(1),
+            ..Default::default()
+        };
+        let header = ml.render_header(&options);
+        let servers = ml.render_servers(&options);
+        assert!(header.lines().all(|l| l.starts_with('#')));
+        assert!(header.ends_with("# from: \tstatus"));
+        assert_eq!(
+            servers,
+            "Server = http://ftp.ntua.gr/pub/linux/archlinux/$repo/os/$arch"
+        );
+        assert_eq!(
+            ml.to_file_content(&options),
+            format!("{header}\n\n{servers}")
+        );
+        let all = ml.render_servers(&RenderOptions {
+            template: Some("{url}".into()),
+            ..Default::default()
+        });
+        assert_eq!(all.lines().count(), 2);
+    }
+
+    #[test]
+    fn render_options() {
+        let content = "https://a.example.org/\nhttps://b.example.org/\nhttps://c.example.org/";
+        let mut ml = MirrorList::from_mirrorlist(content, Distro::Arch);
+        ml.source = Some("status".into());
+        let options = RenderOptions {
+            template: Some("{url}".into()),
+            annotations: vec![Annotation::Age],
+            commented_extras: 1,
+            header: vec![HeaderField::Source, HeaderField::Date],
+            ..RenderOptions::new(Number::Limit(1))
+        };
+        let lines = ml.to_file_content(&options);
+        let lines = lines.lines().collect_vec();
+        assert_eq!(lines[0], "# from: \tstatus");
+        assert!(lines[1].starts_with("# generated: \t"));
+        assert_eq!(
+            lines[3..],
+            [
+                "https://a.example.org/ # age unknown",
+                "#https://b.example.org/ # age unknown"
+            ]
+        );
+        let bare = RenderOptions {
+            header: Vec::new(),
+            ..RenderOptions::new(Number::Limit(1))
+        };
+        assert_eq!(
+            ml.to_file_content(&bare),
+            "Server = https://a.example.org/$repo/os/$arch"
+        );
+    }
+
+    #[test]
+    fn url_normalization() {
+        let issue = |url: &str, protocol: Protocol| {
+            let mut m = Mirror {
+                protocol,
+                ..Mirror::from_url(url)
+            };
+            m.normalize_url();
+            (m.url, m.url_issue)
+        };
+        assert_eq!(
+            issue(" https://a.example.org/arch\t", Protocol::Https),
+            ("https://a.example.org/arch/".into(), None)
+        );
+        assert_eq!(
+            issue("https://a.example.org", Protocol::Https),
+            ("https://a.example.org/".into(), None)
+        );
+        assert_eq!(
+            issue("https://a.example.org/?a=1", Protocol::Https),
+            ("https://a.example.org/?a=1".into(), None)
+        );
+        assert_eq!(
+            issue("https://a.example.org/my arch/", Protocol::Https).1,
+            Some("whitespace in url")
+        );
+        assert_eq!(
+            issue("a.example.org/", Protocol::Https).1,
+            Some("malformed url")
+        );
+        assert_eq!(
+            issue("gopher://a.example.org/", Protocol::Https).1,
+            Some("unsupported scheme")
+        );
+        let mut m = Mirror {
+            protocol: Protocol::Http,
+            ..Mirror::from_url("https://a.example.org/")
+        };
+        m.normalize_url();
+        assert_eq!(m.url_issue, None);
+        assert_eq!(
+            (m.protocol, m.advertised_protocol),
+            (Protocol::Https, Some(Protocol::Http))
+        );
+    }
+
+    #[test]
+    fn group_by_country() {
+        let content = "https://de1.example.org/\nhttps://fr.example.org/\n\
+                       https://de2.example.org/\nhttps://any.example.org/\n\
+                       https://extra.example.org/";
+        let mut ml = MirrorList::from_mirrorlist(content, Distro::Arch);
+        for (m, country) in ml.mirrors.iter_mut().zip(["Germany", "France", "Germany"]) {
+            m.country = Some(country.into());
+        }
+        let options = RenderOptions {
+            template: Some("{url}".into()),
+            commented_extras: 1,
+            group_by_country: true,
+            ..RenderOptions::new(Number::Limit(4))
+        };
+        assert_eq!(
+            ml.render_servers(&options),
+            "## Germany\nhttps://de1.example.org/\nhttps://de2.example.org/\n\n\
+             ## France\nhttps://fr.example.org/\n\n\
+             ## Worldwide\nhttps://any.example.org/\n\n\
+             #https://extra.example.org/"
+        );
+    }
+
+    #[test]
+    fn template() {
+        let j = format!("{{\"urls\":[{MIRROR1},{MIRROR2}]}}");
+        let ml: MirrorList = serde_json::from_str(&j).unwrap();
+        let content = ml.to_file_content_with_template(
+            1,
+            "Server = {url}core/os/$arch # {country_code} {protocol}",
+        );
+        assert!(content.ends_with(
+            "\nServer = http://ftp.ntua.gr/pub/linux/archlinux/core/os/$arch # GR http"
+        ));
+        assert_eq!(
+            ml.to_file_content(&RenderOptions::new(Number::Limit(2))),
+            ml.to_file_content_with_template(2, "Server = {url}$repo/os/$arch")
+        );
+    }
+
+    #[test]
+    fn completion_filter() {
+        let j = format!("{{\"urls\":[{MIRROR0},{MIRROR1},{MIRROR2},{MIRROR3}]}}");
+        let mut ml: MirrorList = serde_json::from_str(&j).unwrap();
+        ml.mirrors[2].detail = Some(details::Details {
+            logs: vec![
+                details::Check {
+                    is_success: true,
+                    ..Default::default()
+                },
+                details::Check::default(),
+            ],
+            ..Default::default()
+        });
+        let filters = Filters {
+            completion: Some(50.0),
+            ..Default::default()
+        };
+        let ml = ml.filter_by(&filters);
+        let urls = ml
+            .mirrors
+            .iter()
+            .map(|m| m.url.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            urls,
+            vec![
+                "http://ftp.ntua.gr/pub/linux/archlinux/",
+                "https://mirror.aarnet.edu.au/pub/archlinux/",
+                "http://mirror.rackspace.com/archlinux/"
+            ]
+        );
+        let ml = ml.filter_by(&Filters {
+            completion: Some(90.0),
+            ..Default::default()
+        });
+        assert_eq!(ml.len(), 1);
+    }
+
+    #[test]
+    fn tier() {
+        let j = format!("{{\"urls\":[{MIRROR0},{MIRROR1},{MIRROR2}]}}");
+        let mut ml: MirrorList = serde_json::from_str(&j).unwrap();
+        for (m, tier) in ml.mirrors.iter_mut().zip([Some(2), Some(1), None]) {
+            m.detail = Some(details::Details {
+                tier,
+                ..Default::default()
+            })
+        }
+        let template = Annotation::apply("Server = {url}", &[Annotation::Tier]);
+        assert!(ml
+            .to_file_content_with_template(1, &template)
+            .ends_with("Server = https://mirrors.rutgers.edu/archlinux/ # tier 2"));
+        let ml = ml.filter_by(&Filters {
+            tier: Some(1),
+            ..Default::default()
+        });
+        assert_eq!(ml.len(), 1);
+        assert_eq!(
+            ml.top_url(),
+            Some("http://ftp.ntua.gr/pub/linux/archlinux/")
+        );
+    }
+
+    #[test]
+    fn age_annotation() {
+        let j = format!("{{\"urls\":[{MIRROR1},{MIRROR0}]}}");
+        let ml: MirrorList = serde_json::from_str(&j).unwrap();
+        let now = ml.mirrors[0].last_sync.unwrap() + chrono::Duration::minutes(90);
+        let ml = ml.with_clock(&clock::FixedClock(now));
+        let template = Annotation::apply("{url}", &[Annotation::Age]);
+        let content = ml.to_file_content_with_template(2, &template);
+        let lines = content.lines().rev().take(2).collect::<Vec<_>>();
+        assert!(lines[1].ends_with(" # age 1.5h"));
+        assert!(lines[0].ends_with(" # age unknown"));
+        assert!(ml
+            .to_file_content_with_template(1, "{url} {age_hours}")
+            .ends_with(" 1.5"));
+    }
+
+    #[test]
+    fn country_filter() {
+        let j = format!("{{\"urls\":[{MIRROR0},{MIRROR1},{MIRROR2},{MIRROR3}]}}");
+        let mut ml: MirrorList = serde_json::from_str(&j).unwrap();
+        ml.mirrors[2].country_code = None;
+        let countries = ["greece", "AU", "usa"]
+            .iter()
+            .map(|c| country::normalize(c).unwrap().code.to_string())
+            .collect::<Vec<_>>();
+        let filtered = ml.clone().filter_by(&Filters {
+            countries: countries[..2].to_vec(),
+            ..Default::default()
+        });
+        assert_eq!(filtered.len(), 2);
+        let filtered = ml.filter_by(&Filters {
+            countries: countries[2..].to_vec(),
+            ..Default::default()
+        });
+        assert_eq!(
+            filtered.top_url(),
+            Some("https://mirrors.rutgers.edu/archlinux/")
+        );
+        assert!(filtered
+            .print_countries()
+            .contains("United States   US    1"));
+    }
+
+    #[test]
+    fn countries_table() {
+        let j = format!("{{\"urls\":[{MIRROR0},{MIRROR1},{MIRROR2}]}}");
+        let mut ml: MirrorList = serde_json::from_str(&j).unwrap();
+        ml.mirrors[0].country = Some("日本".into());
+        ml.mirrors[0].country_code = Some("JP".into());
+        ml.mirrors[1].country = Some("Åland".into());
+        ml.mirrors[1].country_code = Some("AX".into());
+        let table = ml.print_countries();
+        let lines = table.lines().collect::<Vec<_>>();
+        assert!(lines[1..].iter().all(|l| l.width() == lines[1].width()));
+        assert!(lines[2].starts_with("Australia"));
+        assert!(lines[3].starts_with("Åland"));
+        assert!(lines[4].starts_with("日本   "));
+
+        let table = ml.print_countries_with(Some(&collation::Collation::new("en_US.UTF-8")));
+        let lines = table.lines().collect::<Vec<_>>();
+        assert!(lines[2].starts_with("Åland"));
+        assert!(lines[3].starts_with("Australia"));
+    }
+
+    #[tokio::test]
+    async fn empty_list() {
+        let mut ml = MirrorList::default();
+        for key in SortKey::value_variants() {
+            ml.sort(key.clone());
+        }
+        ml.update_download_rate(None, 10).await;
+        assert_eq!(ml.print_countries().lines().count(), 2);
+        assert!(ml
+            .to_file_content(&RenderOptions::new(Number::Limit(10)))
+            .ends_with("\n"));
+        assert_eq!(ml.top_url(), None);
+        let err = ml.ensure_not_empty().unwrap_err();
+        assert_eq!(err.downcast_ref::<Error>(), Some(&Error::EmptySelection));
+
+        let j = format!("{{\"urls\":[{MIRROR0},{MIRROR1}]}}");
+        let mut ml: MirrorList = serde_json::from_str(&j).unwrap();
+        ml.update_download_rate(None, 0).await;
+        assert_eq!(ml.len(), 2);
+        assert!(ml.ensure_not_empty().is_ok());
+    }
+
+    #[test]
+    fn partial_recovery() {
+        let bad_protocol = MIRROR2.replace("\"https\"", "\"gopher\"");
+        let bad_date = MIRROR3.replace("2024-05-04T09:30:12Z", "yesterday");
+        let j = format!("{{\"urls\":[{MIRROR0},{bad_protocol},{MIRROR1},{bad_date},42]}}");
+        let ml: MirrorList = serde_json::from_str(&j).unwrap();
+        assert_eq!(ml.len(), 2);
+        assert_eq!(ml.skipped(), 3);
+        assert!(serde_json::from_str::<MirrorList>("{\"urls\": 3}").is_err());
+    }
+
+    #[tokio::test]
+    async fn non_json_status() {
+        let portal = "<html><head><title>Login</title></head>\n<body>please log in</body></html>";
+        let server = testing::Server::start(vec![
+            testing::Response::new(200, "text/html; charset=utf-8", portal),
+            testing::Response::new(429, "text/plain", "Too many requests"),
+            testing::Response::json(&format!("{{\"urls\":[{MIRROR0}]}}")),
+        ])
+        .await;
+        let err = MirrorList::from_url(&server.url).await.unwrap_err();
+        let Some(Error::UnexpectedResponse {
+            status, snippet, ..
+        }) = err.downcast_ref::<Error>()
+        else {
+            panic!("{err}")
+        };
+        assert_eq!(*status, 200);
+        assert!(snippet.starts_with("<html><head><title>Login</title></head> <body>"));
+        let err = MirrorList::from_url(&server.url).await.unwrap_err();
+        assert!(err.to_string().contains("HTTP status 429"));
+        assert!(err.to_string().contains("retry later"));
+        assert!(err.to_string().contains("--from-mirrorlist"));
+        let ml = MirrorList::from_url(&server.url).await.unwrap();
+        assert_eq!(ml.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn fallback_urls() {
+        let server = testing::Server::start(vec![testing::Response::json(&format!(
+            "{{\"urls\":[{MIRROR0}]}}"
+        ))])
+        .await;
+        let urls = vec!["http://127.0.0.1:1/".to_string(), server.url.clone()];
+        let ml = MirrorList::from_distro_urls(
+            Distro::Arch,
+            &urls,
+            &Retry::default(),
+            &Session::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(ml.len(), 1);
+        assert_eq!(ml.source, Some(server.url));
+        assert!(MirrorList::from_distro_urls(
+            Distro::Arch,
+            &urls[..1],
+            &Retry::default(),
+            &Session::default()
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn rate_deadline() {
+        // accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let mut ml = MirrorList::from_mirrorlist(&url, Distro::Arch);
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_millis(100);
+        ml.update_download_rate_until(None, 10, Some(deadline), &ByteBudget::default())
+            .await;
+        assert_eq!(ml.len(), 1);
+        assert_eq!(ml.mirrors[0].download_rate, None);
+    }
+
+    #[test]
+    fn byte_budget() {
+        let budget = ByteBudget::new(Some(100));
+        assert!(!budget.exhausted());
+        assert!(budget.clone().consume(60));
+        assert!(!budget.consume(60));
+        assert!(budget.exhausted());
+        assert_eq!(budget.used(), 120);
+        let unlimited = ByteBudget::default();
+        assert!(unlimited.consume(u32::MAX.into()));
+        assert!(!unlimited.exhausted());
+    }
+
+    #[tokio::test]
+    async fn throttled_rate() {
+        let mut throttle = Throttle::new(100_000);
+        let start = tokio::time::Instant::now();
+        for _ in 0..3 {
+            throttle.consume(10_000).await;
+        }
+        // the first 10 kB are a burst, the next 20 kB take 0.2 s
+        assert!(start.elapsed() >= std::time::Duration::from_millis(190));
+        let body = "x".repeat(50_000);
+        let server = testing::Server::start(vec![testing::Response::new(
+            200,
+            "application/octet-stream",
+            &body,
+        )])
+        .await;
+        let mut ml = MirrorList::from_mirrorlist(&server.url, Distro::Arch);
+        let budget = ByteBudget::default().with_throttle(Some(50_000));
+        // the timeout is extended by the time taken by the throttle
+        let timeout = Some(chrono::Duration::milliseconds(200));
+        ml.update_download_rate_until(timeout, 1, None, &budget)
+            .await;
+        // MB/s, 0.05 with some slack for the burst
+        let rate = ml.mirrors[0].download_rate.as_ref().unwrap().0;
+        assert!(rate < 0.06, "{rate}");
+    }
+
+    #[tokio::test]
+    async fn rate_in_place() {
+        let server = testing::Server::start(vec![testing::Response::json("data")]).await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let silent = format!("http://{}/", listener.local_addr().unwrap());
+        let content = format!("{silent}\n{}", server.url);
+        let mut ml = MirrorList::from_mirrorlist(&content, Distro::Arch);
+        ml.mirrors[1].note = Some("kept".into());
+        ml.update_download_rate(chrono::Duration::new(1, 0), 2)
+            .await;
+        assert_eq!(
+            ml.urls().collect::<Vec<_>>(),
+            [silent.as_str(), &server.url]
+        );
+        assert_eq!(ml.mirrors[0].download_rate, None);
+        assert!(ml.mirrors[1].download_rate.is_some());
+        assert_eq!(ml.mirrors[1].note.as_deref(), Some("kept"));
+    }
+
+    #[tokio::test]
+    async fn rate_budget() {
+        let server =
+            testing::Server::start(vec![testing::Response::new(200, "text/plain", "data")]).await;
+        let mut ml = MirrorList::from_mirrorlist(&server.url, Distro::Arch);
+        let budget = ByteBudget::new(Some(0));
+        ml.update_download_rate_until(None, 10, None, &budget).await;
+        assert_eq!(ml.mirrors[0].download_rate, None);
+        let budget = ByteBudget::new(Some(1000));
+        ml.update_download_rate_until(None, 10, None, &budget).await;
+        assert!(ml.mirrors[0].download_rate.is_some());
+        assert_eq!(budget.used(), 4);
+        // a download cut by the budget is not a measurement
+        let mut ml = MirrorList::from_mirrorlist(&server.url, Distro::Arch);
+        let budget = ByteBudget::new(Some(2));
+        ml.update_download_rate_until(None, 10, None, &budget).await;
+        assert_eq!(ml.mirrors[0].download_rate, None);
+        assert!(budget.exhausted());
+    }
+
+    #[tokio::test]
+    async fn rate_error_status() {
+        let server =
+            testing::Server::start(vec![testing::Response::new(404, "text/plain", "missing")])
+                .await;
+        let mut ml = MirrorList::from_mirrorlist(&server.url, Distro::Arch);
+        ml.update_download_rate(None, 1).await;
+        assert_eq!(ml.mirrors[0].download_rate, None);
+        assert_eq!(ml.mirrors[0].response, None);
+    }
+
+    #[tokio::test]
+    async fn rate_modes() {
+        let data = testing::Response::new(200, "text/plain", "data");
+        let fast = testing::Server::start(vec![data.clone()]).await;
+        // never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let slow = format!("http://{}/", listener.local_addr().unwrap());
+        let content = format!(
+            "Server = {slow}$repo/os/$arch\nServer = {}$repo/os/$arch",
+            fast.url
+        );
+        let mut ml = MirrorList::from_mirrorlist(&content, Distro::Arch);
+        let timeout = chrono::Duration::new(1, 0);
+        let budget = ByteBudget::default();
+        ml.update_download_rate_mode(timeout, 1, None, &budget, TestMode::Hybrid)
+            .await;
+        assert_eq!(ml.mirrors[0].url, fast.url);
+        assert!(ml.mirrors[0].download_rate.is_some());
+        assert_eq!(ml.mirrors[1].download_rate, None);
+
+        let mut ml = MirrorList::from_mirrorlist(&fast.url, Distro::Arch);
+        ml.update_download_rate_mode(None, 1, None, &budget, TestMode::Sequential)
+            .await;
+        assert!(ml.mirrors[0].download_rate.is_some());
+
+        let content = format!("{0}a/\n{0}b/\n{slow}", fast.url);
+        let mut ml = MirrorList::from_mirrorlist(&content, Distro::Arch);
+        ml.update_download_rate_mode(timeout, 3, None, &budget, TestMode::Adaptive)
+            .await;
+        let rates = ml.mirrors.iter().map(|m| m.download_rate.is_some());
+        assert_eq!(rates.collect::<Vec<_>>(), [true, true, false]);
+
+        // the test finished before the deadline is kept
+        let content = format!("{slow}\n{}", fast.url);
+        let mut ml = MirrorList::from_mirrorlist(&content, Distro::Arch);
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_millis(500);
+        ml.update_download_rate_mode(None, 2, Some(deadline), &budget, TestMode::Adaptive)
+            .await;
+        let rates = ml.mirrors.iter().map(|m| m.download_rate.is_some());
+        assert_eq!(rates.collect::<Vec<_>>(), [false, true]);
+    }
+
+    #[test]
+    fn adaptive_concurrency() {
+        let mut concurrency = Concurrency::default();
+        assert_eq!(concurrency.new_tests(), 2);
+        // nothing to compare with in the first round
+        assert!(!concurrency.update(None));
+        assert_eq!(concurrency.level, 4);
+        concurrency.follow([(0, 5.0), (1, 10.0)].into_iter());
+        assert_eq!(concurrency.reference, Some((1, 10.0)));
+        assert_eq!(concurrency.new_tests(), 3);
+        // the reference keeps its rate, whatever the rates of the others
+        assert!(!concurrency.update(Some(9.0)));
+        assert_eq!(concurrency.level, 8);
+        // it slows down
+        assert!(!concurrency.update(Some(6.0)));
+        assert_eq!(concurrency.level, 8);
+        // its test failed
+        assert!(!concurrency.update(None));
+        assert_eq!(concurrency.level, 8);
+        // it gets less than half of its rate
+        assert!(concurrency.update(Some(4.0)));
+        assert_eq!(concurrency.level, 4);
+        assert_eq!(concurrency.reference, Some((1, 10.0)));
+    }
+
+    #[test]
+    fn cache_headers() {
+        use reqwest::header::{HeaderMap, HeaderValue};
+        let mut headers = HeaderMap::new();
+        assert!(!looks_cached(&headers));
+        headers.insert("age", HeaderValue::from_static("0"));
+        headers.insert("x-cache", HeaderValue::from_static("MISS from cdn"));
+        assert!(!looks_cached(&headers));
+        headers.insert("cf-cache-status", HeaderValue::from_static("hit"));
+        assert!(looks_cached(&headers));
+        let mut headers = HeaderMap::new();
+        headers.insert("age", HeaderValue::from_static("120"));
+        assert!(looks_cached(&headers));
+    }
+
+    #[tokio::test]
+    async fn cached_annotation() {
+        let mut response = testing::Response::new(200, "text/plain", "data");
+        response.headers.push(("X-Cache".into(), "HIT".into()));
+        let server = testing::Server::start(vec![response]).await;
+        let mut ml = MirrorList::from_mirrorlist(&server.url, Distro::Arch);
+        ml.update_download_rate(None, 1).await;
+        let template = Annotation::apply("{url}", &[Annotation::Cached]);
+        assert_eq!(
+            ml.to_file_content_with_template(1, &template)
+                .lines()
+                .last()
+                .unwrap(),
+            format!("{} # cached", server.url)
+        );
+    }
+
+    #[tokio::test]
+    async fn response_info() {
+        let target =
+            testing::Server::start(vec![testing::Response::new(200, "text/plain", "data")]).await;
+        let mut redirect = testing::Response::new(302, "text/plain", "");
+        let location = target.url.replace("127.0.0.1", "localhost");
+        redirect.headers.push(("Location".into(), location.clone()));
+        redirect.headers.push(("Server".into(), "nginx".into()));
+        let server = testing::Server::start(vec![redirect]).await;
+        let mut ml = MirrorList::from_mirrorlist(&server.url, Distro::Arch);
+        ml.update_download_rate(None, 1).await;
+        let info = ml.mirrors[0].response.clone().unwrap();
+        assert_eq!(info.status, 200);
+        assert_eq!(info.final_url, location);
+        assert_eq!(info.server, None);
+        assert_eq!(info.version, "HTTP/1.1");
+        assert!(info.cross_host_redirect);
+    }
+
+    #[test]
+    fn redirects() {
+        let j = format!("{{\"urls\":[{MIRROR0},{MIRROR1},{MIRROR2}]}}");
+        let mut ml: MirrorList = serde_json::from_str(&j).unwrap();
+        for m in ml.mirrors.iter_mut() {
+            m.download_rate = Some(Bandwidth(10.0));
+        }
+        ml.mirrors[1].response = Some(ResponseInfo {
+            status: 200,
+            final_url: "https://cdn.example.net/".into(),
+            server: None,
+            version: "HTTP/1.1".into(),
+            cross_host_redirect: true,
+        });
+        assert_eq!(ml.clone().penalize_redirects(0.5), 1);
+        let mut penalized = ml.clone();
+        penalized.penalize_redirects(0.5);
+        assert_eq!(penalized.mirrors[1].download_rate, Some(Bandwidth(5.0)));
+        assert_eq!(penalized.mirrors[0].download_rate, Some(Bandwidth(10.0)));
+        let removed = ml.remove_redirecting();
+        assert_eq!(removed, ["http://ftp.ntua.gr/pub/linux/archlinux/"]);
+        assert_eq!(ml.len(), 2);
+    }
+
+    #[test]
+    fn prefer_protocols() {
+        let content = "Server = http://a.example.org/$repo/os/$arch
+Server = https://b.example.org/$repo/os/$arch
+Server = https://a.example.org/$repo/os/$arch
+Server = ftp://c.example.org/$repo/os/$arch
+Server = https://b.example.org/archlinux/$repo/os/$arch";
+        let mut ml = MirrorList::from_mirrorlist(content, Distro::Arch);
+        assert_eq!(ml.prefer_protocols(&[Protocol::Https, Protocol::Http]), 1);
+        assert_eq!(
+            ml.urls().collect::<Vec<_>>(),
+            [
+                "https://b.example.org/",
+                "https://a.example.org/",
+                "https://b.example.org/archlinux/",
+                "ftp://c.example.org/"
+            ]
+        );
+    }
+
+    #[test]
+    fn dedupe_backends() {
+        let content = "https://mirrors.edge.kernel.org/archlinux/
+https://mirrors.kernel.org/archlinux/
+https://a.example.org/
+https://cloudflaremirrors.com/archlinux/
+http://mirrors.kernel.org/archlinux/";
+        let mut ml = MirrorList::from_mirrorlist(content, Distro::Arch);
+        assert_eq!(ml.apply_aliases(&aliases::Aliases::builtin()), 4);
+        assert_eq!(ml.redundant_aliases(), 2);
+        let moved = ml.dedupe_backends();
+        assert_eq!(
+            moved,
+            [
+                (
+                    "https://mirrors.kernel.org/archlinux/".into(),
+                    "kernel.org".into()
+                ),
+                (
+                    "http://mirrors.kernel.org/archlinux/".into(),
+                    "kernel.org".into()
+                )
+            ]
+        );
+        assert_eq!(
+            ml.urls().take(3).collect::<Vec<_>>(),
+            [
+                "https://mirrors.edge.kernel.org/archlinux/",
+                "https://a.example.org/",
+                "https://cloudflaremirrors.com/archlinux/"
+            ]
+        );
+        let template = Annotation::apply("{url}", &[Annotation::Backend]);
+        let lines = ml.to_file_content_with_template(5, &template);
+        assert!(lines.contains("https://cloudflaremirrors.com/archlinux/ # backend Cloudflare"));
+    }
+
+    #[tokio::test]
+    async fn keep_alive() {
+        use reqwest::header::{HeaderMap, HeaderValue};
+        use reqwest::Version;
+        let mut headers = HeaderMap::new();
+        assert!(keeps_alive(Version::HTTP_11, &headers));
+        assert!(!keeps_alive(Version::HTTP_10, &headers));
+        headers.insert("connection", HeaderValue::from_static("Keep-Alive"));
+        assert!(keeps_alive(Version::HTTP_10, &headers));
+        headers.insert("connection", HeaderValue::from_static("close"));
+        assert!(!keeps_alive(Version::HTTP_11, &headers));
+
+        // the test server closes every connection, the second request opening
+        // a new one
+        let server = testing::Server::start(vec![testing::Response::json("")]).await;
+        let url = server.url.replace("127.0.0.1", "localhost");
+        let mut ml = MirrorList::from_mirrorlist(&url, Distro::Arch);
+        ml.probe_keep_alive(None, None).await.unwrap();
+        assert_eq!(ml.mirrors[0].keep_alive, Some(false));
+        assert_eq!(ml.remove_without_keep_alive(), [url]);
+    }
+
+    #[test]
+    fn number() {
+        assert_eq!("all".parse::<Number>().unwrap(), Number::All);
+        assert_eq!("0".parse::<Number>().unwrap(), Number::All);
+        assert_eq!("5".parse::<Number>().unwrap(), Number::Limit(5));
+        assert!("-1".parse::<Number>().is_err());
+        assert_eq!(Number::All.of(3), 3);
+        assert_eq!(Number::Limit(5).of(3), 3);
+        assert_eq!(Number::Limit(2).of(3), 2);
+        assert_eq!(serde_json::to_string(&Number::All).unwrap(), "\"all\"");
+        assert_eq!(serde_json::to_string(&Number::Limit(4)).unwrap(), "4");
+        assert_eq!(serde_json::from_str::<Number>("0").unwrap(), Number::All);
+        assert_eq!(
+            serde_json::from_str::<Number>("\"7\"").unwrap(),
+            Number::Limit(7)
+        );
+        assert!(serde_json::from_str::<Number>("\"many\"").is_err());
+    }
+
+    #[test]
+    fn pages() {
+        let content = (0..5)
+            .map(|i| format!("https://{i}.example.org/"))
+            .join("\n");
+        let mut ml = MirrorList::from_mirrorlist(&content, Distro::Arch);
+        // every score is unknown: the order of the list is kept
+        ml.sort(SortKey::Score);
+        let mut offset = Some(0);
+        let mut urls = Vec::new();
+        while let Some(o) = offset {
+            let page = ml.page(o, 2);
+            assert_eq!(page.total, 5);
+            urls.extend(page.mirrors.iter().map(Mirror::url));
+            offset = page.next_offset();
+        }
+        assert_eq!(urls, ml.urls().collect_vec());
+        assert_eq!(ml.page(4, 2).mirrors.len(), 1);
+        let past = ml.page(10, 2);
+        assert!(past.mirrors.is_empty());
+        assert_eq!(past.next_offset(), None);
+        assert_eq!(ml.page(1, usize::MAX).mirrors.len(), 4);
+        assert_eq!(ml.page(1, 0).next_offset(), None);
+    }
 
-impl Mirror {
-    /// Update download rate.
-    async fn update_dl_rate(&mut self, timeout: Option<chrono::Duration>) -> Result<()> {
-        let span = span!(Level::DEBUG, "update download rate", url = self.url.clone());
-        let _guard = span.enter();
-        let client = match timeout {
-            Some(d) => reqwest::Client::builder()
-                .timeout(std::time::Duration::from_secs(
-                    d.num_seconds().try_into().unwrap(),
-                ))
-                .build()?,
-            None => reqwest::Client::new(),
-        };
-        let now = Utc::now();
-        let response = client
-            .get(format!("{}/extra/os/x86_64/extra.db", self.url))
-            .send()
-            .await?;
-        let content = match response.bytes().await {
-            Ok(c) => c,
-            Err(e) => {
-                // TODO: get the first bytes received before the timeout
-                debug!("{:?}", &e);
-                return Err(e.into());
-            }
-        };
-        let end = Utc::now();
-        self.download_rate = Some(Bandwidth::from_duration(end - now, content.len()));
-        info!("donwload rate updated for url {}", self.url.clone());
-        Ok(())
+    #[test]
+    fn failure_policy() {
+        let failed = || Result::<()>::Err(anyhow::anyhow!("unreachable"));
+        let e = FailurePolicy::Fail.handle("probe", failed()).unwrap_err();
+        assert_eq!(format!("{e:#}"), "probe failed: unreachable");
+        assert_eq!(FailurePolicy::Warn.handle("probe", failed()).unwrap(), None);
+        assert_eq!(FailurePolicy::Skip.handle("probe", failed()).unwrap(), None);
+        assert_eq!(FailurePolicy::Fail.handle("probe", Ok(1)).unwrap(), Some(1));
     }
 
-    /// Update download rate. Function that can be used by MirrorList
-    async fn update_download_rate(mut self, timeout: Option<chrono::Duration>) -> Result<Self> {
-        self.update_dl_rate(timeout).await?;
-        Ok(self)
+    #[tokio::test]
+    async fn dead_mirrors() {
+        let alive = testing::Server::start(vec![testing::Response::json("")]).await;
+        let missing =
+            testing::Server::start(vec![testing::Response::new(404, "text/plain", "")]).await;
+        let refused = "http://127.0.0.1:1/";
+        let content = format!("{}\n{refused}\n{}", missing.url, alive.url);
+        let mut ml = MirrorList::from_mirrorlist(&content, Distro::Arch);
+        let dead = ml.remove_dead(None, None).await.unwrap();
+        assert_eq!(dead, [missing.url.as_str(), refused]);
+        assert_eq!(ml.urls().collect_vec(), [alive.url.as_str()]);
     }
 
-    /// Compute mirror age based on last server synchronisation
-    fn age(&self) -> Option<chrono::Duration> {
-        self.last_sync.map(|last_sync| Utc::now() - last_sync)
+    #[tokio::test]
+    async fn resolve_addresses() {
+        let content = "http://127.0.0.1:1/a\nhttp://127.0.0.1:1/b\nhttp://[::1]:1/";
+        let mut ml = MirrorList::from_mirrorlist(content, Distro::Arch);
+        let local = std::net::IpAddr::from([127, 0, 0, 1]);
+        let previous = dns::Addresses::from([("127.0.0.1".into(), vec![local])]);
+        let changed = ml
+            .resolve_addresses(2, None, None, &previous)
+            .await
+            .unwrap();
+        assert!(changed.is_empty());
+        assert_eq!(ml.mirrors[1].addresses, Some(vec![local]));
+        assert_eq!(ml.mirrors[1].dns_changed, Some(false));
+        assert_eq!(ml.mirrors[2].addresses, None);
+        assert_eq!(ml.addresses(3), previous);
+        let moved = dns::Addresses::from([("127.0.0.1".into(), vec![])]);
+        let changed = ml.resolve_addresses(1, None, None, &moved).await.unwrap();
+        assert_eq!(changed, ["127.0.0.1"]);
+        assert_eq!(ml.mirrors[0].dns_changed, Some(true));
     }
-}
-
-#[derive(Debug, Default, Clone, Deserialize)]
-#[serde(rename_all = "lowercase")]
-enum Protocol {
-    Ftp,
-    #[default]
-    Https,
-    Http,
-    Rsync,
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use chrono::TimeDelta;
-    use itertools::Itertools;
-    use tokio;
+    #[tokio::test]
+    async fn iso_files() {
+        let complete = testing::Server::start(vec![testing::Response::json("")]).await;
+        let missing =
+            testing::Server::start(vec![testing::Response::new(404, "text/plain", "")]).await;
+        let content = format!("{}\n{}\nhttp://127.0.0.1:1/", complete.url, missing.url);
+        let mut ml = MirrorList::from_mirrorlist(&content, Distro::Arch);
+        ml.check_iso_files(3, None, None).await.unwrap();
+        let found = ml.mirrors.iter().map(|m| m.iso_files).collect_vec();
+        assert_eq!(found, [Some(true), Some(false), None]);
+        ml.distro = Distro::Manjaro;
+        assert!(ml.check_iso_files(3, None, None).await.is_err());
+    }
 
-    static MIRROR0: &str = r#"
-             {
-                 "url": "https://mirrors.rutgers.edu/archlinux/",
-                 "protocol": "https",
-                 "last_sync": null,
-                 "completion_pct": 0.0,
-                 "delay": null,
-                 "duration_avg": null,
-                 "duration_stddev": null,
-                 "score": null,
-                 "active": true,
-                 "country": "United States",
-                 "country_code": "US",
-                 "isos": true,
-                 "ipv4": true,
-                 "ipv6": false,
-                 "details": "https://archlinux.org/mirrors/rutgers.edu/910/"
-             }"#;
-    static MIRROR1: &str = r#"
-             {
-                 "url": "http://ftp.ntua.gr/pub/linux/archlinux/",
-                 "protocol": "http",
-                 "last_sync": "2024-05-01T14:25:08Z",
-                 "completion_pct": 1.0,
-                 "delay": 6354,
-                 "duration_avg": 0.4358575581256008,
-                 "duration_stddev": 0.6512862688716142,
-                 "score": 2.852143826997215,
-                 "active": true,
-                 "country": "Greece",
-                 "country_code": "GR",
-                 "isos": true,
-                 "ipv4": true,
-                 "ipv6": true,
-                 "details": "https://archlinux.org/mirrors/ntua.gr/333/"
-             }"#;
-    static MIRROR2: &str = r#"
-            {
-                "url": "https://mirror.aarnet.edu.au/pub/archlinux/",
-                "protocol": "https",
-                "last_sync": "2024-04-01T08:22:54Z",
-                "completion_pct": 1.0,
-                "delay": 1863,
-                "duration_avg": 1.1129106909958357,
-                "duration_stddev": 0.23354254068513589,
-                "score": 1.8639532316809715,
-                "active": true,
-                "country": "Australia",
-                "country_code": "AU",
-                "isos": true,
-                "ipv4": true,
-                "ipv6": true,
-                "details": "https://archlinux.org/mirrors/aarnet.edu.au/5/"
-            }"#;
-    static MIRROR3: &str = r#"
-            {
-                "url": "http://mirror.rackspace.com/archlinux/",
-                "protocol": "http",
-                "last_sync": "2024-05-04T09:30:12Z",
-                "completion_pct": 0.8645833333333334,
-                "delay": 12205,
-                "duration_avg": 0.3613546647523579,
-                "duration_stddev": 0.42918278405415544,
-                "score": 4.83564170785653,
-                "active": true,
-                "country": "",
-                "country_code": "",
-                "isos": true,
-                "ipv4": true,
-                "ipv6": false,
-                "details": "https://archlinux.org/mirrors/rackspace.com/712/"
-            }"#;
+    #[tokio::test]
+    async fn reachability() {
+        let server = testing::Server::start(vec![testing::Response::json("")]).await;
+        let content = format!("{}\nhttp://127.0.0.1:1/\nhttp://[::1]:1/", server.url);
+        let mut ml = MirrorList::from_mirrorlist(&content, Distro::Arch);
+        ml.check_reachability(None, None).await.unwrap();
+        let v4 = ml
+            .mirrors
+            .iter()
+            .map(|m| m.reachable.unwrap().ipv4)
+            .collect_vec();
+        assert_eq!(v4, [true, false, false]);
+        ml.mirrors[2].reachable = None;
+        let removed = ml.remove_unreachable(true, false);
+        assert_eq!(
+            removed,
+            [("http://127.0.0.1:1/".into(), "ipv4 unreachable")]
+        );
+        let removed = ml.remove_unreachable(false, true);
+        assert_eq!(removed, [(server.url.clone(), "ipv6 unreachable")]);
+        assert_eq!(ml.urls().collect_vec(), ["http://[::1]:1/"]);
+    }
 
-    #[test]
-    fn mirror0() {
-        let _: Mirror = serde_json::from_str(MIRROR0).unwrap();
+    #[tokio::test]
+    async fn metadata_time() {
+        let fast = testing::Server::start(vec![testing::Response::json("")]).await;
+        let failing =
+            testing::Server::start(vec![testing::Response::new(404, "text/plain", "")]).await;
+        let content = format!("{}\n{}", failing.url, fast.url);
+        let mut ml = MirrorList::from_mirrorlist(&content, Distro::Arch);
+        ml.update_metadata_time(None, None).await.unwrap();
+        assert!(ml.mirrors[1].metadata_time.is_some());
+        assert_eq!(ml.mirrors[0].metadata_time, None);
+        ml.sort(SortKey::Metadata);
+        assert_eq!(ml.mirrors[0].url, fast.url);
     }
 
     #[test]
-    fn mirror1() {
-        let _: Mirror = serde_json::from_str(MIRROR1).unwrap();
+    fn borrowed_status() {
+        let j = format!(
+            "{{\"last_check\": \"2024-05-01T15:00:00Z\", \"urls\":[{MIRROR0},{{\"url\": \"bad\"}},{MIRROR1}]}}"
+        );
+        let borrowed = MirrorList::from_status_str(&j).unwrap();
+        let owned: MirrorList = serde_json::from_str(&j).unwrap();
+        assert_eq!(borrowed.len(), 2);
+        assert_eq!(borrowed.skipped, 1);
+        assert_eq!(borrowed.urls().collect_vec(), owned.urls().collect_vec());
+        assert_eq!(borrowed.last_check, owned.last_check);
+        let from_value: MirrorList =
+            serde_json::from_value(serde_json::from_str(&j).unwrap()).unwrap();
+        assert_eq!(from_value.len(), 2);
     }
 
     #[test]
-    fn sort_delay() {
+    fn measurement_failures() {
         let j = format!("{{\"urls\":[{MIRROR0},{MIRROR1},{MIRROR2}]}}");
         let mut ml: MirrorList = serde_json::from_str(&j).unwrap();
-        ml.sort(SortKey::Delay);
+        ml.mirrors[0].download_rate = Some(Bandwidth(1.0));
+        ml.mirrors[1].download_rate = Some(Bandwidth(f64::NAN));
+        assert!(ml.measurement_failures(3, false, false).is_empty());
+        assert_eq!(ml.measurement_failures(1, true, false).len(), 0);
+        assert_eq!(ml.measurement_failures(3, true, false).len(), 2);
+        ml.mirrors[2].detail = Some(details::Details::default());
         assert_eq!(
-            ml.mirrors[0].url,
-            "https://mirror.aarnet.edu.au/pub/archlinux/"
+            ml.measurement_failures(3, false, true),
+            vec![ml.mirrors[0].url.as_str(), ml.mirrors[1].url.as_str()]
         );
-        assert_eq!(ml.mirrors[2].url, "https://mirrors.rutgers.edu/archlinux/");
     }
 
     #[test]
-    fn sort_age() {
+    fn sort_key_parsing() {
+        for key in SortKey::value_variants() {
+            assert_eq!(
+                key.to_string().parse::<SortKey>().unwrap().to_string(),
+                key.to_string()
+            );
+        }
+        assert!(matches!("last_sync".parse(), Ok(SortKey::Age)));
+        assert!(matches!("Last-Sync".parse(), Ok(SortKey::Age)));
+        assert!(matches!("ra".parse(), Ok(SortKey::Rate)));
+        assert!(matches!("l".parse(), Ok(SortKey::Age)));
+        assert!(matches!("du".parse(), Ok(SortKey::Duration)));
+        let err = "d".parse::<SortKey>().unwrap_err().to_string();
+        assert_eq!(err, "ambiguous sort key 'd', may be delay, duration");
+        assert!("speed".parse::<SortKey>().is_err());
+        assert!("".parse::<SortKey>().is_err());
+    }
+
+    #[test]
+    fn filter_summary() {
         let j = format!("{{\"urls\":[{MIRROR0},{MIRROR1},{MIRROR2}]}}");
-        let mut ml: MirrorList = serde_json::from_str(&j).unwrap();
-        ml.sort(SortKey::Age);
-        // null
-        assert_eq!(ml.mirrors[0].url, "https://mirrors.rutgers.edu/archlinux/");
+        let ml: MirrorList = serde_json::from_str(&j).unwrap();
+        assert!(!ml
+            .to_file_content(&RenderOptions::new(Number::Limit(10)))
+            .contains("# filter"));
+        let ml = ml.filter_by(&Filters {
+            age: Some(12.5),
+            ipv6: true,
+            countries: vec!["DE".into(), "FR".into()],
+            ..Default::default()
+        });
+        let content = ml.to_file_content(&RenderOptions::new(Number::Limit(10)));
+        let filters = content
+            .lines()
+            .filter(|l| l.starts_with("# filter:"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            filters,
+            vec![
+                "# filter: \tcountry DE, FR",
+                "# filter: \tsynchronized in the last 12.5 hours",
+                "# filter: \tipv6",
+            ]
+        );
+        assert!(Filters::default().summary().is_empty());
+    }
 
-        // 2024-04
+    #[test]
+    fn diversity() {
+        let j = format!("{{\"urls\":[{MIRROR1},{MIRROR2},{MIRROR3}]}}");
+        let ml: MirrorList = serde_json::from_str(&j).unwrap();
+        assert!(ml.diversity_warnings(3).is_empty());
+        assert!(ml.diversity_warnings(1).is_empty());
+        let mut same = ml.clone();
+        for m in same.mirrors.iter_mut().take(2) {
+            m.country_code = Some("GR".into());
+            m.protocol = Protocol::Http;
+        }
+        same.mirrors[2].protocol = Protocol::Https;
+        same.mirrors[2].country_code = Some("FR".into());
+        let warnings = same.diversity_warnings(2);
         assert_eq!(
-            ml.mirrors[1].url,
-            "https://mirror.aarnet.edu.au/pub/archlinux/"
+            warnings,
+            vec![
+                "the 2 kept mirrors are all located in GR",
+                "the 2 kept mirrors all use http"
+            ]
         );
+        assert!(same
+            .to_file_content(&RenderOptions::new(Number::Limit(2)))
+            .contains("# warning: \tthe 2 kept mirrors all use http"));
+    }
 
-        // 2024-05
-        assert_eq!(ml.mirrors[2].url, "http://ftp.ntua.gr/pub/linux/archlinux/");
+    #[test]
+    fn local_score() {
+        let j = format!("{{\"urls\":[{MIRROR0},{MIRROR1},{MIRROR2},{MIRROR3}]}}");
+        let mut ml: MirrorList = serde_json::from_str(&j).unwrap();
+        // archweb score is close to the local one
+        for m in &ml.mirrors[1..] {
+            let score = m.local_score().unwrap();
+            assert!((score - m.score.unwrap()).abs() < 1e-6, "{}", m.url);
+        }
+        let expected = ml.mirrors[1].score;
+        ml.mirrors[1].score = None;
+        assert_eq!(ml.fill_missing_scores(), 1);
+        assert!((ml.mirrors[1].score.unwrap() - expected.unwrap()).abs() < 1e-6);
+        // no delay nor completion
+        assert_eq!(ml.mirrors[0].score, None);
     }
 
-    #[tokio::test]
-    async fn update_duration() {
-        let m: Mirror = serde_json::from_str(&MIRROR3).unwrap();
-        let m = m.update_download_rate(None).await.unwrap();
-        assert!(m.download_rate.is_some());
+    #[test]
+    fn random_pick() {
+        use rand::SeedableRng;
+        let j = format!("{{\"urls\":[{MIRROR1},{MIRROR2},{MIRROR3}]}}");
+        let mut ml: MirrorList = serde_json::from_str(&j).unwrap();
+        ml.sort(SortKey::Score);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let mut counts = HashMap::new();
+        for _ in 0..1000 {
+            let mut picked = ml.clone();
+            picked.pick_random(1, 2, &SortKey::Score, &mut rng);
+            assert_eq!(picked.len(), 1);
+            *counts.entry(picked.mirrors[0].url.clone()).or_insert(0) += 1;
+        }
+        // MIRROR3 is out of the pool, MIRROR2 has the best score
+        assert_eq!(counts.len(), 2);
+        assert!(counts[&ml.mirrors[0].url] > counts[&ml.mirrors[1].url]);
+
+        let mut picked = ml.clone();
+        picked.pick_random(5, 3, &SortKey::Age, &mut rng);
+        assert_eq!(
+            picked.mirrors.iter().map(|m| &m.url).collect::<Vec<_>>(),
+            ml.mirrors.iter().map(|m| &m.url).collect::<Vec<_>>()
+        );
+        for (number, pool) in [(0, 3), (2, 0)] {
+            let mut picked = ml.clone();
+            picked.pick_random(number, pool, &SortKey::Score, &mut rng);
+            assert_eq!(picked.len(), ml.len());
+        }
+        let mut empty = MirrorList::default();
+        empty.pick_random(3, 10, &SortKey::Rate, &mut rng);
+        assert!(empty.is_empty());
     }
 
     #[tokio::test]
-    async fn update_duration_large_timeout() {
-        let m: Mirror = serde_json::from_str(&MIRROR3).unwrap();
-        let m = m
-            .update_download_rate(chrono::Duration::new(20, 0))
-            .await
-            .unwrap();
-        assert!(m.download_rate.is_some());
+    async fn race_urls() {
+        let j = format!("{{\"urls\":[{MIRROR1}]}}");
+        let silent = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let slow = format!("http://{}/", silent.local_addr().unwrap());
+        let broken = testing::Server::start(vec![testing::Response::new(
+            200,
+            "text/html",
+            "<html>captive portal</html>",
+        )])
+        .await;
+        let fast = testing::Server::start(vec![testing::Response::json(&j)]).await;
+        let urls = [slow.clone(), broken.url, fast.url.clone()];
+        let mlist = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            MirrorList::race_distro_urls(
+                Distro::Arch,
+                &urls,
+                &Retry::default(),
+                &Session::default(),
+            ),
+        )
+        .await
+        .expect("the silent URL is not waited for")
+        .unwrap();
+        assert_eq!(mlist.len(), 1);
+        assert_eq!(mlist.source, Some(fast.url));
+        assert!(MirrorList::race_distro_urls(
+            Distro::Arch,
+            &[],
+            &Retry::default(),
+            &Session::default()
+        )
+        .await
+        .is_err());
     }
 
-    #[tokio::test]
-    async fn update_duration_small_timeout() {
-        let m: Mirror = serde_json::from_str(&MIRROR3).unwrap();
-        let r = m
-            .clone()
-            .update_download_rate(chrono::Duration::new(0, 1))
-            .await;
-        assert!(r.is_err());
+    #[test]
+    fn staged_selection() {
+        let j = format!("{{\"urls\":[{MIRROR0},{MIRROR1},{MIRROR2},{MIRROR3}]}}");
+        let mut ml: MirrorList = serde_json::from_str(&j).unwrap();
+        assert_eq!(ml.last_sort(), None);
+        ml.sort(SortKey::Age);
+        let order = ml.urls().map(String::from).collect_vec();
+        let mut latest = ml.take_top(3);
+        assert_eq!(latest.urls().collect_vec(), order[..3]);
+        assert_eq!(ml.urls().collect_vec(), order[3..]);
+        assert_eq!(latest.last_sort(), Some(&SortKey::Age));
+        assert_eq!(latest.distro, ml.distro);
+        latest.sort(SortKey::Score);
+        let best = latest.urls().next().unwrap().to_string();
+        let tail = latest.truncate(1);
+        assert_eq!(latest.urls().collect_vec(), [best]);
+        assert_eq!(tail.len(), 2);
+        assert_eq!(tail.last_sort(), Some(&SortKey::Score));
+        assert!(latest.truncate(5).is_empty());
+        latest.shuffle(&mut rand::rng());
+        assert_eq!(latest.last_sort(), None);
+        // the scores computed locally may change the order
+        ml.sort(SortKey::Score);
+        ml.mirrors[0].score = None;
+        assert!(ml.fill_missing_scores() > 0);
+        assert_eq!(ml.last_sort(), None);
     }
 
-    #[tokio::test]
-    async fn update_duration_interrupt() {
-        let m: Mirror = serde_json::from_str(&MIRROR3).unwrap();
-        let mut s = JoinSet::new();
-        s.spawn(m.update_download_rate(None));
-        s.abort_all();
+    #[test]
+    fn shuffle() {
+        use rand::SeedableRng;
+        let j = format!("{{\"urls\":[{MIRROR0},{MIRROR1},{MIRROR2},{MIRROR3}]}}");
+        let ml: MirrorList = serde_json::from_str(&j).unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let orders = (0..20)
+            .map(|_| {
+                let mut shuffled = ml.clone();
+                shuffled.shuffle(&mut rng);
+                shuffled.urls().map(String::from).collect_vec()
+            })
+            .collect::<HashSet<_>>();
+        assert!(orders.len() > 1);
+        for order in orders {
+            assert_eq!(
+                order.iter().sorted().collect_vec(),
+                ml.urls().sorted().collect_vec()
+            );
+        }
     }
 
-    #[tokio::test]
-    async fn update_mirrorlist_dl_rate() {
-        let mut mlist = MirrorList::from_default_url().await.unwrap();
-        mlist.mirrors.truncate(15);
-        let mlentgth = mlist.mirrors.len();
-        mlist.update_download_rate(None, 3).await;
-        mlist.sort(SortKey::Rate);
-        let mirrors = &mlist.mirrors.clone();
-        assert!(!&mirrors.is_empty());
-        assert!(
-            mirrors[0].download_rate.clone().unwrap() >= mirrors[1].download_rate.clone().unwrap()
+    #[test]
+    fn exclusion() {
+        let j = format!("{{\"urls\":[{MIRROR0},{MIRROR1},{MIRROR2}]}}");
+        let mut ml: MirrorList = serde_json::from_str(&j).unwrap();
+        let list = urlfile::UrlFile {
+            entries: vec!["ntua.gr".into(), "https://mirror.aarnet.edu.au/pub".into()],
+            ..Default::default()
+        };
+        assert_eq!(ml.exclude(&list), 2);
+        assert_eq!(ml.top_url(), Some("https://mirrors.rutgers.edu/archlinux/"));
+    }
+
+    #[test]
+    fn pinning() {
+        let j = format!("{{\"urls\":[{MIRROR0},{MIRROR1},{MIRROR2},{MIRROR3}]}}");
+        let ml: MirrorList = serde_json::from_str(&j).unwrap();
+        let list = urlfile::UrlFile {
+            entries: vec!["rackspace.com".into(), "ntua.gr".into()],
+            ..Default::default()
+        };
+        let pinned = ml.select(&list);
+        assert_eq!(pinned.len(), 2);
+        let mut filtered = ml.clone().filter(None, false, false, true);
+        assert_eq!(filtered.len(), 2);
+        // measured after the pinned mirrors were selected
+        for m in filtered.mirrors.iter_mut() {
+            m.download_rate = Some(Bandwidth(1.0));
+        }
+        let added = filtered.pin_first(pinned, None);
+        assert_eq!(added, vec!["http://mirror.rackspace.com/archlinux/"]);
+        assert_eq!(
+            filtered
+                .mirrors
+                .iter()
+                .map(|m| m.url.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "http://ftp.ntua.gr/pub/linux/archlinux/",
+                "http://mirror.rackspace.com/archlinux/",
+                "https://mirror.aarnet.edu.au/pub/archlinux/",
+            ]
         );
-        assert_eq!(mlentgth, mlist.mirrors.len());
+        assert!(filtered.mirrors[0].download_rate.is_some());
+        assert!(filtered.mirrors[1].download_rate.is_none());
+    }
+
+    #[test]
+    fn overrides() {
+        let j = format!("{{\"urls\":[{MIRROR1},{MIRROR3}]}}");
+        let mut ml: MirrorList = serde_json::from_str(&j).unwrap();
+        let overrides: overrides::Overrides = toml::from_str(
+            r#"
+            ["http://mirror.rackspace.com/archlinux"]
+            country = "United States"
+            country_code = "US"
+            note = "capped"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(ml.apply_overrides(&overrides), 1);
+        assert_eq!(ml.mirrors[1].code().as_deref(), Some("US"));
+        assert_eq!(ml.mirrors[0].code().as_deref(), Some("GR"));
+        let template = Annotation::apply("{url} {country}", &[Annotation::Note]);
+        assert_eq!(
+            ml.server_list(2, &template),
+            "http://ftp.ntua.gr/pub/linux/archlinux/ Greece # \n\
+             http://mirror.rackspace.com/archlinux/ United States # capped"
+        );
+    }
+
+    #[test]
+    fn description() {
+        let j = format!("{{\"urls\":[{MIRROR0},{MIRROR1}]}}");
+        let ml: MirrorList = serde_json::from_str(&j).unwrap();
+        let ml = ml.with_clock(&clock::FixedClock("2024-05-01T16:25:08Z".parse().unwrap()));
+        let text = ml.describe();
+        let blocks = text.split("\n\n").collect::<Vec<_>>();
+        assert_eq!(blocks.len(), 2);
+        assert!(blocks[0].contains("last sync: unknown"));
+        assert!(blocks[1].contains("country: Greece (GR)"));
+        assert!(blocks[1].contains("age: 2.0 hours"));
+        assert!(blocks[1].contains("delay: 6354 s"));
+        assert!(!blocks[1].contains("checks:"));
+    }
+
+    #[test]
+    fn rejection_reasons() {
+        let j = format!("{{\"urls\":[{MIRROR0},{MIRROR1},{MIRROR2},{MIRROR3}]}}");
+        let ml: MirrorList = serde_json::from_str(&j).unwrap();
+        let filters = Filters {
+            ipv6: true,
+            countries: vec!["GR".into(), "US".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            ml.rejections(&filters),
+            vec![
+                ("https://mirrors.rutgers.edu/archlinux/".into(), "ipv6"),
+                (
+                    "https://mirror.aarnet.edu.au/pub/archlinux/".into(),
+                    "country"
+                ),
+                ("http://mirror.rackspace.com/archlinux/".into(), "ipv6"),
+            ]
+        );
+        assert_eq!(ml.filter_by(&filters).len(), 1);
     }
 
     #[test]
     fn age_computation() {
         let j = format!("{{\"urls\":[{MIRROR0},{MIRROR1},{MIRROR2}]}}");
-        let mut ml: MirrorList = serde_json::from_str(&j).unwrap();
+        let ml: MirrorList = serde_json::from_str(&j).unwrap();
         let [ref m0, ref m1, ref m2] = ml.mirrors.clone()[0..3] else {
             panic!()
         };
-        assert_eq!(m0.age(), None);
-        assert!(m1.age() < m2.age());
+        let now = Utc::now();
+        assert_eq!(m0.age(now), None);
+        assert!(m1.age(now) < m2.age(now));
+        assert_eq!(
+            m1.age("2024-05-01T15:25:08Z".parse().unwrap()),
+            Some(TimeDelta::hours(1))
+        );
     }
 
     #[test]
     fn age_filter() {
         let j = format!("{{\"urls\":[{MIRROR0},{MIRROR1},{MIRROR2}]}}");
+        let now: DateTime<Utc> = "2024-06-01T12:00:00Z".parse().unwrap();
         let mut ml: MirrorList = serde_json::from_str(&j).unwrap();
+        ml = ml.with_clock(&clock::FixedClock(now));
         let mirror = ml.mirrors[0].clone();
-        let now = Utc::now() + TimeDelta::minutes(10);
+        let now = now + TimeDelta::minutes(10);
         for h in 0..20 {
             ml.mirrors.push(Mirror {
                 last_sync: Some(now - TimeDelta::hours(h)),
@@ -571,7 +4411,14 @@ mod tests {
         }
 
         // one mirror's age is -10min
-        assert_eq!(ml.mirrors.len(), 1)
+        assert_eq!(ml.mirrors.len(), 1);
+
+        ml.mirrors = vec![Mirror {
+            last_sync: Some(now - TimeDelta::minutes(150)),
+            ..mirror
+        }];
+        assert_eq!(ml.clone().filter(Some(3.0), false, false, false).len(), 1);
+        assert_eq!(ml.filter(Some(2.0), false, false, false).len(), 0);
     }
 
     #[test]
@@ -606,6 +4453,104 @@ mod tests {
             .iter()
             .all(|m| m.isos.unwrap_or(false) & m.ipv4.unwrap_or(false) & m.ipv6.unwrap_or(false)));
         assert!(ml.mirrors.len() < cur_len);
-        assert!(ml.mirrors.len() > 0);
+        assert!(!ml.mirrors.is_empty());
+    }
+
+    /// mirror with every value used by sort keys known
+    fn known_mirror() -> impl Strategy<Value = Mirror> {
+        (
+            0i64..2_000_000_000,
+            0.0..1e9f64,
+            0.0..1e6f64,
+            0.0..1e6f64,
+            "[A-Z][a-z]{0,5}",
+            0.0..100f64,
+            0.0..100f64,
+        )
+            .prop_map(
+                |(sync, rate, score, delay, country, duration, metadata)| Mirror {
+                    last_sync: DateTime::from_timestamp(sync, 0),
+                    download_rate: Some(Bandwidth(rate)),
+                    score: Some(score),
+                    delay: Some(delay),
+                    country: Some(country),
+                    duration_avg: Some(duration),
+                    metadata_time: Some(metadata),
+                    ..Default::default()
+                },
+            )
+    }
+
+    /// mirror with any value unknown, and rates possibly not a number
+    fn any_mirror() -> impl Strategy<Value = Mirror> {
+        (
+            known_mirror(),
+            proptest::collection::vec(any::<bool>(), 7),
+            any::<bool>(),
+        )
+            .prop_map(|(mut m, unknown, nan)| {
+                if nan {
+                    m.download_rate = Some(Bandwidth(f64::NAN));
+                }
+                if unknown[0] {
+                    m.last_sync = None;
+                }
+                if unknown[1] {
+                    m.download_rate = None;
+                }
+                if unknown[2] {
+                    m.score = None;
+                }
+                if unknown[3] {
+                    m.delay = None;
+                }
+                if unknown[4] {
+                    m.country = None;
+                }
+                if unknown[5] {
+                    m.duration_avg = None;
+                }
+                if unknown[6] {
+                    m.metadata_time = None;
+                }
+                m
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn sort_key_antisymmetric(m in any_mirror(), n in any_mirror()) {
+            for key in SortKey::value_variants() {
+                prop_assert_eq!(key.cmp(&m, &n), key.cmp(&n, &m).reverse(), "{}", key);
+            }
+        }
+
+        #[test]
+        fn sort_key_transitive(a in any_mirror(), b in any_mirror(), c in any_mirror()) {
+            for key in SortKey::value_variants() {
+                if key.cmp(&a, &b).is_le() && key.cmp(&b, &c).is_le() {
+                    prop_assert!(key.cmp(&a, &c).is_le(), "{}", key);
+                }
+            }
+        }
+
+        #[test]
+        fn sort_key_unknown_last(m in known_mirror()) {
+            let unknown = Mirror::default();
+            let nan = Mirror {
+                download_rate: Some(Bandwidth(f64::NAN)),
+                ..Default::default()
+            };
+            for key in [
+                SortKey::Rate,
+                SortKey::Score,
+                SortKey::Delay,
+                SortKey::Duration,
+                SortKey::Metadata,
+            ] {
+                prop_assert_eq!(key.cmp(&m, &unknown), Ordering::Less, "{}", key);
+                prop_assert_eq!(key.cmp(&unknown, &nan), Ordering::Equal, "{}", key);
+            }
+        }
     }
 }
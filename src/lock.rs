@@ -0,0 +1,95 @@
+//! Advisory lock preventing concurrent runs from interleaving writes.
+//!
+//! The lock is held on a file next to the written mirrorlist, so that runs
+//! writing different files do not wait for each other.
+use crate::Error;
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+/// Lock held until dropped, or until the process exits
+#[derive(Debug)]
+pub struct Lock {
+    path: PathBuf,
+    _file: File,
+}
+
+impl Lock {
+    /// Take the lock. If it is held by another run, either wait for it to be
+    /// released or fail with [`Error::Locked`].
+    pub fn acquire(path: &Path, wait: bool) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("unable to open lock file {}", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) if wait => {
+                info!("waiting for lock {}", path.display());
+                file.lock()?;
+            }
+            Err(TryLockError::WouldBlock) => {
+                return Err(Error::Locked { path: path.into() }.into());
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+        debug!("lock {} acquired", path.display());
+        Ok(Self {
+            path: path.into(),
+            _file: file,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Lock file used when writing `target`, or when no single file is written. The
+/// latter is in the runtime directory, the one given by systemd to a service if
+/// any
+pub fn default_path(target: Option<&Path>) -> PathBuf {
+    match target {
+        Some(target) => {
+            let mut name = target.file_name().unwrap_or_default().to_os_string();
+            name.push(".lock");
+            target.with_file_name(name)
+        }
+        None => std::env::var_os("RUNTIME_DIRECTORY")
+            .or_else(|| std::env::var_os("XDG_RUNTIME_DIR"))
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir)
+            .join("reflecto.lock"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclusive() {
+        let path = std::env::temp_dir().join(format!("reflecto-test-{}.lock", std::process::id()));
+        let lock = Lock::acquire(&path, false).unwrap();
+        let err = Lock::acquire(&path, false).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::Locked { .. })
+        ));
+        drop(lock);
+        assert!(Lock::acquire(&path, false).is_ok());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn path_next_to_target() {
+        assert_eq!(
+            default_path(Some(Path::new("/etc/pacman.d/mirrorlist"))),
+            Path::new("/etc/pacman.d/mirrorlist.lock")
+        );
+        assert!(default_path(None).ends_with("reflecto.lock"));
+    }
+}
@@ -1,16 +1,46 @@
 use chrono::Duration;
-use clap::Parser;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
-use tracing;
-use tracing::info;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use rand::Rng;
+use reflecto::aliases::Aliases;
+use reflecto::allowlist::{self, AllowlistFormat};
+use reflecto::auth;
+use reflecto::bundle;
+use reflecto::config::{self, Config};
+use reflecto::error::ExitStatus;
+use reflecto::explain;
+use reflecto::fetch::{fetch_first_if_changed, Preflight};
+use reflecto::lock::Lock;
+use reflecto::notify::{top_server, Event, Notifier};
+use reflecto::overrides::Overrides;
+use reflecto::pacman::PacmanConf;
+use reflecto::plan::Plan;
+use reflecto::profile::{self, Profile};
+use reflecto::proxy;
+use reflecto::report::{self, OutputFormat};
+use reflecto::speedtest;
+use reflecto::state::{self, State};
+use reflecto::stats::{Histogram, Metric};
+use reflecto::trace::Trace;
+use reflecto::units::{self, Units};
+use reflecto::urlfile::UrlFile;
+use reflecto::{
+    BenchProfile, Error, FailurePolicy, Number, RenderOptions, RunConfig, Session, TestMode,
+};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use tracing::{error, info, warn};
 
 /// A port of Reflector.
 ///
 /// This tool retrieve, filter, sort a list of the lastest Arch Linux mirrors
 /// from the archlinux mirror status
 /// and provide the content of the file `/etc/pacman.d/mirrorlist`.
+///
+/// Exit status: 0 on success, 1 on other errors, 2 when no mirror is left after
+/// filtering, 3 when the mirror status cannot be retrieved, 4 when a file cannot
+/// be written, 5 when a measurement failed with --strict or --on-test-error fail,
+/// 6 when the mirrorlist is older than the threshold of the status command.
 #[derive(Parser, Debug)]
 #[command(version, about, long_about)]
 struct Args {
@@ -22,21 +52,158 @@ struct Args {
     #[arg(long)]
     list_countries: bool,
 
-    /// The URL from which to retrieve the mirror date in JSON format
-    #[arg(long, default_value_t=reflecto::MIRROR_STATUS_URL.into())]
-    url: String,
+    /// Sort the table of countries by the rules of the locale of LC_ALL,
+    /// LC_COLLATE or LANG, e.g. Åland next to Australia, or after Zambia in
+    /// Swedish. Without the collation feature, case and accents are ignored
+    #[arg(long, requires = "list_countries")]
+    collate: bool,
+
+    /// The URL from which to retrieve the mirror date. Defaults to the one of the distribution.
+    /// May be repeated, mirrors of all the URLs are then ranked together
+    #[arg(long)]
+    url: Vec<String>,
+
+    /// Distribution whose mirrors are ranked
+    #[arg(long, default_value_t=reflecto::Distro::Arch)]
+    distro: reflecto::Distro,
+
+    /// TOML or JSON file mapping the fields of a custom status document at --url
+    #[arg(long, requires = "url")]
+    schema: Option<PathBuf>,
+
+    /// URL tried, in order, when the status cannot be retrieved from --url
+    #[arg(long)]
+    fallback_url: Vec<String>,
+
+    /// Request the status URL and its fallbacks at once, keeping the first valid
+    /// status. For equivalent status URLs, e.g. served from several regions
+    #[arg(long)]
+    race_urls: bool,
+
+    /// Number of times the retrieval of the status is retried
+    #[arg(long, default_value_t = 0)]
+    retries: u32,
+
+    /// Delay before the first retry, e.g. 0.5s or 1m. Doubled after each retry
+    #[arg(long, default_value = "1", value_parser = parse_duration)]
+    retry_delay: std::time::Duration,
+
+    /// Header added to the requests, e.g. "Authorization: Bearer TOKEN", for
+    /// private status endpoints and mirrors. Sent to the hosts of the status
+    /// URLs, unless prefixed by a host, e.g.
+    /// "mirror.internal=Authorization: Bearer TOKEN". May be repeated
+    #[arg(long, value_name = "[HOST=]NAME: VALUE")]
+    auth_header: Vec<String>,
+
+    /// Authenticate to the hosts listed in $NETRC or ~/.netrc
+    #[arg(long)]
+    netrc: bool,
+
+    /// Authenticate to the hosts listed in this netrc file
+    #[arg(long)]
+    netrc_file: Option<PathBuf>,
+
+    /// Check first that the network is not intercepted by a captive portal,
+    /// which would make every mirror look fast but broken, by fetching a
+    /// resource of known content. Defaults to the connectivity check of Arch
+    /// Linux
+    #[arg(long, num_args = 0..=1, default_missing_value = reflecto::fetch::PREFLIGHT_URL)]
+    preflight: Option<String>,
+
+    /// Content expected from the --preflight resource
+    #[arg(long, requires = "preflight", default_value = reflecto::fetch::PREFLIGHT_EXPECTED)]
+    preflight_expect: String,
+
+    /// Proxy of every request, e.g. http://proxy:3128 or socks5h://proxy:1080
+    /// to also resolve host names through it. Defaults to the proxies of the
+    /// environment. The reachability check connects directly
+    #[arg(long, value_parser = parse_proxy)]
+    proxy: Option<reqwest::Proxy>,
+
+    /// Rank the servers of a mirrorlist file (or of a file listing URLs) instead of
+    /// retrieving the mirror status
+    #[arg(long, conflicts_with_all = ["url", "schema"])]
+    from_mirrorlist: Option<PathBuf>,
+
+    /// Remove the mirrors whose URL is invalid: malformed, containing
+    /// whitespace, or whose scheme is unsupported or does not match the
+    /// protocol. Otherwise they are only reported
+    #[arg(long)]
+    strict_urls: bool,
 
-    #[arg(short, long, default_value_t=reflecto::SortKey::Score)]
+    /// Write the snapshot of the repositories on this date in the archive of
+    /// the distribution, e.g. 2025/03/01, instead of ranking the mirrors
+    #[arg(long, value_parser = parse_date, conflicts_with_all = ["url", "schema", "from_mirrorlist"])]
+    snapshot: Option<chrono::NaiveDate>,
+
+    /// Sort key: age (or last_sync), rate, country, score, delay or duration.
+    /// Unambiguous prefixes are accepted
+    #[arg(short, long, default_value_t=reflecto::SortKey::Score, value_parser = parse_sort)]
     sort: reflecto::SortKey,
 
-    /// the number of mirrors to keep
-    #[arg(short, long, default_value_t=usize::MAX)]
-    number: usize,
+    /// Print on stderr why each mirror was kept or not, as text or JSON
+    #[arg(long, num_args = 0..=1, default_missing_value = "text")]
+    explain: Option<explain::Format>,
+
+    /// Do not compute the score of mirrors lacking one from their delay, durations
+    /// and completion
+    #[arg(long)]
+    no_score_recompute: bool,
+
+    /// Randomly choose n mirrors among the best ones, weighted by rate or score
+    #[arg(long, value_parser = parse_positive)]
+    pick_random: Option<usize>,
+
+    /// Number of best mirrors among which --pick-random chooses
+    #[arg(long, requires = "pick_random", default_value_t = 10, value_parser = parse_positive)]
+    pool: usize,
+
+    /// the number of mirrors to keep, 0 or all to keep every mirror
+    #[arg(short, long, default_value_t)]
+    number: Number,
+
+    /// Number of mirrors whose rate test must succeed, 0 or all to test every
+    /// mirror. Defaults to --number
+    #[arg(long)]
+    test_limit: Option<Number>,
+
+    /// Format of the generated file
+    #[arg(long, default_value = "mirrorlist")]
+    output_format: OutputFormat,
+
+    /// Write a JSON report of the run there: inputs, decisions, measurements of
+    /// the kept mirrors and timings
+    #[arg(long)]
+    report: Option<PathBuf>,
 
     /// If provided, where to save. otherwise, output on stdin
     #[arg(long)]
     save: Option<PathBuf>,
 
+    /// Only write the output on stdout, never to a file. Logs always go to stderr
+    #[arg(long, conflicts_with_all = ["save", "outputs", "write_fd", "pacman_conf"])]
+    stdout_only: bool,
+
+    /// Print the URL of the best mirror alone instead of the mirrorlist, e.g. for
+    /// pacstrap
+    #[arg(long, conflicts_with_all = ["save", "outputs", "write_fd", "pacman_conf"])]
+    print_top_url: bool,
+
+    /// Only log warnings and errors
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Write the output to this file descriptor, opened by the parent process,
+    /// instead of stdout. Lets reflecto run unprivileged while a privileged parent
+    /// opens the mirrorlist, e.g. with OpenFile= in a systemd service
+    #[arg(long, conflicts_with_all = ["save", "outputs", "pacman_conf"])]
+    write_fd: Option<i32>,
+
+    /// pacman configuration from which the mirrorlist location is read.
+    /// The mirrorlist is then saved there unless --save is given
+    #[arg(long, num_args = 0..=1, default_missing_value = reflecto::pacman::PACMAN_CONF)]
+    pacman_conf: Option<PathBuf>,
+
     /// Only return mirrors that have synchronized in the last n hours. n may be an integer or
     /// a decimal number.
     #[arg(short, long)]
@@ -53,31 +220,1956 @@ struct Args {
     /// Only return mirrors that support IPv6.
     #[arg(long)]
     ipv6: bool,
+
+    /// Only return mirrors located in these countries, given by name or code.
+    /// May be repeated or comma separated
+    #[arg(short, long, value_delimiter = ',', value_parser = CountryParser, hide_possible_values = true)]
+    country: Vec<String>,
+
+    /// Only return mirrors whose percentage of successful checks is at least n
+    #[arg(long)]
+    completion_percent: Option<f64>,
+
+    /// Retrieve the details page of each mirror, to use the history of its checks
+    /// for filtering and sorting
+    #[arg(long)]
+    details: bool,
+
+    /// Only return mirrors of tier n or lower. Implies --details
+    #[arg(long)]
+    tier: Option<u8>,
+
+    /// Information added as a comment after each server line
+    #[arg(long, value_delimiter = ',')]
+    annotate: Vec<reflecto::Annotation>,
+
+    /// Also write this number of mirrors following the selected ones, their
+    /// server lines commented out, as fallbacks to enable by hand
+    #[arg(long, default_value_t = 0)]
+    commented_extras: usize,
+
+    /// Lines of the header of the mirrorlist, in order
+    #[arg(long, value_delimiter = ',', default_values_t = reflecto::HeaderField::DEFAULT)]
+    header: Vec<reflecto::HeaderField>,
+
+    /// Write the server lines without header
+    #[arg(long, conflicts_with = "header")]
+    no_header: bool,
+
+    /// Group the server lines under a `## Country` comment per country, the
+    /// countries in the order of their best mirror
+    #[arg(long)]
+    group_by_country: bool,
+
+    /// URL to which a JSON description of a degradation is POSTed
+    #[arg(long)]
+    notify_webhook: Option<String>,
+
+    /// Shell command receiving a JSON description of a degradation on stdin
+    #[arg(long)]
+    notify_cmd: Option<String>,
+
+    /// Notify when fewer than n mirrors pass the filters
+    #[arg(long, default_value_t = 1)]
+    notify_min_mirrors: usize,
+
+    /// Maximum duration of the whole run, e.g. 60s or 2m. When reached, the mirrorlist
+    /// is written with the data gathered so far
+    #[arg(long, value_parser = parse_duration)]
+    max_duration: Option<std::time::Duration>,
+
+    /// Maximum number of bytes downloaded by all the rate tests. Mirrors not tested
+    /// once it is reached are ranked by score
+    #[arg(long)]
+    max_test_bytes_total: Option<u64>,
+
+    /// Maximum rate of each rate test, e.g. 5MiB/s or 500kB/s, so that the
+    /// tests do not saturate the network of the host. Mirrors faster than it
+    /// are all measured at about this rate
+    #[arg(long, value_parser = parse_rate)]
+    test_throttle: Option<u64>,
+
+    /// Run in the background without impacting the host: lower the CPU and
+    /// I/O priority, test the mirrors one at a time, download at most 32 MiB
+    /// at 2 MiB/s. Options given explicitly take precedence
+    #[arg(long)]
+    nice: bool,
+
+    /// Write the timings of the probes, rate tests and stages of the run to
+    /// this file, as a Chrome trace to open with Perfetto or chrome://tracing
+    #[arg(long, value_name = "PATH")]
+    trace_out: Option<PathBuf>,
+
+    /// Do not measure the mirrors, sorting them by score instead, when the
+    /// connection is metered or the host runs on battery, as told by
+    /// NetworkManager and /sys on Linux
+    #[arg(long)]
+    skip_tests_on_metered: bool,
+
+    /// How download rates are measured: all mirrors at once, one at a time,
+    /// latencies at once then rates one at a time, lowest latency first, or
+    /// in rounds of a concurrency adapted to the link
+    #[arg(long, value_enum, default_value_t)]
+    test_mode: TestMode,
+
+    /// Prefer mirrors of these protocols, in order, to equal mirrors of other
+    /// protocols, and drop the URLs of hosts also listed under a more preferred one
+    #[arg(long, value_delimiter = ',')]
+    prefer_protocol: Vec<reflecto::Protocol>,
+
+    /// Remove the mirrors that are unreachable or lack the repositories, with a
+    /// cheap request to each, before measuring the others
+    #[arg(long)]
+    check_alive: bool,
+
+    /// Connect to the mirrors over IPv4 and IPv6 and report which versions
+    /// actually work, rather than the ones advertised by the status
+    #[arg(long)]
+    check_reachability: bool,
+
+    /// Only return mirrors accepting IPv4 connections from this machine.
+    /// Implies --check-reachability
+    #[arg(long)]
+    reachable_ipv4: bool,
+
+    /// Only return mirrors accepting IPv6 connections from this machine.
+    /// Implies --check-reachability
+    #[arg(long)]
+    reachable_ipv6: bool,
+
+    /// Check that mirrors keep the connection open between requests, as pacman
+    /// reuses connections, and remove the ones closing it
+    #[arg(long)]
+    require_keep_alive: bool,
+
+    /// Check that the written mirrors publish the checksums and signature of
+    /// the latest installation image, commenting out the others with
+    /// --output-format isos
+    #[arg(long)]
+    verify_isos: bool,
+
+    /// Resolve the addresses of the written mirrors and show them in the
+    /// reports, for firewalls allowing connections by address. Changes since
+    /// the last run recorded in the state file are flagged
+    #[arg(long)]
+    resolve: bool,
+
+    /// Remove the mirrors whose rate test is redirected to another host
+    #[arg(long)]
+    no_redirects: bool,
+
+    /// Fraction, between 0 and 1, removed from the download rate of the mirrors
+    /// whose rate test is redirected to another host
+    #[arg(long, default_value_t = 0.0, conflicts_with = "no_redirects")]
+    redirect_penalty: f64,
+
+    /// What is measured: the download rate of a big database, used by --sort rate,
+    /// or the time to fetch small metadata files, used by --sort metadata
+    #[arg(long, value_enum, default_value_t)]
+    bench_profile: BenchProfile,
+
+    /// Test the mirrors in random order, so that machines refreshing at the same time
+    /// do not all start with the same mirrors
+    #[arg(long)]
+    shuffle_tests: bool,
+
+    /// Wait a random time before starting, up to the given duration or within a
+    /// range, e.g. 15m or 1m-15m, to spread the load of machines refreshing at
+    /// the same time
+    #[arg(long, value_parser = parse_delay_range)]
+    random_delay: Option<(std::time::Duration, std::time::Duration)>,
+
+    /// Same output for the same input, e.g. to diff mirrorlists generated in
+    /// CI: no random order nor delay, ties ordered by URL, and ages computed at
+    /// --now or else at the last check of the status
+    #[arg(long, conflicts_with_all = ["shuffle_tests", "pick_random", "random_delay"])]
+    deterministic: bool,
+
+    /// Reference time of age computations and of the header, e.g.
+    /// 2024-05-01T12:00:00Z, instead of the current time
+    #[arg(long)]
+    now: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// Measure download rates one mirror at a time. Same as --test-mode sequential
+    #[arg(long, conflicts_with = "test_mode")]
+    sequential_tests: bool,
+
+    /// Lock file. Defaults to the written file suffixed by .lock, or to reflecto.lock
+    /// in the runtime directory
+    #[arg(long)]
+    lock_file: Option<PathBuf>,
+
+    /// File recording the outcome of the runs writing a mirrorlist, read by the
+    /// status command. Defaults to state.json in the state directory
+    #[arg(long)]
+    state_file: Option<PathBuf>,
+
+    /// Wait for a concurrent run to finish instead of failing
+    #[arg(long, conflicts_with = "no_lock")]
+    wait_lock: bool,
+
+    /// Do not lock the written file against concurrent runs
+    #[arg(long)]
+    no_lock: bool,
+
+    /// Exit with an error if a selected mirror could not be tested (rate test or
+    /// details). Same as --on-details-error fail --on-test-error fail
+    #[arg(long)]
+    strict: bool,
+
+    /// What to do when the details of the mirrors cannot be retrieved. Defaults
+    /// to warn
+    #[arg(long, value_enum)]
+    on_details_error: Option<FailurePolicy>,
+
+    /// What to do when a probe of the mirrors (liveness check, keep-alive or
+    /// metadata measurement) fails
+    #[arg(long, value_enum, default_value_t)]
+    on_probe_error: FailurePolicy,
+
+    /// What to do when a written mirror could not be measured. Defaults to skip
+    #[arg(long, value_enum)]
+    on_test_error: Option<FailurePolicy>,
+
+    /// When measurement failures are fatal, keep the existing file instead of
+    /// writing a degraded mirrorlist
+    #[arg(long)]
+    keep_on_failure: bool,
+
+    /// Configuration file. Defaults to $XDG_CONFIG_HOME/reflecto/config.toml
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Use the options of a profile saved with --save-profile, overridden by the
+    /// ones given on the command line
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Save the options given on the command line as a profile of this name, in
+    /// the profiles directory of the configuration directory
+    #[arg(long)]
+    save_profile: Option<String>,
+
+    /// Directory of the default configuration, blacklist, pins, overrides and
+    /// aliases files. Defaults to $XDG_CONFIG_HOME/reflecto, or to
+    /// $CONFIGURATION_DIRECTORY in a systemd service
+    #[arg(long, global = true)]
+    config_dir: Option<PathBuf>,
+
+    /// Render and save every file of the [outputs] section of the configuration
+    /// instead of a single mirrorlist
+    #[arg(long, conflicts_with = "save")]
+    outputs: bool,
+
+    /// Write the mirrorlist, the status of the kept mirrors and a manifest to
+    /// this directory, laid out as an archiso profile to be copied over it,
+    /// instead of a single mirrorlist
+    #[arg(long, conflicts_with_all = ["save", "outputs"])]
+    bundle: Option<PathBuf>,
+
+    /// File of URLs and domains always excluded.
+    /// Defaults to $XDG_CONFIG_HOME/reflecto/blacklist
+    #[arg(long, global = true)]
+    blacklist: Option<PathBuf>,
+
+    /// File of URLs and domains always kept and ranked first.
+    /// Defaults to $XDG_CONFIG_HOME/reflecto/pins
+    #[arg(long, global = true)]
+    pins: Option<PathBuf>,
+
+    /// TOML file of local corrections of the mirror status.
+    /// Defaults to $XDG_CONFIG_HOME/reflecto/overrides.toml
+    #[arg(long)]
+    overrides: Option<PathBuf>,
+
+    /// TOML file of mirrors known to be CDN frontends or aliases of the same
+    /// backend, added to the built-in ones.
+    /// Defaults to $XDG_CONFIG_HOME/reflecto/aliases.toml
+    #[arg(long)]
+    aliases: Option<PathBuf>,
+
+    /// List a single mirror of each known CDN or backend before the other
+    /// mirrors, so that the first mirrors are distinct servers. See --aliases
+    #[arg(long)]
+    dedupe_backends: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage the mirrors always excluded
+    Blacklist {
+        #[command(subcommand)]
+        action: ListAction,
+    },
+    /// Print everything known about a mirror, given by URL or domain
+    Info {
+        mirror: String,
+        /// Measure the download rate of the mirror
+        #[arg(long)]
+        rate: bool,
+    },
+    /// Refresh the mirrorlist each time the mirror status changes.
+    ///
+    /// Every --url is polled, each one falling back to --fallback-url, and
+    /// parsed with --schema if given. --from-mirrorlist cannot be watched.
+    ///
+    /// SIGHUP reloads the configuration, blacklist, pins, overrides and aliases
+    /// files then refreshes the mirrorlist; SIGUSR1 refreshes it immediately,
+    /// e.g. after a network change.
+    Watch {
+        /// Time between two polls of the status, e.g. 30m
+        #[arg(long, default_value = "30m", value_parser = parse_duration)]
+        interval: std::time::Duration,
+    },
+    /// Periodically compare a mirror with the median of the mirrors, notifying
+    /// when it falls behind
+    Monitor {
+        /// URL of the monitored mirror
+        #[arg(long)]
+        mine: String,
+        /// Time between two checks, e.g. 1h
+        #[arg(long, default_value = "1h", value_parser = parse_duration)]
+        interval: std::time::Duration,
+        /// Number of other mirrors whose download rate is measured for the median.
+        /// 0 disables rate measurement
+        #[arg(long, default_value_t = 5)]
+        sample: usize,
+        /// Check once and exit
+        #[arg(long)]
+        once: bool,
+    },
+    /// Select the mirrors and record the proposed mirrorlist, to be written by apply
+    Plan {
+        /// Where the plan is saved
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Select the mirrors and print the addresses of their hosts as firewall
+    /// rules, for servers only allowed to connect to the chosen mirrors
+    ExportAllowlist {
+        #[arg(long, value_enum, default_value_t)]
+        format: AllowlistFormat,
+    },
+    /// Select the mirrors and print the best one of each country, in the order
+    /// of --sort, as a table or, with --output-format json, as JSON
+    BestPerCountry,
+    /// Write the mirrorlist recorded in a plan, to --save or to the planned file.
+    /// Lets the selection run as an unprivileged user, only apply being run with
+    /// the privileges needed to write the mirrorlist
+    Apply {
+        plan: PathBuf,
+        /// Refuse plans whose content is not a pacman mirrorlist of server lines,
+        /// and only write to --save, ignoring the file named in the plan
+        #[arg(long)]
+        only_mirrorlist: bool,
+    },
+    /// Measure the download rate of arbitrary URLs, e.g. to compare a private mirror
+    /// with public ones. Uses --download-timeout, --max-test-bytes-total,
+    /// --max-duration and --output-format json
+    Speedtest {
+        /// URLs of the downloaded files
+        #[arg(required = true)]
+        urls: Vec<String>,
+        /// Number of URLs tested at once. 1 with --sequential-tests
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
+    },
+    /// Compare two saved mirror status files
+    Diff { old: PathBuf, new: PathBuf },
+    /// Print when the mirrorlist was last refreshed and whether the last run
+    /// succeeded. The state of the system service, in
+    /// /var/lib/reflecto/state.json, is read when the user has none
+    Status {
+        /// Exit with status 6 if the mirrorlist was last refreshed longer ago
+        /// than this, e.g. 7d. The modification time of --save is used when it
+        /// is more recent than the recorded refresh
+        #[arg(long, value_parser = parse_duration)]
+        warn_if_older_than: Option<std::time::Duration>,
+    },
+    /// Print the completion script of a shell
+    Completions { shell: clap_complete::Shell },
+    /// Refresh with the options of a preset, overridden by the ones given on the
+    /// command line. Without name, list the presets
+    Preset {
+        name: Option<String>,
+        /// Print the options of the preset instead of using it
+        #[arg(long, requires = "name")]
+        show: bool,
+    },
+    /// Print the distribution of a metric across the mirrors passing the
+    /// filters, to choose thresholds. Rates are measured first, within
+    /// --test-limit, 50 mirrors by default
+    Stats {
+        /// Metric shown as a histogram
+        #[arg(long, value_enum)]
+        histogram: Metric,
+        /// Number of intervals of the histogram
+        #[arg(long, default_value_t = 10)]
+        bins: usize,
+    },
+    /// Manage the mirrors always kept and ranked first
+    Pin {
+        #[command(subcommand)]
+        action: ListAction,
+    },
+    /// Write a systemd service and timer refreshing the mirrorlist periodically
+    InstallUnits {
+        /// Where the units are written. Defaults to /etc/systemd/system, or to the
+        /// user unit directory with --user
+        #[arg(long)]
+        dir: Option<PathBuf>,
+        /// When the timer triggers, as a systemd calendar event
+        #[arg(long, default_value = "weekly")]
+        on_calendar: String,
+        /// Maximum random delay added to each trigger, e.g. 15m
+        #[arg(long, value_parser = parse_duration)]
+        randomized_delay: Option<std::time::Duration>,
+        /// Run a trigger missed while the machine was off at the next boot
+        #[arg(long)]
+        persistent: bool,
+        /// Units of the user service manager, for rootless setups
+        #[arg(long)]
+        user: bool,
+        /// Run the system service as an unprivileged dynamic user, systemd opening
+        /// the mirrorlist for it. Requires systemd 253 or later
+        #[arg(long, conflicts_with = "user")]
+        unprivileged: bool,
+        /// Sandbox the system service, only allowing it to write the mirrorlist. The
+        /// configuration is then read from /etc/reflecto
+        #[arg(long, conflicts_with = "user")]
+        hardened: bool,
+        /// Mirrorlist written by the service. Defaults to /etc/pacman.d/mirrorlist,
+        /// or to mirrorlist in the configuration directory with --user
+        #[arg(long)]
+        mirrorlist: Option<PathBuf>,
+        /// Options of the refreshes, after --, e.g. -- --country FR --sort rate
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+}
+
+/// Edition of a file of URLs and domains
+#[derive(Subcommand, Debug)]
+enum ListAction {
+    /// Add URLs or domains
+    Add {
+        #[arg(required = true)]
+        entries: Vec<String>,
+    },
+    /// Remove URLs or domains
+    Remove {
+        #[arg(required = true)]
+        entries: Vec<String>,
+    },
+    /// Print every entry
+    List,
 }
 
-#[tokio::main]
-async fn main() {
+fn main() -> ExitCode {
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    // stdout is kept for the output
+    #[cfg(feature = "log")]
     tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
+        .with_writer(std::io::stderr)
+        .with_max_level(if args.quiet {
+            tracing::Level::WARN
+        } else {
+            tracing::Level::INFO
+        })
         .init();
-    let args = Args::parse();
-    let mut mlist = reflecto::MirrorList::from_url(&args.url).await.unwrap();
-    if args.list_countries {
-        println!("{}", mlist.print_countries());
-        return;
+    if args.nice {
+        // before the threads of the runtime are started, as they inherit it
+        lower_priority();
+    }
+    let trace_out = args.trace_out.clone();
+    let trace = match trace_out {
+        Some(_) => Trace::enabled(),
+        None => Trace::default(),
+    };
+    let runtime = tokio::runtime::Runtime::new().expect("unable to start the async runtime");
+    let res = runtime.block_on(run(args, &matches, trace.clone()));
+    // written even if the run failed, as slow runs often time out
+    if let Some(path) = trace_out {
+        if let Err(e) = trace.write(&path) {
+            warn!("{e:#}");
+        }
+    }
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            if cfg!(feature = "log") {
+                error!("{e:#}");
+            } else {
+                eprintln!("error: {e:#}");
+            }
+            ExitStatus::of(&e).into()
+        }
+    }
+}
+
+async fn run(mut args: Args, matches: &ArgMatches, trace: Trace) -> anyhow::Result<()> {
+    if let Some(Command::Completions { shell }) = &args.command {
+        clap_complete::generate(
+            *shell,
+            &mut Args::command(),
+            "reflecto",
+            &mut std::io::stdout(),
+        );
+        return Ok(());
+    }
+    let dir = config_dir(&args);
+    if let Some(name) = &args.save_profile {
+        let Some(dir) = &dir else {
+            anyhow::bail!("no configuration directory, give one with --config-dir");
+        };
+        let path = profile::profile_path(dir, name)?;
+        given_profile(&args, matches).save(&path)?;
+        info!("profile {name} saved to {:?}", path);
+    }
+    let mut options = match (&args.profile, &dir) {
+        (Some(name), Some(dir)) => profile::profile(name, dir)?,
+        (Some(_), None) => anyhow::bail!("no configuration directory, give one with --config-dir"),
+        (None, _) => Profile::default(),
+    };
+    if let Some(Command::Preset { name, show }) = &args.command {
+        let Some(name) = name.clone() else {
+            for name in profile::preset_names(dir.as_deref()) {
+                let description = profile::preset(&name, dir.as_deref())?.description;
+                println!("{name}\t{}", description.unwrap_or_default());
+            }
+            return Ok(());
+        };
+        let preset = profile::preset(&name, dir.as_deref())?;
+        if *show {
+            print!("{}", preset.to_toml());
+            return Ok(());
+        }
+        options = options.or(preset);
+    }
+    apply_profile(&mut args, matches, &options)?;
+    if args.nice {
+        apply_nice(&mut args, matches);
+    }
+    if args.skip_tests_on_metered {
+        if let Some(constraint) = reflecto::power::constraint().await {
+            skip_tests(&mut args, constraint);
+        }
+    }
+    let session = session(&args)?
+        .with_proxy(args.proxy.clone())
+        .with_trace(trace);
+    if args.output_format == OutputFormat::Isos && args.distro.iso_paths().is_none() {
+        anyhow::bail!("no installation image known for {}", args.distro.name());
+    }
+    let blacklist = load_list(args.blacklist.as_deref(), config_file(&args, "blacklist"))?;
+    let pins = load_list(args.pins.as_deref(), config_file(&args, "pins"))?;
+    match &args.command {
+        Some(Command::Blacklist { action }) => return edit_list(blacklist, action),
+        Some(Command::Pin { action }) => return edit_list(pins, action),
+        Some(Command::Diff { old, new }) => {
+            let old = reflecto::MirrorList::from_status_file(old, args.distro)?;
+            let new = reflecto::MirrorList::from_status_file(new, args.distro)?;
+            for change in reflecto::diff::diff(&old, &new) {
+                println!("{change}");
+            }
+            return Ok(());
+        }
+        Some(Command::Apply {
+            plan,
+            only_mirrorlist,
+        }) => return apply(&args, plan, *only_mirrorlist),
+        Some(Command::Status { warn_if_older_than }) => {
+            return status(&args, *warn_if_older_than);
+        }
+        Some(Command::Speedtest { urls, concurrency }) => {
+            let deadline = args.max_duration.map(|d| tokio::time::Instant::now() + d);
+            let timeout = Some(Duration::seconds(args.download_timeout));
+            let pipeline = reflecto::Pipeline::with_session(timeout, deadline, &session)?;
+            let budget = reflecto::ByteBudget::new(args.max_test_bytes_total)
+                .with_throttle(args.test_throttle);
+            let concurrency = if args.sequential_tests {
+                1
+            } else {
+                *concurrency
+            };
+            let tests =
+                speedtest::speedtest(&pipeline, urls.clone(), concurrency, timeout, &budget).await;
+            if tests.len() < urls.len() {
+                warn!(
+                    "deadline reached, {} URLs not tested",
+                    urls.len() - tests.len()
+                );
+            }
+            if args.output_format == OutputFormat::Json {
+                println!("{}", serde_json::to_string_pretty(&tests)?);
+            } else {
+                println!("{}", speedtest::render(&tests));
+            }
+            return Ok(());
+        }
+        Some(Command::InstallUnits {
+            dir,
+            on_calendar,
+            randomized_delay,
+            persistent,
+            user,
+            unprivileged,
+            hardened,
+            mirrorlist,
+            args: options,
+        }) => {
+            let Some(dir) = dir.clone().or_else(|| units::default_dir(*user)) else {
+                anyhow::bail!("no configuration directory, give the directory of the units");
+            };
+            let Some(mirrorlist) = mirrorlist
+                .clone()
+                .or_else(|| units::default_mirrorlist(*user))
+            else {
+                anyhow::bail!("no configuration directory, give the path of the mirrorlist");
+            };
+            let units = Units {
+                exec: std::env::current_exe()?,
+                mirrorlist,
+                args: options.clone(),
+                on_calendar: on_calendar.clone(),
+                randomized_delay: *randomized_delay,
+                persistent: *persistent,
+                user: *user,
+                unprivileged: *unprivileged,
+                hardened: *hardened,
+            };
+            return install_units(&units, &dir);
+        }
+        Some(
+            Command::Info { .. }
+            | Command::Watch { .. }
+            | Command::Monitor { .. }
+            | Command::Plan { .. }
+            | Command::ExportAllowlist { .. }
+            | Command::BestPerCountry
+            | Command::Stats { .. }
+            | Command::Completions { .. }
+            | Command::Preset { .. },
+        )
+        | None => {}
+    }
+    if let (Some((min, max)), false) = (args.random_delay, args.deterministic) {
+        let delay = rand::rng().random_range(min..=max);
+        info!("waiting {delay:?} before starting");
+        tokio::time::sleep(delay).await;
+    }
+    let deadline = args.max_duration.map(|d| tokio::time::Instant::now() + d);
+    if let Some(path) = &args.pacman_conf {
+        let conf = PacmanConf::from_file(path)?;
+        match conf.mirrorlist() {
+            Some(mirrorlist) => {
+                info!(
+                    "mirrorlist {:?} used by {} (architecture: {})",
+                    mirrorlist,
+                    conf.repos_using(mirrorlist).join(", "),
+                    conf.architecture.as_deref().unwrap_or("auto")
+                );
+                if args.save.is_none() && !args.outputs {
+                    args.save = Some(mirrorlist.into());
+                }
+            }
+            None => error!("no mirrorlist included in {:?}", path),
+        }
+    }
+    let config = load_config(&args)?;
+    let _lock = if args.no_lock || (args.save.is_none() && !args.outputs) {
+        None
+    } else {
+        let path = args
+            .lock_file
+            .clone()
+            .unwrap_or_else(|| reflecto::lock::default_path(args.save.as_deref()));
+        Some(Lock::acquire(&path, args.wait_lock)?)
+    };
+    let notifier = Notifier {
+        webhook: args.notify_webhook.clone(),
+        command: args.notify_cmd.clone(),
+        session: session.clone(),
+    };
+    let overrides = load_overrides(&args)?;
+    let aliases = load_aliases(&args)?;
+    let schema = load_schema(&args)?;
+    let mut ctx = Context {
+        config,
+        notifier,
+        blacklist,
+        pins,
+        overrides,
+        aliases,
+        schema,
+        session,
+    };
+    if let Some(date) = args.snapshot {
+        let config = run_config(&args, &ctx, deadline);
+        let mlist = reflecto::run::prepare(
+            &config,
+            reflecto::MirrorList::from_snapshot(args.distro, date)?,
+        );
+        let content = report::render(&mlist, args.output_format, &config.render_options());
+        let res = write_output(&args, &content).map(|()| mlist);
+        record_state(&args, res.as_ref());
+        return res.map(|_| ());
+    }
+    if let Some(url) = &args.preflight {
+        let preflight = Preflight {
+            url: url.clone(),
+            expected: args.preflight_expect.clone(),
+        };
+        let timeout = std::time::Duration::from_secs(args.download_timeout.max(0) as u64);
+        preflight.check(Some(timeout), &ctx.session).await?;
+    }
+    if let Some(Command::Watch { interval }) = &args.command {
+        return watch(&args, &mut ctx, *interval).await;
+    }
+    if let Some(Command::Monitor {
+        mine,
+        interval,
+        sample,
+        once,
+    }) = &args.command
+    {
+        loop {
+            if let Err(e) = monitor(&args, &ctx, mine, *sample).await {
+                error!("{e:#}");
+            }
+            if *once {
+                return Ok(());
+            }
+            tokio::time::sleep(*interval).await;
+        }
+    }
+    let inspects = matches!(
+        &args.command,
+        Some(Command::Info { .. } | Command::Stats { .. })
+    );
+    if !inspects && !args.list_countries {
+        return refresh(&args, &ctx, None, deadline).await.map(|_| ());
+    }
+    let mut mlist = match reflecto::run::fetch(&run_config(&args, &ctx, deadline)).await {
+        Ok(mlist) => mlist,
+        Err(e) => {
+            notify_fetch_failure(&ctx.notifier, &e).await;
+            record_state(&args, Err(&e));
+            return Err(e);
+        }
+    };
+    if let Some(Command::Info { mirror, rate }) = &args.command {
+        return info(
+            &args,
+            &mlist,
+            mirror,
+            *rate,
+            &[("blacklisted", &ctx.blacklist), ("pinned", &ctx.pins)],
+        )
+        .await;
+    }
+    if let Some(Command::Stats { histogram, bins }) = &args.command {
+        mlist.exclude(&ctx.blacklist);
+        let config = run_config(&args, &ctx, deadline);
+        return stats(&args, &config, mlist, *histogram, *bins).await;
+    }
+    mlist.exclude(&ctx.blacklist);
+    println!(
+        "{}",
+        mlist.print_countries_with(
+            args.collate
+                .then(reflecto::collation::Collation::from_env)
+                .as_ref(),
+        )
+    );
+    Ok(())
+}
+
+/// notify that the status could not be retrieved
+async fn notify_fetch_failure(notifier: &Notifier, e: &anyhow::Error) {
+    // the cause, under the context of the failure
+    let cause = e.chain().nth(1).unwrap_or(e.as_ref());
+    let _ = notifier
+        .notify(&Event::RefreshFailed {
+            error: cause.to_string(),
+        })
+        .await;
+}
+
+/// state shared by the refreshes of a run
+struct Context {
+    config: Config,
+    notifier: Notifier,
+    blacklist: UrlFile,
+    pins: UrlFile,
+    overrides: Overrides,
+    aliases: Aliases,
+    /// format of the status, if custom
+    schema: Option<reflecto::SchemaMapping>,
+    /// settings of the requests, see [`session`]
+    session: Session,
+}
+
+impl Context {
+    /// read again the configuration, blacklist, pins, overrides, aliases and
+    /// schema files, keeping the current ones if one is invalid
+    fn reload(&mut self, args: &Args) -> anyhow::Result<()> {
+        let config = load_config(args)?;
+        let blacklist = load_list(args.blacklist.as_deref(), config_file(args, "blacklist"))?;
+        let pins = load_list(args.pins.as_deref(), config_file(args, "pins"))?;
+        let overrides = load_overrides(args)?;
+        let aliases = load_aliases(args)?;
+        let schema = load_schema(args)?;
+        *self = Context {
+            config,
+            notifier: self.notifier.clone(),
+            blacklist,
+            pins,
+            overrides,
+            aliases,
+            schema,
+            session: self.session.clone(),
+        };
+        Ok(())
+    }
+}
+
+/// selection options given on the command line
+fn run_config(args: &Args, ctx: &Context, deadline: Option<tokio::time::Instant>) -> RunConfig {
+    RunConfig {
+        distro: args.distro,
+        urls: args.url.clone(),
+        fallback_urls: args.fallback_url.clone(),
+        race_urls: args.race_urls,
+        retry: reflecto::Retry {
+            retries: args.retries,
+            backoff: args.retry_delay,
+        },
+        schema: ctx.schema.clone(),
+        mirrorlist_file: args.from_mirrorlist.clone(),
+        session: ctx.session.clone(),
+        blacklist: ctx.blacklist.clone(),
+        pins: ctx.pins.clone(),
+        overrides: ctx.overrides.clone(),
+        aliases: ctx.aliases.clone(),
+        dedupe_backends: args.dedupe_backends,
+        filters: reflecto::Filters {
+            age: args.age,
+            isos: args.isos || args.output_format == OutputFormat::Isos,
+            ipv4: args.ipv4,
+            ipv6: args.ipv6,
+            countries: args.country.clone(),
+            completion: args.completion_percent,
+            tier: args.tier,
+        },
+        details: args.details,
+        sort: args.sort.clone(),
+        number: args.number,
+        test_limit: args.test_limit,
+        download_timeout: Duration::seconds(args.download_timeout),
+        deadline,
+        on_details_error: strict_policy(args.on_details_error, args.strict, FailurePolicy::Warn),
+        on_probe_error: args.on_probe_error,
+        on_test_error: strict_policy(args.on_test_error, args.strict, FailurePolicy::Skip),
+        max_test_bytes_total: args.max_test_bytes_total,
+        test_throttle: args.test_throttle,
+        test_mode: if args.sequential_tests {
+            TestMode::Sequential
+        } else {
+            args.test_mode
+        },
+        bench_profile: args.bench_profile,
+        shuffle_tests: args.shuffle_tests,
+        strict_urls: args.strict_urls,
+        now: args.now,
+        deterministic: args.deterministic,
+        check_alive: args.check_alive,
+        check_reachability: args.check_reachability,
+        reachable_ipv4: args.reachable_ipv4,
+        reachable_ipv6: args.reachable_ipv6,
+        require_keep_alive: args.require_keep_alive,
+        verify_isos: args.verify_isos,
+        resolve: resolves(args),
+        previous_addresses: if resolves(args) {
+            previous_addresses(args)
+        } else {
+            Default::default()
+        },
+        prefer_protocols: args.prefer_protocol.clone(),
+        no_redirects: args.no_redirects,
+        redirect_penalty: args.redirect_penalty,
+        recompute_scores: !args.no_score_recompute,
+        pick_random: args.pick_random,
+        pool: args.pool,
+        output_format: args.output_format,
+        annotations: args.annotate.clone(),
+        commented_extras: args.commented_extras,
+        group_by_country: args.group_by_country,
+        header: if args.no_header {
+            Vec::new()
+        } else {
+            args.header.clone()
+        },
+        ..Default::default()
+    }
+}
+
+/// whether the addresses of the written mirrors are resolved
+fn resolves(args: &Args) -> bool {
+    args.resolve || matches!(args.command, Some(Command::ExportAllowlist { .. }))
+}
+
+/// policy of a stage, failures being fatal with --strict unless told otherwise
+fn strict_policy(
+    policy: Option<FailurePolicy>,
+    strict: bool,
+    default: FailurePolicy,
+) -> FailurePolicy {
+    match (policy, strict) {
+        (Some(p), _) => p,
+        (None, true) => FailurePolicy::Fail,
+        (None, false) => default,
+    }
+}
+
+/// select the mirrors and write them, recording the outcome. Returns the
+/// sorted selection
+async fn refresh(
+    args: &Args,
+    ctx: &Context,
+    mlist: Option<reflecto::MirrorList>,
+    deadline: Option<tokio::time::Instant>,
+) -> anyhow::Result<reflecto::MirrorList> {
+    let res = select_and_write(args, ctx, mlist, deadline).await;
+    record_state(args, res.as_ref());
+    res
+}
+
+/// select the mirrors of a retrieved list, or of the status if none, and
+/// write them. Returns the sorted selection
+async fn select_and_write(
+    args: &Args,
+    ctx: &Context,
+    mlist: Option<reflecto::MirrorList>,
+    deadline: Option<tokio::time::Instant>,
+) -> anyhow::Result<reflecto::MirrorList> {
+    let config = run_config(args, ctx, deadline);
+    let res = match mlist {
+        Some(mlist) => reflecto::run::select(&config, reflecto::run::prepare(&config, mlist)).await,
+        None => reflecto::run::run(config).await,
+    };
+    let mut report = match res {
+        Ok(report) => report,
+        Err(e) => {
+            if e.downcast_ref() == Some(&Error::FetchFailed) {
+                notify_fetch_failure(&ctx.notifier, &e).await;
+            }
+            return Err(e);
+        }
+    };
+    if let Some(format) = args.explain {
+        eprintln!("{}", explain::render(&report.decisions, format));
+    }
+    let mlist = &report.mirrors;
+    if report.failure == Some(Error::EmptySelection) {
+        write_report(args, &report)?;
+        let _ = ctx
+            .notifier
+            .notify(&Event::TooFewMirrors {
+                count: 0,
+                threshold: args.notify_min_mirrors,
+            })
+            .await;
+        return report.check().map(|()| report.mirrors);
     }
-    mlist = mlist.filter(args.age, args.isos, args.ipv4, args.ipv6);
-    if let reflecto::SortKey::Rate = args.sort {
-        let timeout = Duration::seconds(args.download_timeout);
-        let _ = mlist.update_download_rate(Some(timeout), args.number).await;
+    let failure = report.failure.clone();
+    if let (Some(e), true) = (&failure, args.keep_on_failure) {
+        write_report(args, &report)?;
+        return Err(anyhow::Error::new(e.clone()).context("existing file kept"));
     }
-    mlist.sort(args.sort);
-    let content = mlist.to_file_content(args.number);
-    if let Some(fp) = args.save {
-        let mut file = File::create(fp.clone()).expect("unable to create file");
-        let _ = file.write_all(&content.into_bytes());
+    for w in mlist.diversity_warnings(args.number.of(mlist.len())) {
+        warn!("{w}");
+    }
+    if !ctx.notifier.is_empty() {
+        notify_degradation(&ctx.notifier, mlist, args).await;
+    }
+    let writing = std::time::Instant::now();
+    let written = write_selection(args, ctx, &report);
+    if written.is_ok() {
+        report.phases.write = writing.elapsed().as_secs_f64();
+    }
+    info!(
+        "durations: {}, mostly {}",
+        report.phases,
+        report.phases.dominant()
+    );
+    // written even if the selection could not be
+    write_report(args, &report)?;
+    written?;
+    match failure {
+        Some(e) => Err(e.into()),
+        None => Ok(report.mirrors),
+    }
+}
+
+/// write the selected mirrors where the command line asks
+fn write_selection(
+    args: &Args,
+    ctx: &Context,
+    report: &reflecto::run::RunReport,
+) -> anyhow::Result<()> {
+    let mlist = &report.mirrors;
+    if let Some(Command::Plan { out }) = &args.command {
+        let number = args.number.of(mlist.len());
+        let plan = Plan::new(mlist, number, report.content.clone(), args.save.as_deref());
+        write_file(out, &plan.to_json())?;
+        info!("plan written to {:?}", out);
+    } else if let Some(Command::ExportAllowlist { format }) = &args.command {
+        let destinations = allowlist::destinations(mlist, args.number.of(mlist.len()));
+        print!("{}", allowlist::render(&destinations, *format));
+    } else if let Some(Command::BestPerCountry) = &args.command {
+        let best = report::best_per_country(mlist);
+        if args.output_format == OutputFormat::Json {
+            println!("{}", serde_json::to_string_pretty(&best)?);
+        } else {
+            println!("{}", report::best_table(&best));
+        }
+    } else if let Some(dir) = &args.bundle {
+        let manifest = bundle::write(dir, mlist, &report.config.render_options())?;
+        info!(
+            "bundle of {} mirrors written to {:?}",
+            manifest.mirrors, dir
+        );
+    } else if args.outputs {
+        save_outputs(&ctx.config, mlist)?;
+    } else if args.print_top_url {
+        println!("{}", mlist.top_url().unwrap_or_default());
+    } else {
+        write_output(args, &report.content)?;
+    }
+    Ok(())
+}
+
+/// write the JSON report, if asked
+fn write_report(args: &Args, report: &reflecto::run::RunReport) -> anyhow::Result<()> {
+    if let Some(path) = &args.report {
+        write_file(path, &report.to_json())?;
+        info!("report written to {:?}", path);
+    }
+    Ok(())
+}
+
+/// compare a mirror with the median of the mirrors, notifying its metrics behind
+async fn monitor(args: &Args, ctx: &Context, mine: &str, sample: usize) -> anyhow::Result<()> {
+    let mut mlist = reflecto::run::fetch(&run_config(args, ctx, None)).await?;
+    if sample > 0 {
+        let timeout = Some(Duration::seconds(args.download_timeout));
+        reflecto::monitor::update_rates(&mut mlist, mine, sample, timeout).await;
+    }
+    let Some(comparisons) = reflecto::monitor::compare(&mlist, mine) else {
+        anyhow::bail!("{mine} is not in the mirror status");
+    };
+    for c in comparisons {
+        if !c.behind {
+            info!("{mine} {c}");
+            continue;
+        }
+        warn!("{mine} {c}");
+        let _ = ctx
+            .notifier
+            .notify(&Event::MirrorBehind {
+                url: mine.into(),
+                metric: c.metric.into(),
+                value: c.value,
+                median: c.median,
+            })
+            .await;
+    }
+    Ok(())
+}
+
+/// what ends the wait between two polls of the status
+enum Wake {
+    Poll,
+    /// reload the configuration files, then refresh
+    Reload,
+    /// refresh even if the status did not change
+    Refresh,
+}
+
+/// signals handled while watching the status
+#[cfg(unix)]
+struct Signals {
+    hangup: tokio::signal::unix::Signal,
+    user1: tokio::signal::unix::Signal,
+}
+
+#[cfg(unix)]
+impl Signals {
+    fn new() -> std::io::Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(Self {
+            hangup: signal(SignalKind::hangup())?,
+            user1: signal(SignalKind::user_defined1())?,
+        })
+    }
+
+    /// wait for the next poll or signal
+    async fn wait(&mut self, interval: std::time::Duration) -> Wake {
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => Wake::Poll,
+            _ = self.hangup.recv() => Wake::Reload,
+            _ = self.user1.recv() => Wake::Refresh,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct Signals;
+
+#[cfg(not(unix))]
+impl Signals {
+    fn new() -> std::io::Result<Self> {
+        Ok(Self)
+    }
+
+    async fn wait(&mut self, interval: std::time::Duration) -> Wake {
+        tokio::time::sleep(interval).await;
+        Wake::Poll
+    }
+}
+
+/// refresh the mirrorlist each time the upstream status changes
+async fn watch(
+    args: &Args,
+    ctx: &mut Context,
+    interval: std::time::Duration,
+) -> anyhow::Result<()> {
+    if args.from_mirrorlist.is_some() {
+        anyhow::bail!("watch polls the mirror status, it cannot be used with --from-mirrorlist");
+    }
+    let urls = if args.url.is_empty() {
+        vec![args.distro.status_url().to_string()]
+    } else {
+        args.url.clone()
+    };
+    // each status URL falls back to the fallback URLs, as when fetching it
+    let sources = urls
+        .into_iter()
+        .map(|url| {
+            std::iter::once(url)
+                .chain(args.fallback_url.iter().cloned())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let mut signals = Signals::new()?;
+    let mut validators = std::collections::HashMap::new();
+    // last mirrors retrieved from each status URL
+    let mut lists: Vec<Option<reflecto::MirrorList>> = vec![None; sources.len()];
+    let mut last_check = None;
+    let mut previous = Vec::new();
+    loop {
+        let mut changed = false;
+        for (candidates, list) in sources.iter().zip(&mut lists) {
+            match fetch_first_if_changed(&ctx.session, candidates, &mut validators).await {
+                Ok(None) => info!("status of {} not modified", candidates[0]),
+                Ok(Some(body)) => {
+                    let parsed = match &ctx.schema {
+                        Some(mapping) => mapping.parse(&body),
+                        None => args.distro.parse(&body),
+                    };
+                    match parsed {
+                        Ok(mlist) => {
+                            *list = Some(mlist);
+                            changed = true;
+                        }
+                        Err(e) => error!("invalid status: {e:#}"),
+                    }
+                }
+                Err(e) => {
+                    let _ = ctx
+                        .notifier
+                        .notify(&Event::RefreshFailed {
+                            error: e.to_string(),
+                        })
+                        .await;
+                    let e = e.context(Error::FetchFailed);
+                    record_state(args, Err(&e));
+                    error!("{e:#}");
+                }
+            }
+        }
+        // as when fetching, every status is needed
+        let merged = lists.iter().cloned().collect::<Option<Vec<_>>>();
+        match merged {
+            Some(merged) if changed => {
+                let mut mlist = reflecto::MirrorList::default();
+                for other in merged {
+                    mlist.merge(other);
+                }
+                if mlist.last_check().is_some() && mlist.last_check() <= last_check {
+                    info!("status not checked since {:?}", last_check);
+                } else {
+                    last_check = mlist.last_check();
+                    let deadline = args.max_duration.map(|d| tokio::time::Instant::now() + d);
+                    match refresh(args, ctx, Some(mlist), deadline).await {
+                        Ok(selected) => {
+                            let current = selected
+                                .urls()
+                                .take(args.number.of(selected.len()))
+                                .map(String::from)
+                                .collect::<Vec<_>>();
+                            for change in reflecto::diff::ranking(&previous, &current) {
+                                info!("{change}");
+                            }
+                            previous = current;
+                        }
+                        Err(e) => error!("{e:#}"),
+                    }
+                }
+            }
+            _ => {}
+        }
+        let wake = signals.wait(interval).await;
+        if let Wake::Reload = wake {
+            match ctx.reload(args) {
+                Ok(()) => info!("configuration reloaded"),
+                Err(e) => {
+                    error!("unable to reload the configuration, keeping the current one: {e:#}")
+                }
+            }
+        }
+        if let Wake::Reload | Wake::Refresh = wake {
+            info!("refreshing the mirrorlist");
+            // forget the statuses, for them to be retrieved and used again
+            validators.clear();
+            lists.fill(None);
+            last_check = None;
+        }
+    }
+}
+
+/// write the content to the file given on the command line, or to stdout
+fn write_output(args: &Args, content: &str) -> anyhow::Result<()> {
+    if let Some(fp) = &args.save {
+        write_file(fp, content)?;
         info!("file written to {:?}", fp);
+    } else if let Some(fd) = args.write_fd {
+        write_fd(fd, content)?;
+        info!("file written to descriptor {fd}");
     } else {
         println!("{}", content);
     }
+    Ok(())
+}
+
+/// write the units and tell how to enable them
+fn install_units(units: &Units, dir: &Path) -> anyhow::Result<()> {
+    for path in units.install(dir)? {
+        info!("{:?} written", path);
+    }
+    let scope = if units.user { " --user" } else { "" };
+    info!(
+        "enable the timer with: systemctl{scope} daemon-reload && systemctl{scope} enable --now {}.timer",
+        units::NAME
+    );
+    if units.user {
+        info!(
+            "include the mirrorlist in the repositories of pacman.conf with: Include = {}",
+            units.mirrorlist.display()
+        );
+    }
+    Ok(())
+}
+
+/// replace the content of a file opened by the parent process
+#[cfg(unix)]
+fn write_fd(fd: std::os::fd::RawFd, content: &str) -> anyhow::Result<()> {
+    use std::io::{Seek, Write};
+    use std::os::fd::FromRawFd;
+    let failed = |e| {
+        anyhow::Error::new(e).context(Error::WriteFailed {
+            path: format!("/dev/fd/{fd}").into(),
+        })
+    };
+    // SAFETY: fcntl only reads the flags of the descriptor
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        return Err(failed(std::io::Error::last_os_error()));
+    }
+    // SAFETY: the descriptor is open, checked above, and handed over to
+    // reflecto on the command line. It is not closed, to be written again by
+    // later refreshes
+    let mut file = std::mem::ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(fd) });
+    // pipes can be neither truncated nor rewound
+    if file.set_len(0).is_ok() {
+        file.rewind().map_err(failed)?;
+    }
+    writeln!(file, "{content}").map_err(failed)
+}
+
+#[cfg(not(unix))]
+fn write_fd(_fd: i32, _content: &str) -> anyhow::Result<()> {
+    anyhow::bail!("--write-fd is only supported on unix")
+}
+
+/// write the mirrorlist recorded in a plan
+fn apply(args: &Args, path: &Path, only_mirrorlist: bool) -> anyhow::Result<()> {
+    let plan = Plan::from_file(path)?;
+    let target = if only_mirrorlist {
+        plan.check_mirrorlist()?;
+        // the planned file is chosen by whoever wrote the plan
+        let Some(save) = &args.save else {
+            anyhow::bail!(
+                "--only-mirrorlist requires --save, the file named in the plan is ignored"
+            );
+        };
+        Some(save)
+    } else {
+        args.save.as_ref().or(plan.target.as_ref())
+    };
+    let Some(target) = target else {
+        println!("{}", plan.content);
+        return Ok(());
+    };
+    let _lock = if args.no_lock {
+        None
+    } else {
+        let path = args
+            .lock_file
+            .clone()
+            .unwrap_or_else(|| reflecto::lock::default_path(Some(target)));
+        Some(Lock::acquire(&path, args.wait_lock)?)
+    };
+    write_file(target, &plan.content)?;
+    info!(
+        "plan of {} with {} mirrors written to {:?}",
+        plan.created,
+        plan.mirrors.len(),
+        target
+    );
+    Ok(())
+}
+
+/// print everything known about the mirrors matching a URL or a domain
+async fn info(
+    args: &Args,
+    mlist: &reflecto::MirrorList,
+    mirror: &str,
+    rate: bool,
+    lists: &[(&str, &UrlFile)],
+) -> anyhow::Result<()> {
+    let query = UrlFile {
+        entries: vec![mirror.into()],
+        ..Default::default()
+    };
+    let mut selected = mlist.select(&query);
+    if selected.is_empty() {
+        anyhow::bail!("no mirror matches {mirror}");
+    }
+    let timeout = Some(Duration::seconds(args.download_timeout));
+    if args.details || args.tier.is_some() {
+        selected.update_details(timeout).await?;
+    }
+    if rate {
+        selected.update_download_rate(timeout, selected.len()).await;
+    }
+    println!("{}", selected.describe());
+    for (name, list) in lists {
+        let count = selected.select(list).len();
+        if count > 0 {
+            println!(
+                "\n{count} of {} mirrors {name} in {:?}",
+                selected.len(),
+                list.path
+            );
+        }
+    }
+    Ok(())
+}
+
+/// print the histogram of a metric across the mirrors
+async fn stats(
+    args: &Args,
+    config: &RunConfig,
+    mlist: reflecto::MirrorList,
+    metric: Metric,
+    bins: usize,
+) -> anyhow::Result<()> {
+    let mut mlist = mlist.filter_by(&config.filters);
+    if metric == Metric::Rate {
+        let limit = config
+            .test_limit
+            .unwrap_or(Number::Limit(STATS_TEST_LIMIT))
+            .of(mlist.len());
+        let budget = reflecto::ByteBudget::new(config.max_test_bytes_total)
+            .with_throttle(config.test_throttle);
+        mlist
+            .update_download_rate_mode(
+                Some(config.download_timeout),
+                limit,
+                config.deadline,
+                &budget,
+                config.test_mode,
+            )
+            .await;
+    }
+    let histogram = Histogram::new(&mlist, metric, bins);
+    if args.output_format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&histogram)?);
+    } else {
+        print!("{}", histogram.render());
+    }
+    Ok(())
+}
+
+/// use the options of a profile not given on the command line
+fn apply_profile(args: &mut Args, matches: &ArgMatches, profile: &Profile) -> anyhow::Result<()> {
+    let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    if let (Some(v), false) = (profile.age, given("age")) {
+        args.age = Some(v);
+    }
+    if let (Some(v), false) = (&profile.country, given("country")) {
+        args.country = v
+            .iter()
+            .map(|c| parse_country(c))
+            .collect::<Result<_, _>>()
+            .map_err(anyhow::Error::msg)?;
+    }
+    if let (Some(v), false) = (profile.isos, given("isos")) {
+        args.isos = v;
+    }
+    if let (Some(v), false) = (profile.ipv4, given("ipv4")) {
+        args.ipv4 = v;
+    }
+    if let (Some(v), false) = (profile.ipv6, given("ipv6")) {
+        args.ipv6 = v;
+    }
+    if let (Some(v), false) = (profile.completion_percent, given("completion_percent")) {
+        args.completion_percent = Some(v);
+    }
+    if let (Some(v), false) = (profile.details, given("details")) {
+        args.details = v;
+    }
+    if let (Some(v), false) = (profile.tier, given("tier")) {
+        args.tier = Some(v);
+    }
+    if let (Some(v), false) = (&profile.sort, given("sort")) {
+        args.sort = v.clone();
+    }
+    if let (Some(v), false) = (profile.number, given("number")) {
+        args.number = v;
+    }
+    if let (Some(v), false) = (profile.download_timeout, given("download_timeout")) {
+        args.download_timeout = v;
+    }
+    if let (Some(v), false) = (&profile.max_duration, given("max_duration")) {
+        args.max_duration = Some(parse_duration(v).map_err(anyhow::Error::msg)?);
+    }
+    if let (Some(v), false) = (profile.max_test_bytes_total, given("max_test_bytes_total")) {
+        args.max_test_bytes_total = Some(v);
+    }
+    if let (Some(v), false) = (
+        profile.test_mode,
+        given("test_mode") || args.sequential_tests,
+    ) {
+        args.test_mode = v;
+    }
+    if let (Some(v), false) = (&profile.prefer_protocol, given("prefer_protocol")) {
+        args.prefer_protocol = v.clone();
+    }
+    Ok(())
+}
+
+/// limits of the measurements with --nice, unless given otherwise
+fn apply_nice(args: &mut Args, matches: &ArgMatches) {
+    let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    if !given("test_mode") {
+        args.sequential_tests = true;
+    }
+    args.max_test_bytes_total.get_or_insert(NICE_TEST_BYTES);
+    args.test_throttle.get_or_insert(NICE_TEST_THROTTLE);
+}
+
+/// sort by score instead of measuring the mirrors, with --skip-tests-on-metered
+fn skip_tests(args: &mut Args, constraint: reflecto::power::Constraint) {
+    if matches!(
+        args.sort,
+        reflecto::SortKey::Rate | reflecto::SortKey::Metadata
+    ) {
+        warn!("{constraint}, sorting by score instead of {}", args.sort);
+        args.sort = reflecto::SortKey::Score;
+    }
+    args.bench_profile = BenchProfile::default();
+}
+
+/// mirrors whose rate is measured by stats without --test-limit
+const STATS_TEST_LIMIT: usize = 50;
+/// bytes downloaded by the rate tests with --nice
+const NICE_TEST_BYTES: u64 = 32 << 20;
+/// rate of each test with --nice, in bytes per second
+const NICE_TEST_THROTTLE: u64 = 2 << 20;
+/// niceness of the process with --nice
+#[cfg(unix)]
+const NICE_LEVEL: libc::c_int = 10;
+
+/// lower the scheduling priority of the process, and thus its I/O priority,
+/// unless it is already lower
+#[cfg(unix)]
+fn lower_priority() {
+    // SAFETY: getpriority and setpriority only read and change the priority
+    // of the process
+    let res = unsafe {
+        if libc::getpriority(libc::PRIO_PROCESS, 0) >= NICE_LEVEL {
+            return;
+        }
+        libc::setpriority(libc::PRIO_PROCESS, 0, NICE_LEVEL)
+    };
+    if res != 0 {
+        warn!(
+            "unable to lower the priority: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(not(unix))]
+fn lower_priority() {
+    warn!("the priority is only lowered on unix");
+}
+
+/// options given on the command line that a profile can hold
+fn given_profile(args: &Args, matches: &ArgMatches) -> Profile {
+    let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    Profile {
+        description: None,
+        age: args.age.filter(|_| given("age")),
+        country: given("country").then(|| args.country.clone()),
+        isos: given("isos").then_some(args.isos),
+        ipv4: given("ipv4").then_some(args.ipv4),
+        ipv6: given("ipv6").then_some(args.ipv6),
+        completion_percent: args
+            .completion_percent
+            .filter(|_| given("completion_percent")),
+        details: given("details").then_some(args.details),
+        tier: args.tier.filter(|_| given("tier")),
+        sort: given("sort").then(|| args.sort.clone()),
+        number: given("number").then_some(args.number),
+        download_timeout: given("download_timeout").then_some(args.download_timeout),
+        max_duration: args
+            .max_duration
+            .filter(|_| given("max_duration"))
+            .map(|d| format!("{}s", d.as_secs_f64())),
+        max_test_bytes_total: args
+            .max_test_bytes_total
+            .filter(|_| given("max_test_bytes_total")),
+        test_mode: if args.sequential_tests {
+            Some(TestMode::Sequential)
+        } else {
+            given("test_mode").then_some(args.test_mode)
+        },
+        prefer_protocol: given("prefer_protocol").then(|| args.prefer_protocol.clone()),
+    }
+}
+
+/// configuration directory given on the command line, or the default one
+fn config_dir(args: &Args) -> Option<PathBuf> {
+    args.config_dir.clone().or_else(config::config_dir)
+}
+
+/// default location of a file of the configuration directory
+fn config_file(args: &Args, name: &str) -> Option<PathBuf> {
+    config_dir(args).map(|d| d.join(name))
+}
+
+/// read a file of URLs and domains from the given path, or from its default one
+fn load_list(path: Option<&Path>, default: Option<PathBuf>) -> anyhow::Result<UrlFile> {
+    match path.map(PathBuf::from).or(default) {
+        Some(path) => UrlFile::load(&path),
+        None => Ok(UrlFile::default()),
+    }
+}
+
+/// print the outcome of the last runs, failing if the mirrorlist is older than
+/// `max_age`
+fn status(args: &Args, max_age: Option<std::time::Duration>) -> anyhow::Result<()> {
+    let now = chrono::Utc::now();
+    let state = match args.state_file.clone().or_else(state::read_path) {
+        Some(path) => {
+            let state = State::load(&path)?;
+            match &state {
+                Some(state) => println!("{}", state.render(now)),
+                None if max_age.is_none() => {
+                    anyhow::bail!("no run recorded in {}", path.display())
+                }
+                None => info!("no run recorded in {}", path.display()),
+            }
+            state
+        }
+        None if max_age.is_none() => {
+            anyhow::bail!("no state directory, give the path of the state file")
+        }
+        None => None,
+    };
+    let Some(max_age) = max_age else {
+        return Ok(());
+    };
+    // the mirrorlist may have been written by another tool
+    let modified = args
+        .save
+        .as_deref()
+        .and_then(|p| std::fs::metadata(p).ok()?.modified().ok())
+        .map(chrono::DateTime::<chrono::Utc>::from);
+    let last_refresh = state.and_then(|s| s.last_success).max(modified);
+    state::ensure_fresh(last_refresh, now, Duration::from_std(max_age)?)?;
+    Ok(())
+}
+
+/// settings of the requests given on the command line. The headers given
+/// without host are only sent to the hosts of the status
+fn session(args: &Args) -> anyhow::Result<Session> {
+    let mut credentials = auth::Credentials::new();
+    for header in &args.auth_header {
+        credentials.add_header(header)?;
+    }
+    credentials.bind_unscoped(
+        status_urls(args)
+            .iter()
+            .filter_map(|u| reqwest::Url::parse(u).ok()?.host_str().map(String::from)),
+    );
+    let netrc = match (&args.netrc_file, args.netrc) {
+        (Some(path), _) => Some(path.clone()),
+        (None, true) => auth::netrc_path(),
+        (None, false) => None,
+    };
+    if let Some(path) = netrc {
+        auth::read_netrc(&path, &mut credentials)?;
+    }
+    Ok(Session::new(credentials))
+}
+
+/// state file given on the command line, or the default one
+fn state_file(args: &Args) -> Option<PathBuf> {
+    args.state_file.clone().or_else(state::default_path)
+}
+
+/// record the outcome of a run writing a mirrorlist, for the status command
+fn record_state(args: &Args, outcome: Result<&reflecto::MirrorList, &anyhow::Error>) {
+    let writes = args.save.is_some() || args.outputs || args.write_fd.is_some();
+    if !writes
+        || matches!(
+            args.command,
+            Some(Command::Plan { .. } | Command::ExportAllowlist { .. } | Command::BestPerCountry)
+        )
+    {
+        return;
+    }
+    let Some(path) = state_file(args) else {
+        return;
+    };
+    let addresses = match outcome {
+        Ok(mlist) => mlist.addresses(args.number.of(mlist.len())),
+        Err(_) => Default::default(),
+    };
+    let outcome = outcome.map(|mlist| mlist.urls().take(args.number.of(mlist.len())).collect());
+    if let Err(e) = State::record(&path, outcome, addresses) {
+        warn!("unable to record the run: {e:#}");
+    }
+}
+
+/// addresses resolved by the last recorded run
+fn previous_addresses(args: &Args) -> reflecto::dns::Addresses {
+    let Some(path) = state_file(args) else {
+        return Default::default();
+    };
+    match State::load(&path) {
+        Ok(state) => state.map(|s| s.addresses).unwrap_or_default(),
+        Err(e) => {
+            warn!("{e:#}");
+            Default::default()
+        }
+    }
+}
+
+/// configuration given on the command line, or the default one if it exists and
+/// is valid
+fn load_config(args: &Args) -> anyhow::Result<Config> {
+    match &args.config {
+        Some(path) => Config::from_file(path),
+        None => Ok(
+            Config::from_file_if_exists(config_file(args, "config.toml").as_deref())
+                .unwrap_or_else(|e| {
+                    // only a configuration given explicitly must be valid
+                    warn!("{e:#}, using the default configuration");
+                    Config::default()
+                }),
+        ),
+    }
+}
+
+/// built-in aliases, with the ones given on the command line or the default
+/// ones if they exist
+fn load_aliases(args: &Args) -> anyhow::Result<Aliases> {
+    match &args.aliases {
+        Some(path) => {
+            let mut aliases = Aliases::builtin();
+            aliases.extend(Aliases::from_file(path)?);
+            Ok(aliases)
+        }
+        None => Aliases::with_file_if_exists(config_file(args, "aliases.toml").as_deref()),
+    }
+}
+
+/// schema mapping given on the command line, if any
+fn load_schema(args: &Args) -> anyhow::Result<Option<reflecto::SchemaMapping>> {
+    args.schema
+        .as_deref()
+        .map(reflecto::SchemaMapping::from_file)
+        .transpose()
+}
+
+/// overrides given on the command line, or the default ones if they exist
+fn load_overrides(args: &Args) -> anyhow::Result<Overrides> {
+    match &args.overrides {
+        Some(path) => Overrides::from_file(path),
+        None => Overrides::from_file_if_exists(config_file(args, "overrides.toml").as_deref()),
+    }
+}
+
+/// apply an edition to a file of URLs and domains
+fn edit_list(mut list: UrlFile, action: &ListAction) -> anyhow::Result<()> {
+    if list.path.as_os_str().is_empty() {
+        anyhow::bail!("no configuration directory, give the path of the file");
+    }
+    match action {
+        ListAction::Add { entries } => {
+            for e in entries {
+                if !list.add(e) {
+                    warn!("{e} already in {:?}", list.path);
+                }
+            }
+            list.save()
+        }
+        ListAction::Remove { entries } => {
+            for e in entries {
+                if !list.remove(e) {
+                    warn!("{e} not in {:?}", list.path);
+                }
+            }
+            list.save()
+        }
+        ListAction::List => {
+            for e in &list.entries {
+                println!("{e}");
+            }
+            Ok(())
+        }
+    }
+}
+
+/// write a file, reporting failures as [`Error::WriteFailed`]
+fn write_file(path: &Path, content: &str) -> anyhow::Result<()> {
+    std::fs::write(path, content).map_err(|e| {
+        anyhow::Error::new(e).context(Error::WriteFailed {
+            path: path.to_path_buf(),
+        })
+    })
+}
+
+/// validate a country given on the command line, returning its code
+fn parse_country(s: &str) -> Result<String, String> {
+    reflecto::country::normalize(s)
+        .map(|c| c.code.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// [`parse_country`], completing the names, codes and aliases of the countries
+#[derive(Clone)]
+struct CountryParser;
+
+impl clap::builder::TypedValueParser for CountryParser {
+    type Value = String;
+
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        arg: Option<&clap::Arg>,
+        value: &std::ffi::OsStr,
+    ) -> Result<String, clap::Error> {
+        let value = clap::builder::StringValueParser::new().parse_ref(cmd, arg, value)?;
+        parse_country(&value).map_err(|e| {
+            clap::Error::raw(
+                clap::error::ErrorKind::ValueValidation,
+                format!(
+                    "invalid value '{value}' for '{}': {e}\n",
+                    arg.map(|a| a.to_string()).unwrap_or_default()
+                ),
+            )
+            .with_cmd(cmd)
+        })
+    }
+
+    fn possible_values(
+        &self,
+    ) -> Option<Box<dyn Iterator<Item = clap::builder::PossibleValue> + '_>> {
+        Some(Box::new(
+            reflecto::country::names().map(clap::builder::PossibleValue::new),
+        ))
+    }
+}
+
+/// parse a sort key, accepting Reflector names and abbreviations
+fn parse_sort(s: &str) -> Result<reflecto::SortKey, String> {
+    s.parse().map_err(|e: anyhow::Error| e.to_string())
+}
+
+/// parse a number of mirrors that cannot be 0
+fn parse_positive(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(0) => Err("expected at least 1".into()),
+        res => res.map_err(|e: std::num::ParseIntError| e.to_string()),
+    }
+}
+
+/// parse a rate in bytes per second, optionally suffixed by a decimal (kB, MB,
+/// GB) or binary (KiB, MiB, GiB) unit and by /s
+fn parse_rate(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let s = s.strip_suffix("/s").unwrap_or(s);
+    let (value, unit) = match s.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(i) => s.split_at(i),
+        None => (s, "B"),
+    };
+    let factor: u64 = match unit.trim() {
+        "B" => 1,
+        "kB" | "KB" => 1000,
+        "KiB" => 1 << 10,
+        "MB" => 1000 * 1000,
+        "MiB" => 1 << 20,
+        "GB" => 1000 * 1000 * 1000,
+        "GiB" => 1 << 30,
+        other => {
+            return Err(format!(
+                "unknown unit '{other}', expected B, kB, MB, GB, KiB, MiB or GiB"
+            ))
+        }
+    };
+    let value = value
+        .trim()
+        .parse::<f64>()
+        .map_err(|e| format!("invalid rate '{s}': {e}"))?;
+    if !value.is_finite() || value <= 0.0 {
+        return Err(format!("invalid rate '{s}', expected a positive number"));
+    }
+    Ok((value * factor as f64) as u64)
+}
+
+fn parse_proxy(s: &str) -> Result<reqwest::Proxy, String> {
+    proxy::parse(s).map_err(|e| format!("{e:#}"))
+}
+
+/// parse a date, e.g. 2025/03/01 or 2025-03-01
+fn parse_date(s: &str) -> Result<chrono::NaiveDate, String> {
+    chrono::NaiveDate::parse_from_str(s, "%Y/%m/%d")
+        .or_else(|_| chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d"))
+        .map_err(|_| format!("invalid date '{s}', expected YYYY/MM/DD"))
+}
+
+/// parse a duration given in seconds, optionally suffixed by s, m or h
+fn parse_duration(s: &str) -> Result<std::time::Duration, String> {
+    let (value, unit) = match s.trim().find(|c: char| c.is_ascii_alphabetic()) {
+        Some(i) => s.trim().split_at(i),
+        None => (s.trim(), "s"),
+    };
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("invalid duration '{s}'"))?;
+    let factor = match unit {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        _ => {
+            return Err(format!(
+                "invalid duration unit '{unit}', expected s, m, h or d"
+            ))
+        }
+    };
+    std::time::Duration::try_from_secs_f64(value * factor).map_err(|e| e.to_string())
+}
+
+/// parse a maximum duration, or a range of durations separated by a dash
+fn parse_delay_range(s: &str) -> Result<(std::time::Duration, std::time::Duration), String> {
+    let (min, max) = match s.split_once('-') {
+        Some((min, max)) => (parse_duration(min)?, parse_duration(max)?),
+        None => (std::time::Duration::ZERO, parse_duration(s)?),
+    };
+    if min > max {
+        return Err(format!(
+            "invalid range '{s}', the minimum exceeds the maximum"
+        ));
+    }
+    Ok((min, max))
+}
+
+/// write every output of the configuration
+fn save_outputs(config: &Config, mlist: &reflecto::MirrorList) -> anyhow::Result<()> {
+    if config.outputs.is_empty() {
+        anyhow::bail!("no output defined in configuration");
+    }
+    for (name, output) in &config.outputs {
+        let selected = mlist
+            .clone()
+            .filter(output.age, output.isos, output.ipv4, output.ipv6);
+        if let Err(e) = selected.ensure_not_empty() {
+            error!("output {name} not written: {e}");
+            continue;
+        }
+        let content = selected.to_file_content(&RenderOptions {
+            template: output.template.clone(),
+            ..RenderOptions::new(output.number.unwrap_or(Number::All))
+        });
+        write_file(&output.path, &content)?;
+        info!("output {name} written to {:?}", output.path);
+    }
+    Ok(())
+}
+
+/// status URLs given on the command line, the one of the distribution if
+/// none, and their fallbacks
+fn status_urls(args: &Args) -> Vec<String> {
+    let mut urls = if args.url.is_empty() {
+        vec![args.distro.status_url().to_string()]
+    } else {
+        args.url.clone()
+    };
+    urls.extend(args.fallback_url.iter().cloned());
+    urls
+}
+
+/// send notifications about the selection, compared to the previously saved file
+async fn notify_degradation(notifier: &Notifier, mlist: &reflecto::MirrorList, args: &Args) {
+    let count = args.number.of(mlist.len());
+    if count < args.notify_min_mirrors {
+        let _ = notifier
+            .notify(&Event::TooFewMirrors {
+                count,
+                threshold: args.notify_min_mirrors,
+            })
+            .await;
+    }
+    if let Some(fp) = &args.save {
+        let previous = std::fs::read_to_string(fp)
+            .ok()
+            .and_then(|c| top_server(&c));
+        let current = mlist.top_url().map(String::from);
+        if previous.is_some() && previous != current {
+            let _ = notifier
+                .notify(&Event::TopMirrorChanged { previous, current })
+                .await;
+        }
+    }
 }
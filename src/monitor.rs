@@ -0,0 +1,152 @@
+//! Comparison of a mirror with the other mirrors of the status, for mirror
+//! operators.
+use crate::{ByteBudget, Distro, Mirror, MirrorList, SortKey};
+use chrono::{DateTime, Utc};
+use std::fmt;
+
+/// A metric of the monitored mirror, compared to the median of every mirror
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub metric: &'static str,
+    pub value: f64,
+    pub median: f64,
+    /// whether the mirror is worse than the median
+    pub behind: bool,
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {:.3} (median {:.3}){}",
+            self.metric,
+            self.value,
+            self.median,
+            if self.behind { ", behind" } else { "" }
+        )
+    }
+}
+
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    values.retain(|v| v.is_finite());
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        Some((values[mid - 1] + values[mid]) / 2.0)
+    } else {
+        Some(values[mid])
+    }
+}
+
+fn same_url(m: &Mirror, url: &str) -> bool {
+    m.url.trim_end_matches('/') == url.trim_end_matches('/')
+}
+
+/// Measure the download rate of the monitored mirror and of the `sample` best
+/// scored other mirrors
+pub async fn update_rates(
+    mlist: &mut MirrorList,
+    mine: &str,
+    sample: usize,
+    timeout: Option<chrono::Duration>,
+) {
+    let distro: Distro = mlist.distro;
+    let session = mlist.session.clone();
+    mlist.sort(SortKey::Score);
+    let mut others = 0;
+    for m in mlist.mirrors.iter_mut() {
+        let own = same_url(m, mine);
+        if !own && others >= sample {
+            continue;
+        }
+        others += usize::from(!own);
+        let _ = m
+            .update_dl_rate(timeout, distro, &ByteBudget::default(), &session)
+            .await;
+    }
+}
+
+/// value of a mirror for a metric, given the reference time
+type Metric = fn(&Mirror, DateTime<Utc>) -> Option<f64>;
+
+fn hours(m: &Mirror, now: DateTime<Utc>) -> Option<f64> {
+    m.age(now).map(|d| d.num_seconds() as f64 / 3600.0)
+}
+
+/// Compare the monitored mirror with the median of the mirrors. `None` if the
+/// mirror is not in the status
+pub fn compare(mlist: &MirrorList, mine: &str) -> Option<Vec<Comparison>> {
+    let own = mlist.mirrors.iter().find(|m| same_url(m, mine))?;
+    let now = mlist.now();
+    // name, value, whether lower is better
+    let metrics: [(&str, Metric, bool); 3] = [
+        ("age (hours)", hours, true),
+        ("completion", |m, _| m.completion(), false),
+        (
+            "rate (MB/s)",
+            |m, _| m.download_rate.as_ref().map(|r| r.0),
+            false,
+        ),
+    ];
+    let comparisons = metrics
+        .iter()
+        .filter_map(|(metric, get, lower_is_better)| {
+            let value = get(own, now)?;
+            let median = median(mlist.mirrors.iter().filter_map(|m| get(m, now)).collect())?;
+            let behind = if *lower_is_better {
+                value > median
+            } else {
+                value < median
+            };
+            Some(Comparison {
+                metric,
+                value,
+                median,
+                behind,
+            })
+        })
+        .collect();
+    Some(comparisons)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+
+    static STATUS: &str = r#"{"urls": [
+        {"url": "https://a.example.org/", "protocol": "https", "completion_pct": 1.0,
+         "last_sync": "2024-05-01T10:00:00Z", "details": ""},
+        {"url": "https://b.example.org/", "protocol": "https", "completion_pct": 0.9,
+         "last_sync": "2024-05-01T11:00:00Z", "details": ""},
+        {"url": "https://mine.example.org/", "protocol": "https", "completion_pct": 0.95,
+         "last_sync": "2024-05-01T06:00:00Z", "details": ""}
+    ]}"#;
+
+    #[test]
+    fn medians() {
+        assert_eq!(median(vec![]), None);
+        assert_eq!(median(vec![3.0, 1.0, 2.0]), Some(2.0));
+        assert_eq!(median(vec![4.0, 1.0, 2.0, f64::NAN, 3.0]), Some(2.5));
+    }
+
+    #[test]
+    fn comparison() {
+        let ml: MirrorList = serde_json::from_str(STATUS).unwrap();
+        let ml = ml.with_clock(&FixedClock("2024-05-01T12:00:00Z".parse().unwrap()));
+        assert!(compare(&ml, "https://unknown.example.org/").is_none());
+        let comparisons = compare(&ml, "https://mine.example.org").unwrap();
+        assert_eq!(comparisons.len(), 2);
+        assert_eq!(
+            comparisons[0].to_string(),
+            "age (hours): 6.000 (median 2.000), behind"
+        );
+        assert_eq!(
+            comparisons[1].to_string(),
+            "completion: 0.950 (median 0.950)"
+        );
+    }
+}
@@ -0,0 +1,197 @@
+//! Notifications sent when a refresh degrades.
+//!
+//! An [`Event`] is serialized as a JSON object and either POSTed to a webhook
+//! or written to the standard input of a shell command.
+use crate::session::Session;
+use anyhow::{bail, Result};
+use chrono::Utc;
+use serde::Serialize;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{debug, warn};
+
+/// Something worth notifying about
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The mirror status could not be retrieved or parsed
+    RefreshFailed { error: String },
+    /// Fewer mirrors than expected passed the filters
+    TooFewMirrors { count: usize, threshold: usize },
+    /// The best ranked mirror is not the same as in the previous mirrorlist
+    TopMirrorChanged {
+        previous: Option<String>,
+        current: Option<String>,
+    },
+    /// A monitored mirror is worse than the median of the mirrors
+    MirrorBehind {
+        url: String,
+        metric: String,
+        value: f64,
+        median: f64,
+    },
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    #[serde(flatten)]
+    event: &'a Event,
+    timestamp: String,
+    host: Option<String>,
+}
+
+impl Event {
+    /// JSON document describing the event
+    pub fn payload(&self) -> String {
+        let payload = Payload {
+            event: self,
+            timestamp: Utc::now().to_rfc3339(),
+            host: std::env::var("HOSTNAME").ok(),
+        };
+        serde_json::to_string(&payload).expect("event is always serializable")
+    }
+
+    /// short name of the event, as used in the payload
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::RefreshFailed { .. } => "refresh_failed",
+            Event::TooFewMirrors { .. } => "too_few_mirrors",
+            Event::TopMirrorChanged { .. } => "top_mirror_changed",
+            Event::MirrorBehind { .. } => "mirror_behind",
+        }
+    }
+}
+
+/// Where to send notifications
+#[derive(Debug, Default, Clone)]
+pub struct Notifier {
+    /// URL receiving the payload as a POST request
+    pub webhook: Option<String>,
+    /// shell command receiving the payload on its standard input
+    pub command: Option<String>,
+    /// settings of the webhook requests, e.g. the proxy
+    pub session: Session,
+}
+
+impl Notifier {
+    pub fn is_empty(&self) -> bool {
+        self.webhook.is_none() && self.command.is_none()
+    }
+
+    /// Send the event to every configured target.
+    ///
+    /// Failures are logged, the first one is returned.
+    pub async fn notify(&self, event: &Event) -> Result<()> {
+        let payload = event.payload();
+        debug!("notify {}", payload);
+        let mut res = Ok(());
+        if let Some(url) = &self.webhook {
+            if let Err(e) = post(&self.session, url, &payload).await {
+                warn!("webhook notification failed: {e}");
+                res = Err(e);
+            }
+        }
+        if let Some(cmd) = &self.command {
+            if let Err(e) = run_command(cmd, event.name(), &payload).await {
+                warn!("command notification failed: {e}");
+                if res.is_ok() {
+                    res = Err(e);
+                }
+            }
+        }
+        res
+    }
+}
+
+async fn post(session: &Session, url: &str, payload: &str) -> Result<()> {
+    let response = session
+        .client_builder()
+        .build()?
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(payload.to_string())
+        .send()
+        .await?;
+    response.error_for_status()?;
+    Ok(())
+}
+
+async fn run_command(cmd: &str, event: &str, payload: &str) -> Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .env("REFLECTO_EVENT", event)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(payload.as_bytes()).await?;
+    }
+    let status = child.wait().await?;
+    if !status.success() {
+        bail!("`{cmd}` exited with {status}");
+    }
+    Ok(())
+}
+
+/// get the URL of the first server of a mirrorlist file content
+pub fn top_server(content: &str) -> Option<String> {
+    content
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("Server"))
+        .filter_map(|l| l.trim_start().strip_prefix('='))
+        .map(|l| l.trim().trim_end_matches("$repo/os/$arch").to_string())
+        .next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload() {
+        let event = Event::TooFewMirrors {
+            count: 1,
+            threshold: 3,
+        };
+        let v: serde_json::Value = serde_json::from_str(&event.payload()).unwrap();
+        assert_eq!(v["event"], "too_few_mirrors");
+        assert_eq!(v["event"], event.name());
+        assert_eq!(v["count"], 1);
+        assert_eq!(v["threshold"], 3);
+        assert!(v["timestamp"].is_string());
+    }
+
+    #[test]
+    fn first_server() {
+        let content = "# header\n#\n\nServer = https://a.org/arch/$repo/os/$arch\n\
+                       Server = https://b.org/arch/$repo/os/$arch";
+        assert_eq!(top_server(content), Some("https://a.org/arch/".into()));
+        assert_eq!(top_server("# empty"), None);
+    }
+
+    #[tokio::test]
+    async fn command() {
+        let out = std::env::temp_dir().join("reflecto_notify_test.json");
+        let notifier = Notifier {
+            webhook: None,
+            command: Some(format!("cat > {}", out.display())),
+            ..Default::default()
+        };
+        let event = Event::RefreshFailed {
+            error: "boom".into(),
+        };
+        notifier.notify(&event).await.unwrap();
+        let v: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&out).unwrap()).unwrap();
+        assert_eq!(v["event"], "refresh_failed");
+        assert_eq!(v["error"], "boom");
+
+        let failing = Notifier {
+            webhook: None,
+            command: Some("exit 3".into()),
+            ..Default::default()
+        };
+        assert!(failing.notify(&event).await.is_err());
+    }
+}
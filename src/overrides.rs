@@ -0,0 +1,94 @@
+//! Local corrections of the mirror status.
+//!
+//! The overrides are read from `$XDG_CONFIG_HOME/reflecto/overrides.toml`, or
+//! from the file given on the command line. Each table is named after the URL of
+//! a mirror, and its fields replace those of the status.
+//!
+//! ```toml
+//! ["http://mirror.rackspace.com/archlinux/"]
+//! country = "United States"
+//! country_code = "US"
+//! note = "bandwidth capped at 10 MB/s"
+//! ```
+use crate::config::config_dir;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Override {
+    pub country: Option<String>,
+    pub country_code: Option<String>,
+    pub score: Option<f64>,
+    pub isos: Option<bool>,
+    pub ipv4: Option<bool>,
+    pub ipv6: Option<bool>,
+    /// free text, available as `{note}` in templates
+    pub note: Option<String>,
+}
+
+/// Overrides, by mirror URL
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Overrides(pub BTreeMap<String, Override>);
+
+impl Overrides {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read overrides {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("invalid overrides {}", path.display()))
+    }
+
+    /// Read the overrides from their default location, if it exists
+    pub fn from_default_path() -> Result<Self> {
+        Self::from_file_if_exists(Self::default_path().as_deref())
+    }
+
+    /// Read the overrides from a file if it exists, none otherwise
+    pub fn from_file_if_exists(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) if path.exists() => Self::from_file(path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    pub fn default_path() -> Option<PathBuf> {
+        config_dir().map(|d| d.join("overrides.toml"))
+    }
+
+    /// Override of a mirror, ignoring a trailing '/' in the URL
+    pub fn get(&self, url: &str) -> Option<&Override> {
+        self.0
+            .iter()
+            .find(|(u, _)| u.trim_end_matches('/') == url.trim_end_matches('/'))
+            .map(|(_, o)| o)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let overrides: Overrides = toml::from_str(
+            r#"
+            ["http://mirror.rackspace.com/archlinux/"]
+            country = "United States"
+            country_code = "US"
+
+            ["https://example.org/arch"]
+            note = "capped"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(overrides.0.len(), 2);
+        let o = overrides.get("https://example.org/arch/").unwrap();
+        assert_eq!(o.note.as_deref(), Some("capped"));
+        assert!(o.country.is_none());
+        assert!(overrides.get("https://example.org/").is_none());
+        assert!(toml::from_str::<Overrides>("[\"https://a/\"]\nfoo = 1").is_err());
+    }
+}
@@ -0,0 +1,138 @@
+//! Minimal reader of `pacman.conf`.
+//!
+//! Only what is needed to locate the mirrorlist is read: the architecture
+//! and, for each repository, its `Include` and `Server` directives.
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub static PACMAN_CONF: &str = "/etc/pacman.conf";
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PacmanConf {
+    /// `Architecture` option, if set
+    pub architecture: Option<String>,
+    pub repos: Vec<Repo>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Repo {
+    pub name: String,
+    /// files included in the repository section
+    pub includes: Vec<PathBuf>,
+    pub servers: Vec<String>,
+}
+
+impl PacmanConf {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read {}", path.display()))?;
+        Ok(Self::parse(&content))
+    }
+
+    pub fn parse(content: &str) -> Self {
+        let mut conf = Self::default();
+        let mut section = String::new();
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.trim().to_string();
+                if section != "options" {
+                    conf.repos.push(Repo {
+                        name: section.clone(),
+                        ..Default::default()
+                    });
+                }
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+            match (section.as_str(), key) {
+                ("options", "Architecture") => conf.architecture = Some(value.into()),
+                ("options", _) => (),
+                (_, "Include") => {
+                    if let Some(repo) = conf.repos.last_mut() {
+                        repo.includes.push(value.into());
+                    }
+                }
+                (_, "Server") => {
+                    if let Some(repo) = conf.repos.last_mut() {
+                        repo.servers.push(value.into());
+                    }
+                }
+                _ => (),
+            }
+        }
+        conf
+    }
+
+    /// The mirrorlist file, i.e. the file included by most repositories
+    pub fn mirrorlist(&self) -> Option<&Path> {
+        let mut counts: HashMap<&Path, usize> = HashMap::new();
+        for include in self.repos.iter().flat_map(|r| r.includes.iter()) {
+            *counts.entry(include.as_path()).or_default() += 1;
+        }
+        counts
+            .into_iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(a.0)))
+            .map(|(path, _)| path)
+    }
+
+    /// Names of the repositories including a given file
+    pub fn repos_using(&self, path: &Path) -> Vec<&str> {
+        self.repos
+            .iter()
+            .filter(|r| r.includes.iter().any(|i| i == path))
+            .map(|r| r.name.as_str())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static CONF: &str = "
+        [options]
+        HoldPkg     = pacman glibc
+        Architecture = auto
+        # Include = /etc/pacman.d/ignored
+
+        #[core-testing]
+        #Include = /etc/pacman.d/mirrorlist
+
+        [core]
+        Include = /etc/pacman.d/mirrorlist
+
+        [extra]
+        Include = /etc/pacman.d/mirrorlist # comment
+
+        [custom]
+        Server = file:///home/custompkgs
+        Include = /etc/pacman.d/custom-mirrorlist
+    ";
+
+    #[test]
+    fn parse() {
+        let conf = PacmanConf::parse(CONF);
+        assert_eq!(conf.architecture.as_deref(), Some("auto"));
+        let names = conf
+            .repos
+            .iter()
+            .map(|r| r.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["core", "extra", "custom"]);
+        assert_eq!(conf.repos[2].servers, vec!["file:///home/custompkgs"]);
+        let mirrorlist = conf.mirrorlist().unwrap();
+        assert_eq!(mirrorlist, Path::new("/etc/pacman.d/mirrorlist"));
+        assert_eq!(conf.repos_using(mirrorlist), vec!["core", "extra"]);
+    }
+
+    #[test]
+    fn no_include() {
+        let conf = PacmanConf::parse("[options]\n[core]\nServer = http://a.org/\n");
+        assert_eq!(conf.mirrorlist(), None);
+    }
+}
@@ -0,0 +1,197 @@
+//! Supplementary retrievals done after the mirror status, e.g. mirror details.
+//!
+//! A [`Pipeline`] runs stages sharing one HTTP client, adding the credentials
+//! of its [`Session`] to the requests. Each stage handles its
+//! items with bounded concurrency, and every stage stops at the deadline of the
+//! pipeline, keeping the results gathered so far.
+use crate::session::{Client, Session};
+use anyhow::Result;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+/// Shared client and deadline of the supplementary retrievals
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    client: Client,
+    deadline: Option<Instant>,
+    session: Session,
+}
+
+/// Results of a stage
+#[derive(Debug)]
+pub struct StageOutput<T> {
+    /// results of the successful items
+    pub results: Vec<T>,
+    pub failures: usize,
+    /// number of items not handled before the deadline
+    pub cancelled: usize,
+}
+
+impl Pipeline {
+    /// Pipeline whose requests time out after `timeout`, and whose stages stop
+    /// at `deadline`
+    pub fn new(timeout: Option<chrono::Duration>, deadline: Option<Instant>) -> Result<Self> {
+        Self::with_session(timeout, deadline, &Session::default())
+    }
+
+    /// Same as [`Pipeline::new`], the requests using the settings of `session`
+    pub fn with_session(
+        timeout: Option<chrono::Duration>,
+        deadline: Option<Instant>,
+        session: &Session,
+    ) -> Result<Self> {
+        let mut builder = session.client_builder();
+        if let Some(d) = timeout {
+            builder = builder.timeout(d.to_std()?);
+        }
+        Ok(Self {
+            client: session.wrap(builder.build()?),
+            deadline,
+            session: session.clone(),
+        })
+    }
+
+    /// Settings of the requests of the pipeline, e.g. for the stages building
+    /// their own client
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Apply `f` to every item, with at most `concurrency` items at once. The
+    /// stage and its items are traced under `name`
+    pub async fn stage<T, F, Fut>(
+        &self,
+        name: &'static str,
+        items: Vec<String>,
+        concurrency: usize,
+        f: F,
+    ) -> StageOutput<T>
+    where
+        T: Send + 'static,
+        F: Fn(Client, String) -> Fut,
+        Fut: Future<Output = Result<T>> + Send + 'static,
+    {
+        let trace = self.session.trace();
+        let mut span = trace.span("stage", name);
+        span.arg("items", items.len());
+        let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+        let mut set = JoinSet::new();
+        for item in items {
+            let label = item.clone();
+            let task = f(self.client.clone(), item);
+            let semaphore = semaphore.clone();
+            let trace = trace.clone();
+            set.spawn(async move {
+                let _permit = semaphore.acquire_owned().await?;
+                // opened once running, so that the span excludes the wait
+                let mut item_span = trace.span("item", name);
+                item_span.arg("item", label);
+                let res = task.await;
+                if let Err(e) = &res {
+                    item_span.arg("error", e);
+                }
+                res
+            });
+        }
+        let mut output = StageOutput {
+            results: Vec::new(),
+            failures: 0,
+            cancelled: 0,
+        };
+        let expired = expiry(self.deadline);
+        tokio::pin!(expired);
+        loop {
+            tokio::select! {
+                res = set.join_next() => match res {
+                    Some(Ok(Ok(r))) => output.results.push(r),
+                    Some(_) => output.failures += 1,
+                    None => break,
+                },
+                _ = &mut expired => {
+                    output.cancelled = set.len();
+                    break;
+                }
+            }
+        }
+        set.shutdown().await;
+        span.arg("failures", output.failures);
+        span.arg("cancelled", output.cancelled);
+        output
+    }
+}
+
+/// Future completing at the deadline, never if there is none
+pub(crate) async fn expiry(deadline: Option<Instant>) {
+    match deadline {
+        Some(d) => tokio::time::sleep_until(d).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn stage() {
+        let pipeline = Pipeline::new(None, None).unwrap();
+        let items = (0..10).map(|n| n.to_string()).collect();
+        let output = pipeline
+            .stage("test", items, 3, |_, item| async move {
+                let n: u32 = item.parse()?;
+                anyhow::ensure!(n.is_multiple_of(2), "odd");
+                Ok(n)
+            })
+            .await;
+        let mut results = output.results;
+        results.sort();
+        assert_eq!(results, [0, 2, 4, 6, 8]);
+        assert_eq!(output.failures, 5);
+        assert_eq!(output.cancelled, 0);
+    }
+
+    #[tokio::test]
+    async fn deadline() {
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let pipeline = Pipeline::new(None, Some(deadline)).unwrap();
+        let items = vec!["fast".into(), "slow".into()];
+        let output = pipeline
+            .stage("test", items, 2, |_, item| async move {
+                if item == "slow" {
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                }
+                Ok(item)
+            })
+            .await;
+        assert_eq!(output.results, ["fast"]);
+        assert_eq!(output.cancelled, 1);
+    }
+
+    #[tokio::test]
+    async fn traced_items() {
+        let trace = crate::trace::Trace::enabled();
+        let session = Session::default().with_trace(trace.clone());
+        let pipeline = Pipeline::with_session(None, None, &session).unwrap();
+        let items = (0..3).map(|n| n.to_string()).collect();
+        pipeline
+            .stage("test", items, 1, |_, item| async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                Ok(item)
+            })
+            .await;
+        let json: serde_json::Value = serde_json::from_str(&trace.to_json()).unwrap();
+        let items = json["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|e| e["cat"] == "item")
+            .collect::<Vec<_>>();
+        assert_eq!(items.len(), 3);
+        // the items waiting for their turn are not traced yet
+        assert!(items.iter().all(|e| e["dur"].as_u64() < Some(40_000)));
+    }
+}
@@ -0,0 +1,124 @@
+//! Selection recorded to be reviewed, then written later.
+//!
+//! `reflecto plan` saves the proposed mirrorlist with the measurements that led
+//! to it, and `reflecto apply` writes exactly that mirrorlist. The selection
+//! can thus run as an unprivileged user, only the write of the plan needing
+//! privileges.
+use crate::error::snippet;
+use crate::report::{self, Row};
+use crate::MirrorList;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Plan {
+    pub created: DateTime<Utc>,
+    /// file the mirrorlist is meant to be written to, if any
+    pub target: Option<PathBuf>,
+    /// selected mirrors, in order, with their metrics
+    pub mirrors: Vec<Row>,
+    /// content of the mirrorlist
+    pub content: String,
+}
+
+impl Plan {
+    /// Plan writing the first `number` mirrors, rendered as `content`
+    pub fn new(mlist: &MirrorList, number: usize, content: String, target: Option<&Path>) -> Self {
+        Self {
+            created: mlist.now(),
+            target: target.map(PathBuf::from),
+            mirrors: report::rows(mlist, number),
+            content,
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read plan {}", path.display()))?;
+        serde_json::from_str(&content).with_context(|| format!("invalid plan {}", path.display()))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("plan is always serializable")
+    }
+
+    /// Check that the content is a pacman mirrorlist: blank lines, comments and
+    /// server lines of http, https, ftp or rsync URLs
+    pub fn check_mirrorlist(&self) -> Result<()> {
+        for (n, line) in self.content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let url = line
+                .strip_prefix("Server")
+                .and_then(|l| l.trim_start().strip_prefix('='))
+                .map(str::trim);
+            match url {
+                Some(url)
+                    if SCHEMES.iter().any(|s| url.starts_with(s))
+                        && !url.contains(char::is_whitespace) => {}
+                _ => anyhow::bail!(
+                    "line {} of the plan is not a server line: \"{}\"",
+                    n + 1,
+                    snippet(line)
+                ),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// URL schemes of the server lines of a mirrorlist
+const SCHEMES: [&str; 4] = ["http://", "https://", "ftp://", "rsync://"];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Distro, Number, RenderOptions};
+
+    #[test]
+    fn round_trip() {
+        let ml = MirrorList::from_mirrorlist(
+            "https://a.example.org/\nhttps://b.example.org/",
+            Distro::Arch,
+        );
+        let content = ml.to_file_content(&RenderOptions::new(Number::Limit(1)));
+        let plan = Plan::new(
+            &ml,
+            1,
+            content.clone(),
+            Some(Path::new("/etc/pacman.d/mirrorlist")),
+        );
+        assert_eq!(plan.mirrors.len(), 1);
+        let json = plan.to_json();
+        let read: Plan = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, plan);
+        assert_eq!(read.content, content);
+        assert!(read.check_mirrorlist().is_ok());
+    }
+
+    #[test]
+    fn not_a_mirrorlist() {
+        let ml = MirrorList::from_mirrorlist("https://a.example.org/", Distro::Arch);
+        let plan = |content: &str| Plan::new(&ml, 1, content.into(), None);
+        assert!(
+            plan("# comment\n\nServer=https://a.example.org/$repo/os/$arch")
+                .check_mirrorlist()
+                .is_ok()
+        );
+        let err = plan("Server = https://a.example.org/\nInclude = /etc/shadow")
+            .check_mirrorlist()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 2 of the plan is not a server line: \"Include = /etc/shadow\""
+        );
+        assert!(plan("Server = file:///tmp/").check_mirrorlist().is_err());
+        assert!(plan("Server = https://a.example.org/ x")
+            .check_mirrorlist()
+            .is_err());
+    }
+}
@@ -0,0 +1,118 @@
+//! Hints that bandwidth is scarce, so that a timer running on a laptop does
+//! not spend it on rate tests.
+//!
+//! On Linux, the connection is metered when NetworkManager says the primary
+//! connection is, e.g. for a phone hotspot, and the host runs on battery when a battery of
+//! `/sys/class/power_supply` is discharging. Other systems give no hint.
+use std::fmt;
+use std::path::Path;
+use tokio::process::Command;
+
+/// Power supplies known by the kernel
+const POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+/// Why bandwidth should be spared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Constraint {
+    /// the connection is billed by volume
+    Metered,
+    /// the host runs on battery
+    Battery,
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Metered => write!(f, "the connection is metered"),
+            Self::Battery => write!(f, "the host runs on battery"),
+        }
+    }
+}
+
+/// The constraint on the bandwidth of the host, if any
+pub async fn constraint() -> Option<Constraint> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+    if metered().await {
+        Some(Constraint::Metered)
+    } else if on_battery(Path::new(POWER_SUPPLY)) {
+        Some(Constraint::Battery)
+    } else {
+        None
+    }
+}
+
+/// Whether NetworkManager considers the primary connection metered. Without
+/// NetworkManager, the connection is not known to be metered
+async fn metered() -> bool {
+    Command::new("nmcli")
+        .args(["--terse", "--fields", "METERED", "general", "status"])
+        .output()
+        .await
+        .ok()
+        .filter(|o| o.status.success())
+        .is_some_and(|o| parse_metered(&String::from_utf8_lossy(&o.stdout)))
+}
+
+/// Whether the output of nmcli says the primary connection is metered, the
+/// guess of NetworkManager included
+fn parse_metered(output: &str) -> bool {
+    output.trim().starts_with("yes")
+}
+
+/// Whether a battery of a directory of power supplies is discharging, the
+/// batteries of peripherals such as wireless mice aside
+fn on_battery(dir: &Path) -> bool {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return false;
+    };
+    let read = |supply: &Path, name: &str| {
+        std::fs::read_to_string(supply.join(name))
+            .map(|v| v.trim().to_string())
+            .unwrap_or_default()
+    };
+    entries.flatten().any(|e| {
+        let supply = e.path();
+        read(&supply, "type") == "Battery"
+            && read(&supply, "scope") != "Device"
+            && read(&supply, "status") == "Discharging"
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nmcli() {
+        assert!(parse_metered("yes\n"));
+        assert!(parse_metered("yes (guessed)\n"));
+        assert!(!parse_metered("no (guessed)\n"));
+        assert!(!parse_metered("unknown\n"));
+        assert!(!parse_metered(""));
+    }
+
+    #[test]
+    fn battery() {
+        let dir = std::env::temp_dir().join(format!("reflecto-power-{}", std::process::id()));
+        let supply = |name: &str, kind: &str, status: Option<&str>| {
+            let path = dir.join(name);
+            std::fs::create_dir_all(&path).unwrap();
+            std::fs::write(path.join("type"), format!("{kind}\n")).unwrap();
+            if let Some(status) = status {
+                std::fs::write(path.join("status"), format!("{status}\n")).unwrap();
+            }
+        };
+        supply("AC", "Mains", None);
+        supply("hidpp_battery_0", "Battery", Some("Discharging"));
+        std::fs::write(dir.join("hidpp_battery_0/scope"), "Device\n").unwrap();
+        assert!(!on_battery(&dir));
+        supply("BAT0", "Battery", Some("Charging"));
+        assert!(!on_battery(&dir));
+        supply("BAT0", "Battery", Some("Discharging"));
+        assert!(on_battery(&dir));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!on_battery(&dir));
+    }
+}
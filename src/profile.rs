@@ -0,0 +1,266 @@
+//! Named sets of command line options.
+//!
+//! Built-in presets bundle filters, sort key and number of mirrors for common
+//! setups. A preset is overridden, or a new one defined, by a TOML file of the
+//! same name in the `presets` directory of the configuration directory.
+//!
+//! Profiles are the options of an invocation saved by the user, in the
+//! `profiles` directory of the configuration directory, to be replayed later.
+//! Options given on the command line take precedence over the ones of a
+//! profile, which take precedence over the ones of a preset.
+//!
+//! ```toml
+//! description = "Nearby mirrors"
+//! country = ["FR", "DE"]
+//! sort = "rate"
+//! number = 5
+//! ```
+use crate::{Number, Protocol, SortKey, TestMode};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Options of a preset, named after the command line options
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    pub description: Option<String>,
+    pub age: Option<f64>,
+    /// countries, by name or code
+    pub country: Option<Vec<String>>,
+    pub isos: Option<bool>,
+    pub ipv4: Option<bool>,
+    pub ipv6: Option<bool>,
+    pub completion_percent: Option<f64>,
+    pub details: Option<bool>,
+    pub tier: Option<u8>,
+    pub sort: Option<SortKey>,
+    pub number: Option<Number>,
+    /// in seconds
+    pub download_timeout: Option<i64>,
+    /// e.g. 60s or 2m
+    pub max_duration: Option<String>,
+    pub max_test_bytes_total: Option<u64>,
+    pub test_mode: Option<TestMode>,
+    pub prefer_protocol: Option<Vec<Protocol>>,
+}
+
+static LAPTOP: &str = r#"
+description = "Recent mirrors ranked by a quick, bounded download test, for changing networks"
+age = 12.0
+sort = "rate"
+number = 10
+download-timeout = 3
+max-test-bytes-total = 20000000
+test-mode = "hybrid"
+prefer-protocol = ["https"]
+"#;
+
+static SERVER: &str = r#"
+description = "Complete, up-to-date mirrors ranked by download rate, using their check history"
+age = 6.0
+completion-percent = 100.0
+details = true
+sort = "rate"
+number = 20
+prefer-protocol = ["https"]
+"#;
+
+static CI: &str = r#"
+description = "A few well-scored HTTPS mirrors chosen without download tests, for fast jobs"
+age = 24.0
+sort = "score"
+number = 5
+max-duration = "30s"
+prefer-protocol = ["https"]
+"#;
+
+/// Built-in presets, by name
+static PRESETS: [(&str, &str); 3] = [("laptop", LAPTOP), ("server", SERVER), ("ci", CI)];
+
+impl Profile {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("invalid options {}", path.display()))
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("options are always serializable")
+    }
+
+    /// Write the options to a file, creating its directory if needed
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("unable to create {}", dir.display()))?;
+        }
+        std::fs::write(path, self.to_toml())
+            .with_context(|| format!("unable to write {}", path.display()))
+    }
+
+    /// Options of `self`, completed by the ones of `other`
+    pub fn or(self, other: Profile) -> Profile {
+        Profile {
+            description: self.description.or(other.description),
+            age: self.age.or(other.age),
+            country: self.country.or(other.country),
+            isos: self.isos.or(other.isos),
+            ipv4: self.ipv4.or(other.ipv4),
+            ipv6: self.ipv6.or(other.ipv6),
+            completion_percent: self.completion_percent.or(other.completion_percent),
+            details: self.details.or(other.details),
+            tier: self.tier.or(other.tier),
+            sort: self.sort.or(other.sort),
+            number: self.number.or(other.number),
+            download_timeout: self.download_timeout.or(other.download_timeout),
+            max_duration: self.max_duration.or(other.max_duration),
+            max_test_bytes_total: self.max_test_bytes_total.or(other.max_test_bytes_total),
+            test_mode: self.test_mode.or(other.test_mode),
+            prefer_protocol: self.prefer_protocol.or(other.prefer_protocol),
+        }
+    }
+}
+
+/// File named after a profile or preset in the subdirectory `kind` of the
+/// configuration directory `dir`, the name staying in that subdirectory
+fn named_path(dir: &Path, kind: &str, name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        anyhow::bail!("invalid {} name {name:?}", kind.trim_end_matches('s'));
+    }
+    Ok(dir.join(kind).join(format!("{name}.toml")))
+}
+
+/// File of a profile saved in the configuration directory `dir`
+pub fn profile_path(dir: &Path, name: &str) -> Result<PathBuf> {
+    named_path(dir, "profiles", name)
+}
+
+/// Profile saved in the configuration directory `dir`
+pub fn profile(name: &str, dir: &Path) -> Result<Profile> {
+    let path = profile_path(dir, name)?;
+    if !path.exists() {
+        anyhow::bail!("no profile {name}, save one with --save-profile {name}");
+    }
+    Profile::from_file(&path)
+}
+
+/// File of a preset defined by the user in the configuration directory `dir`
+pub fn preset_path(dir: &Path, name: &str) -> Result<PathBuf> {
+    named_path(dir, "presets", name)
+}
+
+/// Preset of this name, the one of the configuration directory `dir` replacing
+/// the built-in one
+pub fn preset(name: &str, dir: Option<&Path>) -> Result<Profile> {
+    if let Some(path) = dir
+        .map(|d| preset_path(d, name))
+        .transpose()?
+        .filter(|p| p.exists())
+    {
+        return Profile::from_file(&path);
+    }
+    match PRESETS.iter().find(|(n, _)| *n == name) {
+        Some((_, content)) => Ok(toml::from_str(content).expect("built-in presets are valid")),
+        None => anyhow::bail!(
+            "unknown preset {name}, available presets: {}",
+            preset_names(dir).join(", ")
+        ),
+    }
+}
+
+/// Names of the built-in presets and of the presets of the configuration
+/// directory `dir`, sorted
+pub fn preset_names(dir: Option<&Path>) -> Vec<String> {
+    let mut names = PRESETS
+        .iter()
+        .map(|(n, _)| n.to_string())
+        .collect::<Vec<_>>();
+    if let Some(entries) = dir.and_then(|d| std::fs::read_dir(d.join("presets")).ok()) {
+        names.extend(entries.filter_map(|e| {
+            let path = e.ok()?.path();
+            if path.extension()? != "toml" {
+                return None;
+            }
+            Some(path.file_stem()?.to_string_lossy().into_owned())
+        }));
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_presets() {
+        for (name, _) in PRESETS {
+            let preset = preset(name, None).unwrap();
+            assert!(preset.description.is_some());
+            assert!(preset.sort.is_some());
+        }
+        let laptop = preset("laptop", None).unwrap();
+        assert!(matches!(laptop.sort, Some(SortKey::Rate)));
+        assert_eq!(laptop.test_mode, Some(TestMode::Hybrid));
+        assert_eq!(laptop.prefer_protocol, Some(vec![Protocol::Https]));
+    }
+
+    #[test]
+    fn user_presets() {
+        let dir = std::env::temp_dir().join(format!("reflecto-presets-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("presets")).unwrap();
+        let path = |name| preset_path(&dir, name).unwrap();
+        std::fs::write(path("ci"), "sort = \"age\"\nnumber = 3").unwrap();
+        std::fs::write(path("home"), "country = [\"FR\"]").unwrap();
+        let ci = preset("ci", Some(&dir)).unwrap();
+        assert_eq!(ci.number, Some(Number::Limit(3)));
+        assert_eq!(ci.description, None);
+        assert_eq!(preset_names(Some(&dir)), ["ci", "home", "laptop", "server"]);
+        let err = preset("nope", Some(&dir)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown preset nope, available presets: ci, home, laptop, server"
+        );
+        assert!(preset("../profiles/home", Some(&dir)).is_err());
+        assert!(preset_path(&dir, "").is_err());
+        assert!(preset_path(&dir, "..").is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn saved_profiles() {
+        let dir = std::env::temp_dir().join(format!("reflecto-profiles-{}", std::process::id()));
+        let home = Profile {
+            country: Some(vec!["FR".into()]),
+            sort: Some(SortKey::Rate),
+            ..Default::default()
+        };
+        home.save(&profile_path(&dir, "home").unwrap()).unwrap();
+        assert_eq!(profile("home", &dir).unwrap(), home);
+        assert!(profile("work", &dir).is_err());
+        assert!(profile_path(&dir, "../home").is_err());
+        assert!(profile_path(&dir, ".hidden").is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn precedence() {
+        let home = Profile {
+            sort: Some(SortKey::Age),
+            ..Default::default()
+        };
+        let merged = home.or(preset("ci", None).unwrap());
+        assert_eq!(merged.sort, Some(SortKey::Age));
+        assert_eq!(merged.number, Some(Number::Limit(5)));
+    }
+
+    #[test]
+    fn round_trip() {
+        let laptop = preset("laptop", None).unwrap();
+        let read: Profile = toml::from_str(&laptop.to_toml()).unwrap();
+        assert_eq!(read, laptop);
+        assert!(toml::from_str::<Profile>("colour = \"blue\"").is_err());
+    }
+}
@@ -0,0 +1,42 @@
+//! Proxy of the requests, e.g. a SOCKS one on networks only allowing that
+//! egress.
+//!
+//! The proxy is carried by the [`crate::Session`] of a run, and every client
+//! built by [`crate::Session::client_builder`] goes through it: retrieval of
+//! the status, probes, rate tests and notifications. Without proxy, the
+//! proxies of the environment (`HTTPS_PROXY`, `ALL_PROXY`, ...) are used. The
+//! reachability check connects directly, as it tests the network path.
+use anyhow::{bail, Context, Result};
+use reqwest::Proxy;
+
+/// Proxy of every request, given by URL: `http://`, `https://`, `socks5://`,
+/// or `socks5h://` to resolve host names through the proxy. SOCKS proxies
+/// need the `socks` feature
+pub fn parse(url: &str) -> Result<Proxy> {
+    let scheme = url.split_once("://").map(|(s, _)| s).unwrap_or_default();
+    match scheme {
+        "http" | "https" => {}
+        "socks5" | "socks5h" if cfg!(feature = "socks") => {}
+        "socks5" | "socks5h" => {
+            bail!("reflecto was built without SOCKS support, see the socks feature")
+        }
+        _ => bail!("unsupported proxy {url}, expected an http, https, socks5 or socks5h URL"),
+    }
+    Proxy::all(url).with_context(|| format!("invalid proxy {url}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schemes() {
+        assert!(parse("http://proxy.internal:3128").is_ok());
+        assert_eq!(
+            parse("socks5h://127.0.0.1:1080").is_ok(),
+            cfg!(feature = "socks")
+        );
+        assert!(parse("ftp://proxy.internal").is_err());
+        assert!(parse("proxy.internal:3128").is_err());
+    }
+}
@@ -0,0 +1,635 @@
+//! Reports describing the selected mirrors, for humans.
+use crate::dns::Reachability;
+use crate::{Mirror, MirrorList, RenderOptions, ResponseInfo};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use unicode_width::UnicodeWidthStr;
+
+/// Format of the generated file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// pacman mirrorlist
+    #[default]
+    Mirrorlist,
+    /// standalone HTML page with a sortable table
+    Html,
+    /// GitHub flavored markdown table
+    Markdown,
+    /// JSON array of the mirror metrics
+    Json,
+    /// mirror status JSON, as published by the distribution, with the
+    /// measured metrics in `reflecto_` fields
+    StatusJson,
+    /// URLs of the latest installation image. Implies --isos
+    Isos,
+}
+
+/// Metrics of a selected mirror
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Row {
+    pub rank: usize,
+    pub url: String,
+    pub country: Option<String>,
+    pub protocol: String,
+    pub age_hours: Option<f64>,
+    pub score: Option<f64>,
+    pub delay: Option<f64>,
+    pub completion: Option<f64>,
+    pub duration: Option<f64>,
+    /// measured download rate, in MB/s
+    pub rate: Option<f64>,
+    /// response to the rate test
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<ResponseInfo>,
+    /// whether the connection was kept open between two requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<bool>,
+    /// time, in seconds, to fetch the metadata files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata_time: Option<f64>,
+    /// time, in seconds, to get the headers of the test file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency: Option<f64>,
+    /// resolved addresses of the host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub addresses: Option<Vec<IpAddr>>,
+    /// whether the addresses differ from the ones of the previous run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_changed: Option<bool>,
+    /// whether the checksums and signature of the installation image are
+    /// published
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iso_files: Option<bool>,
+    /// IP versions over which a connection succeeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reachable: Option<Reachability>,
+}
+
+/// column titles, in the order of [`Row::cells`]
+pub const HEADERS: [&str; 10] = [
+    "rank",
+    "url",
+    "country",
+    "protocol",
+    "age (h)",
+    "score",
+    "delay (s)",
+    "completion",
+    "duration (s)",
+    "rate (MB/s)",
+];
+
+impl Row {
+    fn new(rank: usize, m: &Mirror, now: DateTime<Utc>) -> Self {
+        Self {
+            rank,
+            url: m.url.clone(),
+            country: m.code(),
+            protocol: m.protocol.to_string(),
+            age_hours: m.age_hours(now),
+            score: m.score,
+            delay: m.delay,
+            completion: m.completion(),
+            duration: m.duration(),
+            rate: m
+                .download_rate
+                .as_ref()
+                .map(|r| r.0)
+                .filter(|r| r.is_finite()),
+            response: m.response.clone(),
+            keep_alive: m.keep_alive,
+            metadata_time: m.metadata_time,
+            latency: m.latency,
+            addresses: m.addresses.clone(),
+            dns_changed: m.dns_changed,
+            iso_files: m.iso_files,
+            reachable: m.reachable,
+        }
+    }
+
+    /// values as text, empty when unknown
+    pub fn cells(&self) -> [String; 10] {
+        let num = |v: Option<f64>, precision: usize| {
+            v.map(|v| format!("{v:.precision$}")).unwrap_or_default()
+        };
+        [
+            self.rank.to_string(),
+            self.url.clone(),
+            self.country.clone().unwrap_or_default(),
+            self.protocol.clone(),
+            num(self.age_hours, 1),
+            num(self.score, 2),
+            num(self.delay, 0),
+            num(self.completion, 3),
+            num(self.duration, 3),
+            num(self.rate, 3),
+        ]
+    }
+}
+
+/// Metrics of the first `number` mirrors
+pub fn rows(mlist: &MirrorList, number: usize) -> Vec<Row> {
+    let now = mlist.now();
+    mlist
+        .mirrors
+        .iter()
+        .take(number)
+        .enumerate()
+        .map(|(i, m)| Row::new(i + 1, m, now))
+        .collect()
+}
+
+/// Number of mirrors of a country
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CountryCount {
+    pub country: String,
+    pub code: String,
+    /// mirrors of the status
+    pub mirrors: usize,
+    /// written mirrors
+    pub selected: usize,
+}
+
+/// Distribution by country of the mirrors of the status, `available` as
+/// counted by `MirrorList::get_countries`, and of the first `number` mirrors of
+/// the selection. Sorted by country
+pub fn country_counts(
+    available: HashMap<(String, String), usize>,
+    selection: &MirrorList,
+    number: usize,
+) -> Vec<CountryCount> {
+    let selected = crate::count_countries(selection.mirrors.iter().take(number));
+    let mut counts = available
+        .into_iter()
+        .map(|((country, code), mirrors)| CountryCount {
+            selected: selected
+                .get(&(country.clone(), code.clone()))
+                .copied()
+                .unwrap_or_default(),
+            country,
+            code,
+            mirrors,
+        })
+        .collect::<Vec<_>>();
+    counts.sort_by(|a, b| (&a.country, &a.code).cmp(&(&b.country, &b.code)));
+    counts
+}
+
+/// Best mirror of a country
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CountryBest {
+    pub country: String,
+    pub code: String,
+    /// mirrors of the country in the selection
+    pub mirrors: usize,
+    /// first mirror of the country, ranked in the whole selection
+    pub best: Row,
+}
+
+/// First mirror of each country in the order of the selection, sorted by
+/// country. Mirrors without country are ignored
+pub fn best_per_country(mlist: &MirrorList) -> Vec<CountryBest> {
+    let now = mlist.now();
+    let mut best: Vec<CountryBest> = Vec::new();
+    for (i, m) in mlist.mirrors.iter().enumerate() {
+        let (Some(country), Some(code)) = (m.country.as_deref(), m.code()) else {
+            continue;
+        };
+        if country.is_empty() {
+            continue;
+        }
+        match best
+            .iter_mut()
+            .find(|b| b.country == country && b.code == code)
+        {
+            Some(b) => b.mirrors += 1,
+            None => best.push(CountryBest {
+                country: country.into(),
+                code,
+                mirrors: 1,
+                best: Row::new(i + 1, m, now),
+            }),
+        }
+    }
+    best.sort_by(|a, b| (&a.country, &a.code).cmp(&(&b.country, &b.code)));
+    best
+}
+
+/// Table of the best mirror of each country: country, code, number of
+/// mirrors, rank and URL of the best one
+pub fn best_table(best: &[CountryBest]) -> String {
+    let width = best
+        .iter()
+        .map(|b| b.country.width())
+        .chain(["country".len()])
+        .max()
+        .unwrap_or_default();
+    let line = |country: &str, rest: String| {
+        let padding = " ".repeat(width - country.width());
+        format!("{country}{padding} {rest}")
+    };
+    let mut lines = vec![line(
+        "country",
+        format!("code {:>7} {:>4} url", "mirrors", "rank"),
+    )];
+    for b in best {
+        lines.push(line(
+            &b.country,
+            format!(
+                "{:<4} {:>7} {:>4} {}",
+                b.code, b.mirrors, b.best.rank, b.best.url
+            ),
+        ));
+    }
+    lines.join("\n")
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// sort the table by the clicked column, numerically when possible
+static SORT_SCRIPT: &str = r#"
+document.querySelectorAll("th").forEach((th, col) => th.addEventListener("click", () => {
+  const body = th.closest("table").tBodies[0];
+  const key = tr => tr.cells[col].textContent;
+  const asc = th.dataset.asc !== "true";
+  th.dataset.asc = asc;
+  const rows = Array.from(body.rows).sort((a, b) => {
+    const [x, y] = [key(a), key(b)];
+    const [nx, ny] = [parseFloat(x), parseFloat(y)];
+    const cmp = isNaN(nx) || isNaN(ny) ? x.localeCompare(y) : nx - ny;
+    return asc ? cmp : -cmp;
+  });
+  rows.forEach(r => body.appendChild(r));
+}));
+"#;
+
+/// Standalone HTML page listing the first `number` mirrors
+pub fn html(mlist: &MirrorList, number: usize) -> String {
+    let title = format!("{} mirrors", mlist.distro.name());
+    let generated = mlist.now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    let header = HEADERS
+        .iter()
+        .map(|h| format!("<th>{h}</th>"))
+        .collect::<String>();
+    let body = rows(mlist, number)
+        .iter()
+        .map(|r| {
+            let cells = r
+                .cells()
+                .iter()
+                .map(|c| format!("<td>{}</td>", escape(c)))
+                .collect::<String>();
+            format!("<tr>{cells}</tr>")
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+table {{ border-collapse: collapse; }}
+th, td {{ border: 1px solid #ccc; padding: 0.2em 0.5em; }}
+th {{ cursor: pointer; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<p>Generated by reflecto on {generated}.</p>
+<table>
+<thead><tr>{header}</tr></thead>
+<tbody>
+{body}
+</tbody>
+</table>
+<script>{SORT_SCRIPT}</script>
+</body>
+</html>
+"#
+    )
+}
+
+/// Markdown table of the first `number` mirrors
+pub fn markdown(mlist: &MirrorList, number: usize) -> String {
+    let line = |cells: &[String]| format!("| {} |", cells.join(" | "));
+    // text columns are left aligned, numbers right aligned
+    let alignment = HEADERS
+        .iter()
+        .enumerate()
+        .map(|(i, _)| if (1..4).contains(&i) { ":---" } else { "---:" }.to_string())
+        .collect::<Vec<_>>();
+    let mut lines = vec![line(&HEADERS.map(String::from)), line(&alignment)];
+    for row in rows(mlist, number) {
+        let cells = row.cells().map(|c| c.replace('|', "\\|"));
+        lines.push(line(&cells));
+    }
+    lines.join("\n")
+}
+
+/// Content of the generated file, describing the mirrors kept by the options
+pub fn render(mlist: &MirrorList, format: OutputFormat, options: &RenderOptions) -> String {
+    let number = options.limit.of(mlist.len());
+    match format {
+        OutputFormat::Mirrorlist => mlist.to_file_content(options),
+        OutputFormat::Html => html(mlist, number),
+        OutputFormat::Markdown => markdown(mlist, number),
+        OutputFormat::Json => json(mlist, number),
+        OutputFormat::StatusJson => status_json(mlist, number),
+        OutputFormat::Isos => isos(mlist, number),
+    }
+}
+
+/// URLs of the latest installation image on the first `number` mirrors.
+/// Mirrors known to lack its checksums or signature are commented out
+pub fn isos(mlist: &MirrorList, number: usize) -> String {
+    let name = mlist.distro.name();
+    let Some(paths) = mlist.distro.iso_paths() else {
+        return format!("# no installation image known for {name}\n");
+    };
+    let mut lines = vec![format!(
+        "# {name} installation images, generated by reflecto.rs"
+    )];
+    for m in mlist.mirrors.iter().take(number) {
+        let url = format!("{}/{}", m.url.trim_end_matches('/'), paths.image);
+        lines.push(match m.iso_files {
+            Some(false) => format!("# {url} (checksums or signature missing)"),
+            _ => url,
+        });
+    }
+    lines.join("\n") + "\n"
+}
+
+/// JSON array of the metrics of the first `number` mirrors
+pub fn json(mlist: &MirrorList, number: usize) -> String {
+    serde_json::to_string_pretty(&rows(mlist, number)).expect("rows are always serializable")
+}
+
+/// Mirror status, in the shape of the status JSON of archweb
+#[derive(Serialize)]
+struct Status<'a> {
+    last_check: Option<String>,
+    urls: Vec<StatusEntry<'a>>,
+}
+
+/// Mirror of the status JSON, with the measured metrics
+#[derive(Serialize)]
+struct StatusEntry<'a> {
+    url: &'a str,
+    protocol: String,
+    last_sync: Option<String>,
+    completion_pct: Option<f64>,
+    delay: Option<f64>,
+    duration_avg: Option<f64>,
+    duration_stddev: Option<f64>,
+    score: Option<f64>,
+    country: Option<&'a str>,
+    country_code: Option<&'a str>,
+    isos: Option<bool>,
+    ipv4: Option<bool>,
+    ipv6: Option<bool>,
+    details: &'a str,
+    /// measured download rate, in MB/s
+    reflecto_rate: Option<f64>,
+    /// time, in seconds, to get the headers of the test file
+    reflecto_latency: Option<f64>,
+    /// hours since the last synchronization
+    reflecto_age_hours: Option<f64>,
+    /// resolved addresses of the host
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reflecto_addresses: Option<&'a [IpAddr]>,
+}
+
+impl<'a> StatusEntry<'a> {
+    fn new(m: &'a Mirror, now: DateTime<Utc>) -> Self {
+        Self {
+            url: &m.url,
+            protocol: m.protocol.to_string(),
+            last_sync: m.last_sync.map(date),
+            completion_pct: m.completion_pct,
+            delay: m.delay,
+            duration_avg: m.duration_avg,
+            duration_stddev: m.duration_stddev,
+            score: m.score,
+            country: m.country.as_deref(),
+            country_code: m.country_code.as_deref(),
+            isos: m.isos,
+            ipv4: m.ipv4,
+            ipv6: m.ipv6,
+            details: &m.details,
+            reflecto_rate: m
+                .download_rate
+                .as_ref()
+                .map(|r| r.0)
+                .filter(|r| r.is_finite()),
+            reflecto_latency: m.latency,
+            reflecto_age_hours: m.age_hours(now),
+            reflecto_addresses: m.addresses.as_deref(),
+        }
+    }
+}
+
+fn date(d: DateTime<Utc>) -> String {
+    d.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+/// Status JSON of the first `number` mirrors, readable by the tools parsing
+/// the one of archweb, with the measured metrics
+pub fn status_json(mlist: &MirrorList, number: usize) -> String {
+    let status = Status {
+        last_check: mlist.last_check.map(date),
+        urls: mlist
+            .mirrors
+            .iter()
+            .take(number)
+            .map(|m| StatusEntry::new(m, mlist.now()))
+            .collect(),
+    };
+    serde_json::to_string_pretty(&status).expect("status is always serializable")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static STATUS: &str = r#"{"urls": [
+        {"url": "https://a.example.org/?a=1&b=<2>", "protocol": "https", "score": 1.5,
+         "country_code": "FR", "last_sync": null, "details": ""},
+        {"url": "http://b.example.org/", "protocol": "http", "score": 2.0,
+         "last_sync": null, "details": ""}
+    ]}"#;
+
+    #[test]
+    fn table_rows() {
+        let ml: MirrorList = serde_json::from_str(STATUS).unwrap();
+        let rows = rows(&ml, 1);
+        assert_eq!(rows.len(), 1);
+        let cells = rows[0].cells();
+        assert_eq!(cells[0], "1");
+        assert_eq!(cells[2], "FR");
+        assert_eq!(cells[5], "1.50");
+        assert_eq!(cells[9], "");
+    }
+
+    #[test]
+    fn json_rows() {
+        let mut ml: MirrorList = serde_json::from_str(STATUS).unwrap();
+        ml.mirrors[0].response = Some(ResponseInfo {
+            status: 200,
+            final_url: "https://cdn.example.net/extra.db".into(),
+            server: Some("nginx".into()),
+            version: "HTTP/2.0".into(),
+            cross_host_redirect: true,
+        });
+        let rows: Vec<serde_json::Value> = serde_json::from_str(&json(&ml, 10)).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["response"]["cross_host_redirect"], true);
+        assert!(rows[1].get("response").is_none());
+    }
+
+    #[test]
+    fn status_json_shape() {
+        let mut ml = MirrorList::from_status_str(
+            r#"{"last_check": "2024-05-01T15:00:00.123Z", "urls": [
+            {"url": "https://a.example.org/", "protocol": "https", "score": 1.5,
+             "country": "France", "country_code": "FR", "completion_pct": 1.0,
+             "last_sync": "2024-05-01T14:00:00Z", "isos": true, "details": "d"},
+            {"url": "http://b.example.org/", "protocol": "http", "last_sync": null,
+             "details": ""}
+        ]}"#,
+        )
+        .unwrap();
+        ml.mirrors[0].download_rate = Some(crate::Bandwidth(12.5));
+        ml.mirrors[0].latency = Some(0.05);
+        let content = status_json(&ml, 10);
+        let status: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(status["last_check"], "2024-05-01T15:00:00.123Z");
+        let first = &status["urls"][0];
+        assert_eq!(first["last_sync"], "2024-05-01T14:00:00Z");
+        assert_eq!(first["country"], "France");
+        assert_eq!(first["isos"], true);
+        assert_eq!(first["reflecto_rate"], 12.5);
+        assert_eq!(first["reflecto_latency"], 0.05);
+        assert!(first["reflecto_age_hours"].is_f64());
+        assert_eq!(status["urls"][1]["reflecto_rate"], serde_json::Value::Null);
+        // readable back as a status
+        let read = MirrorList::from_status_str(&content).unwrap();
+        assert_eq!(
+            read.urls().collect::<Vec<_>>(),
+            ml.urls().collect::<Vec<_>>()
+        );
+        assert_eq!(read.last_check, ml.last_check);
+    }
+
+    #[test]
+    fn countries() {
+        let all: MirrorList = serde_json::from_str(
+            r#"{"urls": [
+            {"url": "https://a.example.org/", "protocol": "https", "country": "France",
+             "country_code": "FR", "last_sync": null, "details": ""},
+            {"url": "https://b.example.org/", "protocol": "https", "country": "Germany",
+             "country_code": "DE", "last_sync": null, "details": ""},
+            {"url": "https://c.example.org/", "protocol": "https", "country": "France",
+             "country_code": "FR", "last_sync": null, "details": ""}
+        ]}"#,
+        )
+        .unwrap();
+        let counts = country_counts(all.get_countries(), &all, 2);
+        assert_eq!(
+            counts,
+            [
+                CountryCount {
+                    country: "France".into(),
+                    code: "FR".into(),
+                    mirrors: 2,
+                    selected: 1,
+                },
+                CountryCount {
+                    country: "Germany".into(),
+                    code: "DE".into(),
+                    mirrors: 1,
+                    selected: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn best_of_countries() {
+        let ml: MirrorList = serde_json::from_str(
+            r#"{"urls": [
+            {"url": "https://g.example.org/", "protocol": "https", "country": "Germany",
+             "country_code": "DE", "last_sync": null, "details": ""},
+            {"url": "https://any.example.org/", "protocol": "https", "country": "",
+             "country_code": "", "last_sync": null, "details": ""},
+            {"url": "https://f1.example.org/", "protocol": "https", "country": "France",
+             "country_code": "FR", "last_sync": null, "details": ""},
+            {"url": "https://f2.example.org/", "protocol": "https", "country": "France",
+             "country_code": "FR", "last_sync": null, "details": ""}
+        ]}"#,
+        )
+        .unwrap();
+        let best = best_per_country(&ml);
+        assert_eq!(best.len(), 2);
+        assert_eq!(
+            (best[0].code.as_str(), best[0].mirrors, best[0].best.rank),
+            ("FR", 2, 3)
+        );
+        assert_eq!(best[0].best.url, "https://f1.example.org/");
+        assert_eq!(best[1].best.url, "https://g.example.org/");
+        assert_eq!(
+            best_table(&best),
+            "country code mirrors rank url\n\
+             France  FR         2    3 https://f1.example.org/\n\
+             Germany DE         1    1 https://g.example.org/"
+        );
+    }
+
+    #[test]
+    fn html_page() {
+        let ml: MirrorList = serde_json::from_str(STATUS).unwrap();
+        let page = html(&ml, 10);
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert_eq!(page.matches("<tr>").count(), 3);
+        assert!(page.contains("<td>https://a.example.org/?a=1&amp;b=&lt;2&gt;</td>"));
+        assert!(page.contains("<title>Arch Linux mirrors</title>"));
+    }
+
+    #[test]
+    fn iso_urls() {
+        let content = "https://a.example.org/\nhttp://b.example.org/";
+        let mut ml = MirrorList::from_mirrorlist(content, crate::Distro::Arch);
+        ml.mirrors[1].iso_files = Some(false);
+        assert_eq!(
+            isos(&ml, 2),
+            "# Arch Linux installation images, generated by reflecto.rs\n\
+             https://a.example.org/iso/latest/archlinux-x86_64.iso\n\
+             # http://b.example.org/iso/latest/archlinux-x86_64.iso (checksums or signature missing)\n"
+        );
+        ml.distro = crate::Distro::Manjaro;
+        assert_eq!(isos(&ml, 2), "# no installation image known for Manjaro\n");
+    }
+
+    #[test]
+    fn markdown_table() {
+        let ml: MirrorList = serde_json::from_str(STATUS).unwrap();
+        let table = markdown(&ml, 10);
+        let lines = table.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("| rank | url | country |"));
+        assert!(lines[1].starts_with("| ---: | :--- | :--- | :--- | ---: |"));
+        assert_eq!(
+            lines[3],
+            "| 2 | http://b.example.org/ |  | http |  | 2.00 |  |  |  |  |"
+        );
+    }
+}
@@ -0,0 +1,1001 @@
+//! The whole selection as a single call: fetch, filter, measure, sort and
+//! render.
+use crate::aliases::Aliases;
+use crate::explain::{Decision, Explanation};
+use crate::fetch::Preflight;
+use crate::overrides::Overrides;
+use crate::report::{self, OutputFormat};
+use crate::schema::SchemaMapping;
+use crate::urlfile::UrlFile;
+use crate::{
+    clock, dns, Annotation, BenchProfile, ByteBudget, Distro, FailurePolicy, Filters, HeaderField,
+    MirrorList, Number, Pipeline, Protocol, RenderOptions, Retry, Session, SortKey, TestMode,
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Serializer};
+use std::path::PathBuf;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+/// What to select and how
+#[derive(Debug, Clone, Serialize)]
+pub struct RunConfig {
+    pub distro: Distro,
+    /// status URLs, the one of the distribution if empty. Their mirrors are
+    /// merged
+    pub urls: Vec<String>,
+    /// URLs holding the same status, tried in order when a status URL fails
+    pub fallback_urls: Vec<String>,
+    /// request each status URL and its fallbacks at once, keeping the first
+    /// valid status
+    pub race_urls: bool,
+    /// custom format of the status documents
+    #[serde(skip)]
+    pub schema: Option<SchemaMapping>,
+    /// mirrorlist file read instead of the status
+    pub mirrorlist_file: Option<PathBuf>,
+    #[serde(skip)]
+    pub retry: Retry,
+    /// settings of the requests, e.g. the credentials of private status
+    /// endpoints and mirrors
+    #[serde(skip)]
+    pub session: Session,
+    /// connectivity check done first, failing the run when a captive portal
+    /// intercepts requests
+    pub preflight: Option<Preflight>,
+    /// mirrors never selected
+    pub blacklist: UrlFile,
+    /// mirrors always selected first
+    pub pins: UrlFile,
+    pub overrides: Overrides,
+    /// mirrors known to be aliases of the same backend
+    pub aliases: Aliases,
+    /// list a single mirror of each known backend before the other mirrors
+    pub dedupe_backends: bool,
+    pub filters: Filters,
+    /// retrieve the details page of each mirror. Implied by a tier filter
+    pub details: bool,
+    pub sort: SortKey,
+    /// number of mirrors written
+    pub number: Number,
+    /// number of mirrors whose rate test must succeed. `number` if not set
+    pub test_limit: Option<Number>,
+    /// timeout of each request done to measure mirrors. Serialized in seconds
+    #[serde(serialize_with = "serialize_seconds")]
+    pub download_timeout: chrono::Duration,
+    /// when measurements stop, keeping the data gathered so far
+    #[serde(skip)]
+    pub deadline: Option<Instant>,
+    /// when the details cannot be retrieved
+    pub on_details_error: FailurePolicy,
+    /// when a probe of the mirrors, e.g. the liveness check, fails
+    pub on_probe_error: FailurePolicy,
+    /// when a written mirror could not be measured. With `Fail`, [`run`]
+    /// fails with [`crate::Error::MeasurementFailed`]
+    pub on_test_error: FailurePolicy,
+    pub max_test_bytes_total: Option<u64>,
+    /// maximum rate of each rate test, in bytes per second
+    pub test_throttle: Option<u64>,
+    pub test_mode: TestMode,
+    pub bench_profile: BenchProfile,
+    /// test the mirrors in random order rather than by score
+    pub shuffle_tests: bool,
+    /// remove the mirrors whose URL is invalid
+    pub strict_urls: bool,
+    /// reference time for age computations, instead of the system time
+    pub now: Option<DateTime<Utc>>,
+    /// same output for the same input: no shuffling nor random pick, ties
+    /// ordered by URL and, without `now`, the last check of the status as
+    /// reference time. See [`clock::reference`]
+    pub deterministic: bool,
+    /// remove the unreachable mirrors before measuring the others
+    pub check_alive: bool,
+    /// connect to the mirrors over IPv4 and IPv6, recording which work
+    pub check_reachability: bool,
+    /// remove the mirrors to which an IPv4 connection fails. Implies
+    /// `check_reachability`
+    pub reachable_ipv4: bool,
+    /// remove the mirrors to which an IPv6 connection fails. Implies
+    /// `check_reachability`
+    pub reachable_ipv6: bool,
+    /// remove the mirrors closing the connection after each request
+    pub require_keep_alive: bool,
+    /// resolve the addresses of the written mirrors
+    pub resolve: bool,
+    /// addresses recorded by the previous run, to detect changes
+    #[serde(skip)]
+    pub previous_addresses: dns::Addresses,
+    /// check that the written mirrors publish the checksums and signature of
+    /// the installation image
+    pub verify_isos: bool,
+    pub prefer_protocols: Vec<Protocol>,
+    /// remove the mirrors redirecting to another host
+    pub no_redirects: bool,
+    /// fraction removed from the download rate of redirecting mirrors
+    pub redirect_penalty: f64,
+    /// compute the score of mirrors lacking one
+    pub recompute_scores: bool,
+    /// randomly choose this number of mirrors among the `pool` best ones
+    pub pick_random: Option<usize>,
+    pub pool: usize,
+    pub output_format: OutputFormat,
+    pub annotations: Vec<Annotation>,
+    /// number of mirrors following the written ones written commented out
+    pub commented_extras: usize,
+    /// lines of the header of the mirrorlist
+    pub header: Vec<HeaderField>,
+    /// server lines grouped by country
+    pub group_by_country: bool,
+}
+
+impl Default for RunConfig {
+    fn default() -> Self {
+        Self {
+            distro: Distro::default(),
+            urls: Vec::new(),
+            fallback_urls: Vec::new(),
+            race_urls: false,
+            schema: None,
+            mirrorlist_file: None,
+            retry: Retry::default(),
+            session: Session::default(),
+            preflight: None,
+            blacklist: UrlFile::default(),
+            pins: UrlFile::default(),
+            overrides: Overrides::default(),
+            aliases: Aliases::builtin(),
+            dedupe_backends: false,
+            filters: Filters::default(),
+            details: false,
+            sort: SortKey::Score,
+            number: Number::All,
+            test_limit: None,
+            download_timeout: chrono::Duration::seconds(5),
+            deadline: None,
+            on_details_error: FailurePolicy::default(),
+            on_probe_error: FailurePolicy::default(),
+            on_test_error: FailurePolicy::Skip,
+            max_test_bytes_total: None,
+            test_throttle: None,
+            test_mode: TestMode::default(),
+            bench_profile: BenchProfile::default(),
+            shuffle_tests: false,
+            strict_urls: false,
+            now: None,
+            deterministic: false,
+            check_alive: false,
+            check_reachability: false,
+            reachable_ipv4: false,
+            reachable_ipv6: false,
+            require_keep_alive: false,
+            resolve: false,
+            previous_addresses: dns::Addresses::new(),
+            verify_isos: false,
+            prefer_protocols: Vec::new(),
+            no_redirects: false,
+            redirect_penalty: 0.0,
+            recompute_scores: true,
+            pick_random: None,
+            pool: 10,
+            output_format: OutputFormat::default(),
+            annotations: Vec::new(),
+            commented_extras: 0,
+            header: HeaderField::DEFAULT.to_vec(),
+            group_by_country: false,
+        }
+    }
+}
+
+impl RunConfig {
+    fn uses_details(&self) -> bool {
+        self.details || self.filters.tier.is_some()
+    }
+
+    /// number of mirrors whose rate is tested, among `len`
+    fn tests(&self, len: usize) -> usize {
+        self.test_limit.unwrap_or(self.number).of(len)
+    }
+
+    /// How the selection is rendered
+    pub fn render_options(&self) -> RenderOptions {
+        RenderOptions {
+            annotations: self.annotations.clone(),
+            commented_extras: self.commented_extras,
+            group_by_country: self.group_by_country,
+            header: self.header.clone(),
+            ..RenderOptions::new(self.number)
+        }
+    }
+}
+
+/// Outcome of a selection, serializable for auditing
+#[derive(Debug, Serialize)]
+pub struct RunReport {
+    pub started: DateTime<Utc>,
+    /// duration of the selection, in seconds
+    pub elapsed: f64,
+    /// inputs of the selection
+    pub config: RunConfig,
+    /// why each mirror was kept or not
+    pub decisions: Vec<Decision>,
+    /// mirrors kept, sorted, the first `number` ones being written. Serialized
+    /// with their measurements
+    #[serde(serialize_with = "serialize_mirrors")]
+    pub mirrors: MirrorList,
+    /// URLs of the written mirrors whose measurement failed
+    pub failures: Vec<String>,
+    /// why the selection failed: [`crate::Error::EmptySelection`] if no
+    /// mirror is left, [`crate::Error::MeasurementFailed`] if a written mirror
+    /// could not be measured and the failures of the tests are fatal
+    #[serde(serialize_with = "serialize_failure")]
+    pub failure: Option<crate::Error>,
+    /// mirrors of the status and written mirrors, by country
+    pub countries: Vec<report::CountryCount>,
+    /// bytes downloaded by the rate tests
+    pub bytes_downloaded: u64,
+    /// duration of each phase of the run
+    pub phases: Phases,
+    /// rendered output, e.g. the mirrorlist
+    #[serde(skip)]
+    pub content: String,
+}
+
+impl RunReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("report is always serializable")
+    }
+
+    /// The failure of the selection, if any, as an error
+    pub fn check(&self) -> Result<()> {
+        match &self.failure {
+            Some(e) => Err(e.clone().into()),
+            None => Ok(()),
+        }
+    }
+}
+
+fn serialize_failure<S: Serializer>(
+    failure: &Option<crate::Error>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match failure {
+        Some(e) => serializer.serialize_str(&e.to_string()),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Duration of each phase of a run, in seconds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Phases {
+    /// retrieval of the status, fallbacks included
+    pub fetch: f64,
+    pub parse: f64,
+    /// filters and sort, without the measurements
+    pub filter: f64,
+    /// measurements of the mirrors: details, probes and rate tests
+    pub tests: f64,
+    pub render: f64,
+    /// output of the rendered content
+    pub write: f64,
+}
+
+impl Phases {
+    fn named(&self) -> [(&'static str, f64); 6] {
+        [
+            ("fetch", self.fetch),
+            ("parse", self.parse),
+            ("filter", self.filter),
+            ("tests", self.tests),
+            ("render", self.render),
+            ("write", self.write),
+        ]
+    }
+
+    /// Name of the longest phase
+    pub fn dominant(&self) -> &'static str {
+        self.named()
+            .into_iter()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(name, _)| name)
+            .unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for Phases {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let phases = self
+            .named()
+            .iter()
+            .map(|(name, d)| format!("{name} {d:.2}s"))
+            .collect::<Vec<_>>();
+        write!(f, "{}", phases.join(", "))
+    }
+}
+
+/// Await a measurement, adding its duration to the tests phase
+async fn measured<T>(phases: &mut Phases, f: impl std::future::Future<Output = T>) -> T {
+    let start = Instant::now();
+    let res = f.await;
+    phases.tests += start.elapsed().as_secs_f64();
+    res
+}
+
+fn serialize_mirrors<S: Serializer>(mlist: &MirrorList, serializer: S) -> Result<S::Ok, S::Error> {
+    report::rows(mlist, mlist.len()).serialize(serializer)
+}
+
+fn serialize_seconds<S: Serializer>(
+    duration: &chrono::Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.num_milliseconds() as f64 / 1000.0)
+}
+
+/// Retrieve the mirror status and select the mirrors.
+///
+/// Fails if the status cannot be retrieved, or if a stage whose failures are
+/// fatal fails. Otherwise the report is returned, with the failure of the
+/// selection if any, see [`RunReport::failure`] and [`RunReport::check`].
+pub async fn run(config: RunConfig) -> Result<RunReport> {
+    if let Some(preflight) = &config.preflight {
+        preflight
+            .check(config.download_timeout.to_std().ok(), &config.session)
+            .await?;
+    }
+    let mlist = fetch(&config).await?;
+    select(&config, mlist).await
+}
+
+/// Retrieve the mirrors of the status URLs, or of the mirrorlist file, and
+/// [`prepare`] them. Stops at the deadline. Failures to retrieve the status
+/// have the context [`crate::Error::FetchFailed`]
+pub async fn fetch(config: &RunConfig) -> Result<MirrorList> {
+    if let Some(path) = &config.mirrorlist_file {
+        let mlist = MirrorList::from_mirrorlist_file(path, config.distro)?;
+        return Ok(prepare(config, mlist));
+    }
+    let res = match config.deadline {
+        Some(d) => tokio::time::timeout_at(d, retrieve(config))
+            .await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("maximum duration reached"))),
+        None => retrieve(config).await,
+    };
+    Ok(prepare(config, res.context(crate::Error::FetchFailed)?))
+}
+
+/// Mirrors of every status URL, each one falling back to the fallback URLs
+async fn retrieve(config: &RunConfig) -> Result<MirrorList> {
+    let urls = if config.urls.is_empty() {
+        vec![config.distro.status_url().to_string()]
+    } else {
+        config.urls.clone()
+    };
+    let (retry, session) = (&config.retry, &config.session);
+    let started = Instant::now();
+    let mut mlist = MirrorList::default();
+    for url in urls {
+        let candidates = std::iter::once(url)
+            .chain(config.fallback_urls.iter().cloned())
+            .collect::<Vec<_>>();
+        let other = match (&config.schema, config.race_urls) {
+            (Some(mapping), false) => {
+                MirrorList::from_schema_urls(mapping, &candidates, retry, session).await?
+            }
+            (Some(mapping), true) => {
+                MirrorList::race_schema_urls(mapping, &candidates, retry, session).await?
+            }
+            (None, false) => {
+                MirrorList::from_distro_urls(config.distro, &candidates, retry, session).await?
+            }
+            (None, true) => {
+                MirrorList::race_distro_urls(config.distro, &candidates, retry, session).await?
+            }
+        };
+        mlist.merge(other);
+    }
+    // the status URLs are retrieved one after the other
+    mlist.phases.fetch = (started.elapsed().as_secs_f64() - mlist.phases.parse).max(0.0);
+    Ok(mlist)
+}
+
+/// Fix the reference time and the session of a retrieved list, apply the
+/// local overrides and record the backends of the aliases
+pub fn prepare(config: &RunConfig, mlist: MirrorList) -> MirrorList {
+    let clock = clock::reference(config.now, config.deterministic, mlist.last_check());
+    let mut mlist = mlist
+        .with_clock(&*clock)
+        .with_session(config.session.clone());
+    let overridden = mlist.apply_overrides(&config.overrides);
+    if overridden > 0 {
+        info!("{overridden} mirrors overridden");
+    }
+    mlist.apply_aliases(&config.aliases);
+    mlist
+}
+
+/// Select the mirrors of a list, retrieved and [`prepare`]d.
+///
+/// When no mirror is left, the report is returned with an empty list and
+/// [`crate::Error::EmptySelection`] as failure. The mirrors are measured with
+/// the session of `config`.
+pub async fn select(config: &RunConfig, mlist: MirrorList) -> Result<RunReport> {
+    let started = Utc::now();
+    let mut mlist = mlist.with_session(config.session.clone());
+    if config.deterministic {
+        mlist.sort_by_url();
+        if matches!(config.sort, SortKey::Rate | SortKey::Metadata) {
+            warn!(
+                "measured times vary, sorting by {} is not reproducible",
+                config.sort
+            );
+        }
+    }
+    let clock = tokio::time::Instant::now();
+    let mut explanation = Explanation::new(mlist.urls());
+    for (url, note) in mlist.protocol_mismatches() {
+        explanation.note(url, note);
+    }
+    let available = mlist.get_countries();
+    let mut bytes_downloaded = 0;
+    let mut phases = mlist.phases();
+    let filtering = Instant::now();
+    let mut mlist = filter_and_sort(
+        config,
+        mlist,
+        &mut explanation,
+        &mut bytes_downloaded,
+        &mut phases,
+    )
+    .await?;
+    phases.filter = (filtering.elapsed().as_secs_f64() - phases.tests).max(0.0);
+    let number = config.number.of(mlist.len());
+    if config.resolve && !mlist.is_empty() {
+        let res = measured(
+            &mut phases,
+            mlist.resolve_addresses(
+                number,
+                Some(config.download_timeout),
+                config.deadline,
+                &config.previous_addresses,
+            ),
+        )
+        .await;
+        for host in config
+            .on_probe_error
+            .handle("address resolution", res)?
+            .unwrap_or_default()
+        {
+            warn!("addresses of {host} changed since the last run");
+        }
+    }
+    if config.verify_isos && !mlist.is_empty() {
+        let res = measured(
+            &mut phases,
+            mlist.check_iso_files(number, Some(config.download_timeout), config.deadline),
+        )
+        .await;
+        config
+            .on_probe_error
+            .handle("check of the ISO files", res)?;
+    }
+    let (failures, content) = if mlist.is_empty() {
+        (Vec::new(), String::new())
+    } else {
+        let rate = matches!(config.sort, SortKey::Rate);
+        // only the tested mirrors may have failed their rate test
+        let measured = if rate {
+            config.tests(mlist.len())
+        } else {
+            number
+        };
+        let failures = mlist
+            .measurement_failures(measured, rate, config.uses_details())
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        for url in &failures {
+            match config.on_test_error {
+                FailurePolicy::Warn => warn!("measurement failed for {url}"),
+                FailurePolicy::Fail => error!("measurement failed for {url}"),
+                FailurePolicy::Skip => {}
+            }
+        }
+        let rendering = Instant::now();
+        let content = report::render(&mlist, config.output_format, &config.render_options());
+        phases.render = rendering.elapsed().as_secs_f64();
+        (failures, content)
+    };
+    let failure = if mlist.is_empty() {
+        Some(crate::Error::EmptySelection)
+    } else if config.on_test_error == FailurePolicy::Fail && !failures.is_empty() {
+        Some(crate::Error::MeasurementFailed {
+            count: failures.len(),
+        })
+    } else {
+        None
+    };
+    Ok(RunReport {
+        started,
+        elapsed: clock.elapsed().as_secs_f64(),
+        config: config.clone(),
+        decisions: explanation.decisions(mlist.urls().take(number)),
+        countries: report::country_counts(available, &mlist, number),
+        mirrors: mlist,
+        failures,
+        failure,
+        bytes_downloaded,
+        phases,
+        content,
+    })
+}
+
+async fn filter_and_sort(
+    config: &RunConfig,
+    mut mlist: MirrorList,
+    explanation: &mut Explanation,
+    bytes_downloaded: &mut u64,
+    phases: &mut Phases,
+) -> Result<MirrorList> {
+    for url in mlist.select(&config.blacklist).urls() {
+        explanation.reject(url, "blacklist");
+    }
+    let excluded = mlist.exclude(&config.blacklist);
+    if excluded > 0 {
+        info!("{excluded} blacklisted mirrors excluded");
+    }
+    if config.strict_urls {
+        let removed = mlist.remove_invalid_urls();
+        if !removed.is_empty() {
+            info!("{} mirrors with an invalid URL removed", removed.len());
+        }
+        for (url, issue) in removed {
+            explanation.reject(&url, issue);
+        }
+    } else if mlist.invalid_urls() > 0 {
+        warn!(
+            "{} mirrors with an invalid URL kept, use --strict-urls to remove them",
+            mlist.invalid_urls()
+        );
+    }
+    let pinned = mlist.select(&config.pins);
+    let filters = &config.filters;
+    let timeout = Some(config.download_timeout);
+    if config.uses_details() {
+        // only retrieve the details of the mirrors that may be kept
+        let prefilters = Filters {
+            completion: None,
+            tier: None,
+            ..filters.clone()
+        };
+        for (url, reason) in mlist.rejections(&prefilters) {
+            explanation.reject(&url, reason);
+        }
+        mlist = mlist.filter_by(&prefilters);
+        let res = measured(phases, async {
+            let pipeline = Pipeline::with_session(timeout, config.deadline, &config.session)?;
+            mlist.update_details_with(&pipeline).await
+        })
+        .await;
+        config
+            .on_details_error
+            .handle("retrieval of the mirror details", res)?;
+    }
+    for (url, reason) in mlist.rejections(filters) {
+        explanation.reject(&url, reason);
+    }
+    mlist = mlist.filter_by(filters);
+    if config.check_alive && !mlist.is_empty() {
+        let res = measured(phases, mlist.remove_dead(timeout, config.deadline)).await;
+        let dead = config
+            .on_probe_error
+            .handle("liveness check", res)?
+            .unwrap_or_default();
+        if !dead.is_empty() {
+            info!("{} dead mirrors pruned", dead.len());
+        }
+        for url in dead {
+            explanation.reject(&url, "dead");
+        }
+    }
+    let reachable = config.reachable_ipv4 || config.reachable_ipv6;
+    if (config.check_reachability || reachable) && !mlist.is_empty() {
+        let res = measured(phases, mlist.check_reachability(timeout, config.deadline)).await;
+        config.on_probe_error.handle("reachability check", res)?;
+        let removed = mlist.remove_unreachable(config.reachable_ipv4, config.reachable_ipv6);
+        if !removed.is_empty() {
+            info!("{} unreachable mirrors removed", removed.len());
+        }
+        for (url, reason) in removed {
+            explanation.reject(&url, reason);
+        }
+    }
+    if config.require_keep_alive && !mlist.is_empty() {
+        let res = measured(phases, mlist.probe_keep_alive(timeout, config.deadline)).await;
+        if config
+            .on_probe_error
+            .handle("keep-alive probe", res)?
+            .is_some()
+        {
+            for url in mlist.remove_without_keep_alive() {
+                explanation.reject(&url, "keep-alive");
+            }
+        }
+    }
+    if mlist.is_empty() {
+        return Ok(mlist);
+    }
+    if !config.prefer_protocols.is_empty() {
+        let count = mlist.prefer_protocols(&config.prefer_protocols);
+        if count > 0 {
+            info!("{count} URLs of hosts with a preferred protocol removed");
+        }
+    }
+    if config.bench_profile == BenchProfile::Metadata || matches!(config.sort, SortKey::Metadata) {
+        let res = measured(phases, mlist.update_metadata_time(timeout, config.deadline)).await;
+        config.on_probe_error.handle("metadata measurement", res)?;
+    }
+    if let SortKey::Rate = config.sort {
+        let budget =
+            ByteBudget::new(config.max_test_bytes_total).with_throttle(config.test_throttle);
+        if config.shuffle_tests && !config.deterministic {
+            mlist.shuffle(&mut rand::rng());
+        } else if budget.max().is_some() {
+            mlist.sort(SortKey::Score);
+        }
+        let mut limit = config.tests(mlist.len());
+        if config.dedupe_backends {
+            // the aliases moved last by the dedupe must not leave untested
+            // mirrors among the first ones
+            limit += mlist.redundant_aliases();
+        }
+        measured(
+            phases,
+            mlist.update_download_rate_mode(
+                timeout,
+                limit,
+                config.deadline,
+                &budget,
+                config.test_mode,
+            ),
+        )
+        .await;
+        *bytes_downloaded = budget.used();
+        info!("rate tests downloaded {} bytes", budget.used());
+        if config.no_redirects {
+            for url in mlist.remove_redirecting() {
+                explanation.reject(&url, "redirect");
+            }
+            if mlist.is_empty() {
+                return Ok(mlist);
+            }
+        } else if config.redirect_penalty > 0.0 {
+            let count = mlist.penalize_redirects(config.redirect_penalty);
+            if count > 0 {
+                info!("download rate of {count} redirecting mirrors penalized");
+            }
+        }
+    }
+    if config.recompute_scores {
+        let count = mlist.fill_missing_scores();
+        if count > 0 {
+            info!("score computed locally for {count} mirrors");
+        }
+    }
+    mlist.sort(config.sort.clone());
+    if let (Some(n), false) = (config.pick_random, config.deterministic) {
+        mlist.pick_random(n, config.pool, &config.sort, &mut rand::rng());
+    }
+    if config.dedupe_backends {
+        let moved = mlist.dedupe_backends();
+        if !moved.is_empty() {
+            info!("{} aliases of listed backends moved last", moved.len());
+        }
+        for (url, backend) in moved {
+            explanation.note(&url, format!("alias of {backend}, moved last"));
+        }
+    }
+    if !pinned.is_empty() {
+        for url in mlist.pin_first(pinned, Some(&config.sort)) {
+            warn!("pinned mirror {url} kept although it does not pass the filters");
+        }
+    }
+    Ok(mlist)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::explain::Outcome;
+    use crate::testing::{Response, Server};
+
+    static STATUS: &str = r#"{"urls": [
+        {"url": "https://a.example.org/", "protocol": "https", "score": 2.0,
+         "country_code": "FR", "last_sync": null, "details": ""},
+        {"url": "http://b.example.org/", "protocol": "http", "score": 1.0,
+         "country_code": "DE", "last_sync": null, "details": ""},
+        {"url": "https://c.example.org/", "protocol": "https", "score": 3.0,
+         "country_code": "FR", "last_sync": null, "details": ""}
+    ]}"#;
+
+    #[tokio::test]
+    async fn full_run() {
+        let server = Server::start(vec![Response::json(STATUS)]).await;
+        let config = RunConfig {
+            urls: vec![server.url.clone()],
+            blacklist: UrlFile {
+                entries: vec!["c.example.org".into()],
+                ..Default::default()
+            },
+            number: Number::Limit(1),
+            ..Default::default()
+        };
+        let report = run(config).await.unwrap();
+        assert_eq!(
+            report.mirrors.urls().collect::<Vec<_>>(),
+            ["http://b.example.org/", "https://a.example.org/"]
+        );
+        assert!(report.failures.is_empty());
+        assert_eq!(
+            report.content.lines().last(),
+            Some("Server = http://b.example.org/$repo/os/$arch")
+        );
+    }
+
+    #[tokio::test]
+    async fn fallbacks() {
+        let failing = Server::start(vec![Response::new(404, "text/plain", "not found")]).await;
+        let server = Server::start(vec![Response::json(STATUS)]).await;
+        let config = RunConfig {
+            urls: vec![failing.url.clone()],
+            fallback_urls: vec![server.url.clone()],
+            ..Default::default()
+        };
+        let mlist = fetch(&config).await.unwrap();
+        assert_eq!(mlist.len(), 3);
+        assert!(mlist.session().credentials().is_empty());
+        let config = RunConfig {
+            fallback_urls: Vec::new(),
+            ..config
+        };
+        let e = fetch(&config).await.unwrap_err();
+        assert_eq!(e.downcast_ref(), Some(&crate::Error::FetchFailed));
+        assert!(format!("{e:#}").contains("--from-mirrorlist"));
+        let config = RunConfig {
+            mirrorlist_file: Some("/nonexistent/mirrorlist".into()),
+            ..config
+        };
+        let e = fetch(&config).await.unwrap_err();
+        assert_eq!(e.downcast_ref::<crate::Error>(), None);
+        assert!(!format!("{e:#}").contains("--from-mirrorlist"));
+        assert!(format!("{e:#}").starts_with("unable to read mirrorlist /nonexistent/mirrorlist"));
+    }
+
+    #[tokio::test]
+    async fn deterministic_ties() {
+        let status = r#"{"last_check": "2024-05-01T12:00:00Z", "urls": [
+            {"url": "https://z.example.org/", "protocol": "https", "score": 1.0,
+             "last_sync": "2024-05-01T10:00:00Z", "details": ""},
+            {"url": "https://y.example.org/", "protocol": "https", "score": 1.0,
+             "last_sync": "2024-05-01T10:00:00Z", "details": ""}
+        ]}"#;
+        let server = Server::start(vec![Response::json(status)]).await;
+        let config = RunConfig {
+            urls: vec![server.url.clone()],
+            deterministic: true,
+            shuffle_tests: true,
+            pick_random: Some(1),
+            header: vec![HeaderField::Date],
+            annotations: vec![Annotation::Age],
+            ..Default::default()
+        };
+        let report = run(config).await.unwrap();
+        assert_eq!(
+            report.mirrors.urls().collect::<Vec<_>>(),
+            ["https://y.example.org/", "https://z.example.org/"]
+        );
+        let lines = report.content.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "# generated: \t2024-05-01 12:00:00 UTC");
+        assert!(lines[2].ends_with("# age 2.0h"));
+    }
+
+    #[tokio::test]
+    async fn strict_urls() {
+        let status = r#"{"urls": [
+            {"url": "http://a.example.org/my arch/", "protocol": "http", "score": 1.0,
+             "last_sync": null, "details": ""},
+            {"url": " https://b.example.org/arch ", "protocol": "http", "score": 2.0,
+             "last_sync": null, "details": ""}
+        ]}"#;
+        let mlist = MirrorList::from_status_str(status).unwrap();
+        assert_eq!(mlist.invalid_urls(), 1);
+        let lenient = select(&RunConfig::default(), mlist.clone()).await.unwrap();
+        assert_eq!(lenient.mirrors.len(), 2);
+        let config = RunConfig {
+            strict_urls: true,
+            ..Default::default()
+        };
+        let report = select(&config, mlist).await.unwrap();
+        assert_eq!(
+            report.mirrors.urls().collect::<Vec<_>>(),
+            ["https://b.example.org/arch/"]
+        );
+        assert_eq!(
+            report.decisions[0].outcome,
+            Outcome::Rejected {
+                reason: "whitespace in url".into()
+            }
+        );
+        assert_eq!(
+            report.decisions[1].notes,
+            ["protocol http in the status, https used from the URL"]
+        );
+    }
+
+    #[tokio::test]
+    async fn serialized_report() {
+        let mlist: MirrorList = serde_json::from_str(STATUS).unwrap();
+        let config = RunConfig {
+            number: Number::Limit(2),
+            filters: Filters {
+                countries: vec!["FR".into()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let report = select(&config, mlist).await.unwrap();
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["config"]["distro"], "arch");
+        assert_eq!(json["config"]["sort"], "score");
+        assert_eq!(json["config"]["download_timeout"], 5.0);
+        assert_eq!(json["config"]["filters"]["countries"][0], "FR");
+        assert_eq!(json["mirrors"].as_array().unwrap().len(), 2);
+        assert_eq!(json["mirrors"][0]["url"], "https://a.example.org/");
+        assert_eq!(json["decisions"][1]["reason"], "country");
+        assert!(json["countries"].is_array());
+        assert!(json.get("content").is_none());
+        assert_eq!(json["phases"]["fetch"], 0.0);
+        assert!(json["phases"]["filter"].is_f64());
+    }
+
+    #[tokio::test]
+    async fn phases() {
+        let server = Server::start(vec![Response::json(STATUS)]).await;
+        let config = RunConfig {
+            urls: vec![server.url.clone()],
+            ..Default::default()
+        };
+        let report = run(config).await.unwrap();
+        assert!(report.phases.fetch > 0.0);
+        assert_eq!(report.phases.tests, 0.0);
+        assert_eq!(report.phases.write, 0.0);
+        let config = RunConfig {
+            check_alive: true,
+            ..Default::default()
+        };
+        let report = select(&config, report.mirrors).await.unwrap();
+        // the liveness check of unresolvable hosts
+        assert!(report.phases.tests > 0.0);
+        let phases = Phases {
+            fetch: 0.5,
+            tests: 12.25,
+            ..Default::default()
+        };
+        assert_eq!(phases.dominant(), "tests");
+        assert_eq!(
+            phases.to_string(),
+            "fetch 0.50s, parse 0.00s, filter 0.00s, tests 12.25s, render 0.00s, write 0.00s"
+        );
+    }
+
+    #[tokio::test]
+    async fn failure_policies() {
+        let mlist: MirrorList = serde_json::from_str(STATUS).unwrap();
+        // every request fails with a negative timeout
+        let config = RunConfig {
+            download_timeout: chrono::Duration::seconds(-1),
+            check_alive: true,
+            on_probe_error: FailurePolicy::Fail,
+            ..Default::default()
+        };
+        let e = select(&config, mlist.clone()).await.unwrap_err();
+        assert!(e.to_string().starts_with("liveness check failed"));
+        let config = RunConfig {
+            on_probe_error: FailurePolicy::Skip,
+            sort: SortKey::Rate,
+            number: Number::Limit(2),
+            ..config
+        };
+        let report = select(&config, mlist.clone()).await.unwrap();
+        assert_eq!(report.mirrors.len(), 3);
+        assert_eq!(report.failures.len(), 2);
+        let limited = RunConfig {
+            test_limit: Some(Number::Limit(1)),
+            ..config.clone()
+        };
+        let report = select(&limited, mlist).await.unwrap();
+        assert_eq!(report.failures.len(), 1);
+        let server = Server::start(vec![Response::json(STATUS)]).await;
+        let config = RunConfig {
+            urls: vec![server.url],
+            on_test_error: FailurePolicy::Fail,
+            ..config
+        };
+        let report = run(config).await.unwrap();
+        assert_eq!(report.mirrors.len(), 3);
+        assert_eq!(
+            report.failure,
+            Some(crate::Error::MeasurementFailed { count: 2 })
+        );
+        let e = report.check().unwrap_err();
+        assert_eq!(
+            e.downcast_ref(),
+            Some(&crate::Error::MeasurementFailed { count: 2 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_limit() {
+        let mut servers = Vec::new();
+        for _ in 0..3 {
+            let file = Response::new(200, "application/octet-stream", &"x".repeat(10_000));
+            servers.push(Server::start(vec![file]).await);
+        }
+        let content = servers.iter().map(|s| s.url.as_str()).collect::<Vec<_>>();
+        let mlist = MirrorList::from_mirrorlist(&content.join("\n"), Distro::Arch);
+        let config = RunConfig {
+            sort: SortKey::Rate,
+            number: Number::Limit(1),
+            test_limit: Some(Number::All),
+            test_mode: TestMode::Sequential,
+            ..Default::default()
+        };
+        let report = select(&config, mlist).await.unwrap();
+        let rows = report::rows(&report.mirrors, 3);
+        assert!(rows.iter().all(|r| r.rate.is_some()));
+        assert_eq!(report.content.matches("Server = ").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn resolved_addresses() {
+        let content = "http://127.0.0.1:1/\nhttp://localhost:1/";
+        let mlist = MirrorList::from_mirrorlist(content, Distro::Arch);
+        let config = RunConfig {
+            number: Number::Limit(1),
+            resolve: true,
+            previous_addresses: dns::Addresses::from([("127.0.0.1".into(), vec![])]),
+            ..Default::default()
+        };
+        let report = select(&config, mlist).await.unwrap();
+        let rows = report::rows(&report.mirrors, 2);
+        assert_eq!(rows[0].addresses, Some(vec!["127.0.0.1".parse().unwrap()]));
+        assert_eq!(rows[0].dns_changed, Some(true));
+        assert_eq!(rows[1].addresses, None);
+    }
+
+    #[tokio::test]
+    async fn empty_selection() {
+        let mlist: MirrorList = serde_json::from_str(STATUS).unwrap();
+        let config = RunConfig {
+            filters: Filters {
+                countries: vec!["GR".into()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let report = select(&config, mlist).await.unwrap();
+        assert!(report.mirrors.is_empty());
+        assert!(report.content.is_empty());
+        let server = Server::start(vec![Response::json(STATUS)]).await;
+        let config = RunConfig {
+            urls: vec![server.url],
+            ..config
+        };
+        let report = run(config).await.unwrap();
+        assert!(report.mirrors.is_empty());
+        assert_eq!(report.failure, Some(crate::Error::EmptySelection));
+        assert!(report
+            .to_json()
+            .contains("\"failure\": \"no mirror matches"));
+    }
+}
@@ -0,0 +1,200 @@
+//! Mapping of custom status documents onto mirror fields.
+//!
+//! A mapping file tells which JSON fields of a mirror status document contain
+//! the URL, protocol, last synchronisation date, country... of each mirror.
+//! Fields are designated by their path, components being separated by dots.
+//!
+//! ```toml
+//! mirrors = "data.servers"
+//! url = "address"
+//! last_sync = "status.synced_at"
+//! country = "location.country"
+//! ```
+use crate::{Mirror, MirrorList, Protocol};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::Path;
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SchemaMapping {
+    /// path of the array of mirrors. The document itself if not set
+    pub mirrors: Option<String>,
+    pub url: String,
+    pub protocol: Option<String>,
+    /// RFC 3339 date or UNIX timestamp
+    pub last_sync: Option<String>,
+    pub country: Option<String>,
+    pub country_code: Option<String>,
+    pub score: Option<String>,
+    pub delay: Option<String>,
+    pub isos: Option<String>,
+    pub ipv4: Option<String>,
+    pub ipv6: Option<String>,
+}
+
+impl SchemaMapping {
+    /// Read a mapping file, in TOML or in JSON depending on its extension
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read mapping file {}", path.display()))?;
+        let mapping = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(&content)?,
+            _ => toml::from_str(&content)?,
+        };
+        Ok(mapping)
+    }
+
+    /// Build a mirror list from a status document following the mapping
+    pub fn parse(&self, body: &str) -> Result<MirrorList> {
+        let document: Value = serde_json::from_str(body)?;
+        let entries = match &self.mirrors {
+            Some(path) => lookup(&document, path)
+                .ok_or_else(|| anyhow!("no field {path} in status document"))?,
+            None => &document,
+        };
+        let Some(entries) = entries.as_array() else {
+            bail!("mirrors are not a JSON array");
+        };
+        let mut mirrors = entries
+            .iter()
+            .map(|e| self.mirror(e))
+            .collect::<Result<Vec<_>>>()?;
+        crate::normalize_urls(&mut mirrors);
+        Ok(MirrorList {
+            mirrors,
+            ..Default::default()
+        })
+    }
+
+    fn mirror(&self, entry: &Value) -> Result<Mirror> {
+        let url = lookup(entry, &self.url)
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("mirror without {} field: {}", self.url, entry))?;
+        let string = |field: &Option<String>| {
+            field
+                .as_deref()
+                .and_then(|f| lookup(entry, f))
+                .and_then(Value::as_str)
+                .map(String::from)
+        };
+        let number = |field: &Option<String>| {
+            field
+                .as_deref()
+                .and_then(|f| lookup(entry, f))
+                .and_then(Value::as_f64)
+        };
+        let boolean = |field: &Option<String>| {
+            field
+                .as_deref()
+                .and_then(|f| lookup(entry, f))
+                .and_then(Value::as_bool)
+        };
+        let protocol = match string(&self.protocol).as_deref() {
+            Some("ftp") => Protocol::Ftp,
+            Some("http") => Protocol::Http,
+            Some("https") => Protocol::Https,
+            Some("rsync") => Protocol::Rsync,
+            _ => Protocol::from_url(url),
+        };
+        let last_sync = self
+            .last_sync
+            .as_deref()
+            .and_then(|f| lookup(entry, f))
+            .and_then(parse_date);
+        Ok(Mirror {
+            protocol,
+            last_sync,
+            country: string(&self.country),
+            country_code: string(&self.country_code),
+            score: number(&self.score),
+            delay: number(&self.delay),
+            isos: boolean(&self.isos),
+            ipv4: boolean(&self.ipv4),
+            ipv6: boolean(&self.ipv6),
+            ..Mirror::from_url(url)
+        })
+    }
+}
+
+/// get the value at a dotted path
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .filter(|p| !p.is_empty())
+        .try_fold(value, |v, key| match v {
+            Value::Array(a) => a.get(key.parse::<usize>().ok()?),
+            _ => v.get(key),
+        })
+}
+
+fn parse_date(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::String(s) => DateTime::parse_from_rfc3339(s).ok().map(|d| d.into()),
+        Value::Number(n) => DateTime::from_timestamp(n.as_i64()?, 0),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static STATUS: &str = r#"{
+        "data": {
+            "servers": [
+                {
+                    "address": "http://mirror.internal/arch/",
+                    "status": {"synced_at": 1714573508, "ok": true},
+                    "location": {"country": "France", "code": "FR"}
+                },
+                {
+                    "address": "https://mirror2.internal/arch/",
+                    "status": {"synced_at": "2024-05-01T14:25:08Z"}
+                }
+            ]
+        }
+    }"#;
+
+    static MAPPING: &str = r#"
+        mirrors = "data.servers"
+        url = "address"
+        last_sync = "status.synced_at"
+        country = "location.country"
+        country_code = "location.code"
+    "#;
+
+    #[test]
+    fn custom_schema() {
+        let mapping: SchemaMapping = toml::from_str(MAPPING).unwrap();
+        let ml = mapping.parse(STATUS).unwrap();
+        assert_eq!(ml.mirrors.len(), 2);
+        let [ref m0, ref m1] = ml.mirrors[..] else {
+            panic!()
+        };
+        assert_eq!(m0.url, "http://mirror.internal/arch/");
+        assert!(matches!(m0.protocol, Protocol::Http));
+        assert_eq!(m0.country.as_deref(), Some("France"));
+        assert_eq!(m0.country_code.as_deref(), Some("FR"));
+        assert_eq!(m0.last_sync, m1.last_sync);
+        assert!(matches!(m1.protocol, Protocol::Https));
+        assert!(m1.country.is_none());
+    }
+
+    #[test]
+    fn missing_url() {
+        let mapping = SchemaMapping {
+            mirrors: Some("data.servers".into()),
+            url: "url".into(),
+            ..Default::default()
+        };
+        assert!(mapping.parse(STATUS).is_err());
+        let mapping = SchemaMapping {
+            mirrors: Some("data.nothing".into()),
+            url: "address".into(),
+            ..Default::default()
+        };
+        assert!(mapping.parse(STATUS).is_err());
+    }
+}
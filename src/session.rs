@@ -0,0 +1,110 @@
+//! Settings shared by the requests of a run.
+//!
+//! A [`Session`] holds the credentials added to the requests, the proxy they
+//! go through and the [`Trace`] recording their timings. It is given to
+//! the retrieval of the status, and carried by the [`crate::MirrorList`] and
+//! the [`crate::Pipeline`] measuring it, so that two runs in the same process
+//! do not share their settings.
+use crate::auth::Credentials;
+use crate::trace::Trace;
+use anyhow::Result;
+use reqwest::{ClientBuilder, Proxy, RequestBuilder};
+use std::sync::Arc;
+
+/// Settings of the requests of a run
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    credentials: Arc<Credentials>,
+    /// see [`crate::proxy`]
+    proxy: Option<Proxy>,
+    trace: Trace,
+}
+
+impl Session {
+    pub fn new(credentials: Credentials) -> Self {
+        Self {
+            credentials: Arc::new(credentials),
+            proxy: None,
+            trace: Trace::default(),
+        }
+    }
+
+    /// Same session, its requests going through `proxy`
+    pub fn with_proxy(self, proxy: Option<Proxy>) -> Self {
+        Self { proxy, ..self }
+    }
+
+    /// Same session, its requests being recorded by `trace`
+    pub fn with_trace(self, trace: Trace) -> Self {
+        Self { trace, ..self }
+    }
+
+    pub fn credentials(&self) -> &Credentials {
+        &self.credentials
+    }
+
+    pub fn trace(&self) -> &Trace {
+        &self.trace
+    }
+
+    /// Builder of a client for the requests of the session
+    pub fn client_builder(&self) -> ClientBuilder {
+        let builder = reqwest::Client::builder();
+        match &self.proxy {
+            Some(proxy) => builder.proxy(proxy.clone()),
+            None => builder,
+        }
+    }
+
+    /// Client adding the credentials of the session to its requests
+    pub fn client(&self) -> Result<Client> {
+        Ok(self.wrap(self.client_builder().build()?))
+    }
+
+    /// Client built from [`Session::client_builder`], adding the credentials
+    /// of the session to its requests
+    pub fn wrap(&self, client: reqwest::Client) -> Client {
+        Client {
+            inner: client,
+            credentials: self.credentials.clone(),
+        }
+    }
+}
+
+/// HTTP client adding the credentials matching the host of each request
+#[derive(Debug, Clone)]
+pub struct Client {
+    inner: reqwest::Client,
+    credentials: Arc<Credentials>,
+}
+
+impl Client {
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.credentials.apply(self.inner.get(url), url)
+    }
+
+    pub fn head(&self, url: &str) -> RequestBuilder {
+        self.credentials.apply(self.inner.head(url), url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Response, Server};
+
+    #[tokio::test]
+    async fn proxy() {
+        // the test server answers whatever the requested host
+        let server = Server::start(vec![Response::json("proxied")]).await;
+        let session = Session::default().with_proxy(Some(Proxy::all(&server.url).unwrap()));
+        let response = session
+            .client()
+            .unwrap()
+            .get("http://mirror.invalid/")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.text().await.unwrap(), "proxied");
+    }
+}
@@ -0,0 +1,129 @@
+//! Rate tests of arbitrary URLs, e.g. to compare a private mirror with public
+//! ones.
+//!
+//! Each URL is downloaded as is, with the same cache busting, byte budget and
+//! deadline as the rate tests of mirrors.
+use crate::{cmp, rate_test, ByteBudget, Pipeline, ResponseInfo, Session};
+use anyhow::Result;
+use serde::Serialize;
+use std::cmp::Ordering;
+
+/// Outcome of the rate test of a URL
+#[derive(Debug, Clone, Serialize)]
+pub struct SpeedTest {
+    pub url: String,
+    /// download rate, in MB/s
+    pub rate: Option<f64>,
+    /// whether the file seems to be served from a cache
+    pub cached: Option<bool>,
+    pub response: Option<ResponseInfo>,
+    /// why the test failed
+    pub error: Option<String>,
+}
+
+impl SpeedTest {
+    /// Test the download rate of a URL
+    pub async fn run(
+        url: String,
+        timeout: Option<chrono::Duration>,
+        budget: &ByteBudget,
+        session: &Session,
+    ) -> Self {
+        match rate_test(&url, timeout, budget, session).await {
+            Ok(test) => Self {
+                url,
+                rate: test.rate.as_ref().map(|r| r.0).filter(|r| r.is_finite()),
+                cached: Some(test.cached),
+                error: test
+                    .rate
+                    .is_none()
+                    .then(|| "download budget exhausted".to_string()),
+                response: Some(test.response),
+            },
+            Err(e) => Self {
+                url,
+                rate: None,
+                cached: None,
+                response: None,
+                error: Some(format!("{e:#}")),
+            },
+        }
+    }
+
+    /// Fastest first, failed tests last
+    fn cmp_rate(&self, other: &Self) -> Ordering {
+        cmp::numbers(self.rate.map(|r| -r), other.rate.map(|r| -r))
+    }
+}
+
+/// Test the download rate of every URL, `concurrency` at a time, until the
+/// deadline of the pipeline. Returns the tests, fastest first; URLs not tested
+/// before the deadline are missing
+pub async fn speedtest(
+    pipeline: &Pipeline,
+    urls: Vec<String>,
+    concurrency: usize,
+    timeout: Option<chrono::Duration>,
+    budget: &ByteBudget,
+) -> Vec<SpeedTest> {
+    let output = pipeline
+        .stage("speed test", urls, concurrency, |_, url| {
+            let (budget, session) = (budget.clone(), pipeline.session().clone());
+            async move { Result::Ok(SpeedTest::run(url, timeout, &budget, &session).await) }
+        })
+        .await;
+    let mut tests = output.results;
+    tests.sort_by(SpeedTest::cmp_rate);
+    tests
+}
+
+/// One line per test: rate, URL and failure if any
+pub fn render(tests: &[SpeedTest]) -> String {
+    tests
+        .iter()
+        .map(|t| match (&t.rate, &t.error) {
+            (Some(rate), _) => {
+                let cached = if t.cached == Some(true) {
+                    " (cached)"
+                } else {
+                    ""
+                };
+                format!("{rate:>8.2} MB/s  {}{cached}", t.url)
+            }
+            (None, Some(error)) => format!("{:>8}       {}: {error}", "failed", t.url),
+            (None, None) => format!("{:>8}       {}: empty response", "failed", t.url),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Response, Server};
+
+    #[tokio::test]
+    async fn speedtest_urls() {
+        let server = Server::start(vec![
+            Response::new(200, "application/octet-stream", &"x".repeat(10_000)),
+            Response::new(404, "text/plain", "not found"),
+        ])
+        .await;
+        let pipeline = Pipeline::new(None, None).unwrap();
+        let budget = ByteBudget::default();
+        let urls = vec![
+            format!("{}file.bin", server.url),
+            format!("{}missing.bin", server.url),
+            "http://127.0.0.1:1/unreachable".into(),
+        ];
+        // one at a time, for the responses to be served in order
+        let tests = speedtest(&pipeline, urls, 1, None, &budget).await;
+        assert_eq!(tests.len(), 3);
+        assert!(tests[0].rate.is_some());
+        assert_eq!(tests[0].response.as_ref().unwrap().status, 200);
+        assert!(tests[1..].iter().all(|t| t.error.is_some()));
+        let text = render(&tests);
+        assert!(text.contains("MB/s"));
+        assert!(text.contains("missing.bin: HTTP status 404"));
+    }
+}
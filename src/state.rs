@@ -0,0 +1,284 @@
+//! Outcome of the last runs writing a mirrorlist, for monitoring.
+//!
+//! The state is a small JSON file, in the state directory given by systemd to
+//! a service, or in `$XDG_STATE_HOME/reflecto` (`~/.local/state/reflecto` if
+//! not set). The state of the system service is read when the user has none.
+use crate::dns;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// What is recorded after each run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct State {
+    /// when the last run ended
+    pub last_run: DateTime<Utc>,
+    /// exit status of the last run. See [`crate::error::ExitStatus`]
+    pub exit_status: u8,
+    /// why the last run failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// when the mirrorlist was last written
+    pub last_success: Option<DateTime<Utc>>,
+    /// hash of the URLs of the mirrors last written, to detect changes
+    pub mirrors_hash: Option<String>,
+    /// addresses of the hosts of the mirrors last resolved
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub addresses: dns::Addresses,
+}
+
+impl State {
+    /// State after a run ending at `now`, the written URLs if it succeeded
+    pub fn after(
+        previous: Option<State>,
+        now: DateTime<Utc>,
+        outcome: std::result::Result<Vec<&str>, &anyhow::Error>,
+    ) -> Self {
+        let (last_success, mirrors_hash, addresses) = previous
+            .map(|s| (s.last_success, s.mirrors_hash, s.addresses))
+            .unwrap_or_default();
+        match outcome {
+            Ok(urls) => Self {
+                last_run: now,
+                exit_status: 0,
+                error: None,
+                last_success: Some(now),
+                mirrors_hash: Some(mirrors_hash_of(&urls)),
+                addresses,
+            },
+            Err(e) => Self {
+                last_run: now,
+                exit_status: crate::error::ExitStatus::of(e) as u8,
+                error: Some(format!("{e:#}")),
+                last_success,
+                mirrors_hash,
+                addresses,
+            },
+        }
+    }
+
+    /// The recorded state, if any run was recorded
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("unable to read {}", path.display())),
+        };
+        serde_json::from_str(&content)
+            .map(Some)
+            .with_context(|| format!("invalid state {}", path.display()))
+    }
+
+    /// Write the state, creating its directory if needed
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("unable to create {}", dir.display()))?;
+        }
+        let content = serde_json::to_string_pretty(self).expect("state is always serializable");
+        std::fs::write(path, content).with_context(|| format!("unable to write {}", path.display()))
+    }
+
+    /// Record a run ending now in the state file, with the addresses it
+    /// resolved. The previous addresses are kept if it resolved none
+    pub fn record(
+        path: &Path,
+        outcome: std::result::Result<Vec<&str>, &anyhow::Error>,
+        addresses: dns::Addresses,
+    ) -> Result<Self> {
+        let previous = Self::load(path).unwrap_or_default();
+        let mut state = Self::after(previous, Utc::now(), outcome);
+        if !addresses.is_empty() {
+            state.addresses = addresses;
+        }
+        state.save(path)?;
+        Ok(state)
+    }
+
+    /// Human readable summary, ages computed at `now`
+    pub fn render(&self, now: DateTime<Utc>) -> String {
+        let when = |d: DateTime<Utc>| {
+            format!(
+                "{} ({} ago)",
+                d.format("%Y-%m-%d %H:%M:%S UTC"),
+                ago(now - d)
+            )
+        };
+        let refreshed = match self.last_success {
+            Some(d) => when(d),
+            None => "never".into(),
+        };
+        let outcome = match &self.error {
+            None if self.exit_status == 0 => "succeeded".to_string(),
+            None => format!("failed with status {}", self.exit_status),
+            Some(e) => format!("failed with status {}: {e}", self.exit_status),
+        };
+        format!(
+            "last refresh: {refreshed}\nlast run: {}, {outcome}",
+            when(self.last_run)
+        )
+    }
+}
+
+/// Fail with [`crate::Error::Stale`] if the mirrorlist, last refreshed at
+/// `last_refresh`, is older than `max` at `now`
+pub fn ensure_fresh(
+    last_refresh: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+    max: chrono::Duration,
+) -> std::result::Result<(), crate::Error> {
+    match last_refresh {
+        Some(d) if now - d <= max => Ok(()),
+        _ => Err(crate::Error::Stale { last_refresh }),
+    }
+}
+
+/// Duration rounded to its largest unit, e.g. 3h or 2d
+fn ago(d: chrono::Duration) -> String {
+    match d.num_seconds().max(0) {
+        s if s < 60 => format!("{s}s"),
+        s if s < 3600 => format!("{}m", s / 60),
+        s if s < 86400 => format!("{}h", s / 3600),
+        s => format!("{}d", s / 86400),
+    }
+}
+
+/// FNV-1a hash of the URLs, stable across versions and platforms
+pub fn mirrors_hash_of(urls: &[&str]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for url in urls {
+        for b in url.bytes().chain([b'\n']) {
+            hash ^= u64::from(b);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{hash:016x}")
+}
+
+/// Default location of the state file: in the state directory given by
+/// systemd to a service, or in the reflecto directory of the user state
+/// directory
+pub fn default_path() -> Option<PathBuf> {
+    std::env::var_os("STATE_DIRECTORY")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("XDG_STATE_HOME")
+                .filter(|d| !d.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/state")))
+                .map(|d| d.join("reflecto"))
+        })
+        .map(|d| d.join("state.json"))
+}
+
+/// State file of the system service, in the `StateDirectory` of its unit
+pub const SERVICE_PATH: &str = "/var/lib/reflecto/state.json";
+
+/// Location of the state file to read: the default one, or the one of the
+/// system service when it does not exist
+pub fn read_path() -> Option<PathBuf> {
+    existing_or(default_path(), Path::new(SERVICE_PATH))
+}
+
+/// the path if it exists, or the fallback if it exists, or the path
+fn existing_or(path: Option<PathBuf>, fallback: &Path) -> Option<PathBuf> {
+    match path {
+        Some(path) if path.exists() => Some(path),
+        path if fallback.exists() => {
+            if let Some(path) = path {
+                tracing::debug!(
+                    "{} not found, reading {}",
+                    path.display(),
+                    fallback.display()
+                );
+            }
+            Some(fallback.to_path_buf())
+        }
+        path => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn successive_runs() {
+        let start = DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z")
+            .unwrap()
+            .to_utc();
+        let urls = vec!["https://a.example.org/", "https://b.example.org/"];
+        let ok = State::after(None, start, Ok(urls.clone()));
+        assert_eq!(ok.exit_status, 0);
+        assert_eq!(ok.last_success, Some(start));
+        let e = anyhow::Error::new(crate::Error::EmptySelection);
+        let later = start + chrono::Duration::hours(3);
+        let failed = State::after(Some(ok.clone()), later, Err(&e));
+        assert_eq!(failed.exit_status, 2);
+        assert_eq!(failed.last_success, Some(start));
+        assert_eq!(failed.mirrors_hash, ok.mirrors_hash);
+        assert_eq!(
+            failed.render(later + chrono::Duration::minutes(5)),
+            "last refresh: 2024-05-01 12:00:00 UTC (3h ago)\n\
+             last run: 2024-05-01 15:00:00 UTC (5m ago), failed with status 2: \
+             no mirror matches the given criteria, the mirrorlist would be empty"
+        );
+        let reordered = State::after(None, later, Ok(vec![urls[1], urls[0]]));
+        assert_ne!(reordered.mirrors_hash, ok.mirrors_hash);
+    }
+
+    #[test]
+    fn service_fallback() {
+        let dir =
+            std::env::temp_dir().join(format!("reflecto-state-fallback-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (user, service) = (dir.join("user.json"), dir.join("service.json"));
+        assert_eq!(
+            existing_or(Some(user.clone()), &service),
+            Some(user.clone())
+        );
+        assert_eq!(existing_or(None, &service), None);
+        std::fs::write(&service, "{}").unwrap();
+        assert_eq!(
+            existing_or(Some(user.clone()), &service),
+            Some(service.clone())
+        );
+        assert_eq!(existing_or(None, &service), Some(service.clone()));
+        std::fs::write(&user, "{}").unwrap();
+        assert_eq!(existing_or(Some(user.clone()), &service), Some(user));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn freshness() {
+        let now = Utc::now();
+        let week = chrono::Duration::days(7);
+        assert!(ensure_fresh(Some(now - chrono::Duration::days(2)), now, week).is_ok());
+        let old = Some(now - chrono::Duration::days(8));
+        assert_eq!(
+            ensure_fresh(old, now, week),
+            Err(crate::Error::Stale { last_refresh: old })
+        );
+        assert!(ensure_fresh(None, now, week).is_err());
+    }
+
+    #[test]
+    fn state_file() {
+        let path = std::env::temp_dir()
+            .join(format!("reflecto-state-{}", std::process::id()))
+            .join("state.json");
+        assert_eq!(State::load(&path).unwrap(), None);
+        let addresses =
+            dns::Addresses::from([("a.example.org".into(), vec!["192.0.2.1".parse().unwrap()])]);
+        let urls = vec!["https://a.example.org/"];
+        let state = State::record(&path, Ok(urls.clone()), addresses.clone()).unwrap();
+        assert_eq!(State::load(&path).unwrap(), Some(state));
+        let state = State::record(&path, Ok(urls), dns::Addresses::new()).unwrap();
+        assert_eq!(state.addresses, addresses);
+        assert_eq!(mirrors_hash_of(&[]), "cbf29ce484222325");
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+}
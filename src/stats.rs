@@ -0,0 +1,165 @@
+//! Distribution of a metric across the mirrors, printed as a histogram to
+//! choose thresholds of the filters.
+//!
+//! The score and the age come from the mirror status; the download rate is
+//! only known for the mirrors tested beforehand.
+use crate::MirrorList;
+use serde::Serialize;
+use std::fmt::Write;
+
+/// Width of the longest bar of a rendered histogram
+const BAR_WIDTH: usize = 40;
+
+/// Metric whose distribution is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+    /// Score of the mirror status, lower is better
+    Score,
+    /// Measured download rate, in MB/s
+    Rate,
+    /// Hours since the last synchronization
+    Age,
+}
+
+impl Metric {
+    fn unit(self) -> &'static str {
+        match self {
+            Self::Score => "",
+            Self::Rate => " MB/s",
+            Self::Age => " h",
+        }
+    }
+}
+
+/// Number of mirrors by interval of values of a metric
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Histogram {
+    pub metric: Metric,
+    /// lower bound of the first interval
+    pub min: f64,
+    /// width of each interval; the last one includes its upper bound
+    pub width: f64,
+    pub counts: Vec<usize>,
+    /// number of mirrors without value
+    pub missing: usize,
+}
+
+impl Histogram {
+    /// Histogram of the metric over the mirrors of the list, in `bins`
+    /// intervals of the same width between the lowest and highest values
+    pub fn new(mlist: &MirrorList, metric: Metric, bins: usize) -> Self {
+        let now = mlist.now();
+        let values = mlist.mirrors.iter().map(|m| match metric {
+            Metric::Score => m.score,
+            Metric::Rate => m.download_rate.as_ref().map(|r| r.0),
+            Metric::Age => m.age_hours(now),
+        });
+        Self::of_values(metric, values, bins)
+    }
+
+    fn of_values(metric: Metric, values: impl Iterator<Item = Option<f64>>, bins: usize) -> Self {
+        let mut missing = 0;
+        let values = values
+            .filter_map(|v| {
+                let v = v.filter(|v| v.is_finite());
+                missing += usize::from(v.is_none());
+                v
+            })
+            .collect::<Vec<_>>();
+        let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        if values.is_empty() {
+            return Self {
+                metric,
+                min: 0.0,
+                width: 0.0,
+                counts: Vec::new(),
+                missing,
+            };
+        }
+        // equal values all fall in a single interval
+        let bins = if max > min { bins.max(1) } else { 1 };
+        let width = (max - min) / bins as f64;
+        let mut counts = vec![0; bins];
+        for v in values {
+            let i = if width > 0.0 {
+                ((v - min) / width) as usize
+            } else {
+                0
+            };
+            counts[i.min(bins - 1)] += 1;
+        }
+        Self {
+            metric,
+            min,
+            width,
+            counts,
+            missing,
+        }
+    }
+
+    /// One line per interval, its bounds, its number of mirrors and a bar
+    /// proportional to it, then the number of mirrors without value
+    pub fn render(&self) -> String {
+        let unit = self.metric.unit();
+        let highest = self.counts.iter().copied().max().unwrap_or(0);
+        let mut out = String::new();
+        for (i, count) in self.counts.iter().enumerate() {
+            let low = self.min + self.width * i as f64;
+            let high = low + self.width;
+            let bar = (count * BAR_WIDTH).div_ceil(highest.max(1));
+            let line = format!(
+                "{low:>9.2} - {high:>9.2}{unit} {count:>5} {}",
+                "#".repeat(bar)
+            );
+            let _ = writeln!(out, "{}", line.trim_end());
+        }
+        if self.counts.is_empty() {
+            out += "no value\n";
+        }
+        if self.missing > 0 {
+            let _ = writeln!(out, "{} mirrors without value", self.missing);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Distro;
+
+    #[test]
+    fn bins() {
+        let values = [
+            Some(1.0),
+            Some(2.0),
+            Some(2.5),
+            Some(5.0),
+            None,
+            Some(f64::NAN),
+        ];
+        let h = Histogram::of_values(Metric::Score, values.into_iter(), 4);
+        assert_eq!((h.min, h.width), (1.0, 1.0));
+        assert_eq!(h.counts, [1, 2, 0, 1]);
+        assert_eq!(h.missing, 2);
+        let lines = h.render();
+        let lines = lines.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[1].ends_with(&format!("2 {}", "#".repeat(BAR_WIDTH))));
+        assert!(lines[2].ends_with(" 0"));
+        assert_eq!(lines[4], "2 mirrors without value");
+        let same = Histogram::of_values(Metric::Age, [Some(3.0), Some(3.0)].into_iter(), 4);
+        assert_eq!(same.counts, [2]);
+    }
+
+    #[test]
+    fn unknown_rates() {
+        let content = "https://a.example.org/\nhttps://b.example.org/";
+        let ml = MirrorList::from_mirrorlist(content, Distro::Arch);
+        let h = Histogram::new(&ml, Metric::Rate, 10);
+        assert!(h.counts.is_empty());
+        assert_eq!(h.render(), "no value\n2 mirrors without value\n");
+    }
+}
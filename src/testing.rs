@@ -0,0 +1,67 @@
+//! Helpers for tests needing an HTTP server.
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Canned HTTP response
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: &str) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type".into(), content_type.into())],
+            body: body.into(),
+        }
+    }
+
+    pub fn json(body: &str) -> Self {
+        Self::new(200, "application/json", body)
+    }
+}
+
+/// Local HTTP server answering the given responses in order, the last one
+/// being repeated.
+pub struct Server {
+    pub url: String,
+}
+
+impl Server {
+    pub async fn start(responses: Vec<Response>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            for n in 0.. {
+                let Ok((mut stream, _)) = listener.accept().await else {
+                    return;
+                };
+                let response = responses[n.min(responses.len() - 1)].clone();
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match stream.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    let mut head = format!("HTTP/1.1 {} Status\r\n", response.status);
+                    for (name, value) in &response.headers {
+                        head.push_str(&format!("{name}: {value}\r\n"));
+                    }
+                    head.push_str(&format!(
+                        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+                        response.body.len()
+                    ));
+                    let _ = stream.write_all(head.as_bytes()).await;
+                    let _ = stream.write_all(response.body.as_bytes()).await;
+                });
+            }
+        });
+        Self { url }
+    }
+}
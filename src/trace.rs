@@ -0,0 +1,206 @@
+//! Timings of a run, written as a Chrome trace to investigate slow runs.
+//!
+//! Given an enabled [`Trace`], through its [`crate::Session`], every stage of a
+//! [`crate::pipeline::Pipeline`], each of its items and each rate test record
+//! a span. [`Trace::write`] saves them in the trace event format read by
+//! `chrome://tracing` and Perfetto. Spans running at the same time are put on
+//! distinct lanes, shown as threads.
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+/// Recorder of spans, disabled by default
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    recording: Option<Arc<Mutex<Recording>>>,
+}
+
+#[derive(Debug)]
+struct Recording {
+    start: Instant,
+    events: Vec<Event>,
+    /// whether each lane is used by a running span
+    lanes: Vec<bool>,
+}
+
+impl Recording {
+    /// First free lane, marked as used
+    fn take_lane(&mut self) -> usize {
+        let lane = match self.lanes.iter().position(|used| !used) {
+            Some(lane) => lane,
+            None => {
+                self.lanes.push(false);
+                self.lanes.len() - 1
+            }
+        };
+        self.lanes[lane] = true;
+        lane
+    }
+}
+
+/// Complete event of the trace event format
+#[derive(Debug, Clone, Serialize)]
+struct Event {
+    name: String,
+    cat: &'static str,
+    ph: &'static str,
+    /// start, in microseconds since the trace started
+    ts: u64,
+    /// duration, in microseconds
+    dur: u64,
+    pid: u32,
+    tid: usize,
+    args: BTreeMap<&'static str, String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct File<'a> {
+    trace_events: &'a [Event],
+    display_time_unit: &'static str,
+}
+
+impl Trace {
+    /// Trace recording the spans from now on
+    pub fn enabled() -> Self {
+        Self {
+            recording: Some(Arc::new(Mutex::new(Recording {
+                start: Instant::now(),
+                events: Vec::new(),
+                lanes: Vec::new(),
+            }))),
+        }
+    }
+
+    /// Span of `category`, recorded when dropped. Does nothing unless the
+    /// trace is enabled
+    pub fn span(&self, category: &'static str, name: impl Into<String>) -> Span {
+        let started = self.recording.as_ref().map(|r| {
+            let lane = lock(r).take_lane();
+            (r.clone(), lane, Instant::now())
+        });
+        Span {
+            category,
+            name: name.into(),
+            args: BTreeMap::new(),
+            started,
+        }
+    }
+
+    /// Spans recorded so far, as JSON
+    pub fn to_json(&self) -> String {
+        let events = self
+            .recording
+            .as_ref()
+            .map(|r| lock(r).events.clone())
+            .unwrap_or_default();
+        let file = File {
+            trace_events: &events,
+            display_time_unit: "ms",
+        };
+        serde_json::to_string(&file).unwrap_or_default()
+    }
+
+    /// Write the spans recorded so far to a file
+    pub fn write(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_json())
+            .with_context(|| format!("unable to write the trace to {}", path.display()))
+    }
+}
+
+fn lock(recording: &Mutex<Recording>) -> MutexGuard<'_, Recording> {
+    recording.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Running span
+#[derive(Debug)]
+pub struct Span {
+    category: &'static str,
+    name: String,
+    args: BTreeMap<&'static str, String>,
+    /// recording, lane and start, when recorded
+    started: Option<(Arc<Mutex<Recording>>, usize, Instant)>,
+}
+
+impl Span {
+    /// Attach a value to the span, e.g. the measured URL
+    pub fn arg(&mut self, key: &'static str, value: impl fmt::Display) {
+        if self.started.is_some() {
+            self.args.insert(key, value.to_string());
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some((recording, lane, started)) = self.started.take() else {
+            return;
+        };
+        let mut trace = lock(&recording);
+        trace.lanes[lane] = false;
+        let ts = micros(started.saturating_duration_since(trace.start));
+        trace.events.push(Event {
+            name: std::mem::take(&mut self.name),
+            cat: self.category,
+            ph: "X",
+            ts,
+            dur: micros(started.elapsed()),
+            pid: std::process::id(),
+            tid: lane,
+            args: std::mem::take(&mut self.args),
+        });
+    }
+}
+
+fn micros(d: std::time::Duration) -> u64 {
+    d.as_micros().try_into().unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lanes() {
+        let mut trace = Recording {
+            start: Instant::now(),
+            events: Vec::new(),
+            lanes: Vec::new(),
+        };
+        assert_eq!(trace.take_lane(), 0);
+        assert_eq!(trace.take_lane(), 1);
+        trace.lanes[0] = false;
+        assert_eq!(trace.take_lane(), 0);
+        assert_eq!(trace.take_lane(), 2);
+    }
+
+    #[test]
+    fn spans() {
+        let disabled = Trace::default();
+        let span = disabled.span("test", "trace disabled");
+        assert!(span.started.is_none());
+        drop(span);
+        assert_eq!(
+            disabled.to_json(),
+            r#"{"traceEvents":[],"displayTimeUnit":"ms"}"#
+        );
+        let trace = Trace::enabled();
+        let outer = trace.span("test", "trace outer");
+        let mut inner = trace.span("test", "trace inner");
+        inner.arg("item", "https://a.example.org/");
+        drop(inner);
+        drop(outer);
+        let json: serde_json::Value = serde_json::from_str(&trace.to_json()).unwrap();
+        let events = json["traceEvents"].as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["name"], "trace inner");
+        assert_eq!(events[0]["args"]["item"], "https://a.example.org/");
+        assert_eq!(events[0]["ph"], "X");
+        assert_ne!(events[0]["tid"], events[1]["tid"]);
+        assert!(events[1]["dur"].as_u64() >= events[0]["dur"].as_u64());
+    }
+}
@@ -0,0 +1,325 @@
+//! systemd units refreshing the mirrorlist periodically.
+//!
+//! A oneshot service runs reflecto and a timer starts it. System units write
+//! `/etc/pacman.d/mirrorlist`; user units, for rootless setups, write a file
+//! owned by the user, to be included from `pacman.conf`. Unprivileged system
+//! units run reflecto as a dynamic user, systemd opening the mirrorlist and
+//! handing it over with `--write-fd`. Hardened system units run in a sandbox
+//! allowing only the write of the mirrorlist and of the state of the runs,
+//! reading the configuration from `/etc/reflecto`. The state of the runs of
+//! system units is in `/var/lib/reflecto`.
+use crate::config;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Name of the units, without extension
+pub const NAME: &str = "reflecto";
+
+/// Mirrorlist written by the system service
+pub const SYSTEM_MIRRORLIST: &str = "/etc/pacman.d/mirrorlist";
+
+/// Service and timer running reflecto
+#[derive(Debug, Clone, PartialEq)]
+pub struct Units {
+    /// path of the reflecto executable
+    pub exec: PathBuf,
+    /// mirrorlist written by the service
+    pub mirrorlist: PathBuf,
+    /// other options of reflecto
+    pub args: Vec<String>,
+    /// when the timer triggers, as a systemd calendar event
+    pub on_calendar: String,
+    /// maximum random delay added to each trigger
+    pub randomized_delay: Option<Duration>,
+    /// run a trigger missed while the machine was off at the next boot
+    pub persistent: bool,
+    /// units of the user service manager
+    pub user: bool,
+    /// run the system service as an unprivileged dynamic user
+    pub unprivileged: bool,
+    /// sandbox the system service
+    pub hardened: bool,
+}
+
+impl Default for Units {
+    fn default() -> Self {
+        Self {
+            exec: PathBuf::from("/usr/bin/reflecto"),
+            mirrorlist: PathBuf::from(SYSTEM_MIRRORLIST),
+            args: Vec::new(),
+            on_calendar: "weekly".into(),
+            randomized_delay: None,
+            persistent: false,
+            user: false,
+            unprivileged: false,
+            hardened: false,
+        }
+    }
+}
+
+impl Units {
+    /// Content of the service unit
+    pub fn service(&self) -> String {
+        let mut lines = vec![
+            "[Unit]".to_string(),
+            "Description=Refresh the pacman mirrorlist with reflecto".into(),
+        ];
+        if !self.user {
+            // the user service manager has no network target
+            lines.push("Wants=network-online.target".into());
+            lines.push("After=network-online.target".into());
+        }
+        let mut command = vec![self.exec.to_string_lossy().into_owned()];
+        let unprivileged = self.unprivileged && !self.user;
+        if unprivileged {
+            // the file opened by systemd is the first passed descriptor
+            command.extend(["--write-fd".into(), "3".into()]);
+        } else {
+            command.extend([
+                "--save".into(),
+                self.mirrorlist.to_string_lossy().into_owned(),
+            ]);
+        }
+        command.extend(self.args.iter().cloned());
+        let command = command
+            .iter()
+            .map(|a| quote(a))
+            .collect::<Vec<_>>()
+            .join(" ");
+        lines.extend([String::new(), "[Service]".into(), "Type=oneshot".into()]);
+        if !self.user {
+            // where the outcome of the runs is recorded
+            lines.push("StateDirectory=reflecto".into());
+        }
+        if unprivileged {
+            lines.extend([
+                "DynamicUser=yes".into(),
+                // specifiers are expanded in paths, which are not unquoted. The
+                // file is opened read-write without truncating it, so that it
+                // keeps its content until --write-fd replaces it
+                format!(
+                    "OpenFile={}",
+                    self.mirrorlist.to_string_lossy().replace('%', "%%")
+                ),
+            ]);
+        }
+        if self.hardened && !self.user {
+            lines.extend(HARDENING.iter().map(|l| l.to_string()));
+            if !unprivileged {
+                let dir = self.mirrorlist.parent().unwrap_or(Path::new("/"));
+                // the lock file is next to the mirrorlist
+                lines.push(format!(
+                    "ReadWritePaths={}",
+                    quote_word(&dir.to_string_lossy())
+                ));
+            }
+        }
+        lines.push(format!("ExecStart={command}"));
+        lines.join("\n") + "\n"
+    }
+
+    /// Content of the timer unit
+    pub fn timer(&self) -> String {
+        let mut lines = vec![
+            "[Unit]".to_string(),
+            "Description=Refresh the pacman mirrorlist with reflecto periodically".into(),
+            String::new(),
+            "[Timer]".into(),
+            format!("OnCalendar={}", self.on_calendar),
+        ];
+        if let Some(delay) = self.randomized_delay {
+            lines.push(format!("RandomizedDelaySec={}", delay.as_secs()));
+        }
+        if self.persistent {
+            lines.push("Persistent=true".into());
+        }
+        lines.extend([
+            String::new(),
+            "[Install]".into(),
+            "WantedBy=timers.target".into(),
+        ]);
+        lines.join("\n") + "\n"
+    }
+
+    /// Write the units in `dir`, creating it if needed. Returns the written files
+    pub fn install(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("unable to create {}", dir.display()))?;
+        let mut written = Vec::new();
+        for (extension, content) in [("service", self.service()), ("timer", self.timer())] {
+            let path = dir.join(format!("{NAME}.{extension}"));
+            std::fs::write(&path, content)
+                .with_context(|| format!("unable to write {}", path.display()))?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+}
+
+/// Sandboxing of hardened services. The configuration is read from
+/// `/etc/reflecto`, and the lock of runs writing several files is in
+/// `/run/reflecto`
+const HARDENING: [&str; 22] = [
+    "ConfigurationDirectory=reflecto",
+    "RuntimeDirectory=reflecto",
+    "ProtectSystem=strict",
+    "ProtectHome=yes",
+    "PrivateTmp=yes",
+    "PrivateDevices=yes",
+    "NoNewPrivileges=yes",
+    "CapabilityBoundingSet=",
+    "ProtectKernelTunables=yes",
+    "ProtectKernelModules=yes",
+    "ProtectKernelLogs=yes",
+    "ProtectControlGroups=yes",
+    "ProtectClock=yes",
+    "ProtectHostname=yes",
+    "RestrictAddressFamilies=AF_INET AF_INET6 AF_UNIX",
+    "RestrictNamespaces=yes",
+    "RestrictRealtime=yes",
+    "RestrictSUIDSGID=yes",
+    "LockPersonality=yes",
+    "MemoryDenyWriteExecute=yes",
+    "SystemCallArchitectures=native",
+    "SystemCallFilter=@system-service",
+];
+
+/// Directory of the units: the one of the system administrator, or the one of
+/// the user
+pub fn default_dir(user: bool) -> Option<PathBuf> {
+    if user {
+        config::config_home().map(|d| d.join("systemd").join("user"))
+    } else {
+        Some(PathBuf::from("/etc/systemd/system"))
+    }
+}
+
+/// Default mirrorlist written by the system units, or by the user units
+pub fn default_mirrorlist(user: bool) -> Option<PathBuf> {
+    if user {
+        config::config_dir().map(|d| d.join("mirrorlist"))
+    } else {
+        Some(PathBuf::from(SYSTEM_MIRRORLIST))
+    }
+}
+
+/// quote an argument of a command line of a unit, escaping specifiers and
+/// environment variables
+fn quote(arg: &str) -> String {
+    quote_word(&arg.replace('$', "$$"))
+}
+
+/// quote a word of a setting of a unit, e.g. a path, escaping specifiers
+fn quote_word(word: &str) -> String {
+    let escaped = word
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%");
+    if escaped.is_empty() || escaped.contains(char::is_whitespace) {
+        format!("\"{escaped}\"")
+    } else {
+        escaped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_units() {
+        let units = Units {
+            args: vec![
+                "--country".into(),
+                "FR,DE".into(),
+                "--sort".into(),
+                "rate".into(),
+            ],
+            randomized_delay: Some(Duration::from_secs(900)),
+            persistent: true,
+            ..Default::default()
+        };
+        let service = units.service();
+        assert!(service.contains("After=network-online.target"));
+        assert!(service.contains("Type=oneshot\nStateDirectory=reflecto\n"));
+        assert!(service.contains(
+            "ExecStart=/usr/bin/reflecto --save /etc/pacman.d/mirrorlist --country FR,DE --sort rate\n"
+        ));
+        let timer = units.timer();
+        assert!(timer.contains("OnCalendar=weekly\nRandomizedDelaySec=900\nPersistent=true\n"));
+        assert!(timer.ends_with("WantedBy=timers.target\n"));
+    }
+
+    #[test]
+    fn user_units() {
+        let units = Units {
+            mirrorlist: "/home/me/.config/reflecto/mirrorlist".into(),
+            on_calendar: "daily".into(),
+            user: true,
+            ..Default::default()
+        };
+        assert!(!units.service().contains("network-online"));
+        assert!(!units.service().contains("StateDirectory"));
+        let timer = units.timer();
+        assert!(timer.contains("OnCalendar=daily\n"));
+        assert!(!timer.contains("RandomizedDelaySec"));
+        assert!(!timer.contains("Persistent"));
+    }
+
+    #[test]
+    fn unprivileged_units() {
+        let units = Units {
+            unprivileged: true,
+            ..Default::default()
+        };
+        let service = units.service();
+        assert!(service.contains(
+            "DynamicUser=yes\nOpenFile=/etc/pacman.d/mirrorlist\nExecStart=/usr/bin/reflecto --write-fd 3\n"
+        ));
+        assert!(!service.contains("--save"));
+    }
+
+    #[test]
+    fn hardened_units() {
+        let units = Units {
+            hardened: true,
+            ..Default::default()
+        };
+        let service = units.service();
+        assert!(service.contains("ProtectSystem=strict\n"));
+        assert!(service.contains("PrivateTmp=yes\n"));
+        assert!(service.contains("ReadWritePaths=/etc/pacman.d\nExecStart="));
+        let unprivileged = Units {
+            unprivileged: true,
+            ..units
+        };
+        let service = unprivileged.service();
+        assert!(service.contains("ConfigurationDirectory=reflecto\n"));
+        assert!(!service.contains("ReadWritePaths"));
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote("rate"), "rate");
+        assert_eq!(quote("Server = {url}"), "\"Server = {url}\"");
+        assert_eq!(quote("100%"), "100%%");
+        assert_eq!(quote(""), "\"\"");
+        assert_eq!(quote("Server = ${url}"), "\"Server = $${url}\"");
+        assert_eq!(quote_word("/srv/$mirrors"), "/srv/$mirrors");
+    }
+
+    #[test]
+    fn install() {
+        let dir = std::env::temp_dir().join(format!("reflecto-units-{}", std::process::id()));
+        let written = Units::default().install(&dir).unwrap();
+        assert_eq!(
+            written,
+            [dir.join("reflecto.service"), dir.join("reflecto.timer")]
+        );
+        assert!(std::fs::read_to_string(&written[1])
+            .unwrap()
+            .contains("OnCalendar=weekly"));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
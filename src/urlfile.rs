@@ -0,0 +1,129 @@
+//! Files listing mirrors by URL or by domain, one per line.
+//!
+//! ```text
+//! # slow from here
+//! https://mirror.example.org/archlinux/
+//! example.com
+//! ```
+//!
+//! A domain matches the mirrors hosted on it or on one of its subdomains.
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct UrlFile {
+    pub path: PathBuf,
+    pub entries: Vec<String>,
+}
+
+impl UrlFile {
+    /// Read the file, considering a missing file as empty
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("unable to read {}", path.display())),
+        };
+        Ok(Self {
+            path: path.into(),
+            entries: parse(&content),
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut content = self.entries.join("\n");
+        content.push('\n');
+        std::fs::write(&self.path, content)
+            .with_context(|| format!("unable to write {}", self.path.display()))
+    }
+
+    /// Add an entry, returning false if it was already present
+    pub fn add(&mut self, entry: &str) -> bool {
+        let entry = entry.trim();
+        if entry.is_empty() || self.entries.iter().any(|e| same(e, entry)) {
+            return false;
+        }
+        self.entries.push(entry.into());
+        true
+    }
+
+    /// Remove an entry, returning false if it was absent
+    pub fn remove(&mut self, entry: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|e| !same(e, entry.trim()));
+        self.entries.len() != len
+    }
+
+    /// Whether a mirror URL matches an entry
+    pub fn matches(&self, url: &str) -> bool {
+        self.entries.iter().any(|e| entry_matches(e, url))
+    }
+}
+
+fn parse(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(|l| l.split('#').next().unwrap_or_default().trim())
+        .filter(|l| !l.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// compare URLs ignoring a trailing '/'
+fn same(a: &str, b: &str) -> bool {
+    a.trim_end_matches('/') == b.trim_end_matches('/')
+}
+
+fn entry_matches(entry: &str, url: &str) -> bool {
+    if entry.contains("://") {
+        return url
+            .trim_end_matches('/')
+            .starts_with(entry.trim_end_matches('/'));
+    }
+    let host = reqwest::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_lowercase));
+    let domain = entry.trim_matches('.').to_lowercase();
+    host.is_some_and(|h| h == domain || h.ends_with(&format!(".{domain}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matching() {
+        let list = UrlFile {
+            entries: parse("# comment\nhttps://mirror.example.org/arch/\n\nexample.com # slow\n"),
+            ..Default::default()
+        };
+        assert_eq!(list.entries.len(), 2);
+        assert!(list.matches("https://mirror.example.org/arch/"));
+        assert!(list.matches("https://mirror.example.org/arch"));
+        assert!(!list.matches("http://mirror.example.org/arch/"));
+        assert!(list.matches("https://example.com/archlinux/"));
+        assert!(list.matches("rsync://ftp.Example.com/archlinux/"));
+        assert!(!list.matches("https://notexample.com/archlinux/"));
+    }
+
+    #[test]
+    fn edit() {
+        let path = std::env::temp_dir().join(format!("reflecto-urlfile-{}", std::process::id()));
+        let mut list = UrlFile::load(&path).unwrap();
+        assert!(list.entries.is_empty());
+        assert!(list.add("example.com"));
+        assert!(list.add("https://a.example.org/"));
+        assert!(!list.add("https://a.example.org"));
+        list.save().unwrap();
+        let mut list = UrlFile::load(&path).unwrap();
+        assert_eq!(list.entries, vec!["example.com", "https://a.example.org/"]);
+        assert!(list.remove("https://a.example.org"));
+        assert!(!list.remove("b.example.org"));
+        assert_eq!(list.entries, vec!["example.com"]);
+        let _ = std::fs::remove_file(path);
+    }
+}